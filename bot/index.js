@@ -7,26 +7,48 @@ app.use(bodyParser.json());
 
 const PORT = process.env.PORT || 3000;
 
+// Polled by the scanner before it starts sending servers
+app.get('/health', (req, res) => {
+    res.json({ status: 'ok' });
+});
+
 app.post('/join', async (req, res) => {
-    const { host, port, version } = req.body;
+    // account is optional: { username, uuid, access_token }, see BotAccount in src/config.rs
+    const { host, port, version, account } = req.body;
 
     if (!host || !port) {
         return res.status(400).json({ error: 'Host and port are required' });
     }
 
     try {
-        const result = await scanServer(host, parseInt(port), version);
+        const result = await scanServer(host, parseInt(port), version, account);
         res.json(result);
     } catch (e) {
         res.status(500).json({ error: e.message });
     }
 });
 
-async function scanServer(host, port, version) {
+// Joins with an access token the scanner already has, nothing is logged in or refreshed here
+function sessionAuth(account) {
+    return (client, options) => {
+        client.username = account.username;
+        client.session = {
+            accessToken: account.access_token,
+            selectedProfile: { id: account.uuid, name: account.username }
+        };
+        options.accessToken = account.access_token;
+        options.haveCredentials = true;
+        client.emit('session', client.session);
+        options.connect(client);
+    };
+}
+
+async function scanServer(host, port, version, account) {
     return new Promise((resolve) => {
-        const username = 'Seeker' + Math.floor(Math.random() * 10000);
+        const username = account ? account.username : 'Seeker' + Math.floor(Math.random() * 10000);
         let plugins = [];
         let chatLog = [];
+        let worldName = null;
         let ended = false;
 
         // Timeout de segurança global para a promessa
@@ -40,7 +62,7 @@ async function scanServer(host, port, version) {
             host: host,
             port: port,
             username: username,
-            auth: 'offline',
+            auth: account ? sessionAuth(account) : 'offline',
             hideErrors: true,
             checkTimeoutInterval: 10000
         };
@@ -51,6 +73,18 @@ async function scanServer(host, port, version) {
 
         let bot;
 
+        // Read by WorldInfo in src/database.rs, keys: name, dimension, gamemode, difficulty
+        function worldInfo() {
+            if (!bot || !bot.game || !bot.game.dimension) return null;
+
+            return {
+                name: worldName,
+                dimension: bot.game.dimension,
+                gamemode: bot.game.gameMode || null,
+                difficulty: bot.game.difficulty || null
+            };
+        }
+
         function finish(status, extra = {}) {
             if (ended) return;
             ended = true;
@@ -70,6 +104,7 @@ async function scanServer(host, port, version) {
                 version: detectedVersion,
                 plugins: [...new Set(plugins)], // unique
                 chat: chatLog,
+                world: worldInfo(),
                 ...extra
             });
         }
@@ -87,6 +122,13 @@ async function scanServer(host, port, version) {
                 return;
             }
 
+            // World names were added to the login packet in 1.16 and moved under worldState in 1.20.2
+            const readWorldName = (packet) => {
+                worldName = packet.worldName || (packet.worldState && packet.worldState.name) || worldName;
+            };
+            bot._client.on('login', readWorldName);
+            bot._client.on('respawn', readWorldName);
+
             bot.on('login', () => {
                 // Command Jitter: 0 to 500ms extra delay
                 const jitter = () => Math.floor(Math.random() * 500);
@@ -0,0 +1,2 @@
+ALTER TABLE servers ADD COLUMN channels JSONB;
+ALTER TABLE servers ADD COLUMN modpack JSONB;
@@ -0,0 +1 @@
+ALTER TABLE servers ADD COLUMN raw_json JSONB;
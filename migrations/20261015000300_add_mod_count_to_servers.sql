@@ -0,0 +1 @@
+ALTER TABLE servers ADD COLUMN mod_count INTEGER;
@@ -0,0 +1,2 @@
+ALTER TABLE servers ADD COLUMN last_connect_ok BOOLEAN;
+ALTER TABLE servers ADD COLUMN connect_failures INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+ALTER TABLE servers ADD COLUMN canonical_version TEXT;
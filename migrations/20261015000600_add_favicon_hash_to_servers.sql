@@ -0,0 +1,2 @@
+ALTER TABLE servers ADD COLUMN favicon_hash TEXT;
+CREATE INDEX servers_favicon_hash_idx ON servers (favicon_hash);
@@ -0,0 +1 @@
+ALTER TABLE servers ADD COLUMN mods_truncated BOOLEAN NOT NULL DEFAULT FALSE;
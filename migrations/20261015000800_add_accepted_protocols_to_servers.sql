@@ -0,0 +1,2 @@
+ALTER TABLE servers ADD COLUMN min_accepted_protocol INTEGER;
+ALTER TABLE servers ADD COLUMN max_accepted_protocol INTEGER;
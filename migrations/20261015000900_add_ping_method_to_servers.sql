@@ -0,0 +1 @@
+ALTER TABLE servers ADD COLUMN ping_method TEXT;
@@ -0,0 +1,5 @@
+-- Every server is a single IPv4 host. NOT VALID enforces this for new and updated rows
+-- without failing on rows already stored, the rescanner skips those with a warning.
+-- Once they're cleaned up, run: ALTER TABLE servers VALIDATE CONSTRAINT servers_address_ipv4_host;
+ALTER TABLE servers ADD CONSTRAINT servers_address_ipv4_host
+    CHECK (family(address) = 4 AND masklen(address) = 32) NOT VALID;
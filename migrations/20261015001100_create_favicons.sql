@@ -0,0 +1,7 @@
+-- Favicons are stored once per image as raw bytes, servers reference them by favicon_hash.
+-- servers.icon only keeps favicons that aren't valid base64, icons already stored there
+-- move over as their servers are pinged again
+CREATE TABLE favicons (
+    hash TEXT PRIMARY KEY,
+    image BYTEA NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE servers ADD COLUMN lenient_parse BOOLEAN NOT NULL DEFAULT FALSE;
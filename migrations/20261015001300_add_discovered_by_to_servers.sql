@@ -0,0 +1 @@
+ALTER TABLE servers ADD COLUMN discovered_by TEXT;
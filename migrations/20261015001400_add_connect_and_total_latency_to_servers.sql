@@ -0,0 +1,6 @@
+ALTER TABLE servers ADD COLUMN connect_latency INTEGER;
+ALTER TABLE servers ADD COLUMN total_latency INTEGER;
+
+COMMENT ON COLUMN servers.latency IS 'Ping/Pong round trip in ms, or the TCP connect time when the server does not answer the Ping packet (always the case for legacy servers)';
+COMMENT ON COLUMN servers.connect_latency IS 'TCP connect time in ms';
+COMMENT ON COLUMN servers.total_latency IS 'Whole ping in ms, including reading the status and a failed modern attempt before a legacy ping';
@@ -0,0 +1,9 @@
+ALTER TABLE servers ADD COLUMN tcp_rtt_us INTEGER;
+ALTER TABLE servers ADD COLUMN tcp_mss INTEGER;
+ALTER TABLE servers ADD COLUMN tcp_pmtu INTEGER;
+ALTER TABLE servers ADD COLUMN tcp_signature TEXT;
+
+COMMENT ON COLUMN servers.tcp_rtt_us IS 'Kernel smoothed RTT in microseconds right after the TCP handshake';
+COMMENT ON COLUMN servers.tcp_mss IS 'Segment size used towards the server, below 1460 usually means a tunnel or VPN in the path';
+COMMENT ON COLUMN servers.tcp_pmtu IS 'Path MTU towards the server';
+COMMENT ON COLUMN servers.tcp_signature IS 'TCP options from the server SYN-ACK, e.g. ws=7,sack,ts';
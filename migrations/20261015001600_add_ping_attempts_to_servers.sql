@@ -0,0 +1,3 @@
+ALTER TABLE servers ADD COLUMN ping_attempts INTEGER;
+
+COMMENT ON COLUMN servers.ping_attempts IS 'Pings needed for the last response, above 1 when earlier attempts timed out (scanner.ping_retries)';
@@ -0,0 +1,7 @@
+ALTER TABLE servers ADD COLUMN ping_failures INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE servers ADD COLUMN opted_out BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE servers ADD COLUMN rescan_after BIGINT;
+
+COMMENT ON COLUMN servers.ping_failures IS 'Failed pings in a row since the last response';
+COMMENT ON COLUMN servers.opted_out IS 'The server asked not to be scanned, everything but the address and port was removed';
+COMMENT ON COLUMN servers.rescan_after IS 'Rescans skip the server until this unix time, see rescanner.backoff';
@@ -0,0 +1,6 @@
+ALTER TABLE servers ADD COLUMN links TEXT[];
+
+-- For finding every server advertising the same link, e.g. WHERE links @> ARRAY['discord.gg/example']
+CREATE INDEX IF NOT EXISTS servers_links_index ON servers USING GIN (links);
+
+COMMENT ON COLUMN servers.links IS 'URLs, domains, Discord invites and addresses found in the description';
@@ -0,0 +1,3 @@
+ALTER TABLE servers ADD COLUMN security_profile TEXT;
+
+COMMENT ON COLUMN servers.security_profile IS 'Chat signing posture from preventsChatReports and enforcesSecureChat: unknown, secure_chat_optional, secure_chat_enforced, no_chat_reports, no_chat_reports_mod or conflicting';
@@ -0,0 +1,9 @@
+ALTER TABLE servers ADD COLUMN description_plain TEXT;
+
+-- For filtering on the detected server software, e.g. WHERE software = 'Purpur'
+CREATE INDEX IF NOT EXISTS servers_software_index ON servers (software);
+
+-- For searching descriptions, e.g. WHERE to_tsvector('simple', description_plain) @@ plainto_tsquery('simple', 'skyblock')
+CREATE INDEX IF NOT EXISTS servers_description_plain_index ON servers USING GIN (to_tsvector('simple', description_plain));
+
+COMMENT ON COLUMN servers.description_plain IS 'The description as plain text, with formatting codes removed';
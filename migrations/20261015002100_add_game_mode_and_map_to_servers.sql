@@ -0,0 +1,5 @@
+ALTER TABLE servers ADD COLUMN game_mode TEXT;
+ALTER TABLE servers ADD COLUMN map TEXT;
+
+COMMENT ON COLUMN servers.game_mode IS 'Game mode some plugins and proxies add to the status response, in lowercase';
+COMMENT ON COLUMN servers.map IS 'Map or level name some plugins and proxies add to the status response';
@@ -0,0 +1,6 @@
+ALTER TABLE servers ADD COLUMN mc_version TEXT;
+
+-- For prefix searches across forks, e.g. WHERE mc_version LIKE '1.20.%'
+CREATE INDEX IF NOT EXISTS servers_mc_version_index ON servers (mc_version text_pattern_ops);
+
+COMMENT ON COLUMN servers.mc_version IS 'Minecraft version from the version name, or the protocol''s release when the name has none';
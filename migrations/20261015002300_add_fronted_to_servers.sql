@@ -0,0 +1,3 @@
+ALTER TABLE servers ADD COLUMN fronted BOOLEAN;
+
+COMMENT ON COLUMN servers.fronted IS 'Whether the server looks like it is behind a TCP proxy such as TCPShield or Cloudflare Spectrum, NULL when not checked';
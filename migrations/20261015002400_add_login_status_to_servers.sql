@@ -0,0 +1,3 @@
+ALTER TABLE servers ADD COLUMN login_status TEXT;
+
+COMMENT ON COLUMN servers.login_status IS 'How the server answered a login attempt: online_mode, offline_accessible, whitelisted, full, banned, wrong_version, login_plugin or disconnected';
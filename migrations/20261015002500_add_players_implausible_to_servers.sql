@@ -0,0 +1,3 @@
+ALTER TABLE servers ADD COLUMN players_implausible BOOLEAN;
+
+COMMENT ON COLUMN servers.players_implausible IS 'Whether the reported player counts are negative, absurdly large or far past the max, such servers are left out of player statistics';
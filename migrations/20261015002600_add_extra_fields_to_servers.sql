@@ -0,0 +1,3 @@
+ALTER TABLE servers ADD COLUMN extra_fields JSONB;
+
+COMMENT ON COLUMN servers.extra_fields IS 'Top level status fields the parser does not model, such as chat preview or fork specific flags';
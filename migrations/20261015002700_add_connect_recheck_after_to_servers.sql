@@ -0,0 +1,3 @@
+ALTER TABLE servers ADD COLUMN connect_recheck_after BIGINT;
+
+COMMENT ON COLUMN servers.connect_recheck_after IS 'Unix time after which triage passes check the server again, even if it failed too many connection checks in a row';
@@ -0,0 +1,4 @@
+-- Protocols shared by several releases used to store the whole range, e.g. 1.21-1.21.1
+UPDATE servers SET mc_version = NULL WHERE mc_version LIKE '%-%';
+
+COMMENT ON COLUMN servers.mc_version IS 'Minecraft version from the version name, or the protocol''s release when the name has none and only one release uses it';
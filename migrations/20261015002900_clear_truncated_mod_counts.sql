@@ -0,0 +1,4 @@
+-- A truncated mod list only gives a lower bound, which used to be stored as the count
+UPDATE servers SET mod_count = NULL WHERE mods_truncated;
+
+COMMENT ON COLUMN servers.mod_count IS 'Mods listed in forge data, NULL when the list was truncated';
@@ -0,0 +1,164 @@
+use crate::config::BackpressureConfig;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// Window over which database writes are judged
+const WINDOW: Duration = Duration::from_secs(10);
+// Too few writes to judge the database by
+const MIN_SAMPLES: u32 = 20;
+
+/// Pauses dispatching new pings while database writes are failing or slow, so
+/// the scanner doesn't find servers faster than it can store them
+#[derive(Debug)]
+pub struct DatabaseBreaker {
+	max_error_rate: f64,
+	max_write_latency: Duration,
+	pause: Duration,
+	state: Mutex<BreakerState>,
+}
+
+#[derive(Debug)]
+struct BreakerState {
+	window_start: Instant,
+	writes: u32,
+	errors: u32,
+	total_latency: Duration,
+	paused_until: Option<Instant>,
+}
+
+impl BreakerState {
+	fn reset_window(&mut self, now: Instant) {
+		self.window_start = now;
+		self.writes = 0;
+		self.errors = 0;
+		self.total_latency = Duration::ZERO;
+	}
+}
+
+impl DatabaseBreaker {
+	/// Returns None when backpressure is disabled
+	pub fn new(config: &BackpressureConfig) -> Option<Self> {
+		if !config.enabled {
+			return None;
+		}
+
+		Some(Self {
+			max_error_rate: config.max_error_rate,
+			max_write_latency: Duration::from_millis(config.max_write_latency_ms),
+			pause: Duration::from_secs(config.pause_secs),
+			state: Mutex::new(BreakerState {
+				window_start: Instant::now(),
+				writes: 0,
+				errors: 0,
+				total_latency: Duration::ZERO,
+				paused_until: None,
+			}),
+		})
+	}
+
+	/// Records the outcome of a database write. The latency includes waiting
+	/// for a connection from the pool
+	pub fn record(&self, latency: Duration, database_error: bool) {
+		let now = Instant::now();
+		let mut state = self.state.lock().unwrap();
+
+		// Writes from pings dispatched before the pause don't count towards the next window
+		if state.paused_until.is_some() {
+			return;
+		}
+
+		if now.duration_since(state.window_start) > WINDOW {
+			state.reset_window(now);
+		}
+
+		state.writes += 1;
+		state.total_latency += latency;
+		if database_error {
+			state.errors += 1;
+		}
+
+		if state.writes < MIN_SAMPLES {
+			return;
+		}
+
+		let error_rate = state.errors as f64 / state.writes as f64;
+		let average_latency = state.total_latency / state.writes;
+
+		if error_rate > self.max_error_rate || average_latency > self.max_write_latency {
+			warn!(
+				"Database is falling behind ({:.0}% errors, {}ms average write), pausing new pings for {}s",
+				error_rate * 100.0,
+				average_latency.as_millis(),
+				self.pause.as_secs()
+			);
+			state.paused_until = Some(now + self.pause);
+		}
+	}
+
+	/// Waits until the database has had time to catch up
+	pub async fn wait_until_healthy(&self) {
+		loop {
+			let paused_until = self.state.lock().unwrap().paused_until;
+
+			match paused_until {
+				None => return,
+				Some(until) if until > Instant::now() => tokio::time::sleep_until(until.into()).await,
+				Some(_) => {
+					let mut state = self.state.lock().unwrap();
+					// Another dispatcher may have resumed already
+					if state.paused_until.take().is_some() {
+						info!("Resuming pings, database writes will be checked again");
+						state.reset_window(Instant::now());
+					}
+					return;
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn breaker() -> DatabaseBreaker {
+		DatabaseBreaker::new(&BackpressureConfig {
+			enabled: true,
+			max_error_rate: 0.5,
+			max_write_latency_ms: 1000,
+			pause_secs: 0,
+		})
+		.unwrap()
+	}
+
+	#[test]
+	fn test_trips_on_errors() {
+		let breaker = breaker();
+		for i in 0..MIN_SAMPLES {
+			breaker.record(Duration::from_millis(5), i % 4 != 0);
+		}
+		assert!(breaker.state.lock().unwrap().paused_until.is_some());
+	}
+
+	#[test]
+	fn test_trips_on_latency() {
+		let breaker = breaker();
+		for _ in 0..MIN_SAMPLES {
+			breaker.record(Duration::from_secs(2), false);
+		}
+		assert!(breaker.state.lock().unwrap().paused_until.is_some());
+	}
+
+	#[tokio::test]
+	async fn test_stays_closed_when_healthy() {
+		let breaker = breaker();
+		for _ in 0..MIN_SAMPLES * 2 {
+			breaker.record(Duration::from_millis(5), false);
+		}
+		assert!(breaker.state.lock().unwrap().paused_until.is_none());
+
+		// Returns immediately when nothing is wrong
+		breaker.wait_until_healthy().await;
+	}
+}
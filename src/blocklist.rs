@@ -0,0 +1,204 @@
+use crate::download;
+use anyhow::{bail, Context, Result};
+use sqlx::types::ipnet::Ipv4Net;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Address ranges that are never scanned, kept sorted and merged so lookups are a binary search
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BlockSet {
+	ranges: Vec<(u32, u32)>,
+}
+
+impl BlockSet {
+	/// Parses one CIDR or address per line, blank lines and anything after a `#` are ignored.
+	/// Returns the set along with how many lines couldn't be parsed
+	pub fn parse(content: &str) -> (BlockSet, usize) {
+		let mut ranges = Vec::new();
+		let mut invalid = 0;
+
+		for line in content.lines() {
+			let line = line.split('#').next().unwrap_or_default().trim();
+			if line.is_empty() {
+				continue;
+			}
+
+			if let Ok(network) = line.parse::<Ipv4Net>() {
+				ranges.push((network.network().to_bits(), network.broadcast().to_bits()));
+			} else if let Ok(address) = line.parse::<Ipv4Addr>() {
+				ranges.push((address.to_bits(), address.to_bits()));
+			} else {
+				invalid += 1;
+			}
+		}
+
+		(BlockSet::from_ranges(ranges), invalid)
+	}
+
+	pub fn from_cidrs(cidrs: impl IntoIterator<Item = Ipv4Net>) -> BlockSet {
+		let ranges = cidrs.into_iter().map(|network| (network.network().to_bits(), network.broadcast().to_bits()));
+		BlockSet::from_ranges(ranges.collect())
+	}
+
+	/// Every address in either set
+	pub fn union(&self, other: &BlockSet) -> BlockSet {
+		BlockSet::from_ranges(self.ranges.iter().chain(&other.ranges).copied().collect())
+	}
+
+	fn from_ranges(mut ranges: Vec<(u32, u32)>) -> BlockSet {
+		ranges.sort_unstable();
+		let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+		for (start, end) in ranges {
+			match merged.last_mut() {
+				Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+				_ => merged.push((start, end)),
+			}
+		}
+
+		BlockSet { ranges: merged }
+	}
+
+	pub fn contains(&self, address: Ipv4Addr) -> bool {
+		let address = address.to_bits();
+		let index = self.ranges.partition_point(|(start, _)| *start <= address);
+		index > 0 && self.ranges[index - 1].1 >= address
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.ranges.is_empty()
+	}
+
+	/// The set as the fewest CIDRs that cover it, for the scan engines' exclude files
+	pub fn to_cidrs(&self) -> Vec<Ipv4Net> {
+		let mut cidrs = Vec::new();
+
+		for &(start, end) in &self.ranges {
+			let (mut start, end) = (start as u64, end as u64);
+			while start <= end {
+				// The largest block aligned on `start` that doesn't run past `end`
+				let mut prefix_len = 32 - start.trailing_zeros().min(32);
+				while start + (1 << (32 - prefix_len)) - 1 > end {
+					prefix_len += 1;
+				}
+
+				cidrs.push(Ipv4Net::new(Ipv4Addr::from_bits(start as u32), prefix_len as u8).unwrap());
+				start += 1 << (32 - prefix_len);
+			}
+		}
+
+		cidrs
+	}
+}
+
+/// The current blocklist, shared by the scan engines and every ping. A refresh swaps in
+/// a whole new set, so each check sees either the old list or the new one, never a mix
+#[derive(Debug, Default)]
+pub struct Blocklist {
+	current: RwLock<Arc<BlockSet>>,
+}
+
+impl Blocklist {
+	/// Fetches the list from `url`, failing if that doesn't work so scanning never starts
+	/// without it. After that it's refreshed every `refresh_interval`, 0 never refreshes
+	pub async fn start(url: String, refresh_interval: Duration) -> Result<Arc<Blocklist>> {
+		let blocklist = Arc::new(Blocklist::default());
+		blocklist.refresh(&url).await?;
+
+		if !refresh_interval.is_zero() {
+			let blocklist = blocklist.clone();
+			tokio::spawn(async move { blocklist.refresh_periodically(&url, refresh_interval).await });
+		}
+
+		Ok(blocklist)
+	}
+
+	pub fn snapshot(&self) -> Arc<BlockSet> {
+		self.current.read().unwrap().clone()
+	}
+
+	pub fn contains(&self, address: Ipv4Addr) -> bool {
+		self.snapshot().contains(address)
+	}
+
+	pub fn replace(&self, set: BlockSet) {
+		*self.current.write().unwrap() = Arc::new(set);
+	}
+
+	/// Downloads the list and swaps it in, the current list is untouched on failure
+	pub async fn refresh(&self, url: &str) -> Result<()> {
+		let download = download::fetch(url, download::MAX_CIDR_LIST_BYTES)
+			.await
+			.context("Failed to download blocklist")?;
+		let content = String::from_utf8(download.body).context("Blocklist is not valid text")?;
+
+		let (set, invalid) = BlockSet::parse(&content);
+		// An error page served with a 200 shouldn't wipe out the list
+		if set.is_empty() && invalid > 0 {
+			bail!("Blocklist has no valid entries, {} lines couldn't be parsed", invalid);
+		}
+		if invalid > 0 {
+			warn!("Skipped {} invalid blocklist entries", invalid);
+		}
+
+		info!("Loaded blocklist with {} ranges from {}", set.ranges.len(), url);
+		self.replace(set);
+		Ok(())
+	}
+
+	async fn refresh_periodically(&self, url: &str, interval: Duration) {
+		loop {
+			tokio::time::sleep(interval).await;
+			if let Err(e) = self.refresh(url).await {
+				warn!("Failed to refresh blocklist, keeping the previous one: {:#}", e);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_block_set() {
+		let (set, invalid) = BlockSet::parse(
+			"# Opt-outs\n10.0.0.0/24\n10.0.1.0/24 # merged with the line above\n192.168.1.7\n\nnot an address\n",
+		);
+		assert_eq!(invalid, 1);
+
+		assert!(set.contains(Ipv4Addr::new(10, 0, 0, 0)));
+		assert!(set.contains(Ipv4Addr::new(10, 0, 1, 255)));
+		assert!(set.contains(Ipv4Addr::new(192, 168, 1, 7)));
+		assert!(!set.contains(Ipv4Addr::new(10, 0, 2, 0)));
+		assert!(!set.contains(Ipv4Addr::new(192, 168, 1, 8)));
+		assert!(!set.contains(Ipv4Addr::new(9, 255, 255, 255)));
+
+		assert_eq!(
+			set.to_cidrs().iter().map(|cidr| cidr.to_string()).collect::<Vec<_>>(),
+			vec!["10.0.0.0/23", "192.168.1.7/32"]
+		);
+
+		let (everything, _) = BlockSet::parse("0.0.0.0/0");
+		assert!(everything.contains(Ipv4Addr::new(255, 255, 255, 255)));
+		assert_eq!(everything.to_cidrs().len(), 1);
+
+		let union = set.union(&BlockSet::from_cidrs(["10.0.2.0/24".parse().unwrap(), "1.2.3.4/32".parse().unwrap()]));
+		assert_eq!(
+			union.to_cidrs().iter().map(|cidr| cidr.to_string()).collect::<Vec<_>>(),
+			vec!["1.2.3.4/32", "10.0.0.0/23", "10.0.2.0/24", "192.168.1.7/32"]
+		);
+	}
+
+	#[test]
+	fn test_blocklist_swap() {
+		let blocklist = Blocklist::default();
+		let before = blocklist.snapshot();
+
+		blocklist.replace(BlockSet::parse("1.2.3.4").0);
+		assert!(blocklist.contains(Ipv4Addr::new(1, 2, 3, 4)));
+		// A snapshot taken before the swap keeps seeing the old list
+		assert!(!before.contains(Ipv4Addr::new(1, 2, 3, 4)));
+	}
+}
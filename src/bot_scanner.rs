@@ -1,12 +1,13 @@
-use crate::config::BotConfig;
-use crate::database::{BotServerDetails, Database, ScanCandidate};
+use crate::config::{BotAccount, BotConfig};
+use crate::database::{BotServerDetails, Database, EventType, ScanCandidate, Severity, WorldInfo};
+use anyhow::{bail, Context};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::process::Stdio;
 use std::time::Duration;
 use tokio::process::Command;
-use tokio::time::sleep;
-use tracing::{error, info, warn};
+use tokio::time::{sleep, Instant};
+use tracing::{debug, error, info, warn};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct BotResponse {
@@ -15,15 +16,20 @@ struct BotResponse {
     version: Option<String>,
     plugins: Option<Vec<String>>,
     chat: Option<Vec<String>>,
+    world: Option<WorldInfo>,
     reason: Option<String>,
     error: Option<String>,
 }
 
+/// Body of a /join request. `account` is left out when no accounts are configured, the bot
+/// then joins in offline mode
 #[derive(Debug, Serialize)]
-struct BotRequest {
+struct BotRequest<'a> {
     host: String,
     port: u16,
     version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    account: Option<&'a BotAccount>,
 }
 
 pub struct BotScanner {
@@ -44,14 +50,19 @@ impl BotScanner {
         }
     }
 
-    pub async fn start(&self) {
+    /// Runs the bot and scans with it, errors when the accounts can't be loaded or the bot's
+    /// API never comes up
+    pub async fn start(&self) -> anyhow::Result<()> {
         if !self.config.enabled {
             info!("Bot scanner is disabled in config.");
-            return;
+            return Ok(());
         }
 
         info!("Starting Bot Scanner...");
 
+        // Checked before the bot is started, there's nothing to clean up yet
+        let accounts = self.config.load_accounts().context("Failed to load bot accounts")?;
+
         // Try to check if bot is already running by pinging the port?
         // Or just try to spawn. 
         // For robustness, let's try to spawn if we can't connect.
@@ -65,32 +76,81 @@ impl BotScanner {
             .env("PORT", api_port.to_string())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
+            // Aborting the monitor below drops the child, which kills it
+            .kill_on_drop(true)
             .spawn();
 
+        let mut monitor = None;
         match child {
             Ok(mut child_proc) => {
                 info!("Bot process started with PID: {:?}", child_proc.id());
                 // Monitor process in background
-                tokio::spawn(async move {
+                monitor = Some(tokio::spawn(async move {
                     match child_proc.wait().await {
                         Ok(status) => error!("Bot process exited with status: {}", status),
                         Err(e) => error!("Bot process error: {}", e),
                     }
-                });
+                }));
             }
             Err(e) => {
                 warn!("Failed to spawn bot process: {}. Assuming external bot is running or node is missing.", e);
             }
         }
 
-        // Give it some time to start
-        sleep(Duration::from_secs(5)).await;
+        // Scanning before the API is up fails the whole first batch
+        while !self.wait_until_ready().await {
+            if !self.config.keep_waiting {
+                if let Some(monitor) = monitor {
+                    monitor.abort();
+                }
+                bail!(
+                    "Bot API on port {} didn't answer within {}s",
+                    api_port, self.config.ready_timeout_secs
+                );
+            }
+
+            warn!("Bot API on port {} still isn't answering, waiting...", api_port);
+        }
+
+        if accounts.is_empty() {
+            info!("No bot accounts configured, online mode servers can't be joined");
+        } else {
+            info!("Joining with {} bot accounts", accounts.len());
+        }
+
+        self.scan_loop(&accounts).await;
+        Ok(())
+    }
+
+    /// Polls the bot's /health route with backoff, returns false if it didn't
+    /// answer within `ready_timeout_secs`
+    async fn wait_until_ready(&self) -> bool {
+        let url = format!("http://localhost:{}/health", self.config.api_port);
+        let deadline = Instant::now() + Duration::from_secs(self.config.ready_timeout_secs);
+        let mut delay = Duration::from_millis(250);
+
+        loop {
+            match self.client.get(&url).timeout(Duration::from_secs(2)).send().await {
+                Ok(resp) if resp.status().is_success() => {
+                    info!("Bot API is ready");
+                    return true;
+                }
+                Ok(resp) => debug!("Bot health check returned {}", resp.status()),
+                Err(e) => debug!("Bot health check failed: {}", e),
+            }
 
-        self.scan_loop().await;
+            if Instant::now() + delay > deadline {
+                return false;
+            }
+
+            sleep(delay).await;
+            delay = (delay * 2).min(Duration::from_secs(5));
+        }
     }
 
-    async fn scan_loop(&self) {
+    async fn scan_loop(&self, accounts: &[BotAccount]) {
         info!("Entering bot scan loop...");
+        let mut joins = 0usize;
         loop {
             // Fetch candidates
             let candidates = match self.database.get_bot_scan_candidates(50).await {
@@ -111,12 +171,15 @@ impl BotScanner {
             info!("Processing {} candidates...", candidates.len());
 
             for candidate in candidates {
-                self.process_candidate(candidate).await;
+                // Each join uses the next account, spreading them over the login rate limits
+                let account = (!accounts.is_empty()).then(|| &accounts[joins % accounts.len()]);
+                joins = joins.wrapping_add(1);
+                self.process_candidate(candidate, account).await;
             }
         }
     }
 
-    async fn process_candidate(&self, candidate: ScanCandidate) {
+    async fn process_candidate(&self, candidate: ScanCandidate, account: Option<&BotAccount>) {
         let ip_str = candidate.address.addr().to_string();
         let port = candidate.port as u16;
         let url = format!("http://localhost:{}/join", self.config.api_port);
@@ -125,9 +188,13 @@ impl BotScanner {
             host: ip_str.clone(),
             port,
             version: candidate.version.clone(),
+            account,
         };
 
-        info!("Scanning {}:{} with bot...", ip_str, port);
+        match account {
+            Some(account) => info!("Scanning {}:{} with bot as {}...", ip_str, port, account.username),
+            None => info!("Scanning {}:{} with bot...", ip_str, port),
+        }
 
         match self.client.post(&url).json(&request).send().await {
             Ok(resp) => {
@@ -136,7 +203,7 @@ impl BotScanner {
                         Ok(bot_res) => {
                             let details = BotServerDetails {
                                 plugins: bot_res.plugins.unwrap_or_default(),
-                                world_info: None, // Bot doesn't return this yet
+                                world_info: bot_res.world,
                                 detailed_version: bot_res.version,
                                 auth_type: None, // Bot doesn't return this explicitly yet
                                 join_success: bot_res.online,
@@ -149,8 +216,8 @@ impl BotScanner {
                                 
                                 self.database.log_event(
                                     Some(candidate.address),
-                                    "INFO".to_string(),
-                                    "BOT_SCAN_COMPLETE".to_string(),
+                                    Severity::Info,
+                                    EventType::BotScanComplete,
                                     format!("Bot scan finished. Success: {}", bot_res.online)
                                 );
                             }
@@ -0,0 +1,636 @@
+use clap::{Parser, ValueEnum};
+use crate::blocklist::Blocklist;
+use crate::config::{load_config, Config, LogLevel, ScanEngine};
+use crate::database::{create_schema, Database, EventType, Severity, MIGRATOR};
+use crate::health::Health;
+use crate::profile::Profile;
+use crate::scanner::{Mode, Scanner};
+use crate::events::{self, EventFilter};
+use crate::geoip::GeoIp;
+use crate::{country_tracking, db_check, import, installer};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, PgPool};
+use futures_util::future::join_all;
+use crate::rate_limit::PrefixRateLimiter;
+use crate::write_queue::WriteQueue;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tracing::log::LevelFilter;
+use tracing::{error, info, info_span, warn, Instrument, Span};
+
+#[derive(Parser, Debug)]
+#[clap(about = "Scans the internet for minecraft servers and indexes them")]
+#[clap(rename_all = "kebab-case")]
+struct Args {
+	#[clap(help = "Specifies the mode to run")]
+	#[clap(default_value = "rescanner")]
+	#[clap(long, short = 'm')]
+	mode: Mode,
+
+	#[clap(help = "Specifies the location of the config file")]
+	#[clap(default_value = "config.toml")]
+	#[clap(long, short = 'c')]
+	config_file: String,
+
+	#[clap(help = "Preset for timeouts, concurrency and rates, settings in the config file still win")]
+	#[clap(long, value_enum)]
+	profile: Option<Profile>,
+
+	#[clap(help = "Specifies the scan engine")]
+	#[clap(long, short = 'e')]
+	engine: Option<ScanEngine>,
+
+	#[clap(help = "Specifies a country code to target (e.g. BR, US)")]
+	#[clap(long)]
+	country: Option<String>,
+
+	#[clap(help = "Specifies a CIDR or IP to target (e.g. 192.168.1.0/24)", long, short = 't')]
+	target: Option<String>,
+
+	#[clap(help = "Specifies a port range (e.g. 25565 or 25500-25600)", long, short = 'p')]
+	ports: Option<String>,
+
+	#[clap(help = "Directory for the cache, binaries, status files and each run's own files")]
+	#[clap(long)]
+	work_dir: Option<PathBuf>,
+
+	#[clap(help = "Rescans only the ip:port entries in this file instead of the database")]
+	#[clap(long)]
+	targets_file: Option<PathBuf>,
+
+	#[clap(help = "Maximum number of servers to rescan per pass, or to reparse")]
+	#[clap(long)]
+	limit: Option<i64>,
+
+	#[clap(help = "Rescans only servers matching a filter (e.g. \"country = BR and online > 10\")")]
+	#[clap(long = "where")]
+	filter: Option<String>,
+
+	#[clap(help = "Also rescans every address on the whole port range, not just the ports servers were found on")]
+	#[clap(long)]
+	include_config_range: bool,
+
+	#[clap(help = "Checks that each port accepts connections before pinging it")]
+	#[clap(long)]
+	triage: bool,
+
+	#[clap(help = "Scans only this fraction of the target's addresses (e.g. 0.01)")]
+	#[clap(long)]
+	sample: Option<f64>,
+
+	#[clap(help = "Seed picking the sampled addresses, the same seed repeats a sample")]
+	#[clap(long)]
+	sample_seed: Option<u64>,
+
+	#[clap(help = "Appends every discovered ip:port to this file, use - for stdout")]
+	#[clap(long)]
+	found_out: Option<String>,
+
+	#[clap(help = "Protocol version sent in the handshake (e.g. 47 or -1)")]
+	#[clap(long, allow_negative_numbers = true)]
+	handshake_protocol: Option<i32>,
+
+	#[clap(help = "Only sends modern (1.7+) pings, skipping the legacy fallback")]
+	#[clap(long, conflicts_with = "legacy_only")]
+	no_legacy_fallback: bool,
+
+	#[clap(help = "Only sends legacy (pre 1.7) pings")]
+	#[clap(long)]
+	legacy_only: bool,
+
+	#[clap(help = "Pings every server found with each protocol in scanner.protocol_probe.versions")]
+	#[clap(long)]
+	probe_protocols: bool,
+
+	#[clap(help = "Scans once and exits, overriding repeat in the config file")]
+	#[clap(long, conflicts_with = "repeat")]
+	once: bool,
+
+	#[clap(help = "Keeps scanning until stopped, overriding repeat in the config file")]
+	#[clap(long)]
+	repeat: bool,
+
+	#[clap(help = "Applies pending migrations and creates missing tables in db-check mode")]
+	#[clap(long)]
+	repair: bool,
+
+	#[clap(help = "File of servers to import in import mode, CSV (ip,port[,version,protocol]) or JSON Lines, - for stdin")]
+	#[clap(long, required_if_eq("mode", "import"))]
+	input: Option<String>,
+
+	#[clap(help = "Only shows these event types in watch-events mode, comma separated")]
+	#[clap(long, value_enum, value_delimiter = ',')]
+	event_type: Vec<EventType>,
+
+	#[clap(help = "Only shows events at least this severe in watch-events mode")]
+	#[clap(long, value_enum)]
+	min_severity: Option<Severity>,
+
+	#[clap(help = "Validates the config with the other arguments applied, prints it and exits")]
+	#[clap(long)]
+	config_check: bool,
+
+	#[clap(help = "Logs more detail, repeat for more (-vv)")]
+	#[clap(long, short = 'v', action = clap::ArgAction::Count)]
+	verbose: u8,
+
+	#[clap(help = "Logs less detail, repeat for less (-qq)")]
+	#[clap(long, short = 'q', action = clap::ArgAction::Count, conflicts_with = "verbose")]
+	quiet: u8,
+}
+
+/// Runs the command line scanner with the process' arguments, exits the process on fatal errors
+pub async fn run() {
+	let arguments = Args::parse();
+
+	let config = load_config(&arguments.config_file);
+
+	// The level comes from the config file, so it's loaded before logging is set up
+	let level = config
+		.as_ref()
+		.map_or(LogLevel::default(), |config| config.logging.level)
+		.adjusted(arguments.verbose as i32 - arguments.quiet as i32);
+	let subscriber = tracing_subscriber::fmt().with_max_level(tracing::Level::from(level));
+
+	// Keep stdout clean for the found hosts stream or the events being watched
+	if arguments.found_out.as_deref() == Some("-") || matches!(arguments.mode, Mode::WatchEvents) {
+		subscriber.with_writer(std::io::stderr).init();
+	} else {
+		subscriber.init();
+	}
+
+	let mut config = match config {
+		Ok(config) => config,
+		Err(e) => {
+			error!("Fatal error loading config file: {}", e);
+			std::process::exit(1);
+		}
+	};
+
+	if let Some(profile) = arguments.profile {
+		// Only what the file leaves out is filled in, so it's parsed again to see what that is
+		let explicit = std::fs::read_to_string(&arguments.config_file)
+			.ok()
+			.and_then(|contents| contents.parse::<toml::Table>().ok())
+			.unwrap_or_default();
+		profile.apply(&mut config, &explicit);
+	}
+
+	apply_overrides(&mut config, &arguments);
+
+	// Asking for a single scan on the command line doesn't need a warning
+	if !config.scanner.repeat
+		&& !arguments.once
+		&& !matches!(arguments.mode, Mode::BotScan | Mode::WatchEvents)
+		&& !arguments.mode.is_one_shot()
+	{
+		warn!("Repeat is not enabled in config file! Will only scan once!");
+	}
+
+	let validation = match arguments.mode {
+		Mode::Discovery | Mode::Both => config.validate().and_then(|_| config.validate_discovery()),
+		_ => config.validate(),
+	};
+
+	if let Err(e) = validation {
+		error!("Invalid config: {}", e);
+		std::process::exit(1);
+	}
+
+	// Whichever scanner finished first would exit the process and take the other one with it
+	if matches!(arguments.mode, Mode::Both) && !config.scanner.repeat {
+		error!("Mode both only supports repeated scans, drop --once or set scanner.repeat = true");
+		std::process::exit(1);
+	}
+
+	if arguments.config_check {
+		match config.to_redacted_toml() {
+			Ok(toml) => {
+				print!("{}", toml);
+				return;
+			}
+			Err(e) => {
+				error!("Failed to print config: {}", e);
+				std::process::exit(1);
+			}
+		}
+	}
+
+	if let Err(e) = installer::install_binaries(&config.paths.bin_dir()).await {
+		error!("Failed to install binaries: {}", e);
+	}
+
+	info!("Using config file: {}", arguments.config_file);
+
+	let pool = connect_database(&config).await;
+
+	if let (Some(pool), Some(schema)) = (&pool, &config.database.schema) {
+		if let Err(e) = create_schema(pool, schema).await {
+			error!("Failed to create schema {}: {}", schema, e);
+			std::process::exit(1);
+		}
+	}
+
+	if let (Some(pool), Mode::DbCheck) = (&pool, &arguments.mode) {
+		match db_check::check(pool, &config, arguments.repair).await {
+			Ok(true) => return,
+			Ok(false) => std::process::exit(1),
+			Err(e) => {
+				error!("Failed to check the database: {}", e);
+				std::process::exit(1);
+			}
+		}
+	}
+
+	if let Some(pool) = &pool {
+		// Run migrations automatically
+		if let Err(e) = MIGRATOR.run(pool).await {
+			error!("Failed to run migrations: {}", e);
+			std::process::exit(1);
+		}
+
+		if let (Mode::Import, Some(input)) = (&arguments.mode, &arguments.input) {
+			if let Err(e) = import::import(&Database::new(pool.clone()), input).await {
+				error!("Failed to import {}: {:#}", input, e);
+				std::process::exit(1);
+			}
+			return;
+		}
+
+		if let Mode::WatchEvents = arguments.mode {
+			let filter = EventFilter {
+				types: arguments.event_type.clone(),
+				min_severity: arguments.min_severity,
+			};
+			if let Err(e) = events::watch(&Database::new(pool.clone()), &filter).await {
+				error!("Failed to read the scan log: {}", e);
+				std::process::exit(1);
+			}
+			return;
+		}
+
+		// Nothing to restart or track for modes that just report on the database
+		if arguments.mode.is_one_shot() {
+			Scanner::new()
+				.config(config)
+				.mode(arguments.mode)
+				.pool(Some(pool.clone()))
+				.build()
+				.start()
+				.await;
+			return;
+		}
+
+		if config.database.pool_stats_interval_secs > 0 {
+			tokio::spawn(
+				Database::new(pool.clone())
+					.log_pool_stats(Duration::from_secs(config.database.pool_stats_interval_secs)),
+			);
+		}
+
+		if config.country_tracking.enabled {
+			// Create tables
+			if country_tracking::create_tables(pool).await.is_err() {
+				error!("failed to create tables");
+				std::process::exit(1);
+			}
+
+			// Spawn task to update database
+			tokio::task::spawn(country_tracking::country_tracking(
+				pool.clone(),
+				config.clone(),
+			));
+		}
+	} else {
+		error!("Failed to connect to database, giving up");
+		std::process::exit(1);
+	}
+
+	// Fetched once here so restarts of the scanner task share the list and its refresher
+	let blocklist = match &config.targeting.blocklist_url {
+		Some(url) => {
+			let refresh_interval = Duration::from_secs(config.targeting.blocklist_refresh_secs);
+			match Blocklist::start(url.clone(), refresh_interval).await {
+				Ok(blocklist) => Some(blocklist),
+				Err(e) => {
+					error!("Failed to load blocklist from {}, refusing to scan without it: {:#}", url, e);
+					std::process::exit(1);
+				}
+			}
+		}
+		None => None,
+	};
+
+	let health = if config.health.enabled {
+		let listener = match TcpListener::bind(config.health.address).await {
+			Ok(listener) => listener,
+			Err(e) => {
+				error!("Failed to bind health check server to {}: {}", config.health.address, e);
+				std::process::exit(1);
+			}
+		};
+
+		let health = Arc::new(Health::new(config.health.max_idle_secs.map(Duration::from_secs)));
+		// Always set by now, startup gives up without a database
+		if let Some(pool) = &pool {
+			tokio::spawn(health.clone().serve(listener, pool.clone()));
+		}
+		Some(health)
+	} else {
+		None
+	};
+
+	let replicas = connect_replicas(&config).await;
+
+	// Shared by every scanner so running both modes doesn't double the per network rate
+	let rate_limiter = PrefixRateLimiter::new(&config.scanner.rate_limit).map(Arc::new);
+	// Read into memory once, restarted scanners keep using the same copy
+	let geoip = GeoIp::open(&config.geoip.databases).map(Arc::new);
+	// One file and one task draining it, however many scanners write to it
+	let write_queue = WriteQueue::new(&config.database.write_queue, &config.paths).map(Arc::new);
+	if let (Some(write_queue), Some(pool)) = (&write_queue, &pool) {
+		tokio::spawn(write_queue.clone().run(
+			Database::new(pool.clone()),
+			Duration::from_secs(config.rescanner.backoff.opt_out_secs),
+		));
+	}
+	let parts = arguments.mode.parts();
+
+	let scanners = parts.iter().map(|mode| {
+		let mut config = config.clone();
+		let mut span = Span::none();
+
+		if parts.len() > 1 {
+			let name = mode.to_possible_value().map_or_else(String::new, |v| v.get_name().to_string());
+			// Each scanner reports its own progress
+			if let Some(path) = &config.scanner.status_file {
+				config.scanner.status_file = Some(path_with_suffix(path, &name));
+			}
+			span = info_span!("scanner", mode = %name);
+		}
+
+		run_scanner(
+			mode.clone(),
+			config,
+			pool.clone(),
+			replicas.clone(),
+			blocklist.clone(),
+			rate_limiter.clone(),
+			geoip.clone(),
+			write_queue.clone(),
+			health.clone(),
+			span,
+		)
+	});
+
+	join_all(scanners).await;
+}
+
+/// Runs a scanner for `mode`, restarting it whenever it finishes or panics
+#[allow(clippy::too_many_arguments)]
+async fn run_scanner(
+	mode: Mode,
+	config: Config,
+	pool: Option<PgPool>,
+	replicas: Vec<PgPool>,
+	blocklist: Option<Arc<Blocklist>>,
+	rate_limiter: Option<Arc<PrefixRateLimiter>>,
+	geoip: Option<Arc<GeoIp>>,
+	write_queue: Option<Arc<WriteQueue>>,
+	health: Option<Arc<Health>>,
+	span: Span,
+) {
+	let mut backoff = Duration::from_secs(1);
+
+	async move {
+		loop {
+			info!("Starting scanner task...");
+
+			let scanner = Scanner::new()
+				.config(config.clone())
+				.mode(mode.clone())
+				.pool(pool.clone())
+				.replicas(replicas.clone())
+				.blocklist(blocklist.clone())
+				.rate_limiter(rate_limiter.clone())
+				.geoip(geoip.clone())
+				.write_queue(write_queue.clone())
+				.health(health.clone());
+
+			let handle = tokio::spawn(async move { scanner.build().start().await }.in_current_span());
+
+			match handle.await {
+				Ok(_) => {
+					info!("Scanner finished successfully. Restarting in 5s...");
+					tokio::time::sleep(Duration::from_secs(5)).await;
+					backoff = Duration::from_secs(1);
+				}
+				Err(e) => {
+					error!("Scanner task panicked: {}. Restarting in {:?}...", e, backoff);
+					tokio::time::sleep(backoff).await;
+					backoff = std::cmp::min(backoff * 2, Duration::from_secs(60));
+				}
+			}
+		}
+	}
+	.instrument(span)
+	.await
+}
+
+/// `status.json` becomes `status-discovery.json`
+fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+	let mut name = path.file_stem().unwrap_or_default().to_os_string();
+	name.push("-");
+	name.push(suffix);
+	if let Some(extension) = path.extension() {
+		name.push(".");
+		name.push(extension);
+	}
+	path.with_file_name(name)
+}
+
+/// Applies the command line arguments on top of the config file
+fn apply_overrides(config: &mut Config, arguments: &Args) {
+	if let Some(engine) = &arguments.engine {
+		config.scanner.engine = engine.clone();
+	}
+
+	if let Some(country) = &arguments.country {
+		config.targeting.country = Some(country.clone());
+		// A single country on the command line replaces the configured rotation and command
+		config.targeting.rotation.clear();
+		config.targeting.command = None;
+	}
+
+	if let Some(target) = &arguments.target {
+		config.targeting.custom_target = Some(target.clone());
+		// Disable country targeting if specific target is provided
+		config.targeting.country = None;
+		config.targeting.rotation.clear();
+		config.targeting.command = None;
+	}
+
+	if let Some(ports_str) = &arguments.ports {
+		if let Some((start, end)) = ports_str.split_once('-') {
+			config.scanner.port_range_start = start.parse().expect("Invalid start port");
+			config.scanner.port_range_end = end.parse().expect("Invalid end port");
+		} else {
+			let port = ports_str.parse().expect("Invalid port");
+			config.scanner.port_range_start = port;
+			config.scanner.port_range_end = port;
+		}
+	}
+
+	if let Some(work_dir) = &arguments.work_dir {
+		config.paths.work_dir = Some(work_dir.clone());
+	}
+
+	if let Some(targets_file) = &arguments.targets_file {
+		config.rescanner.targets_file = Some(targets_file.clone());
+	}
+
+	if let Some(filter) = &arguments.filter {
+		config.rescanner.filter = Some(filter.clone());
+	}
+
+	if let Some(limit) = arguments.limit {
+		config.rescanner.limit = Some(limit);
+	}
+
+	if arguments.include_config_range {
+		config.rescanner.include_config_range = true;
+	}
+
+	if arguments.triage {
+		config.rescanner.triage = true;
+	}
+
+	if let Some(sample) = arguments.sample {
+		config.targeting.sample_fraction = Some(sample);
+	}
+
+	if let Some(sample_seed) = arguments.sample_seed {
+		config.targeting.sample_seed = Some(sample_seed);
+	}
+
+	// Picked once so every pass and every restart of the scanner scans the same sample, and
+	// kept short so it's easy to copy from the log
+	if config.targeting.sample_fraction.is_some() && config.targeting.sample_seed.is_none() {
+		config.targeting.sample_seed = Some(rand::random::<u32>() as u64);
+	}
+
+	if let Some(found_out) = &arguments.found_out {
+		config.discovery.output_file = Some(found_out.clone());
+	}
+
+	if let Some(handshake_protocol) = arguments.handshake_protocol {
+		config.scanner.handshake_protocol = handshake_protocol;
+	}
+
+	if arguments.no_legacy_fallback {
+		config.scanner.legacy_fallback = false;
+		config.scanner.legacy_only = false;
+	}
+
+	if arguments.legacy_only {
+		config.scanner.legacy_only = true;
+	}
+
+	if arguments.probe_protocols {
+		config.scanner.protocol_probe.enabled = true;
+	}
+
+	if arguments.once {
+		config.scanner.repeat = false;
+	} else if arguments.repeat {
+		config.scanner.repeat = true;
+	}
+}
+
+/// Connects to the database, retrying with backoff until `database.connect_timeout_secs`
+/// runs out, since the database may still be starting when the scanner does
+async fn connect_database(config: &Config) -> Option<PgPool> {
+	let database = &config.database;
+	let options = connect_options(config, &database.host, database.port, &database.user, &database.password);
+
+	let deadline = Instant::now() + Duration::from_secs(config.database.connect_timeout_secs);
+	let mut backoff = Duration::from_secs(1);
+	let mut attempt = 1;
+
+	loop {
+		let result = pool_options().connect_with(options.clone()).await;
+
+		let e = match result {
+			Ok(pool) => return Some(pool),
+			Err(e) => e,
+		};
+
+		let remaining = deadline.saturating_duration_since(Instant::now());
+		if remaining.is_zero() {
+			error!("Failed to connect to database (attempt {}): {}", attempt, e);
+			return None;
+		}
+
+		let wait = backoff.min(remaining);
+		warn!("Failed to connect to database (attempt {}): {}. Retrying in {:?}...", attempt, e, wait);
+		tokio::time::sleep(wait).await;
+
+		backoff = std::cmp::min(backoff * 2, Duration::from_secs(30));
+		attempt += 1;
+	}
+}
+
+/// Connects to each of `database.replicas` once, by the time they're needed the primary is
+/// up. Replicas that can't be reached are left out and their share of reads goes elsewhere
+async fn connect_replicas(config: &Config) -> Vec<PgPool> {
+	let database = &config.database;
+	let mut pools = Vec::new();
+
+	for replica in &database.replicas {
+		let options = connect_options(
+			config,
+			&replica.host,
+			replica.port.unwrap_or(database.port),
+			replica.user.as_deref().unwrap_or(&database.user),
+			replica.password.as_deref().unwrap_or(&database.password),
+		);
+
+		match pool_options().connect_with(options).await {
+			Ok(pool) => {
+				info!("Connected to read replica {}", replica.host);
+				pools.push(pool);
+			}
+			Err(e) => warn!("Failed to connect to read replica {}, not using it: {}", replica.host, e),
+		}
+	}
+
+	pools
+}
+
+fn connect_options(config: &Config, host: &str, port: u16, user: &str, password: &str) -> PgConnectOptions {
+	let mut options = PgConnectOptions::new()
+		.username(user)
+		.password(password)
+		.host(host)
+		.port(port)
+		.database(&config.database.table)
+		// Turn off slow statement logging, this clogs the console
+		.log_slow_statements(LevelFilter::Off, Duration::from_secs(60));
+
+	// Every connection only sees the configured schema, so the unqualified table names in
+	// the queries and migrations all resolve there
+	if let Some(schema) = &config.database.schema {
+		options = options.options([("search_path", schema.as_str())]);
+	}
+
+	options
+}
+
+fn pool_options() -> PgPoolOptions {
+	PgPoolOptions::new()
+		// Refresh connections every 24 hours
+		.max_lifetime(Duration::from_secs(86400))
+		.acquire_slow_threshold(Duration::from_secs(60))
+}
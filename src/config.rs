@@ -1,10 +1,13 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::cmp::max;
 use std::fs::File;
 use std::io::{ErrorKind, Read};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
 use tracing::error;
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Config {
 	pub database: Database,
 	pub scanner: ScannerConfig,
@@ -18,21 +21,175 @@ pub struct Config {
 	pub country_tracking: CountryTracking,
 	#[serde(default)]
 	pub bot: BotConfig,
+	#[serde(default)]
+	pub paths: Paths,
+	#[serde(default)]
+	pub rescanner: RescannerConfig,
+	#[serde(default)]
+	pub discovery: DiscoveryConfig,
+	// Extra places servers are sent to after being stored in the database
+	#[serde(default)]
+	pub sinks: Vec<SinkConfig>,
+	#[serde(default)]
+	pub logging: LoggingConfig,
+	#[serde(default)]
+	pub health: HealthConfig,
+	#[serde(default)]
+	pub geoip: GeoIpConfig,
+}
+
+// Country and ASN of each server from local MaxMind format databases, instead of the
+// countries table filled in by country tracking
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct GeoIpConfig {
+	// e.g. ["GeoLite2-Country.mmdb", "GeoLite2-ASN.mmdb"], each address is looked up in
+	// order until both are known. Missing files are skipped with a warning
+	pub databases: Vec<PathBuf>,
+}
+
+// HTTP liveness (/healthz) and readiness (/readyz) probes for container orchestration
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct HealthConfig {
+	pub enabled: bool,
+	pub address: SocketAddr,
+	// Liveness fails when a pass has been running this long without a ping finishing,
+	// unset only checks that the process is responsive
+	pub max_idle_secs: Option<u64>,
+}
+
+impl Default for HealthConfig {
+	fn default() -> Self {
+		HealthConfig {
+			enabled: false,
+			address: SocketAddr::from(([127, 0, 0, 1], 8080)),
+			max_idle_secs: None,
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct LoggingConfig {
+	// Most detailed messages that get printed, messages about single hosts are logged at debug
+	pub level: LogLevel,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+	Error,
+	Warn,
+	#[default]
+	Info,
+	Debug,
+	Trace,
+}
+
+impl LogLevel {
+	const ALL: [LogLevel; 5] = [LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace];
+
+	/// Moves `steps` levels towards trace, or towards error when negative
+	pub fn adjusted(self, steps: i32) -> LogLevel {
+		let index = (self as i32 + steps).clamp(0, Self::ALL.len() as i32 - 1);
+		Self::ALL[index as usize]
+	}
+}
+
+impl From<LogLevel> for tracing::Level {
+	fn from(level: LogLevel) -> Self {
+		match level {
+			LogLevel::Error => tracing::Level::ERROR,
+			LogLevel::Warn => tracing::Level::WARN,
+			LogLevel::Info => tracing::Level::INFO,
+			LogLevel::Debug => tracing::Level::DEBUG,
+			LogLevel::Trace => tracing::Level::TRACE,
+		}
+	}
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Database {
 	pub host: String,
 	pub port: u16,
 	pub table: String,
 	pub user: String,
 	pub password: String,
+	// Log connection pool usage this often, 0 turns it off
+	#[serde(default)]
+	pub pool_stats_interval_secs: u64,
+	// Keep retrying the initial connection for this long, 0 only tries once
+	#[serde(default = "default_connect_timeout_secs")]
+	pub connect_timeout_secs: u64,
+	// Schema the tables are kept in, created if it doesn't exist. Lets several scanners share
+	// one database, unset uses the server's search_path (normally public)
+	#[serde(default)]
+	pub schema: Option<String>,
+	// Read replicas the rescan streams its server list from, in turn each pass. Writes always
+	// go to the primary above
+	#[serde(default)]
+	pub replicas: Vec<DatabaseReplica>,
+	#[serde(default)]
+	pub write_queue: WriteQueueConfig,
+}
+
+fn default_connect_timeout_secs() -> u64 {
+	60
+}
+
+// Servers that failed to be written are kept on disk and written again once the database is back
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct WriteQueueConfig {
+	pub enabled: bool,
+	// One serialized server per line. When unset, write_queue.jsonl in the run directory if
+	// paths.run_id is set and in the cache directory otherwise. Only one instance can use a
+	// queue at a time, the others run without one
+	pub path: Option<PathBuf>,
+	// Servers failing to be written are dropped once this many are queued
+	pub max_entries: usize,
+	// How often the queue is written back while it isn't empty
+	pub retry_secs: u64,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+impl Default for WriteQueueConfig {
+	fn default() -> Self {
+		WriteQueueConfig {
+			enabled: false,
+			path: None,
+			max_entries: 100_000,
+			retry_secs: 10,
+		}
+	}
+}
+
+// Unset fields are taken from the primary, the database name and schema always are
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct DatabaseReplica {
+	pub host: String,
+	#[serde(default)]
+	pub port: Option<u16>,
+	#[serde(default)]
+	pub user: Option<String>,
+	#[serde(default)]
+	pub password: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ScannerConfig {
 	pub repeat: bool,
 	pub scan_delay: u64,
+	// Percentage scan_delay and the country CIDR cache lifetime are randomly moved up or
+	// down by each time, so scanners started together don't stay in step
+	#[serde(default)]
+	pub delay_jitter_percent: u8,
+	// Cron expression (UTC) for when scans start, takes priority over scan_delay
+	#[serde(default)]
+	pub schedule: Option<String>,
+	// Passes running longer than this are stopped early and the next one starts as usual
+	#[serde(default)]
+	pub max_pass_duration_secs: Option<u64>,
 	pub port_range_start: u16,
 	pub port_range_end: u16,
 	#[serde(default)]
@@ -41,9 +198,101 @@ pub struct ScannerConfig {
 	pub adaptive: AdaptiveConfig,
 	#[serde(default)]
 	pub jitter: JitterConfig,
+	// Prepend a PROXY protocol header to every connection
+	#[serde(default)]
+	pub proxy_protocol: Option<ProxyProtocol>,
+	// Protocol version sent in the handshake, -1 asks for the server's own version
+	#[serde(default = "default_handshake_protocol")]
+	pub handshake_protocol: i32,
+	// Upper bound of the random delay between the handshake and status request packets
+	#[serde(default)]
+	pub handshake_jitter_ms: u64,
+	// Local addresses outgoing pings are spread over, empty lets the OS choose
+	#[serde(default)]
+	pub source_addresses: Vec<Ipv4Addr>,
+	// Look up and store the PTR record of every server found
+	#[serde(default)]
+	pub reverse_dns: bool,
+	// Resolver used for PTR lookups, defaults to the system resolver
+	#[serde(default)]
+	pub dns_resolver: Option<Ipv4Addr>,
+	#[serde(default)]
+	pub rate_limit: RateLimitConfig,
+	// Keep the full status response so it can be reprocessed later, uses a lot more storage
+	#[serde(default)]
+	pub store_raw_json: bool,
+	#[serde(default)]
+	pub backpressure: BackpressureConfig,
+	// Run masscan and rustscan through sudo on Unix, turn off when already running as root
+	#[serde(default = "default_use_sudo")]
+	pub use_sudo: bool,
+	#[serde(default)]
+	pub protocol_probe: ProtocolProbeConfig,
+	#[serde(default)]
+	pub login_probe: LoginProbeConfig,
+	// JSON file describing the current pass, rewritten as the scan progresses
+	#[serde(default)]
+	pub status_file: Option<PathBuf>,
+	// Log rescan and discovery progress this often when there's no terminal to show it on,
+	// 0 turns it off
+	#[serde(default = "default_progress_log_secs")]
+	pub progress_log_secs: u64,
+	// Only store servers running these versions, they still have to be pinged to find out
+	#[serde(default)]
+	pub version_filter: VersionFilter,
+	// Status responses declaring a longer JSON string are rejected without reading them
+	#[serde(default = "default_max_status_bytes")]
+	pub max_status_bytes: usize,
+	// How long a ping attempt may take, from connecting to reading the status
+	#[serde(default = "default_ping_timeout_ms")]
+	pub timeout_ms: u64,
+	// Store the RTT, MSS, path MTU and TCP options seen during the handshake, Linux only
+	#[serde(default)]
+	pub tcp_fingerprint: bool,
+	// Times the whole ping is repeated when it timed out, refused connections are never retried
+	#[serde(default)]
+	pub ping_retries: u32,
+	// Try a legacy (pre 1.7) ping when the modern one fails. Turning it off halves the time
+	// spent on hosts that don't answer, but servers older than 1.7 are missed
+	#[serde(default = "default_legacy_fallback")]
+	pub legacy_fallback: bool,
+	// Only send legacy pings, for surveys of old servers. Takes priority over legacy_fallback
+	#[serde(default)]
+	pub legacy_only: bool,
+	// Flag servers that look like they're behind a TCP proxy such as TCPShield or Cloudflare
+	// Spectrum. Costs a second ping for servers advertising a domain or with a PTR record
+	#[serde(default)]
+	pub fronting_detection: bool,
+}
+
+fn default_progress_log_secs() -> u64 {
+	60
+}
+
+fn default_legacy_fallback() -> bool {
+	true
+}
+
+fn default_handshake_protocol() -> i32 {
+	crate::protocol::DEFAULT_HANDSHAKE_PROTOCOL
+}
+
+fn default_use_sudo() -> bool {
+	true
 }
 
-#[derive(Deserialize, Clone, Debug)]
+// Each retry waits longer than the last, past this a single address ties up a permit for minutes
+const MAX_PING_RETRIES: u32 = 5;
+
+fn default_max_status_bytes() -> usize {
+	crate::protocol::DEFAULT_MAX_STATUS_BYTES
+}
+
+fn default_ping_timeout_ms() -> u64 {
+	crate::scanner::TIMEOUT_SECS.as_millis() as u64
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct AdaptiveConfig {
 	pub min_delay_ms: u64,
 	pub max_delay_ms: u64,
@@ -62,7 +311,7 @@ impl Default for AdaptiveConfig {
 	}
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct JitterConfig {
 	pub min_jitter_ms: u64,
 	pub max_jitter_ms: u64,
@@ -77,7 +326,116 @@ impl Default for JitterConfig {
 	}
 }
 
-#[derive(Deserialize, Clone, Debug, Default, clap::ValueEnum, PartialEq)]
+// Limits connections per network regardless of global concurrency
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RateLimitConfig {
+	// Connections per second allowed to each network, 0 disables the limit
+	pub per_prefix_per_sec: f64,
+	// Prefix length that defines a network, 24 limits each /24
+	pub prefix_len: u8,
+	// Connections a network can receive at once before the rate applies
+	pub burst: f64,
+}
+
+impl Default for RateLimitConfig {
+	fn default() -> Self {
+		RateLimitConfig {
+			per_prefix_per_sec: 0.0,
+			prefix_len: 24,
+			burst: 5.0,
+		}
+	}
+}
+
+// Pauses scanning when the database can't keep up with the servers being found
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct BackpressureConfig {
+	pub enabled: bool,
+	// Fraction of database writes allowed to fail before pausing
+	pub max_error_rate: f64,
+	// Average time a database write may take before pausing
+	pub max_write_latency_ms: u64,
+	// How long to stop dispatching new pings once the database falls behind
+	pub pause_secs: u64,
+}
+
+impl Default for BackpressureConfig {
+	fn default() -> Self {
+		BackpressureConfig {
+			enabled: true,
+			max_error_rate: 0.5,
+			max_write_latency_ms: 5000,
+			pause_secs: 15,
+		}
+	}
+}
+
+// A server is kept when it matches any protocol range or name, an empty filter keeps everything
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct VersionFilter {
+	// Inclusive protocol ranges, e.g. [[47, 47], [754, 767]]
+	pub protocols: Vec<(i32, i32)>,
+	// Case-insensitive parts of the version name, e.g. ["1.8", "Paper"]
+	pub names: Vec<String>,
+}
+
+impl VersionFilter {
+	pub fn is_empty(&self) -> bool {
+		self.protocols.is_empty() && self.names.is_empty()
+	}
+
+	pub fn matches(&self, protocol: i32, name: &str) -> bool {
+		if self.is_empty() {
+			return true;
+		}
+
+		let name = name.to_lowercase();
+		self.protocols.iter().any(|(min, max)| (*min..=*max).contains(&protocol))
+			|| self.names.iter().any(|part| name.contains(&part.to_lowercase()))
+	}
+}
+
+// Pings every server found again with each listed protocol to find the versions it accepts,
+// multiplies the number of pings sent so it's off by default
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct ProtocolProbeConfig {
+	pub enabled: bool,
+	pub versions: Vec<i32>,
+}
+
+impl Default for ProtocolProbeConfig {
+	fn default() -> Self {
+		ProtocolProbeConfig {
+			enabled: false,
+			// 1.8.9, 1.12.2, 1.16.5, 1.20.1, 1.21.1, 1.21.4
+			versions: vec![47, 340, 754, 763, 767, 769],
+		}
+	}
+}
+
+// Starts a login on every server found to see whether it can be joined, and stops at the
+// first answer. Offline mode servers let the username in for a moment, so it's off by default
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct LoginProbeConfig {
+	pub enabled: bool,
+	// Sent in Login Start, shows up in the server's log and briefly in its player list
+	pub username: String,
+}
+
+impl Default for LoginProbeConfig {
+	fn default() -> Self {
+		LoginProbeConfig {
+			enabled: false,
+			username: "ServerSeeker".to_string(),
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, clap::ValueEnum, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum ScanEngine {
 	#[default]
@@ -85,51 +443,486 @@ pub enum ScanEngine {
 	Rustscan,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+impl ScanEngine {
+	/// Name stored in the `discovered_by` column
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			ScanEngine::Masscan => "masscan",
+			ScanEngine::Rustscan => "rustscan",
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct DiscoveryConfig {
+	// Append every open ip:port found by the engines to this file, "-" for stdout
+	pub output_file: Option<String>,
+	// Most pings discovery has in flight at once, unlimited when unset. Keeps bursts of
+	// results from crowding out the rescanner when both run in one process
+	#[serde(default)]
+	pub concurrency: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RescanOrder {
+	// Servers that haven't been seen for the longest time first
+	#[default]
+	OldestFirst,
+	// Recently online servers first, keeps active servers the freshest
+	NewestFirst,
+	// Servers with the most players first
+	MostPopulous,
+	// Unpredictable order that changes every pass
+	Random,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkConfig {
+	// POSTs each server as JSON
+	Webhook {
+		url: String,
+		#[serde(default = "default_webhook_timeout_ms")]
+		timeout_ms: u64,
+	},
+}
+
+fn default_webhook_timeout_ms() -> u64 {
+	5000
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RescannerConfig {
+	// Rescan only the `ip:port` entries in this file instead of the servers table
+	pub targets_file: Option<PathBuf>,
+	// Maximum number of servers to rescan per pass
+	pub limit: Option<i64>,
+	// Servers are pinged on the ports they were found on, this also tries every address
+	// on the whole port range
+	#[serde(default)]
+	pub include_config_range: bool,
+	// Which servers are rescanned first
+	#[serde(default)]
+	pub order: RescanOrder,
+	// Check that the port accepts connections before doing a full ping
+	#[serde(default)]
+	pub triage: bool,
+	#[serde(default = "default_triage_timeout_ms")]
+	pub triage_timeout_ms: u64,
+	// Connection checks running at once, separate from the full ping limit since they only
+	// open and close a socket. The per-prefix rate limit is only applied to full pings, so
+	// it doesn't slow down checks, but it still paces the pings of servers that pass them
+	#[serde(default = "default_triage_concurrency")]
+	pub triage_concurrency: usize,
+	// Servers that failed triage this many times in a row are skipped by triage passes
+	#[serde(default = "default_max_connect_failures")]
+	pub max_connect_failures: i32,
+	// How long those servers are skipped after their last failed check before it's tried again
+	#[serde(default = "default_connect_recheck_secs")]
+	pub connect_recheck_secs: u64,
+	// Only rescan servers matching this filter, e.g. "country = BR and online > 10"
+	#[serde(default)]
+	pub filter: Option<String>,
+	#[serde(default)]
+	pub backoff: RescanBackoffConfig,
+	// Shortest time from the start of one pass to the start of the next, a pass that finishes
+	// sooner sleeps the rest instead of scan_delay. Scanner schedules still take priority
+	#[serde(default)]
+	pub min_cycle_secs: Option<u64>,
+}
+
+// Servers that keep failing or opted out are skipped by rescans for a while, the skip is
+// cleared as soon as one answers normally again
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct RescanBackoffConfig {
+	// Failed pings in a row before a server is skipped, 0 never skips failing servers
+	pub after_failures: i32,
+	// How long the first skip lasts
+	pub initial_secs: u64,
+	// Each further failure multiplies the skip by this
+	pub multiplier: f64,
+	pub max_secs: u64,
+	// How long opted out servers go before they're checked again
+	pub opt_out_secs: u64,
+}
+
+impl Default for RescanBackoffConfig {
+	fn default() -> Self {
+		RescanBackoffConfig {
+			after_failures: 5,
+			initial_secs: 6 * 60 * 60,
+			multiplier: 2.0,
+			max_secs: 30 * 24 * 60 * 60,
+			opt_out_secs: 30 * 24 * 60 * 60,
+		}
+	}
+}
+
+fn default_triage_timeout_ms() -> u64 {
+	2000
+}
+
+fn default_triage_concurrency() -> usize {
+	4000
+}
+
+fn default_max_connect_failures() -> i32 {
+	5
+}
+
+fn default_connect_recheck_secs() -> u64 {
+	7 * 24 * 60 * 60
+}
+
+impl Default for RescannerConfig {
+	fn default() -> Self {
+		RescannerConfig {
+			targets_file: None,
+			limit: None,
+			include_config_range: false,
+			order: RescanOrder::default(),
+			triage: false,
+			triage_timeout_ms: default_triage_timeout_ms(),
+			triage_concurrency: default_triage_concurrency(),
+			max_connect_failures: default_max_connect_failures(),
+			connect_recheck_secs: default_connect_recheck_secs(),
+			filter: None,
+			backoff: RescanBackoffConfig::default(),
+			min_cycle_secs: None,
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocol {
+	V1,
+	V2,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Masscan {
 	pub config_file: String,
+	// Full path to the masscan binary, found on PATH (or in the bin directory on Windows) when unset
+	#[serde(default)]
+	pub binary_path: Option<PathBuf>,
+	// Continue from the paused.conf masscan leaves behind when it's interrupted, instead of
+	// starting the next scan from the beginning of its target
+	#[serde(default = "default_masscan_resume")]
+	pub resume: bool,
+	// Packets per second, overrides the rate in config_file
+	#[serde(default)]
+	pub rate: Option<u64>,
+}
+
+fn default_masscan_resume() -> bool {
+	true
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Rustscan {
 	pub command: String,
+	// Full path to the rustscan binary, takes priority over command
+	#[serde(default)]
+	pub binary_path: Option<PathBuf>,
+	// Read RustScan's greppable output instead of its human readable lines. Greppable output
+	// lists an address's ports once they're all scanned, turn it off to ping hosts as they're found
+	#[serde(default = "default_rustscan_greppable")]
+	pub greppable: bool,
+}
+
+fn default_rustscan_greppable() -> bool {
+	true
 }
 
 impl Default for Rustscan {
 	fn default() -> Self {
 		Rustscan {
 			command: "rustscan".to_string(),
+			binary_path: None,
+			greppable: default_rustscan_greppable(),
 		}
 	}
 }
 
-#[derive(Deserialize, Clone, Debug, Default)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Targeting {
 	pub country: Option<String>,
-	#[serde(skip)]
+	#[serde(skip_deserializing)]
 	pub custom_target: Option<String>,
+	// Targets cycled through by discovery, one per scan cycle
+	#[serde(default)]
+	pub rotation: Vec<RotationEntry>,
+	// Prefer targets that haven't been scanned for the longest time
+	#[serde(default)]
+	pub rotation_weighted: bool,
+	// Shell command run every cycle that prints the targets, CIDRs or addresses one per
+	// line. Used over the country, the rotation and --target take priority
+	#[serde(default)]
+	pub command: Option<String>,
+	// Target commands, here and in the rotation, are killed and the cycle skipped after this long
+	#[serde(default = "default_command_timeout_secs")]
+	pub command_timeout_secs: u64,
+	// Where country CIDR lists are downloaded from, {country} is replaced with the
+	// lowercase country code. Gzip and zstd compressed lists are supported
+	#[serde(default)]
+	pub source_url_template: Option<String>,
+	// CIDR list of networks that are never scanned, e.g. opt-out requests. Fetched at
+	// startup, the scanner won't start if that fails
+	#[serde(default)]
+	pub blocklist_url: Option<String>,
+	// How often the blocklist is fetched again, the last good list is kept when that fails.
+	// 0 only fetches it at startup
+	#[serde(default = "default_blocklist_refresh_secs")]
+	pub blocklist_refresh_secs: u64,
+	// Scan only this fraction of the addresses in the target, e.g. 0.01 for 1%
+	#[serde(default)]
+	pub sample_fraction: Option<f64>,
+	// Picks which addresses are in the sample, the same seed and fraction always pick the
+	// same ones and a larger fraction adds to them. Chosen at random and logged when unset
+	#[serde(default)]
+	pub sample_seed: Option<u64>,
+	// Scan private, loopback, multicast and other reserved ranges. Off by default so a
+	// mistyped target can't reach internal networks, turn it on to scan a LAN
+	#[serde(default)]
+	pub allow_private: bool,
+}
+
+fn default_blocklist_refresh_secs() -> u64 {
+	3600
+}
+
+fn default_command_timeout_secs() -> u64 {
+	300
+}
+
+impl Default for Targeting {
+	fn default() -> Self {
+		Targeting {
+			country: None,
+			custom_target: None,
+			rotation: Vec::new(),
+			rotation_weighted: false,
+			command: None,
+			command_timeout_secs: default_command_timeout_secs(),
+			source_url_template: None,
+			blocklist_url: None,
+			blocklist_refresh_secs: default_blocklist_refresh_secs(),
+			sample_fraction: None,
+			sample_seed: None,
+			allow_private: false,
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationEntry {
+	Country(String),
+	Asn(String),
+	File(String),
+	Cidr(String),
+	Command(String),
+}
+
+impl std::fmt::Display for RotationEntry {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			RotationEntry::Country(c) => write!(f, "country {}", c),
+			RotationEntry::Asn(a) => write!(f, "ASN {}", a),
+			RotationEntry::File(p) => write!(f, "file {}", p),
+			RotationEntry::Cidr(c) => write!(f, "CIDR {}", c),
+			RotationEntry::Command(c) => write!(f, "command {}", c),
+		}
+	}
+}
+
+// Directories used for downloaded and generated files. When unset these default
+// to XDG locations on Linux/macOS and to the working directory everywhere else
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Paths {
+	// Holds everything below that isn't set, and relative paths elsewhere in the config
+	// like the status file. Files only this run uses go in runs/<run_id>
+	pub work_dir: Option<PathBuf>,
+	// Names this run's directory in work_dir, the process id when unset. Instances sharing a
+	// work_dir need their own to keep masscan's resume state and the write queue apart
+	pub run_id: Option<String>,
+	pub cache_dir: Option<PathBuf>,
+	pub bin_dir: Option<PathBuf>,
+	pub temp_dir: Option<PathBuf>,
+}
+
+impl Paths {
+	// Absolute, masscan runs from the run directory and is handed paths inside it
+	fn work_dir(&self) -> Option<PathBuf> {
+		let work_dir = self.work_dir.as_ref()?;
+		Some(std::path::absolute(work_dir).unwrap_or_else(|_| work_dir.clone()))
+	}
+
+	/// Where files only this run uses are kept, like target lists and masscan's resume
+	/// state, so instances sharing a work_dir don't overwrite each other's. None without a work_dir
+	pub fn run_dir(&self) -> Option<PathBuf> {
+		let run_id = self.run_id.clone().unwrap_or_else(|| std::process::id().to_string());
+		Some(self.work_dir()?.join("runs").join(run_id))
+	}
+
+	/// Where masscan keeps its resume state. A run directory named after the process id is
+	/// new on every restart, so without a run_id it's the work_dir itself
+	pub fn resume_dir(&self) -> Option<PathBuf> {
+		match &self.run_id {
+			Some(_) => self.run_dir(),
+			None => self.work_dir(),
+		}
+	}
+
+	/// Relative paths in the config are taken from work_dir when it's set
+	pub fn resolve(&self, path: &Path) -> PathBuf {
+		match self.work_dir() {
+			Some(work_dir) if path.is_relative() => work_dir.join(path),
+			_ => path.to_path_buf(),
+		}
+	}
+
+	/// Where downloaded CIDR lists are cached
+	pub fn cache_dir(&self) -> PathBuf {
+		self.cache_dir
+			.clone()
+			.or_else(|| self.work_dir().map(|dir| dir.join("cache")))
+			.or_else(|| xdg_dir("XDG_CACHE_HOME", ".cache"))
+			.unwrap_or_else(|| PathBuf::from("cache"))
+	}
+
+	/// Where the installer places masscan/rustscan binaries
+	pub fn bin_dir(&self) -> PathBuf {
+		self.bin_dir
+			.clone()
+			.or_else(|| self.work_dir().map(|dir| dir.join("bin")))
+			.or_else(|| xdg_dir("XDG_DATA_HOME", ".local/share").map(|p| p.join("bin")))
+			.unwrap_or_else(|| PathBuf::from("bin"))
+	}
+
+	/// Where temporary files such as expanded target lists are written
+	pub fn temp_dir(&self) -> PathBuf {
+		self.temp_dir
+			.clone()
+			.or_else(|| self.run_dir())
+			.or_else(|| cfg!(unix).then(|| std::env::temp_dir().join("serverseeker")))
+			.unwrap_or_else(|| PathBuf::from("."))
+	}
+}
+
+// Resolves $VAR/serverseeker, falling back to $HOME/<fallback>/serverseeker as the XDG spec describes
+fn xdg_dir(var: &str, home_fallback: &str) -> Option<PathBuf> {
+	if !cfg!(unix) {
+		return None;
+	}
+
+	let base = match std::env::var_os(var).map(PathBuf::from) {
+		// The spec says relative paths are invalid and should be ignored
+		Some(path) if path.is_absolute() => path,
+		_ => PathBuf::from(std::env::var_os("HOME")?).join(home_fallback),
+	};
+
+	Some(base.join("serverseeker"))
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 #[allow(dead_code)]
 pub struct PlayerTracking {
 	pub enabled: bool,
 	pub players: Vec<String>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct CountryTracking {
 	pub enabled: bool,
 	pub update_frequency: u64,
 	pub ipinfo_token: String,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct BotConfig {
 	pub enabled: bool,
 	pub api_port: u16,
 	pub script_path: String,
 	pub concurrency: usize,
+	// How long to wait for the bot's /health route to answer before scanning
+	#[serde(default = "default_bot_ready_timeout_secs")]
+	pub ready_timeout_secs: u64,
+	// Keep waiting for the bot after the timeout instead of exiting
+	#[serde(default)]
+	pub keep_waiting: bool,
+	// Accounts the bot joins with, one after another, so online mode servers can be entered.
+	// Without any it joins with a random offline name
+	#[serde(default)]
+	pub accounts: Vec<BotAccount>,
+	// TOML file with more `[[accounts]]`, to keep tokens out of the main config
+	#[serde(default)]
+	pub accounts_file: Option<PathBuf>,
+}
+
+// Sent to the bot as `account` in each /join request
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+pub struct BotAccount {
+	pub username: String,
+	// Minecraft profile UUID
+	pub uuid: String,
+	// Minecraft services access token, these expire after about a day and aren't refreshed
+	pub access_token: String,
+}
+
+// Keeps tokens out of logs and panics
+impl std::fmt::Debug for BotAccount {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("BotAccount")
+			.field("username", &self.username)
+			.field("uuid", &self.uuid)
+			.field("access_token", &"<redacted>")
+			.finish()
+	}
+}
+
+#[derive(Deserialize)]
+struct BotAccountsFile {
+	accounts: Vec<BotAccount>,
+}
+
+impl BotConfig {
+	/// The accounts from the config followed by the ones in `accounts_file`
+	pub fn load_accounts(&self) -> Result<Vec<BotAccount>, std::io::Error> {
+		let mut accounts = self.accounts.clone();
+
+		if let Some(path) = &self.accounts_file {
+			let contents = std::fs::read_to_string(path)?;
+			let file: BotAccountsFile =
+				toml::from_str(&contents).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+			accounts.extend(file.accounts);
+		}
+
+		check_accounts(&accounts).map_err(|message| std::io::Error::new(ErrorKind::InvalidData, message))?;
+		Ok(accounts)
+	}
+}
+
+/// Every account needs all three values, and each username can only be joined with once
+fn check_accounts(accounts: &[BotAccount]) -> Result<(), String> {
+	let mut usernames = std::collections::HashSet::new();
+	for account in accounts {
+		if account.username.trim().is_empty() || account.uuid.trim().is_empty() || account.access_token.is_empty() {
+			return Err("every account needs a username, uuid and access_token".to_string());
+		}
+		// Minecraft usernames are case insensitive
+		if !usernames.insert(account.username.trim().to_lowercase()) {
+			return Err(format!("{} is listed more than once", account.username.trim()));
+		}
+	}
+	Ok(())
+}
+
+fn default_bot_ready_timeout_secs() -> u64 {
+	60
 }
 
 impl Default for BotConfig {
@@ -139,10 +932,21 @@ impl Default for BotConfig {
 			api_port: 3000,
 			script_path: "bot/index.js".to_string(),
 			concurrency: 5,
+			ready_timeout_secs: default_bot_ready_timeout_secs(),
+			keep_waiting: false,
+			accounts: Vec::new(),
+			accounts_file: None,
 		}
 	}
 }
 
+/// A Postgres identifier that means the same quoted or not
+fn is_plain_identifier(name: &str) -> bool {
+	name.len() <= 63
+		&& name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+		&& name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
 impl Default for Config {
 	fn default() -> Self {
 		Config {
@@ -152,18 +956,51 @@ impl Default for Config {
 				table: "postgres".to_string(),
 				user: "postgres".to_string(),
 				password: "password".to_string(),
+				pool_stats_interval_secs: 0,
+				connect_timeout_secs: default_connect_timeout_secs(),
+				schema: None,
+				replicas: Vec::new(),
+				write_queue: WriteQueueConfig::default(),
 			},
 			scanner: ScannerConfig {
 				repeat: true,
 				scan_delay: 60,
+				delay_jitter_percent: 0,
+				schedule: None,
+				max_pass_duration_secs: None,
 				port_range_start: 25565,
 				port_range_end: 25565,
 				engine: ScanEngine::Masscan,
 				adaptive: AdaptiveConfig::default(),
 				jitter: JitterConfig::default(),
+				proxy_protocol: None,
+				handshake_protocol: default_handshake_protocol(),
+				handshake_jitter_ms: 0,
+				source_addresses: Vec::new(),
+				reverse_dns: false,
+				dns_resolver: None,
+				rate_limit: RateLimitConfig::default(),
+				store_raw_json: false,
+				backpressure: BackpressureConfig::default(),
+				use_sudo: default_use_sudo(),
+				protocol_probe: ProtocolProbeConfig::default(),
+				login_probe: LoginProbeConfig::default(),
+				status_file: None,
+				progress_log_secs: default_progress_log_secs(),
+				version_filter: VersionFilter::default(),
+				max_status_bytes: default_max_status_bytes(),
+				timeout_ms: default_ping_timeout_ms(),
+				tcp_fingerprint: false,
+				ping_retries: 0,
+				legacy_fallback: default_legacy_fallback(),
+				legacy_only: false,
+				fronting_detection: false,
 			},
 			masscan: Masscan {
 				config_file: "masscan.conf".to_string(),
+				binary_path: None,
+				resume: default_masscan_resume(),
+				rate: None,
 			},
 			rustscan: Rustscan::default(),
 			targeting: Targeting::default(),
@@ -177,6 +1014,13 @@ impl Default for Config {
 				ipinfo_token: "".to_string(),
 			},
 			bot: BotConfig::default(),
+			paths: Paths::default(),
+			rescanner: RescannerConfig::default(),
+			discovery: DiscoveryConfig::default(),
+			sinks: Vec::new(),
+			logging: LoggingConfig::default(),
+			health: HealthConfig::default(),
+			geoip: GeoIpConfig::default(),
 		}
 	}
 }
@@ -195,9 +1039,423 @@ impl ScannerConfig {
 	}
 }
 
+/// A config value that failed validation, naming the offending field
+#[derive(Debug, Error)]
+#[error("invalid value for `{field}`: {message}")]
+pub struct ConfigError {
+	pub field: &'static str,
+	pub message: String,
+}
+
+impl ConfigError {
+	fn new(field: &'static str, message: impl Into<String>) -> Self {
+		Self {
+			field,
+			message: message.into(),
+		}
+	}
+}
+
+impl Config {
+	/// Checks invariants that would otherwise cause panics or confusing errors mid-scan
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		if self.scanner.port_range_start > self.scanner.port_range_end {
+			return Err(ConfigError::new(
+				"scanner.port_range_start",
+				format!(
+					"{} is greater than scanner.port_range_end ({})",
+					self.scanner.port_range_start, self.scanner.port_range_end
+				),
+			));
+		}
+
+		if self.scanner.timeout_ms == 0 {
+			return Err(ConfigError::new("scanner.timeout_ms", "must be at least 1"));
+		}
+
+		// The delay counts towards the ping timeout, anything close to it fails every ping
+		if self.scanner.handshake_jitter_ms > 0 && self.scanner.handshake_jitter_ms.saturating_mul(2) >= self.scanner.timeout_ms {
+			return Err(ConfigError::new(
+				"scanner.handshake_jitter_ms",
+				format!("must be less than half of scanner.timeout_ms ({}ms)", self.scanner.timeout_ms / 2),
+			));
+		}
+
+		if self.scanner.max_pass_duration_secs == Some(0) {
+			return Err(ConfigError::new("scanner.max_pass_duration_secs", "must be at least 1"));
+		}
+
+		if self.scanner.delay_jitter_percent > 100 {
+			return Err(ConfigError::new("scanner.delay_jitter_percent", "can't be more than 100"));
+		}
+
+		// Vanilla only accepts 3 to 16 word characters, anything else is kicked before it says anything useful
+		let username = &self.scanner.login_probe.username;
+		if !(3..=16).contains(&username.len()) || !username.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+			return Err(ConfigError::new(
+				"scanner.login_probe.username",
+				"must be 3 to 16 letters, digits or underscores",
+			));
+		}
+
+		if self.scanner.protocol_probe.enabled && self.scanner.protocol_probe.versions.is_empty() {
+			return Err(ConfigError::new("scanner.protocol_probe.versions", "must not be empty when enabled"));
+		}
+
+		if let Some(filter) = &self.rescanner.filter {
+			if let Err(e) = filter.parse::<crate::rescan_filter::RescanFilter>() {
+				return Err(ConfigError::new("rescanner.filter", e));
+			}
+		}
+
+		if self.scanner.max_status_bytes == 0 {
+			return Err(ConfigError::new("scanner.max_status_bytes", "must be at least 1"));
+		}
+
+		if self.scanner.ping_retries > MAX_PING_RETRIES {
+			return Err(ConfigError::new(
+				"scanner.ping_retries",
+				format!("can't be more than {}", MAX_PING_RETRIES),
+			));
+		}
+
+		// Postgres refuses a negative LIMIT, and it's used for reparse and the GeoIP backfill too
+		if self.rescanner.limit.is_some_and(|limit| limit < 0) {
+			return Err(ConfigError::new("rescanner.limit", "must not be negative"));
+		}
+
+		if self.rescanner.triage_concurrency == 0 {
+			return Err(ConfigError::new("rescanner.triage_concurrency", "must be at least 1"));
+		}
+
+		if self.rescanner.backoff.after_failures < 0 {
+			return Err(ConfigError::new("rescanner.backoff.after_failures", "must not be negative"));
+		}
+
+		let multiplier = self.rescanner.backoff.multiplier;
+		if multiplier.is_nan() || multiplier < 1.0 {
+			return Err(ConfigError::new("rescanner.backoff.multiplier", "must be at least 1"));
+		}
+
+		if self.discovery.concurrency == Some(0) {
+			return Err(ConfigError::new("discovery.concurrency", "must be at least 1"));
+		}
+
+		if let Some(schedule) = &self.scanner.schedule {
+			if let Err(e) = schedule.parse::<crate::schedule::Schedule>() {
+				return Err(ConfigError::new("scanner.schedule", e));
+			}
+		}
+
+		for (field, value) in [
+			("database.host", &self.database.host),
+			("database.table", &self.database.table),
+			("database.user", &self.database.user),
+		] {
+			if value.trim().is_empty() {
+				return Err(ConfigError::new(field, "must not be empty"));
+			}
+		}
+
+		for replica in &self.database.replicas {
+			if replica.host.trim().is_empty() {
+				return Err(ConfigError::new("database.replicas.host", "must not be empty"));
+			}
+		}
+
+		// A single directory name under work_dir/runs
+		if let Some(run_id) = &self.paths.run_id {
+			if run_id.is_empty()
+				|| run_id.starts_with('.')
+				|| !run_id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+			{
+				return Err(ConfigError::new(
+					"paths.run_id",
+					"must be letters, digits, dashes, underscores and dots, not starting with a dot",
+				));
+			}
+		}
+
+		if self.database.write_queue.enabled {
+			if self.database.write_queue.max_entries == 0 {
+				return Err(ConfigError::new("database.write_queue.max_entries", "must be at least 1"));
+			}
+			if self.database.write_queue.retry_secs == 0 {
+				return Err(ConfigError::new("database.write_queue.retry_secs", "must be at least 1"));
+			}
+		}
+
+		// Used unquoted in search_path, so only names that don't need quoting are allowed
+		if let Some(schema) = &self.database.schema {
+			if !is_plain_identifier(schema) {
+				return Err(ConfigError::new(
+					"database.schema",
+					"must be lowercase letters, digits and underscores, not starting with a digit",
+				));
+			}
+		}
+
+		// Ones from accounts_file are checked when the bot scanner loads them
+		check_accounts(&self.bot.accounts).map_err(|message| ConfigError::new("bot.accounts", message))?;
+
+		if let Some(fraction) = self.targeting.sample_fraction {
+			if fraction.is_nan() || fraction <= 0.0 || fraction > 1.0 {
+				return Err(ConfigError::new("targeting.sample_fraction", "must be more than 0 and at most 1"));
+			}
+		}
+
+		if let Some(country) = &self.targeting.country {
+			validate_country_code("targeting.country", country)?;
+		}
+
+		for entry in &self.targeting.rotation {
+			match entry {
+				RotationEntry::Country(country) => validate_country_code("targeting.rotation", country)?,
+				RotationEntry::Command(command) if command.trim().is_empty() => {
+					return Err(ConfigError::new("targeting.rotation", "commands must not be empty"));
+				}
+				_ => {}
+			}
+		}
+
+		if self.targeting.command.as_ref().is_some_and(|command| command.trim().is_empty()) {
+			return Err(ConfigError::new("targeting.command", "must not be empty"));
+		}
+		if self.targeting.command_timeout_secs == 0 {
+			return Err(ConfigError::new("targeting.command_timeout_secs", "must be at least 1"));
+		}
+
+		Ok(())
+	}
+
+	/// Checks the settings only discovery mode depends on
+	pub fn validate_discovery(&self) -> Result<(), ConfigError> {
+		let (field, binary_path) = match self.scanner.engine {
+			ScanEngine::Masscan => ("masscan.binary_path", &self.masscan.binary_path),
+			ScanEngine::Rustscan => ("rustscan.binary_path", &self.rustscan.binary_path),
+		};
+
+		if let Some(path) = binary_path {
+			if !path.is_file() {
+				return Err(ConfigError::new(field, format!("{} does not exist", path.display())));
+			}
+		}
+
+		match self.scanner.engine {
+			ScanEngine::Masscan => {
+				if !Path::new(&self.masscan.config_file).exists() {
+					return Err(ConfigError::new(
+						"masscan.config_file",
+						format!("{} does not exist", self.masscan.config_file),
+					));
+				}
+			}
+			ScanEngine::Rustscan => {
+				if self.rustscan.command.trim().is_empty() {
+					return Err(ConfigError::new("rustscan.command", "must not be empty"));
+				}
+
+				// Unlike masscan, rustscan can't fall back to scanning everything
+				if self.targeting.custom_target.is_none()
+					&& self.targeting.country.is_none()
+					&& self.targeting.command.is_none()
+					&& self.targeting.rotation.is_empty()
+				{
+					return Err(ConfigError::new(
+						"targeting",
+						"rustscan needs a target, set targeting.country, targeting.rotation, targeting.command or pass --target",
+					));
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// The config as TOML with credentials replaced, safe to paste into bug reports
+	pub fn to_redacted_toml(&self) -> Result<String, toml::ser::Error> {
+		const REDACTED: &str = "<redacted>";
+
+		let mut config = self.clone();
+		config.database.password = REDACTED.to_string();
+		for replica in &mut config.database.replicas {
+			if replica.password.is_some() {
+				replica.password = Some(REDACTED.to_string());
+			}
+		}
+		if !config.country_tracking.ipinfo_token.is_empty() {
+			config.country_tracking.ipinfo_token = REDACTED.to_string();
+		}
+		for account in &mut config.bot.accounts {
+			account.access_token = REDACTED.to_string();
+		}
+		for sink in &mut config.sinks {
+			match sink {
+				// Webhook URLs often carry a token in the path or query
+				SinkConfig::Webhook { url, .. } => *url = REDACTED.to_string(),
+			}
+		}
+
+		toml::to_string_pretty(&config)
+	}
+}
+
+fn validate_country_code(field: &'static str, country: &str) -> Result<(), ConfigError> {
+	if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+		return Err(ConfigError::new(
+			field,
+			format!("\"{}\" is not a two letter country code", country),
+		));
+	}
+
+	Ok(())
+}
+
 pub fn load_config(path: &str) -> Result<Config, std::io::Error> {
 	let mut file = File::open(path)?;
 	let mut contents = String::new();
 	file.read_to_string(&mut contents).unwrap_or_default();
 	toml::from_str(&contents).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_version_filter() {
+		let filter: VersionFilter = toml::from_str(
+			r#"
+			protocols = [[47, 47], [754, 767]]
+			names = ["paper"]
+			"#,
+		)
+		.unwrap();
+
+		assert!(filter.matches(47, "1.8.9"));
+		assert!(filter.matches(763, "1.20.1"));
+		assert!(!filter.matches(340, "1.12.2"));
+		assert!(!filter.matches(768, "1.21.3"));
+		// Names are matched case-insensitively, whatever the protocol
+		assert!(filter.matches(340, "Paper 1.12.2"));
+		assert!(!filter.matches(-1, "Velocity 3.3.0"));
+
+		let names_only = VersionFilter {
+			protocols: Vec::new(),
+			names: vec!["1.8".to_string()],
+		};
+		assert!(names_only.matches(-1, "Spigot 1.8.8"));
+		assert!(!names_only.matches(47, "BungeeCord 1.21"));
+
+		assert!(VersionFilter::default().matches(5, "anything"));
+	}
+
+	#[test]
+	fn test_schema_names() {
+		let mut config = Config::default();
+		for (schema, valid) in [("scanner_eu", true), ("_2", true), ("Scanner", false), ("1st", false), ("a;b", false), ("", false)] {
+			config.database.schema = Some(schema.to_string());
+			assert_eq!(config.validate().is_ok(), valid, "{}", schema);
+		}
+	}
+
+	#[test]
+	fn test_bot_accounts() {
+		let account = |username: &str| BotAccount {
+			username: username.to_string(),
+			uuid: "4566e69f-c907-48ee-8d71-d7ba5aa00d20".to_string(),
+			access_token: "token".to_string(),
+		};
+		assert!(check_accounts(&[account("Steve"), account("Alex")]).is_ok());
+		assert!(check_accounts(&[account("Steve"), account(" ")]).is_err());
+		assert_eq!(check_accounts(&[account("Steve"), account("steve")]).unwrap_err(), "steve is listed more than once");
+
+		let mut config = Config::default();
+		config.bot.accounts = vec![account("Steve"), account("Steve")];
+		assert!(config.validate().is_err());
+	}
+
+	#[test]
+	fn test_handshake_jitter() {
+		let mut config = Config::default();
+		for (timeout_ms, jitter_ms, valid) in [(1, 0, true), (1000, 499, true), (1000, 500, false), (1, 1, false)] {
+			config.scanner.timeout_ms = timeout_ms;
+			config.scanner.handshake_jitter_ms = jitter_ms;
+			assert_eq!(config.validate().is_ok(), valid, "{}ms jitter in {}ms", jitter_ms, timeout_ms);
+		}
+	}
+
+	#[test]
+	fn test_rescan_limit() {
+		let mut config = Config::default();
+		for (limit, valid) in [(None, true), (Some(0), true), (Some(500), true), (Some(-1), false)] {
+			config.rescanner.limit = limit;
+			assert_eq!(config.validate().is_ok(), valid, "{:?}", limit);
+		}
+	}
+
+	#[test]
+	fn test_work_dir() {
+		let mut paths = Paths {
+			work_dir: Some(PathBuf::from("/srv/scanner")),
+			run_id: Some("eu-1".to_string()),
+			..Paths::default()
+		};
+		assert_eq!(paths.cache_dir(), Path::new("/srv/scanner/cache"));
+		assert_eq!(paths.bin_dir(), Path::new("/srv/scanner/bin"));
+		assert_eq!(paths.temp_dir(), Path::new("/srv/scanner/runs/eu-1"));
+		assert_eq!(paths.resume_dir().unwrap(), Path::new("/srv/scanner/runs/eu-1"));
+		assert_eq!(paths.resolve(Path::new("status.json")), Path::new("/srv/scanner/status.json"));
+		assert_eq!(paths.resolve(Path::new("/var/status.json")), Path::new("/var/status.json"));
+
+		// Set directories still win
+		paths.temp_dir = Some(PathBuf::from("/tmp/own"));
+		assert_eq!(paths.temp_dir(), Path::new("/tmp/own"));
+
+		paths.run_id = None;
+		assert!(paths.run_dir().unwrap().ends_with(std::process::id().to_string()));
+		// Resume state has to be found again after a restart
+		assert_eq!(paths.resume_dir().unwrap(), Path::new("/srv/scanner"));
+		assert_eq!(Paths::default().run_dir(), None);
+		assert_eq!(Paths::default().resolve(Path::new("status.json")), Path::new("status.json"));
+
+		let mut config = Config::default();
+		for (run_id, valid) in [("eu-1", true), ("run_2.b", true), ("", false), ("..", false), ("a/b", false)] {
+			config.paths.run_id = Some(run_id.to_string());
+			assert_eq!(config.validate().is_ok(), valid, "{}", run_id);
+		}
+	}
+
+	#[test]
+	fn test_redacted_toml() {
+		let mut config = Config::default();
+		config.database.password = "hunter2".to_string();
+		config.database.replicas.push(DatabaseReplica {
+			host: "replica1.internal".to_string(),
+			port: None,
+			user: Some("reader".to_string()),
+			password: Some("swordfish".to_string()),
+		});
+		config.country_tracking.ipinfo_token = "abc123".to_string();
+		config.targeting.custom_target = Some("10.0.0.0/8".to_string());
+		config.sinks.push(SinkConfig::Webhook {
+			url: "https://discord.com/api/webhooks/1/secret".to_string(),
+			timeout_ms: 5000,
+		});
+		config.bot.accounts.push(BotAccount {
+			username: "Seeker".to_string(),
+			uuid: "069a79f444e94726a5befca90e38aaf5".to_string(),
+			access_token: "eyJhbGciOi".to_string(),
+		});
+
+		let toml = config.to_redacted_toml().unwrap();
+		assert!(!toml.contains("hunter2"));
+		assert!(!toml.contains("swordfish"));
+		assert!(!toml.contains("eyJhbGciOi"));
+		assert!(!format!("{:?}", config.bot).contains("eyJhbGciOi"));
+		assert!(!toml.contains("abc123"));
+		assert!(!toml.contains("secret"));
+		// Overrides from the command line are part of the output
+		assert!(toml.contains("custom_target = \"10.0.0.0/8\""));
+	}
+}
@@ -1,14 +1,19 @@
-use crate::response::Server;
+use crate::config::RescanBackoffConfig;
+use crate::rescan_filter::RescanFilter;
+use crate::response::{favicon_data_uri, hash_favicon, Server};
 use crate::utils::RunError;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sqlx::migrate::Migrator;
 use sqlx::postgres::{PgQueryResult, PgRow};
 use sqlx::types::ipnet::{IpNet, Ipv4Net};
 use sqlx::types::Uuid;
 use sqlx::{FromRow, PgPool, Row};
 use std::net::SocketAddrV4;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tracing::error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
 
 #[derive(Debug)]
 struct AddressInfo {
@@ -25,18 +30,57 @@ impl FromRow<'_, PgRow> for AddressInfo {
 	}
 }
 
+/// Leaves out servers rescans are skipping for now, see `record_ping_failure`
+pub const RESCAN_DUE: &str = "(rescan_after IS NULL OR rescan_after <= EXTRACT(EPOCH FROM now()))";
+
+/// Leaves out servers that failed the connection check bound as `$param` times in a row, until
+/// they're due for another check, see `record_connect`. Nothing is left out when it's NULL
+pub fn connect_due(param: usize) -> String {
+	format!(
+		"(${param}::int IS NULL OR connect_failures < ${param} OR connect_recheck_after IS NULL \
+		OR connect_recheck_after <= EXTRACT(EPOCH FROM now()))"
+	)
+}
+
+/// Migrations embedded at build time, run on startup
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// The primary pool, which every write goes through, and the read replicas rescans stream
+/// from. Without replicas reads use the primary too
 #[derive(Debug, Clone)]
-pub struct Database(pub PgPool);
+pub struct Database(pub PgPool, Arc<Replicas>);
+
+#[derive(Debug, Default)]
+struct Replicas {
+	pools: Vec<PgPool>,
+	next: AtomicUsize,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BotServerDetails {
 	pub plugins: Vec<String>,
-	pub world_info: Option<Value>,
+	pub world_info: Option<WorldInfo>,
 	pub detailed_version: Option<String>,
 	pub auth_type: Option<String>,
 	pub join_success: bool,
 }
 
+/// Where the bot ended up after joining, stored in `server_details.world_info`.
+/// The bot reports it as the `world` object of its /join response, e.g.
+/// `{"name": "world", "dimension": "minecraft:overworld", "gamemode": "survival", "difficulty": "normal"}`.
+/// Every key is optional, older servers don't send a world name
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorldInfo {
+	/// Name of the world the bot spawned in
+	pub name: Option<String>,
+	/// Dimension the bot spawned in, e.g. `minecraft:overworld`
+	pub dimension: Option<String>,
+	/// `survival`, `creative`, `adventure` or `spectator`, lobbies are usually adventure
+	pub gamemode: Option<String>,
+	/// `peaceful`, `easy`, `normal` or `hard`
+	pub difficulty: Option<String>,
+}
+
 #[derive(Debug, FromRow)]
 pub struct ScanCandidate {
 	pub address: IpNet,
@@ -45,29 +89,267 @@ pub struct ScanCandidate {
 	pub protocol: Option<i32>,
 }
 
+/// Severity stored in the `nivel_log` column of scan_logs, ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum Severity {
+	Info,
+	Warn,
+	Error,
+}
+
+impl Severity {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Severity::Info => "INFO",
+			Severity::Warn => "WARN",
+			Severity::Error => "ERROR",
+		}
+	}
+}
+
+/// Every kind of event written to the `tipo_evento` column of scan_logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EventType {
+	/// A discovery or rescan pass started
+	ScanStart,
+	/// Masscan or RustScan reported an open port
+	HostFound,
+	/// A server answered a ping and was stored
+	ServerUpdated,
+	/// Something other than Minecraft answered on the port
+	NotMinecraft,
+	/// The bot finished joining a server
+	BotScanComplete,
+}
+
+impl EventType {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			EventType::ScanStart => "SCAN_START",
+			EventType::HostFound => "HOST_FOUND",
+			EventType::ServerUpdated => "SERVER_UPDATED",
+			EventType::NotMinecraft => "NOT_MINECRAFT",
+			EventType::BotScanComplete => "BOT_SCAN_COMPLETE",
+		}
+	}
+}
+
+/// Creates the schema from `database.schema` if it's missing. The name has been validated
+/// as a plain identifier, so it can go into the statement as is
+pub async fn create_schema(pool: &PgPool, schema: &str) -> Result<(), sqlx::Error> {
+	sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {}", schema)).execute(pool).await?;
+	Ok(())
+}
+
 impl Database {
 	pub fn new(pool: PgPool) -> Self {
-		Self(pool)
+		Self(pool, Arc::default())
+	}
+
+	pub fn with_replicas(self, replicas: Vec<PgPool>) -> Self {
+		Self(self.0, Arc::new(Replicas { pools: replicas, next: AtomicUsize::new(0) }))
 	}
 
-	/// Gets the count of servers from database
-	pub async fn count_servers(&self) -> Result<i64, sqlx::Error> {
-		let result = sqlx::query("SELECT COUNT(*) FROM servers")
-			.fetch_one(&self.0)
-			.await?
-			.get("count");
+	/// Pool for reads that can lag behind a little, the current replica if there are any
+	pub fn read_pool(&self) -> &PgPool {
+		let replicas = &self.1;
+		if replicas.pools.is_empty() {
+			return &self.0;
+		}
+
+		&replicas.pools[replicas.next.load(Ordering::Relaxed) % replicas.pools.len()]
+	}
+
+	/// Moves reads on to the next replica, called once per pass so a pass's queries all see
+	/// the same one
+	pub fn next_replica(&self) {
+		self.1.next.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Gets the count of servers from database, leaving out servers that failed
+	/// `max_connect_failures` connection checks in a row when it's set and servers
+	/// not matching the filter
+	pub async fn count_servers(
+		&self,
+		max_connect_failures: Option<i32>,
+		filter: Option<&RescanFilter>,
+	) -> Result<i64, sqlx::Error> {
+		let query = match filter {
+			Some(filter) => format!(
+				"SELECT COUNT(*) FROM servers WHERE {} AND {} AND {}",
+				RESCAN_DUE,
+				connect_due(1),
+				filter.to_sql(2)
+			),
+			None => format!(
+				"SELECT COUNT(*) FROM servers WHERE {} AND {}",
+				RESCAN_DUE,
+				connect_due(1)
+			),
+		};
+
+		let mut query = sqlx::query(&query).bind(max_connect_failures);
+		if let Some(filter) = filter {
+			query = filter.bind(query);
+		}
+
+		let result = query.fetch_one(self.read_pool()).await?.get("count");
 
 		Ok(result)
 	}
 
-	/// Deletes a server from the database
-	async fn delete_server(&self, address: IpNet) -> Result<PgQueryResult, sqlx::Error> {
-		sqlx::query("DELETE FROM servers WHERE address = $1")
-			.bind(address)
+	/// Pings a rescan adds by also trying every port from `start` to `end` on each address,
+	/// leaving out the ports addresses are already known on
+	pub async fn count_range_pings(
+		&self,
+		max_connect_failures: Option<i32>,
+		filter: Option<&RescanFilter>,
+		start: u16,
+		end: u16,
+	) -> Result<i64, sqlx::Error> {
+		let mut query = format!(
+			"SELECT COUNT(DISTINCT address) * ($2::int - $1::int + 1)
+			- COUNT(*) FILTER (WHERE port BETWEEN $1 AND $2) AS count
+			FROM servers WHERE {} AND {}",
+			RESCAN_DUE,
+			connect_due(3)
+		);
+		if let Some(filter) = filter {
+			query = format!("{} AND {}", query, filter.to_sql(4));
+		}
+
+		let mut query = sqlx::query(&query).bind(start as i32).bind(end as i32).bind(max_connect_failures);
+		if let Some(filter) = filter {
+			query = filter.bind(query);
+		}
+
+		Ok(query.fetch_one(self.read_pool()).await?.get("count"))
+	}
+
+	/// Periodically logs how busy the connection pool is. Acquire time is measured by
+	/// taking a connection each interval, so it shows how long a ping task would wait
+	pub async fn log_pool_stats(self, interval: Duration) {
+		let mut ticker = tokio::time::interval(interval);
+		// The first tick completes immediately, there's nothing to report yet
+		ticker.tick().await;
+
+		loop {
+			ticker.tick().await;
+
+			let size = self.0.size();
+			let idle = self.0.num_idle() as u32;
+			let max = self.0.options().get_max_connections();
+
+			let start = Instant::now();
+			let acquire = match self.0.acquire().await {
+				Ok(_) => format!("{}ms", start.elapsed().as_millis()),
+				Err(e) => format!("failed ({})", e),
+			};
+
+			info!(
+				"Database pool: {} active, {} idle, {}/{} open, acquire took {}",
+				size.saturating_sub(idle),
+				idle,
+				size,
+				max,
+				acquire
+			);
+		}
+	}
+
+	/// Stores the result of a connection check, a successful check resets the failure streak.
+	/// A failed one is checked again `recheck` later at the latest, however long the streak
+	pub async fn record_connect(
+		&self,
+		socket: SocketAddrV4,
+		open: bool,
+		recheck: Duration,
+	) -> Result<PgQueryResult, sqlx::Error> {
+		sqlx::query(
+			"UPDATE servers SET
+			last_connect_ok = $3,
+			connect_failures = CASE WHEN $3 THEN 0 ELSE connect_failures + 1 END,
+			connect_recheck_after = CASE WHEN $3 THEN NULL ELSE EXTRACT(EPOCH FROM now())::bigint + $4 END
+			WHERE address = $1 AND port = $2",
+		)
+		.bind(IpNet::from(Ipv4Net::from(*socket.ip())))
+		.bind(socket.port() as i32)
+		.bind(open)
+		.bind(recheck.as_secs().min(i64::MAX as u64) as i64)
+		.execute(&self.0)
+		.await
+	}
+
+	/// Stores a favicon image under its hash, images already stored are left alone
+	async fn store_favicon(&self, hash: &str, image: &[u8]) -> Result<PgQueryResult, sqlx::Error> {
+		sqlx::query("INSERT INTO favicons (hash, image) VALUES ($1, $2) ON CONFLICT (hash) DO NOTHING")
+			.bind(hash)
+			.bind(image)
 			.execute(&self.0)
 			.await
 	}
 
+	/// Gets a stored favicon as the data URL servers send it in
+	pub async fn get_favicon(&self, hash: &str) -> Result<Option<String>, sqlx::Error> {
+		let image: Option<Vec<u8>> = sqlx::query_scalar("SELECT image FROM favicons WHERE hash = $1")
+			.bind(hash)
+			.fetch_optional(&self.0)
+			.await?;
+
+		Ok(image.map(|image| favicon_data_uri(&image)))
+	}
+
+	/// Removes everything stored about an address that opted out, leaving only a row for
+	/// the port it answered on so rescans leave it alone until `recheck_after` has passed
+	async fn mark_opted_out(&self, socket: SocketAddrV4, recheck_after: Duration) -> Result<(), sqlx::Error> {
+		let address = IpNet::from(Ipv4Net::from(*socket.ip()));
+		let rescan_after = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default() + recheck_after;
+		let mut transaction = self.0.begin().await?;
+
+		for query in [
+			"DELETE FROM servers WHERE address = $1",
+			"DELETE FROM players WHERE address = $1",
+			"DELETE FROM mods WHERE address = $1",
+		] {
+			sqlx::query(query).bind(address).execute(&mut *transaction).await?;
+		}
+
+		sqlx::query("INSERT INTO servers (address, port, opted_out, rescan_after) VALUES ($1, $2, true, $3)")
+			.bind(address)
+			.bind(socket.port() as i32)
+			.bind(rescan_after.as_secs() as i64)
+			.execute(&mut *transaction)
+			.await?;
+
+		transaction.commit().await
+	}
+
+	/// Counts a failed ping against a known server. Once it has failed `after_failures`
+	/// times in a row rescans skip it, for longer after every further failure
+	pub async fn record_ping_failure(
+		&self,
+		socket: SocketAddrV4,
+		backoff: &RescanBackoffConfig,
+	) -> Result<PgQueryResult, sqlx::Error> {
+		// The exponent is capped so a long dead server can't overflow POWER
+		sqlx::query(
+			"UPDATE servers SET
+			ping_failures = ping_failures + 1,
+			rescan_after = CASE WHEN $3 > 0 AND ping_failures + 1 >= $3
+				THEN EXTRACT(EPOCH FROM now())::bigint + LEAST($6, $4 * POWER($5, LEAST(ping_failures + 1 - $3, 64)))::bigint
+				ELSE rescan_after END
+			WHERE address = $1 AND port = $2",
+		)
+		.bind(IpNet::from(Ipv4Net::from(*socket.ip())))
+		.bind(socket.port() as i32)
+		.bind(backoff.after_failures)
+		.bind(backoff.initial_secs as f64)
+		.bind(backoff.multiplier)
+		.bind(backoff.max_secs as f64)
+		.execute(&self.0)
+		.await
+	}
+
 	async fn get_country_info(&self, address: &IpNet) -> Result<Option<AddressInfo>, sqlx::Error> {
 			sqlx::query_as("SELECT country_code, asn FROM countries WHERE $1 <<= network")
 				.bind(address)
@@ -78,9 +360,15 @@ impl Database {
 	/// Updates a single server in the database, this includes all mods
 	/// and players that come with it. Will also remove a server from the
 	/// database if it has requested to be removed
-	pub async fn update_server(&self, server: Server, socket: SocketAddrV4) -> anyhow::Result<()> {
+	pub async fn update_server(
+		&self,
+		server: Server,
+		socket: SocketAddrV4,
+		seen: SystemTime,
+		opt_out_recheck: Duration,
+	) -> anyhow::Result<()> {
 		let address = IpNet::from(Ipv4Net::from(*socket.ip()));
-		let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i32;
+		let timestamp = seen.duration_since(UNIX_EPOCH)?.as_secs() as i32;
 
 		// Format description if it exists
 		let formatted = server
@@ -88,18 +376,59 @@ impl Database {
 			.as_ref()
 			.map(|v| server.build_formatted_description(v));
 
-		// Delete server if it's opted out
+		// Opted out servers only keep a placeholder row, so they're rechecked later
 		if server.check_opt_out() {
-			self.delete_server(address).await?;
+			self.mark_opted_out(socket, opt_out_recheck).await?;
 			return Err(RunError::ServerOptOut)?;
 		}
 
-		let address_information = self.get_country_info(&address).await?.unwrap_or(AddressInfo {
+		// Fabric/Quilt channel names and modpack metadata, stored as JSON
+		let channels = server
+			.channels
+			.as_ref()
+			.and_then(|_| serde_json::to_value(server.known_channels()).ok());
+		let mod_count = server.mod_count();
+		let mods_truncated = server.mods_truncated();
+		let canonical_version = server.canonical_version();
+		let links = server.extract_links();
+		let security_profile = server.security_profile().as_str();
+		// Formatting codes stripped, for full text search
+		let plain_description = server.plain_description();
+		let game_mode = server.game_mode();
+		let map = server.map();
+		let (software, mc_version) = server.parse_version();
+		// Names the fork when get_type only knows it's a Java server
+		let software = software.unwrap_or_else(|| server.get_type().to_string());
+		let extra_fields = server.extra_fields();
+		// Favicons are stored once per image as bytes, the text column only keeps ones that don't decode
+		let favicon = server.decoded_favicon();
+		let favicon_hash = match &favicon {
+			Some(image) => Some(hash_favicon(image)),
+			None => server.favicon_hash(),
+		};
+		if let (Some(image), Some(hash)) = (&favicon, &favicon_hash) {
+			self.store_favicon(hash, image).await?;
+		}
+		let modpack = server.modpack_data.as_ref().and_then(|m| serde_json::to_value(m).ok());
+
+		let address_information = match (&server.country_code, &server.asn) {
+			(Some(country_code), Some(asn)) => AddressInfo {
+				country_code: country_code.clone(),
+				asn: asn.clone(),
+			},
+			(country_code, asn) => {
+				let stored = self.get_country_info(&address).await?.unwrap_or(AddressInfo {
 					country_code: "XX".to_string(),
 					asn: "Unknown".to_string(),
 				});
+				AddressInfo {
+					country_code: country_code.clone().unwrap_or(stored.country_code),
+					asn: asn.clone().unwrap_or(stored.asn),
+				}
+			}
+		};
 
-		sqlx::query(
+		let updated = sqlx::query(
 			"INSERT INTO servers (
 			address,
 			port,
@@ -117,8 +446,38 @@ impl Database {
 			max_players,
 		       country,
 		   	asn,
-			latency
-			) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+			latency,
+			channels,
+			modpack,
+			hostname,
+			raw_json,
+			mod_count,
+			canonical_version,
+			favicon_hash,
+			mods_truncated,
+			min_accepted_protocol,
+			max_accepted_protocol,
+			ping_method,
+			lenient_parse,
+			discovered_by,
+			connect_latency,
+			total_latency,
+			tcp_rtt_us,
+			tcp_mss,
+			tcp_pmtu,
+			tcp_signature,
+			ping_attempts,
+			links,
+			security_profile,
+			description_plain,
+			game_mode,
+			map,
+			mc_version,
+			fronted,
+			login_status,
+			players_implausible,
+			extra_fields
+			) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30, $31, $32, $33, $34, $35, $36, $37, $38, $39, $40, $41, $42, $43, $44, $45, $46, $47)
 		   	ON CONFLICT (address, port) DO UPDATE SET
 		   	software = EXCLUDED.software,
 		   	version = EXCLUDED.version,
@@ -133,14 +492,48 @@ impl Database {
 		   	max_players = EXCLUDED.max_players,
 		   	country = EXCLUDED.country,
 		   	asn = EXCLUDED.asn,
-			latency = EXCLUDED.latency",
+			latency = EXCLUDED.latency,
+			channels = EXCLUDED.channels,
+			modpack = EXCLUDED.modpack,
+			hostname = COALESCE(EXCLUDED.hostname, servers.hostname),
+			raw_json = COALESCE(EXCLUDED.raw_json, servers.raw_json),
+			mod_count = EXCLUDED.mod_count,
+			canonical_version = EXCLUDED.canonical_version,
+			favicon_hash = EXCLUDED.favicon_hash,
+			mods_truncated = EXCLUDED.mods_truncated,
+			min_accepted_protocol = COALESCE(EXCLUDED.min_accepted_protocol, servers.min_accepted_protocol),
+			max_accepted_protocol = COALESCE(EXCLUDED.max_accepted_protocol, servers.max_accepted_protocol),
+			ping_method = EXCLUDED.ping_method,
+			lenient_parse = EXCLUDED.lenient_parse,
+			discovered_by = COALESCE(servers.discovered_by, EXCLUDED.discovered_by),
+			connect_latency = EXCLUDED.connect_latency,
+			total_latency = EXCLUDED.total_latency,
+			tcp_rtt_us = COALESCE(EXCLUDED.tcp_rtt_us, servers.tcp_rtt_us),
+			tcp_mss = COALESCE(EXCLUDED.tcp_mss, servers.tcp_mss),
+			tcp_pmtu = COALESCE(EXCLUDED.tcp_pmtu, servers.tcp_pmtu),
+			tcp_signature = COALESCE(EXCLUDED.tcp_signature, servers.tcp_signature),
+			ping_attempts = EXCLUDED.ping_attempts,
+			links = EXCLUDED.links,
+			security_profile = EXCLUDED.security_profile,
+			description_plain = EXCLUDED.description_plain,
+			game_mode = EXCLUDED.game_mode,
+			map = EXCLUDED.map,
+			mc_version = EXCLUDED.mc_version,
+			fronted = COALESCE(EXCLUDED.fronted, servers.fronted),
+			login_status = COALESCE(EXCLUDED.login_status, servers.login_status),
+			players_implausible = EXCLUDED.players_implausible,
+			extra_fields = EXCLUDED.extra_fields,
+			ping_failures = 0,
+			opted_out = false,
+			rescan_after = NULL
+			WHERE servers.last_seen IS NULL OR servers.last_seen <= EXCLUDED.last_seen",
 		)
 		.bind(address)
 		.bind(socket.port() as i32)
-		.bind(server.get_type())
+		.bind(software)
 		.bind(server.version.name)
 		.bind(server.version.protocol)
-		.bind(server.favicon)
+		.bind(server.favicon.filter(|_| favicon.is_none()))
 		// description_raw is for storing raw JSON descriptions
 		// useful for applications that want to parse descriptions in their own way
 		.bind(server.description_raw)
@@ -156,9 +549,45 @@ impl Database {
 		.bind(address_information.country_code)
 		.bind(address_information.asn)
 		.bind(server.latency)
+		.bind(channels)
+		.bind(modpack)
+		.bind(server.hostname)
+		.bind(server.raw_json)
+		.bind(mod_count)
+		.bind(canonical_version)
+		.bind(favicon_hash)
+		.bind(mods_truncated)
+		.bind(server.accepted_protocols.map(|(min, _)| min))
+		.bind(server.accepted_protocols.map(|(_, max)| max))
+		.bind(server.ping_method)
+		.bind(server.lenient_parse)
+		.bind(server.discovered_by)
+		.bind(server.connect_latency)
+		.bind(server.total_latency)
+		.bind(server.tcp_fingerprint.as_ref().map(|f| f.rtt_us as i32))
+		.bind(server.tcp_fingerprint.as_ref().map(|f| f.mss as i32))
+		.bind(server.tcp_fingerprint.as_ref().map(|f| f.pmtu as i32))
+		.bind(server.tcp_fingerprint.as_ref().map(|f| f.signature()))
+		.bind(server.ping_attempts)
+		.bind(links)
+		.bind(security_profile)
+		.bind(plain_description)
+		.bind(game_mode)
+		.bind(map)
+		.bind(mc_version)
+		.bind(server.fronted)
+		.bind(server.login_status)
+		.bind(server.players.implausible())
+		.bind(extra_fields)
 		.execute(&self.0)
 		.await?;
 
+		// A later ping already updated the row, this one is stale. Placeholders from an opt out
+		// or an import have no last_seen and are always replaced
+		if updated.rows_affected() == 0 {
+			return Ok(());
+		}
+
 		if let Some(sample) = server.players.sample {
 			for player in sample {
 				if let Ok(uuid) = Uuid::parse_str(&player.id) {
@@ -194,7 +623,7 @@ impl Database {
 		Ok(())
 	}
 
-	pub fn log_event(&self, ip: Option<IpNet>, level: String, event_type: String, message: String) {
+	pub fn log_event(&self, ip: Option<IpNet>, severity: Severity, event_type: EventType, message: String) {
 		let pool = self.0.clone();
 	       
 	       // Debug info to console to confirm logging is attempted
@@ -205,8 +634,8 @@ impl Database {
 				"INSERT INTO scan_logs (ip_alvo, nivel_log, tipo_evento, mensagem_detalhada) VALUES ($1, $2, $3, $4)",
 			)
 			.bind(ip)
-			.bind(level)
-			.bind(event_type)
+			.bind(severity.as_str())
+			.bind(event_type.as_str())
 			.bind(message)
 			.execute(&pool)
 			.await;
@@ -217,10 +646,78 @@ impl Database {
 		});
 	}
 
+	/// Rewrites every column derived from the status response of a server, leaves
+	/// scan history like first_seen, latency and players untouched
+	pub async fn update_derived_columns(
+		&self,
+		address: IpNet,
+		port: i32,
+		server: &Server,
+	) -> Result<PgQueryResult, sqlx::Error> {
+		let formatted = server
+			.description_raw
+			.as_ref()
+			.map(|v| server.build_formatted_description(v));
+		let channels = server
+			.channels
+			.as_ref()
+			.and_then(|_| serde_json::to_value(server.known_channels()).ok());
+		let modpack = server.modpack_data.as_ref().and_then(|m| serde_json::to_value(m).ok());
+		let (software, mc_version) = server.parse_version();
+
+		sqlx::query(
+			"UPDATE servers SET
+			software = $3,
+			version = $4,
+			protocol = $5,
+			description_formatted = $6,
+			channels = $7,
+			modpack = $8,
+			mod_count = $9,
+			mods_truncated = $10,
+			canonical_version = $11,
+			favicon_hash = $12,
+			links = $13,
+			security_profile = $14,
+			description_plain = $15,
+			game_mode = $16,
+			map = $17,
+			mc_version = $18,
+			players_implausible = $19,
+			extra_fields = $20
+			WHERE address = $1 AND port = $2",
+		)
+		.bind(address)
+		.bind(port)
+		.bind(software.unwrap_or_else(|| server.get_type().to_string()))
+		.bind(&server.version.name)
+		.bind(server.version.protocol)
+		.bind(formatted)
+		.bind(channels)
+		.bind(modpack)
+		.bind(server.mod_count())
+		.bind(server.mods_truncated())
+		.bind(server.canonical_version())
+		.bind(server.favicon_hash())
+		.bind(server.extract_links())
+		.bind(server.security_profile().as_str())
+		.bind(server.plain_description())
+		.bind(server.game_mode())
+		.bind(server.map())
+		.bind(mc_version)
+		.bind(server.players.implausible())
+		.bind(server.extra_fields())
+		.execute(&self.0)
+		.await
+	}
+
+	/// Servers worth joining with the bot. Modded servers that listed their whole
+	/// mod list in the status response are left out, joining wouldn't tell us more
 	pub async fn get_bot_scan_candidates(&self, limit: i64) -> Result<Vec<ScanCandidate>, sqlx::Error> {
 		sqlx::query_as::<_, ScanCandidate>(
 			"SELECT address, port, version, protocol FROM servers
 			WHERE latency IS NOT NULL
+			AND NOT (COALESCE(mod_count, 0) > 0 AND NOT mods_truncated)
 			AND NOT EXISTS (
 				SELECT 1 FROM server_details
 				WHERE server_details.address = servers.address
@@ -251,7 +748,7 @@ impl Database {
 		.bind(address)
 		.bind(port)
 		.bind(serde_json::to_value(details.plugins).unwrap_or(Value::Null))
-		.bind(details.world_info)
+		.bind(details.world_info.and_then(|world| serde_json::to_value(world).ok()))
 		.bind(details.detailed_version)
 		.bind(details.auth_type)
 		.bind(timestamp)
@@ -0,0 +1,170 @@
+use crate::config::Config;
+use crate::country_tracking;
+use crate::database::MIGRATOR;
+use crate::stats::print_table;
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+use tracing::{error, info, warn};
+
+/// Tables the scanner reads or writes, with the feature that needs them and the
+/// migration that creates them
+struct FeatureTable {
+	name: &'static str,
+	feature: &'static str,
+	created_by: i64,
+	required: fn(&Config) -> bool,
+}
+
+const FEATURE_TABLES: &[FeatureTable] = &[
+	FeatureTable { name: "servers", feature: "core", created_by: 20240120000000, required: |_| true },
+	FeatureTable { name: "players", feature: "core", created_by: 20240120000000, required: |_| true },
+	FeatureTable { name: "mods", feature: "core", created_by: 20240120000000, required: |_| true },
+	FeatureTable { name: "scan_logs", feature: "core", created_by: 20260120120000, required: |_| true },
+	FeatureTable { name: "favicons", feature: "core", created_by: 20261015001100, required: |_| true },
+	FeatureTable {
+		name: "countries",
+		feature: "country_tracking",
+		created_by: 20240120000000,
+		required: |config| config.country_tracking.enabled,
+	},
+	FeatureTable {
+		name: "server_details",
+		feature: "bot",
+		created_by: 20260120200000,
+		required: |config| config.bot.enabled,
+	},
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MigrationState {
+	Applied,
+	Pending,
+	// sqlx records a migration that failed halfway as unsuccessful
+	Failed,
+	// The file changed after it was applied
+	Modified,
+}
+
+impl MigrationState {
+	fn as_str(self) -> &'static str {
+		match self {
+			MigrationState::Applied => "applied",
+			MigrationState::Pending => "pending",
+			MigrationState::Failed => "failed",
+			MigrationState::Modified => "modified since applied",
+		}
+	}
+}
+
+/// Looks the table up through the search path, which is where the scanner's queries find it
+async fn table_exists(pool: &PgPool, name: &str) -> Result<bool, sqlx::Error> {
+	sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+		.bind(name)
+		.fetch_one(pool)
+		.await
+}
+
+/// Migration versions recorded in the database, with whether they succeeded and their checksum
+async fn applied_migrations(pool: &PgPool) -> Result<HashMap<i64, (bool, Vec<u8>)>, sqlx::Error> {
+	if !table_exists(pool, "_sqlx_migrations").await? {
+		return Ok(HashMap::new());
+	}
+
+	let rows = sqlx::query("SELECT version, success, checksum FROM _sqlx_migrations").fetch_all(pool).await?;
+	rows.iter()
+		.map(|row| Ok((row.try_get("version")?, (row.try_get("success")?, row.try_get("checksum")?))))
+		.collect()
+}
+
+async fn migration_states(pool: &PgPool) -> Result<Vec<(i64, String, MigrationState)>, sqlx::Error> {
+	let applied = applied_migrations(pool).await?;
+
+	Ok(MIGRATOR
+		.iter()
+		.filter(|migration| !migration.migration_type.is_down_migration())
+		.map(|migration| {
+			let state = match applied.get(&migration.version) {
+				None => MigrationState::Pending,
+				Some((false, _)) => MigrationState::Failed,
+				Some((true, checksum)) if *checksum != *migration.checksum => MigrationState::Modified,
+				Some((true, _)) => MigrationState::Applied,
+			};
+			(migration.version, migration.description.to_string(), state)
+		})
+		.collect())
+}
+
+/// Prints which migrations have run and which tables exist, compared with what the
+/// enabled features need. With `repair`, pending migrations are run and missing tables
+/// that can be created outside of migrations are. Returns whether everything is in order
+pub async fn check(pool: &PgPool, config: &Config, repair: bool) -> Result<bool, sqlx::Error> {
+	let mut states = migration_states(pool).await?;
+
+	if repair && states.iter().any(|(_, _, state)| *state == MigrationState::Pending) {
+		info!("Running pending migrations");
+		match MIGRATOR.run(pool).await {
+			Ok(()) => states = migration_states(pool).await?,
+			Err(e) => error!("Failed to run migrations: {}", e),
+		}
+	}
+
+	print_table(
+		"Migrations",
+		&["Version", "Description", "State"],
+		states
+			.iter()
+			.map(|(version, description, state)| {
+				vec![version.to_string(), description.clone(), state.as_str().to_string()]
+			})
+			.collect(),
+	);
+
+	let mut healthy = states.iter().all(|(_, _, state)| *state == MigrationState::Applied);
+	let mut repairable = states.iter().any(|(_, _, state)| *state == MigrationState::Pending);
+	let mut rows = Vec::new();
+
+	for table in FEATURE_TABLES {
+		let required = (table.required)(config);
+		let mut exists = table_exists(pool, table.name).await?;
+
+		// The countries table is also created by country tracking itself
+		let creatable = table.name == "countries";
+		if repair && required && !exists && creatable {
+			info!("Creating the countries table");
+			match country_tracking::create_tables(pool).await {
+				Ok(()) => exists = true,
+				Err(e) => error!("Failed to create the countries table: {}", e),
+			}
+		}
+
+		let state = match (exists, required) {
+			(true, _) => "ok",
+			(false, true) => "missing",
+			(false, false) => "missing, not needed",
+		};
+		if !exists && required {
+			healthy = false;
+			repairable |= creatable;
+			let applied = states.iter().any(|(version, _, state)| {
+				*version == table.created_by && *state == MigrationState::Applied
+			});
+			if applied && !creatable {
+				warn!(
+					"{} is missing even though migration {} ran, it has to be restored from a backup \
+					or the migration removed from _sqlx_migrations to recreate it",
+					table.name, table.created_by
+				);
+			}
+		}
+
+		rows.push(vec![table.name.to_string(), table.feature.to_string(), state.to_string()]);
+	}
+
+	print_table("Tables", &["Table", "Feature", "State"], rows);
+
+	if repairable && !repair {
+		info!("Run with --repair to apply pending migrations and create missing tables");
+	}
+
+	Ok(healthy)
+}
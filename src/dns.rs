@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+const PTR_CACHE_TTL: Duration = Duration::from_secs(600);
+const PTR_CACHE_MAX_ENTRIES: usize = 100_000;
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+const FALLBACK_RESOLVER: Ipv4Addr = Ipv4Addr::new(1, 1, 1, 1);
+
+// Lookup time and result for each address
+type PtrCache = HashMap<Ipv4Addr, (Instant, Option<String>)>;
+
+// PTR results are cached for a while so servers on several ports of the same
+// address only trigger one lookup
+static PTR_CACHE: LazyLock<Mutex<PtrCache>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Looks up the PTR record of an address, None if there isn't one
+pub async fn reverse_lookup(address: Ipv4Addr, resolver: Option<Ipv4Addr>) -> Option<String> {
+	if let Some((time, hostname)) = PTR_CACHE.lock().unwrap().get(&address) {
+		if time.elapsed() < PTR_CACHE_TTL {
+			return hostname.clone();
+		}
+	}
+
+	let resolver = resolver.or_else(system_resolver).unwrap_or(FALLBACK_RESOLVER);
+	let hostname = match tokio::time::timeout(LOOKUP_TIMEOUT, query_ptr(address, resolver)).await {
+		Ok(Ok(hostname)) => hostname,
+		Ok(Err(e)) => {
+			debug!("PTR lookup for {} failed: {}", address, e);
+			None
+		}
+		Err(_) => {
+			debug!("PTR lookup for {} timed out", address);
+			None
+		}
+	};
+
+	let mut cache = PTR_CACHE.lock().unwrap();
+	if cache.len() >= PTR_CACHE_MAX_ENTRIES {
+		cache.retain(|_, (time, _)| time.elapsed() < PTR_CACHE_TTL);
+	}
+	cache.insert(address, (Instant::now(), hostname.clone()));
+
+	hostname
+}
+
+// First nameserver listed in /etc/resolv.conf
+fn system_resolver() -> Option<Ipv4Addr> {
+	let content = std::fs::read_to_string("/etc/resolv.conf").ok()?;
+
+	content
+		.lines()
+		.filter_map(|line| line.trim().strip_prefix("nameserver"))
+		.find_map(|address| address.trim().parse().ok())
+}
+
+async fn query_ptr(address: Ipv4Addr, resolver: Ipv4Addr) -> std::io::Result<Option<String>> {
+	let socket = UdpSocket::bind("0.0.0.0:0").await?;
+	socket.connect(SocketAddr::from((resolver, 53))).await?;
+
+	let id: u16 = rand::random();
+	socket.send(&build_ptr_query(id, address)).await?;
+
+	let mut buffer = [0u8; 512];
+	let n = socket.recv(&mut buffer).await?;
+
+	Ok(parse_ptr_response(id, &buffer[..n]))
+}
+
+fn build_ptr_query(id: u16, address: Ipv4Addr) -> Vec<u8> {
+	let mut query = Vec::with_capacity(64);
+	query.extend_from_slice(&id.to_be_bytes());
+	query.extend_from_slice(&[0x01, 0x00]); // Recursion desired
+	query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // 1 question
+
+	// d.c.b.a.in-addr.arpa
+	let octets = address.octets();
+	for label in octets.iter().rev().map(|o| o.to_string()).chain(["in-addr".into(), "arpa".into()]) {
+		query.push(label.len() as u8);
+		query.extend_from_slice(label.as_bytes());
+	}
+	query.push(0);
+
+	query.extend_from_slice(&[0, 12, 0, 1]); // Type PTR, class IN
+	query
+}
+
+fn parse_ptr_response(id: u16, response: &[u8]) -> Option<String> {
+	if response.len() < 12 || response[0..2] != id.to_be_bytes() {
+		return None;
+	}
+
+	// Any rcode other than NOERROR (e.g. NXDOMAIN) means there is no PTR
+	if response[3] & 0x0F != 0 {
+		return None;
+	}
+
+	let questions = u16::from_be_bytes([response[4], response[5]]);
+	let answers = u16::from_be_bytes([response[6], response[7]]);
+	let mut index = 12;
+
+	for _ in 0..questions {
+		index = skip_name(response, index)? + 4;
+	}
+
+	for _ in 0..answers {
+		index = skip_name(response, index)?;
+		let record_type = u16::from_be_bytes([*response.get(index)?, *response.get(index + 1)?]);
+		let length = u16::from_be_bytes([*response.get(index + 8)?, *response.get(index + 9)?]) as usize;
+		index += 10;
+
+		if record_type == 12 {
+			return read_name(response, index);
+		}
+		index += length;
+	}
+
+	None
+}
+
+// Returns the index just past a (possibly compressed) name
+fn skip_name(message: &[u8], mut index: usize) -> Option<usize> {
+	loop {
+		let length = *message.get(index)?;
+		match length {
+			0 => return Some(index + 1),
+			l if l & 0xC0 == 0xC0 => return Some(index + 2),
+			l => index += l as usize + 1,
+		}
+	}
+}
+
+fn read_name(message: &[u8], mut index: usize) -> Option<String> {
+	let mut labels = Vec::new();
+
+	// Bounded so a pointer loop can't spin forever
+	for _ in 0..128 {
+		let length = *message.get(index)?;
+		match length {
+			0 => return Some(labels.join(".")),
+			l if l & 0xC0 == 0xC0 => {
+				index = (((l & 0x3F) as usize) << 8) | *message.get(index + 1)? as usize;
+			}
+			l => {
+				let label = message.get(index + 1..index + 1 + l as usize)?;
+				labels.push(String::from_utf8_lossy(label).into_owned());
+				index += l as usize + 1;
+			}
+		}
+	}
+
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_ptr_response() {
+		let id = 0x1234;
+		let mut response = build_ptr_query(id, Ipv4Addr::new(1, 2, 3, 4));
+		response[2] = 0x81; // Response, recursion desired
+		response[3] = 0x80; // Recursion available, NOERROR
+		response[7] = 1; // 1 answer
+
+		// Answer name is a pointer to the question name at offset 12
+		response.extend_from_slice(&[0xC0, 12, 0, 12, 0, 1, 0, 0, 0x0E, 0x10]);
+		let name = b"\x02mc\x07example\x03com\x00";
+		response.extend_from_slice(&(name.len() as u16).to_be_bytes());
+		response.extend_from_slice(name);
+
+		assert_eq!(parse_ptr_response(id, &response).as_deref(), Some("mc.example.com"));
+		assert_eq!(parse_ptr_response(id + 1, &response), None);
+
+		// NXDOMAIN
+		response[3] = 0x83;
+		assert_eq!(parse_ptr_response(id, &response), None);
+	}
+}
@@ -0,0 +1,160 @@
+use anyhow::{bail, Context, Result};
+use reqwest::{Client, Response};
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Covers the whole transfer, a stalled mirror can't hold up startup for longer than this
+const TOTAL_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Largest CIDR list accepted, the biggest countries are a few megabytes
+pub const MAX_CIDR_LIST_BYTES: u64 = 32 * 1024 * 1024;
+/// Largest scan engine download accepted, they're only downloaded on Windows
+#[cfg(target_os = "windows")]
+pub const MAX_BINARY_BYTES: u64 = 64 * 1024 * 1024;
+
+pub struct Download {
+	pub body: Vec<u8>,
+	pub content_type: Option<String>,
+}
+
+async fn start(url: &str, max_bytes: u64) -> Result<Response> {
+	let client = Client::builder()
+		.connect_timeout(CONNECT_TIMEOUT)
+		.timeout(TOTAL_TIMEOUT)
+		.build()
+		.context("Failed to create HTTP client")?;
+
+	let response = client
+		.get(url)
+		.send()
+		.await
+		.with_context(|| format!("Failed to download {}", url))?
+		.error_for_status()
+		.context("Server returned error")?;
+
+	// Checked again while reading, the header is only what the server claims
+	if let Some(length) = response.content_length().filter(|length| *length > max_bytes) {
+		bail!("{} is {} bytes, more than the {} byte limit", url, length, max_bytes);
+	}
+
+	Ok(response)
+}
+
+/// Downloads `url` into memory, failing once the body grows past `max_bytes`
+pub async fn fetch(url: &str, max_bytes: u64) -> Result<Download> {
+	let mut response = start(url, max_bytes).await?;
+	let content_type = response
+		.headers()
+		.get(reqwest::header::CONTENT_TYPE)
+		.and_then(|v| v.to_str().ok())
+		.map(str::to_string);
+
+	let mut body = Vec::new();
+	while let Some(chunk) = response.chunk().await.context("Failed to get response body")? {
+		if (body.len() + chunk.len()) as u64 > max_bytes {
+			bail!("{} is larger than the {} byte limit", url, max_bytes);
+		}
+		body.extend_from_slice(&chunk);
+	}
+
+	Ok(Download { body, content_type })
+}
+
+/// Streams `url` to `path` without holding it in memory. The body is written to a
+/// temporary file first, so a failed download never leaves a partial file at `path`
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub async fn fetch_to_file(url: &str, path: &Path, max_bytes: u64) -> Result<()> {
+	let mut response = start(url, max_bytes).await?;
+
+	let mut temp_path = path.as_os_str().to_owned();
+	temp_path.push(".part");
+
+	let result = async {
+		let mut file = File::create(&temp_path).await.context("Failed to create download file")?;
+		let mut written = 0u64;
+
+		while let Some(chunk) = response.chunk().await.context("Failed to get response body")? {
+			written += chunk.len() as u64;
+			if written > max_bytes {
+				bail!("{} is larger than the {} byte limit", url, max_bytes);
+			}
+			file.write_all(&chunk).await.context("Failed to write download file")?;
+		}
+
+		file.flush().await.context("Failed to write download file")?;
+		Ok(())
+	}
+	.await;
+
+	match result {
+		Ok(()) => tokio::fs::rename(&temp_path, path).await.context("Failed to move download into place"),
+		Err(e) => {
+			let _ = tokio::fs::remove_file(&temp_path).await;
+			Err(e)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::TestDir;
+	use tokio::io::AsyncReadExt;
+	use tokio::net::TcpListener;
+
+	/// Answers one request with `body`, optionally without a Content-Length header
+	async fn serve(body: Vec<u8>, content_length: bool) -> String {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let address = listener.local_addr().unwrap();
+
+		tokio::spawn(async move {
+			let (mut stream, _) = listener.accept().await.unwrap();
+			let mut request = [0u8; 1024];
+			let _ = stream.read(&mut request).await;
+
+			let mut response = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n".to_vec();
+			if content_length {
+				response.extend_from_slice(format!("Content-Length: {}\r\n", body.len()).as_bytes());
+			}
+			response.extend_from_slice(b"\r\n");
+			response.extend_from_slice(&body);
+			let _ = stream.write_all(&response).await;
+		});
+
+		format!("http://{}/list.txt", address)
+	}
+
+	#[tokio::test]
+	async fn test_fetch_size_limit() {
+		let url = serve(b"1.0.0.0/24\n".to_vec(), true).await;
+		let download = fetch(&url, 1024).await.unwrap();
+		assert_eq!(download.body, b"1.0.0.0/24\n");
+		assert_eq!(download.content_type.as_deref(), Some("text/plain"));
+
+		// Rejected from the header alone
+		let url = serve(vec![b'x'; 2048], true).await;
+		assert!(fetch(&url, 1024).await.is_err());
+
+		// Without a length the body is cut off while reading
+		let url = serve(vec![b'x'; 2048], false).await;
+		assert!(fetch(&url, 1024).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn test_fetch_to_file() {
+		let dir = TestDir::new("download");
+		let path = dir.path().join("masscan.exe");
+
+		let url = serve(vec![b'x'; 2048], false).await;
+		assert!(fetch_to_file(&url, &path, 1024).await.is_err());
+		// Nothing is left behind by a failed download
+		assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+		let url = serve(vec![b'x'; 512], true).await;
+		fetch_to_file(&url, &path, 1024).await.unwrap();
+		assert_eq!(std::fs::read(&path).unwrap().len(), 512);
+	}
+}
@@ -0,0 +1,170 @@
+use crate::database::{Database, EventType, Severity};
+use clap::ValueEnum;
+use sqlx::FromRow;
+use std::time::Duration;
+
+// How often scan_logs is checked for new rows
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Events that pass the filter printed from before the watch started, like tail
+const BACKLOG: i64 = 10;
+// Rows fetched per query, a busy scanner catches up over a few polls
+const BATCH_SIZE: i64 = 1000;
+
+/// One row of scan_logs
+#[derive(Debug, FromRow)]
+pub struct Event {
+	pub id: i64,
+	// UTC, formatted by Postgres
+	pub at: Option<String>,
+	pub ip: Option<String>,
+	pub severity: String,
+	pub event_type: String,
+	pub message: Option<String>,
+}
+
+/// Which events are printed, everything when both are empty
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+	pub types: Vec<EventType>,
+	pub min_severity: Option<Severity>,
+}
+
+impl EventFilter {
+	fn type_names(&self) -> Option<Vec<&'static str>> {
+		(!self.types.is_empty()).then(|| self.types.iter().map(|t| t.as_str()).collect())
+	}
+
+	fn severity_names(&self) -> Option<Vec<&'static str>> {
+		let min_severity = self.min_severity?;
+		Some(
+			Severity::value_variants()
+				.iter()
+				.filter(|severity| **severity >= min_severity)
+				.map(|severity| severity.as_str())
+				.collect(),
+		)
+	}
+}
+
+/// e.g. `2026-10-15 12:34:56 WARN  HOST_FOUND 203.0.113.7 Port: 25565 (Masscan)`
+pub fn format_event(event: &Event) -> String {
+	let mut line = format!(
+		"{} {:<5} {}",
+		event.at.as_deref().unwrap_or("-"),
+		event.severity,
+		event.event_type
+	);
+	if let Some(ip) = &event.ip {
+		line.push(' ');
+		line.push_str(ip);
+	}
+	if let Some(message) = event.message.as_deref().filter(|message| !message.is_empty()) {
+		line.push(' ');
+		line.push_str(message);
+	}
+	line
+}
+
+// Rows of scan_logs that pass the filter, bound as $1 and $2
+const FILTERED_EVENTS: &str = "SELECT id, to_char(timestamp AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS') AS at,
+		host(ip_alvo) AS ip, nivel_log AS severity, tipo_evento AS event_type, mensagem_detalhada AS message
+	FROM scan_logs
+	WHERE ($1::text[] IS NULL OR tipo_evento = ANY($1))
+		AND ($2::text[] IS NULL OR nivel_log = ANY($2))";
+
+/// Events with ids in `after..=up_to` that pass the filter, oldest first
+async fn events_between(
+	database: &Database,
+	after: i64,
+	up_to: i64,
+	filter: &EventFilter,
+) -> Result<Vec<Event>, sqlx::Error> {
+	sqlx::query_as(&format!("{FILTERED_EVENTS} AND id > $3 AND id <= $4 ORDER BY id LIMIT $5"))
+		.bind(filter.type_names())
+		.bind(filter.severity_names())
+		.bind(after)
+		.bind(up_to)
+		.bind(BATCH_SIZE)
+		.fetch_all(&database.0)
+		.await
+}
+
+/// The last `BACKLOG` events up to `up_to` that pass the filter, oldest first
+async fn backlog(database: &Database, up_to: i64, filter: &EventFilter) -> Result<Vec<Event>, sqlx::Error> {
+	let mut events: Vec<Event> = sqlx::query_as(&format!("{FILTERED_EVENTS} AND id <= $3 ORDER BY id DESC LIMIT $4"))
+		.bind(filter.type_names())
+		.bind(filter.severity_names())
+		.bind(up_to)
+		.bind(BACKLOG)
+		.fetch_all(&database.0)
+		.await?;
+	events.reverse();
+	Ok(events)
+}
+
+async fn newest_id(database: &Database) -> Result<i64, sqlx::Error> {
+	sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM scan_logs").fetch_one(&database.0).await
+}
+
+/// Prints events from scan_logs to stdout as they're written, by any scanner sharing the
+/// database, until stopped. New rows are found by polling for ids past the last one seen,
+/// so an insert committed after a later id was already read is skipped
+pub async fn watch(database: &Database, filter: &EventFilter) -> Result<(), sqlx::Error> {
+	let mut after = newest_id(database).await?;
+	for event in backlog(database, after, filter).await? {
+		println!("{}", format_event(&event));
+	}
+
+	loop {
+		let up_to = newest_id(database).await?;
+		let events = events_between(database, after, up_to, filter).await?;
+
+		for event in &events {
+			println!("{}", format_event(event));
+		}
+
+		if events.len() < BATCH_SIZE as usize {
+			// Everything up to `up_to` was checked, including rows the filter left out
+			after = up_to.max(after);
+			tokio::time::sleep(POLL_INTERVAL).await;
+		} else if let Some(event) = events.last() {
+			after = event.id;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_format_event() {
+		let mut event = Event {
+			id: 1,
+			at: Some("2026-10-15 12:34:56".to_string()),
+			ip: Some("203.0.113.7".to_string()),
+			severity: "INFO".to_string(),
+			event_type: "HOST_FOUND".to_string(),
+			message: Some("Port: 25565 (Masscan)".to_string()),
+		};
+		assert_eq!(format_event(&event), "2026-10-15 12:34:56 INFO  HOST_FOUND 203.0.113.7 Port: 25565 (Masscan)");
+
+		event.ip = None;
+		event.message = Some(String::new());
+		event.severity = "ERROR".to_string();
+		assert_eq!(format_event(&event), "2026-10-15 12:34:56 ERROR HOST_FOUND");
+	}
+
+	#[test]
+	fn test_filter_names() {
+		assert_eq!(EventFilter::default().type_names(), None);
+		assert_eq!(EventFilter::default().severity_names(), None);
+
+		let filter = EventFilter {
+			types: vec![EventType::HostFound, EventType::ScanStart],
+			min_severity: Some(Severity::Warn),
+		};
+		assert_eq!(filter.type_names(), Some(vec!["HOST_FOUND", "SCAN_START"]));
+		assert_eq!(filter.severity_names(), Some(vec!["WARN", "ERROR"]));
+	}
+}
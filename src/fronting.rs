@@ -0,0 +1,149 @@
+use crate::ping::{ping_server, PingMethod, PingOptions};
+use crate::rate_limit::PrefixRateLimiter;
+use crate::response::Server;
+use crate::utils::RunError;
+use std::net::SocketAddrV4;
+use tracing::debug;
+
+// Text TCP proxies put in the status they answer with, matched case insensitively against
+// the description and version name. Gating proxies answer unknown hostnames with their own status
+const BANNERS: &[(&str, &str)] = &[
+	("tcpshield", "TCPShield"),
+	("cloudflare", "Cloudflare"),
+	("invalid hostname", "a hostname gate"),
+];
+
+// Networks that only front other servers, Spectrum runs on Cloudflare's own ASN
+const PROXY_ASNS: &[(&str, &str)] = &[("AS13335", "Cloudflare")];
+
+/// Signs of a proxy that need nothing but the status already received
+fn passive_signal(server: &Server) -> Option<String> {
+	let text = format!(
+		"{} {}",
+		server.plain_description().unwrap_or_default(),
+		server.version.name
+	)
+	.to_lowercase();
+
+	if let Some((_, name)) = BANNERS.iter().find(|(marker, _)| text.contains(marker)) {
+		return Some(format!("status mentions {}", name));
+	}
+
+	let asn = server.asn.as_deref()?;
+	PROXY_ASNS
+		.iter()
+		.find(|(proxy_asn, _)| *proxy_asn == asn)
+		.map(|(_, name)| format!("address belongs to {}", name))
+}
+
+/// The hostname players most likely connect with, a bare domain advertised in the MOTD or
+/// otherwise the PTR record
+fn probe_host(server: &Server) -> Option<String> {
+	let advertised = server
+		.extract_links()
+		.into_iter()
+		// Leaves out URLs, invites, ports and plain addresses
+		.find(|link| !link.contains(['/', ':']) && link.bytes().any(|b| b.is_ascii_alphabetic()));
+
+	advertised.or_else(|| server.hostname.clone()).map(|host| host.to_lowercase())
+}
+
+/// Whether two statuses came from the same server. Only compares what a server doesn't
+/// change between pings, descriptions are often animated or randomized
+fn same_status(a: &Server, b: &Server) -> bool {
+	a.version == b.version && a.players.max == b.players.max && a.favicon_hash() == b.favicon_hash()
+}
+
+/// Guesses whether a server that answered a modern ping sits behind a TCP proxy like
+/// TCPShield or Cloudflare Spectrum. Besides what the status shows, the server is pinged
+/// again with a hostname it's likely reached by, directly hosted servers ignore it while
+/// proxies route or gate on it. `None` when the second ping couldn't connect or timed out
+pub async fn detect(
+	socket: SocketAddrV4,
+	server: &Server,
+	options: &PingOptions,
+	rate_limiter: Option<&PrefixRateLimiter>,
+) -> Option<bool> {
+	if let Some(signal) = passive_signal(server) {
+		debug!("{} looks fronted, {}", socket, signal);
+		return Some(true);
+	}
+
+	let Some(host) = probe_host(server) else {
+		return Some(false);
+	};
+
+	if let Some(rate_limiter) = rate_limiter {
+		rate_limiter.acquire(*socket.ip()).await;
+	}
+
+	let options = PingOptions {
+		handshake_host: Some(host.clone()),
+		// Legacy pings don't carry a hostname
+		methods: &[PingMethod::Proper],
+		..options.clone()
+	};
+
+	match ping_server(socket, options).await {
+		Ok(result) if same_status(server, &result.server) => Some(false),
+		Ok(_) => {
+			debug!("{} looks fronted, it answers differently for {}", socket, host);
+			Some(true)
+		}
+		// Says nothing about the hostname, the server may just have gone away
+		Err(RunError::TimedOut(_) | RunError::IOError(_)) => None,
+		Err(e) => {
+			debug!("{} looks fronted, it refuses {} with {}", socket, host, e.kind());
+			Some(true)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn server(description: &str, version: &str) -> Server {
+		let json = serde_json::json!({
+			"version": {"name": version, "protocol": 767},
+			"players": {"max": 100, "online": 5},
+			"description": description,
+		});
+		Server::from_lenient_json(&json.to_string()).unwrap()
+	}
+
+	#[test]
+	fn test_passive_signal() {
+		assert!(passive_signal(&server("§cInvalid hostname, connect with play.example.com", "1.21.1")).is_some());
+		assert!(passive_signal(&server("A Minecraft Server", "TCPShield.com")).is_some());
+		assert!(passive_signal(&server("A Minecraft Server", "Paper 1.21.1")).is_none());
+
+		let mut spectrum = server("A Minecraft Server", "Paper 1.21.1");
+		spectrum.asn = Some("AS13335".to_string());
+		assert!(passive_signal(&spectrum).is_some());
+	}
+
+	#[test]
+	fn test_probe_host() {
+		let mut advertised = server("Join at Play.Example.com | discord.gg/abc | 203.0.113.7:25565", "1.21.1");
+		advertised.hostname = Some("static.203-0-113-7.example.net".to_string());
+		assert_eq!(probe_host(&advertised).as_deref(), Some("play.example.com"));
+
+		let mut ptr = server("https://store.example.com", "1.21.1");
+		ptr.hostname = Some("mc.example.net".to_string());
+		assert_eq!(probe_host(&ptr).as_deref(), Some("mc.example.net"));
+
+		assert_eq!(probe_host(&server("A Minecraft Server", "1.21.1")), None);
+	}
+
+	#[test]
+	fn test_same_status() {
+		let mut other = server("A Minecraft Server", "Paper 1.21.1");
+		other.players.online = 9;
+		assert!(same_status(&server("A Minecraft Server", "Paper 1.21.1"), &other));
+		assert!(same_status(&server("Day 12 of the event", "Paper 1.21.1"), &server("Day 13 of the event", "Paper 1.21.1")));
+		other.players.max = 500;
+		assert!(!same_status(&server("A Minecraft Server", "Paper 1.21.1"), &other));
+		assert!(!same_status(&server("A Minecraft Server", "Paper 1.21.1"), &server("Lobby", "Velocity 3.3.0")));
+	}
+}
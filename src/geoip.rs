@@ -0,0 +1,429 @@
+use crate::database::Database;
+use anyhow::{bail, Context, Result};
+use futures_util::TryStreamExt;
+use serde_json::{Map, Number, Value};
+use sqlx::types::ipnet::IpNet;
+use sqlx::Row;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+// The metadata section starts after the last occurrence of this marker
+const METADATA_MARKER: &[u8] = b"\xAB\xCD\xEFMaxMind.com";
+// Zeroes between the search tree and the data section
+const DATA_SECTION_SEPARATOR: usize = 16;
+// Nested pointers and containers this deep only show up in broken files
+const MAX_DEPTH: u8 = 32;
+
+/// Country and ASN of an address, as far as the databases know
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Location {
+	pub country_code: Option<String>,
+	// Formatted like the countries table, e.g. AS13335
+	pub asn: Option<String>,
+}
+
+/// Local MaxMind format databases, looked up in order until both values are found. Works
+/// with the GeoLite2 Country, City and ASN databases and with ipinfo's, which share the format
+pub struct GeoIp {
+	databases: Vec<(PathBuf, Mmdb)>,
+}
+
+impl std::fmt::Debug for GeoIp {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_list().entries(self.databases.iter().map(|(path, _)| path)).finish()
+	}
+}
+
+impl GeoIp {
+	/// Opens every database that can be read, the ones that can't are skipped with a warning.
+	/// None when there's nothing to look addresses up in
+	pub fn open(paths: &[PathBuf]) -> Option<GeoIp> {
+		let databases: Vec<(PathBuf, Mmdb)> = paths
+			.iter()
+			.filter_map(|path| match Mmdb::open(path) {
+				Ok(database) => Some((path.clone(), database)),
+				Err(e) => {
+					warn!("Skipping GeoIP database {}: {:#}", path.display(), e);
+					None
+				}
+			})
+			.collect();
+
+		(!databases.is_empty()).then_some(GeoIp { databases })
+	}
+
+	pub fn lookup(&self, address: Ipv4Addr) -> Location {
+		let mut location = Location::default();
+
+		for (path, database) in &self.databases {
+			let record = match database.lookup(address) {
+				Ok(Some(record)) => record,
+				Ok(None) => continue,
+				Err(e) => {
+					warn!("Failed to look up {} in {}: {:#}", address, path.display(), e);
+					continue;
+				}
+			};
+
+			if location.country_code.is_none() {
+				location.country_code = country_code(&record);
+			}
+			if location.asn.is_none() {
+				location.asn = asn(&record);
+			}
+			if location.country_code.is_some() && location.asn.is_some() {
+				break;
+			}
+		}
+
+		location
+	}
+}
+
+fn country_code(record: &Value) -> Option<String> {
+	["/country/iso_code", "/registered_country/iso_code", "/country_code"]
+		.iter()
+		.find_map(|pointer| record.pointer(pointer)?.as_str())
+		.map(str::to_string)
+}
+
+fn asn(record: &Value) -> Option<String> {
+	if let Some(number) = record.get("autonomous_system_number").and_then(Value::as_u64) {
+		return Some(format!("AS{}", number));
+	}
+
+	record.get("asn").and_then(Value::as_str).map(str::to_string)
+}
+
+/// A MaxMind DB file read into memory, see https://maxmind.github.io/MaxMind-DB/
+struct Mmdb {
+	bytes: Vec<u8>,
+	node_count: usize,
+	record_size: usize,
+	// Node IPv4 lookups start at, IPv6 databases keep IPv4 under ::/96
+	ipv4_start: usize,
+}
+
+impl Mmdb {
+	fn open(path: &Path) -> Result<Mmdb> {
+		Mmdb::from_bytes(std::fs::read(path).context("Failed to read the file")?)
+	}
+
+	fn from_bytes(bytes: Vec<u8>) -> Result<Mmdb> {
+		let Some(marker) = bytes.windows(METADATA_MARKER.len()).rposition(|window| window == METADATA_MARKER) else {
+			bail!("Not a MaxMind DB file, the metadata is missing");
+		};
+
+		let metadata_start = marker + METADATA_MARKER.len();
+		let (metadata, _) = Decoder { bytes: &bytes[metadata_start..] }.decode(0, 0)?;
+		let field = |name: &str| {
+			metadata.get(name).and_then(Value::as_u64).map(|value| value as usize).with_context(|| format!("Metadata has no {}", name))
+		};
+
+		let node_count = field("node_count")?;
+		let record_size = field("record_size")?;
+		if !matches!(record_size, 24 | 28 | 32) {
+			bail!("Unsupported record size {}", record_size);
+		}
+		if node_count * record_size / 4 + DATA_SECTION_SEPARATOR > marker {
+			bail!("The search tree is larger than the file");
+		}
+
+		let mut database = Mmdb {
+			bytes,
+			node_count,
+			record_size,
+			ipv4_start: 0,
+		};
+
+		if field("ip_version")? == 6 {
+			let mut node = 0;
+			for _ in 0..96 {
+				if node >= node_count {
+					break;
+				}
+				node = database.record(node, 0);
+			}
+			database.ipv4_start = node;
+		}
+
+		Ok(database)
+	}
+
+	fn record(&self, node: usize, bit: u8) -> usize {
+		let offset = node * self.record_size / 4;
+		let b = |i: usize| self.bytes[offset + i] as usize;
+
+		match (self.record_size, bit) {
+			(24, 0) => b(0) << 16 | b(1) << 8 | b(2),
+			(24, _) => b(3) << 16 | b(4) << 8 | b(5),
+			// The middle byte holds the high nibble of both records
+			(28, 0) => (b(3) & 0xF0) << 20 | b(0) << 16 | b(1) << 8 | b(2),
+			(28, _) => (b(3) & 0x0F) << 24 | b(4) << 16 | b(5) << 8 | b(6),
+			(_, 0) => b(0) << 24 | b(1) << 16 | b(2) << 8 | b(3),
+			(_, _) => b(4) << 24 | b(5) << 16 | b(6) << 8 | b(7),
+		}
+	}
+
+	fn lookup(&self, address: Ipv4Addr) -> Result<Option<Value>> {
+		let bits = address.to_bits();
+		let mut node = self.ipv4_start;
+
+		for i in 0..32 {
+			if node >= self.node_count {
+				break;
+			}
+			node = self.record(node, (bits >> (31 - i) & 1) as u8);
+		}
+
+		if node <= self.node_count {
+			return Ok(None);
+		}
+
+		let data_start = self.node_count * self.record_size / 4 + DATA_SECTION_SEPARATOR;
+		// Valid records skip the separator, broken files can point into it
+		let Some(offset) = (node - self.node_count).checked_sub(DATA_SECTION_SEPARATOR) else {
+			bail!("Record {} points into the data section separator", node);
+		};
+		let (record, _) = Decoder { bytes: &self.bytes[data_start..] }.decode(offset, 0)?;
+		Ok(Some(record))
+	}
+}
+
+/// Reads values from a data section, pointers are offsets from its start
+struct Decoder<'a> {
+	bytes: &'a [u8],
+}
+
+impl Decoder<'_> {
+	fn read(&self, offset: usize, length: usize) -> Result<&[u8]> {
+		self.bytes.get(offset..offset + length).context("Value runs past the end of the data")
+	}
+
+	fn uint(&self, offset: usize, length: usize) -> Result<u64> {
+		if length > 8 {
+			bail!("Integer of {} bytes is too large", length);
+		}
+		Ok(self.read(offset, length)?.iter().fold(0, |value, byte| value << 8 | *byte as u64))
+	}
+
+	/// Decodes the value at `offset`, returning it with the offset right after it
+	fn decode(&self, offset: usize, depth: u8) -> Result<(Value, usize)> {
+		if depth > MAX_DEPTH {
+			bail!("Data is nested too deeply");
+		}
+
+		let control = self.read(offset, 1)?[0];
+		let mut offset = offset + 1;
+		let mut kind = control >> 5;
+
+		if kind == 1 {
+			let size = (control >> 3 & 0x3) as usize;
+			let low = (control & 0x7) as u64;
+			let pointer = match size {
+				0 => low << 8 | self.uint(offset, 1)?,
+				1 => (low << 16 | self.uint(offset, 2)?) + 2048,
+				2 => (low << 24 | self.uint(offset, 3)?) + 526_336,
+				_ => self.uint(offset, 4)?,
+			};
+			let (value, _) = self.decode(pointer as usize, depth + 1)?;
+			return Ok((value, offset + size + 1));
+		}
+
+		if kind == 0 {
+			kind = 7 + self.read(offset, 1)?[0];
+			offset += 1;
+		}
+
+		let mut size = (control & 0x1F) as usize;
+		if size >= 29 {
+			let extra = size - 28;
+			let value = self.uint(offset, extra)? as usize;
+			offset += extra;
+			size = match extra {
+				1 => 29 + value,
+				2 => 285 + value,
+				_ => 65_821 + value,
+			};
+		}
+
+		let value = match kind {
+			2 => Value::String(String::from_utf8_lossy(self.read(offset, size)?).into_owned()),
+			3 => Value::from(f64::from_be_bytes(self.read(offset, 8)?.try_into()?)),
+			4 => Value::Array(self.read(offset, size)?.iter().map(|byte| Value::from(*byte)).collect()),
+			5 | 6 | 9 => Value::Number(Number::from(self.uint(offset, size)?)),
+			// Too large for JSON numbers, and nothing looked up here uses them
+			10 => Value::Null,
+			8 => {
+				let value = self.uint(offset, size)? as u32 as i32;
+				Value::Number(Number::from(value))
+			}
+			7 => {
+				let mut map = Map::new();
+				for _ in 0..size {
+					let (key, next) = self.decode(offset, depth + 1)?;
+					let (value, next) = self.decode(next, depth + 1)?;
+					offset = next;
+					if let Value::String(key) = key {
+						map.insert(key, value);
+					}
+				}
+				return Ok((Value::Object(map), offset));
+			}
+			11 => {
+				let mut array = Vec::with_capacity(size);
+				for _ in 0..size {
+					let (value, next) = self.decode(offset, depth + 1)?;
+					offset = next;
+					array.push(value);
+				}
+				return Ok((Value::Array(array), offset));
+			}
+			// Booleans keep their value in the size bits
+			14 => return Ok((Value::Bool(size != 0), offset)),
+			15 => Value::from(f32::from_be_bytes(self.read(offset, 4)?.try_into()?) as f64),
+			kind => bail!("Unknown data type {}", kind),
+		};
+
+		let length = match kind {
+			3 => 8,
+			15 => 4,
+			_ => size,
+		};
+		Ok((value, offset + length))
+	}
+}
+
+/// Looks up every stored address and writes the country and ASN to its servers. Values the
+/// databases don't know are left as they are
+pub async fn backfill(database: &Database, geoip: &GeoIp, limit: Option<i64>) -> Result<(), sqlx::Error> {
+	let mut rows = sqlx::query("SELECT DISTINCT address FROM servers ORDER BY address LIMIT $1")
+		.bind(limit)
+		.fetch(&database.0);
+
+	let (mut updated, mut unknown) = (0u64, 0u64);
+	while let Some(row) = rows.try_next().await? {
+		let address: IpNet = row.try_get("address")?;
+		let IpAddr::V4(ip) = address.addr() else {
+			continue;
+		};
+
+		let location = geoip.lookup(ip);
+		if location == Location::default() {
+			unknown += 1;
+			continue;
+		}
+
+		sqlx::query(
+			"UPDATE servers SET country = COALESCE($2, country), asn = COALESCE($3, asn) WHERE address = $1",
+		)
+		.bind(address)
+		.bind(location.country_code)
+		.bind(location.asn)
+		.execute(&database.0)
+		.await?;
+		updated += 1;
+
+		if updated % 10_000 == 0 {
+			info!("Backfilled {} addresses", updated);
+		}
+	}
+
+	info!("GeoIP backfill finished: {} addresses updated, {} not in the databases", updated, unknown);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn string(value: &str) -> Vec<u8> {
+		let mut bytes = vec![2 << 5 | value.len() as u8];
+		bytes.extend_from_slice(value.as_bytes());
+		bytes
+	}
+
+	fn uint32(value: u32) -> Vec<u8> {
+		let mut bytes = vec![6 << 5 | 4];
+		bytes.extend_from_slice(&value.to_be_bytes());
+		bytes
+	}
+
+	fn map(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+		let mut bytes = vec![7 << 5 | entries.len() as u8];
+		for (key, value) in entries {
+			bytes.extend(string(key));
+			bytes.extend_from_slice(value);
+		}
+		bytes
+	}
+
+	/// An IPv4 database with one node, 0.0.0.0/1 has a record and 128.0.0.0/1 doesn't
+	fn database(record: Vec<u8>) -> Mmdb {
+		let node_count = 1u32;
+		let mut bytes = Vec::new();
+		// Left points at the first byte of the data section, right means not found
+		bytes.extend_from_slice(&(node_count + DATA_SECTION_SEPARATOR as u32).to_be_bytes()[1..]);
+		bytes.extend_from_slice(&node_count.to_be_bytes()[1..]);
+		bytes.extend_from_slice(&[0; DATA_SECTION_SEPARATOR]);
+		bytes.extend(record);
+		bytes.extend_from_slice(METADATA_MARKER);
+		bytes.extend(map(&[
+			("node_count", uint32(node_count)),
+			("record_size", uint32(24)),
+			("ip_version", uint32(4)),
+		]));
+
+		Mmdb::from_bytes(bytes).unwrap()
+	}
+
+	#[test]
+	fn test_lookup() {
+		let maxmind = database(map(&[
+			("country", map(&[("iso_code", string("BR"))])),
+			("autonomous_system_number", uint32(28573)),
+		]));
+		let record = maxmind.lookup(Ipv4Addr::new(45, 1, 2, 3)).unwrap().unwrap();
+		assert_eq!(country_code(&record).as_deref(), Some("BR"));
+		assert_eq!(asn(&record).as_deref(), Some("AS28573"));
+		assert_eq!(maxmind.lookup(Ipv4Addr::new(200, 1, 2, 3)).unwrap(), None);
+
+		let ipinfo = database(map(&[("country_code", string("US")), ("asn", string("AS13335"))]));
+		let geoip = GeoIp {
+			databases: vec![(PathBuf::from("ipinfo.mmdb"), ipinfo)],
+		};
+		assert_eq!(
+			geoip.lookup(Ipv4Addr::new(1, 1, 1, 1)),
+			Location {
+				country_code: Some("US".to_string()),
+				asn: Some("AS13335".to_string()),
+			}
+		);
+	}
+
+	#[test]
+	fn test_record_into_separator() {
+		let mut broken = database(map(&[("country_code", string("US"))]));
+		// Past the node count, but short of the data section
+		broken.bytes[2] = 5;
+		assert!(broken.lookup(Ipv4Addr::new(1, 1, 1, 1)).is_err());
+		assert_eq!(broken.lookup(Ipv4Addr::new(200, 1, 2, 3)).unwrap(), None);
+	}
+
+	#[test]
+	fn test_pointers() {
+		// The record is a pointer to a string stored right after it
+		let decoder = Decoder { bytes: &[1 << 5, 2, 2 << 5 | 2, b'B', b'R'] };
+		assert_eq!(decoder.decode(0, 0).unwrap(), (Value::String("BR".to_string()), 2));
+
+		// A pointer to itself never ends
+		let decoder = Decoder { bytes: &[1 << 5, 0] };
+		assert!(decoder.decode(0, 0).is_err());
+	}
+
+	#[test]
+	fn test_open_rejects_other_files() {
+		assert!(Mmdb::from_bytes(b"not a database".to_vec()).is_err());
+	}
+}
@@ -0,0 +1,237 @@
+use sqlx::PgPool;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+/// How often the heartbeat task ticks, a heartbeat older than `HEARTBEAT_STALE` means
+/// the runtime is too starved to run it
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+const HEARTBEAT_STALE: Duration = Duration::from_secs(10);
+
+// Probes send a short request line and a few headers, nothing past this is read
+const MAX_REQUEST_BYTES: usize = 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// What the probes report on. Scanners mark passes and finished pings, the server
+/// keeps its own heartbeat
+#[derive(Debug)]
+pub struct Health {
+	started: Instant,
+	// Milliseconds since `started`
+	heartbeat: AtomicU64,
+	last_activity: AtomicU64,
+	passes_started: AtomicU64,
+	// Passes started and not finished yet, one per scanner at most
+	passes_running: AtomicU64,
+	// A running pass with nothing finishing for this long counts as wedged
+	max_idle: Option<Duration>,
+}
+
+impl Health {
+	pub fn new(max_idle: Option<Duration>) -> Self {
+		Self {
+			started: Instant::now(),
+			heartbeat: AtomicU64::new(0),
+			last_activity: AtomicU64::new(0),
+			passes_started: AtomicU64::new(0),
+			passes_running: AtomicU64::new(0),
+			max_idle,
+		}
+	}
+
+	fn now(&self) -> u64 {
+		self.started.elapsed().as_millis() as u64
+	}
+
+	fn age(&self, timestamp: &AtomicU64) -> Duration {
+		Duration::from_millis(self.now().saturating_sub(timestamp.load(Ordering::Relaxed)))
+	}
+
+	pub fn pass_started(&self) {
+		self.passes_started.fetch_add(1, Ordering::Relaxed);
+		self.passes_running.fetch_add(1, Ordering::Relaxed);
+		self.record_activity();
+	}
+
+	/// Waiting between passes isn't idling, only running passes are checked for activity
+	pub fn pass_finished(&self) {
+		let _ = self
+			.passes_running
+			.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |running| running.checked_sub(1));
+	}
+
+	/// Called whenever a ping finishes, successful or not
+	pub fn record_activity(&self) {
+		self.last_activity.store(self.now(), Ordering::Relaxed);
+	}
+
+	fn alive(&self) -> Result<(), String> {
+		let heartbeat = self.age(&self.heartbeat);
+		if heartbeat > HEARTBEAT_STALE {
+			return Err(format!("heartbeat is {}s old", heartbeat.as_secs()));
+		}
+
+		if let Some(max_idle) = self.max_idle {
+			let idle = self.age(&self.last_activity);
+			if self.passes_running.load(Ordering::Relaxed) > 0 && idle > max_idle {
+				return Err(format!("no ping has finished in {}s", idle.as_secs()));
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn ready(&self, pool: &PgPool) -> Result<(), String> {
+		if self.passes_started.load(Ordering::Relaxed) == 0 {
+			return Err("no scan has started yet".to_string());
+		}
+
+		match tokio::time::timeout(DATABASE_TIMEOUT, sqlx::query("SELECT 1").execute(pool)).await {
+			Ok(Ok(_)) => Ok(()),
+			Ok(Err(e)) => Err(format!("database unreachable: {}", e)),
+			Err(_) => Err("database unreachable: timed out".to_string()),
+		}
+	}
+
+	/// Answers `/healthz` and `/readyz` on `listener` until the process exits
+	pub async fn serve(self: Arc<Self>, listener: TcpListener, pool: PgPool) {
+		let heartbeat = self.clone();
+		tokio::spawn(async move {
+			let mut ticker = tokio::time::interval(HEARTBEAT_INTERVAL);
+			loop {
+				ticker.tick().await;
+				heartbeat.heartbeat.store(heartbeat.now(), Ordering::Relaxed);
+			}
+		});
+
+		if let Ok(address) = listener.local_addr() {
+			info!("Serving health checks on http://{}", address);
+		}
+
+		loop {
+			let (stream, _) = match listener.accept().await {
+				Ok(connection) => connection,
+				Err(e) => {
+					debug!("Failed to accept health check connection: {}", e);
+					continue;
+				}
+			};
+
+			let health = self.clone();
+			let pool = pool.clone();
+			tokio::spawn(async move {
+				if let Err(e) = health.handle(stream, &pool).await {
+					debug!("Failed to answer health check: {}", e);
+				}
+			});
+		}
+	}
+
+	async fn handle(&self, mut stream: TcpStream, pool: &PgPool) -> std::io::Result<()> {
+		let mut buffer = vec![0u8; MAX_REQUEST_BYTES];
+		let read = tokio::time::timeout(REQUEST_TIMEOUT, stream.read(&mut buffer)).await??;
+
+		let response = match request_path(&buffer[..read]) {
+			Some("/healthz") => match self.alive() {
+				Ok(()) => http_response(200, "ok"),
+				Err(reason) => http_response(503, &reason),
+			},
+			Some("/readyz") => match self.ready(pool).await {
+				Ok(()) => http_response(200, "ok"),
+				Err(reason) => http_response(503, &reason),
+			},
+			Some(_) => http_response(404, "not found"),
+			None => http_response(400, "bad request"),
+		};
+
+		stream.write_all(response.as_bytes()).await?;
+		stream.shutdown().await
+	}
+}
+
+/// Path of a GET or HEAD request, without the query string
+fn request_path(request: &[u8]) -> Option<&str> {
+	let line = std::str::from_utf8(request).ok()?.lines().next()?;
+	let mut parts = line.split(' ');
+
+	match (parts.next()?, parts.next()?) {
+		("GET" | "HEAD", target) => target.split('?').next(),
+		_ => None,
+	}
+}
+
+fn http_response(status: u16, body: &str) -> String {
+	let reason = match status {
+		200 => "OK",
+		400 => "Bad Request",
+		404 => "Not Found",
+		_ => "Service Unavailable",
+	};
+
+	format!(
+		"HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+		status,
+		reason,
+		body.len() + 1,
+		body
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_request_path() {
+		assert_eq!(request_path(b"GET /healthz HTTP/1.1\r\nHost: pod\r\n\r\n"), Some("/healthz"));
+		assert_eq!(request_path(b"HEAD /readyz?verbose=1 HTTP/1.1\r\n\r\n"), Some("/readyz"));
+		assert_eq!(request_path(b"POST /healthz HTTP/1.1\r\n\r\n"), None);
+		assert_eq!(request_path(b"\xff\xfe"), None);
+	}
+
+	#[tokio::test]
+	async fn test_probes() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let address = listener.local_addr().unwrap();
+		// Nothing listens on port 1, so readiness fails on the database
+		let pool = PgPool::connect_lazy("postgres://serverseeker@127.0.0.1:1/serverseeker").unwrap();
+		let health = Arc::new(Health::new(None));
+		tokio::spawn(health.clone().serve(listener, pool));
+
+		let get = |path: &'static str| async move {
+			let mut stream = TcpStream::connect(address).await.unwrap();
+			stream.write_all(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes()).await.unwrap();
+			let mut response = String::new();
+			stream.read_to_string(&mut response).await.unwrap();
+			response
+		};
+
+		assert!(get("/healthz").await.starts_with("HTTP/1.1 200 OK"));
+		assert!(get("/readyz").await.ends_with("no scan has started yet\n"));
+
+		health.pass_started();
+		let response = get("/readyz").await;
+		assert!(response.starts_with("HTTP/1.1 503"));
+		assert!(response.contains("database unreachable"));
+
+		assert!(get("/metrics").await.starts_with("HTTP/1.1 404"));
+	}
+
+	#[test]
+	fn test_idle_only_while_running() {
+		let health = Health::new(Some(Duration::ZERO));
+		// Heartbeat at 0, so it's still fresh
+		assert!(health.alive().is_ok());
+
+		health.pass_started();
+		std::thread::sleep(Duration::from_millis(5));
+		assert!(health.alive().unwrap_err().starts_with("no ping has finished"));
+
+		health.pass_finished();
+		assert!(health.alive().is_ok());
+	}
+}
@@ -0,0 +1,199 @@
+use crate::database::Database;
+use serde::Deserialize;
+use sqlx::types::ipnet::{IpNet, Ipv4Net};
+use std::net::Ipv4Addr;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tracing::{info, warn};
+
+// Rows sent to the database in one statement
+const BATCH_SIZE: usize = 1000;
+
+/// One server from an import file, only the address and port are required
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ImportRecord {
+	#[serde(alias = "address")]
+	pub ip: Ipv4Addr,
+	pub port: u16,
+	#[serde(default)]
+	pub version: Option<String>,
+	#[serde(default)]
+	pub protocol: Option<i32>,
+}
+
+/// Parses one line of an import file. JSON Lines objects and `ip,port[,version[,protocol]]`
+/// CSV rows can be mixed, blank lines, `#` comments and a CSV header give `Ok(None)`
+pub fn parse_record(line: &str) -> Result<Option<ImportRecord>, String> {
+	let line = line.trim();
+	if line.is_empty() || line.starts_with('#') {
+		return Ok(None);
+	}
+
+	if line.starts_with('{') {
+		return serde_json::from_str(line).map(Some).map_err(|e| e.to_string());
+	}
+
+	let fields = split_csv(line);
+	let field = |i: usize| fields.get(i).map(String::as_str).filter(|f| !f.is_empty());
+
+	let Some(ip) = field(0) else {
+		return Err("missing ip".to_string());
+	};
+	if matches!(ip.to_ascii_lowercase().as_str(), "ip" | "address") {
+		return Ok(None);
+	}
+
+	let ip = ip.parse().map_err(|_| format!("invalid ip \"{}\"", ip))?;
+	let port = match field(1) {
+		Some(port) => match port.parse() {
+			Ok(0) | Err(_) => return Err(format!("invalid port \"{}\"", port)),
+			Ok(port) => port,
+		},
+		None => return Err("missing port".to_string()),
+	};
+	let protocol = match field(3) {
+		Some(protocol) => Some(protocol.parse().map_err(|_| format!("invalid protocol \"{}\"", protocol))?),
+		None => None,
+	};
+
+	Ok(Some(ImportRecord {
+		ip,
+		port,
+		version: field(2).map(str::to_string),
+		protocol,
+	}))
+}
+
+/// Splits a CSV row on commas, fields in double quotes can contain commas and `""`
+fn split_csv(line: &str) -> Vec<String> {
+	let mut fields = Vec::new();
+	let mut field = String::new();
+	let mut quoted = false;
+	let mut chars = line.chars().peekable();
+
+	while let Some(c) = chars.next() {
+		match c {
+			'"' if quoted && chars.peek() == Some(&'"') => {
+				field.push('"');
+				chars.next();
+			}
+			'"' => quoted = !quoted,
+			',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+			c => field.push(c),
+		}
+	}
+	fields.push(field.trim().to_string());
+
+	fields
+}
+
+/// Reads servers from `path`, or stdin when it's `-`, and adds them to the servers table so
+/// the next rescan pings them. Data already stored for a server is kept, the import only
+/// fills in what's missing. Malformed rows are skipped with a warning
+pub async fn import(database: &Database, path: &str) -> anyhow::Result<()> {
+	let reader: Box<dyn AsyncBufRead + Unpin + Send> = if path == "-" {
+		Box::new(BufReader::new(tokio::io::stdin()))
+	} else {
+		Box::new(BufReader::new(tokio::fs::File::open(path).await?))
+	};
+
+	let mut lines = reader.lines();
+	let (mut line_number, mut imported, mut skipped) = (0u64, 0u64, 0u64);
+	let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+	while let Some(line) = lines.next_line().await? {
+		line_number += 1;
+
+		match parse_record(&line) {
+			Ok(Some(record)) => batch.push(record),
+			Ok(None) => {}
+			Err(e) => {
+				warn!("Skipping line {}: {}", line_number, e);
+				skipped += 1;
+			}
+		}
+
+		if batch.len() >= BATCH_SIZE {
+			imported += insert_batch(database, &batch).await?;
+			batch.clear();
+			info!("Imported {} servers", imported);
+		}
+	}
+
+	if !batch.is_empty() {
+		imported += insert_batch(database, &batch).await?;
+	}
+
+	info!("Import finished: {} servers imported, {} lines skipped", imported, skipped);
+	Ok(())
+}
+
+async fn insert_batch(database: &Database, batch: &[ImportRecord]) -> Result<u64, sqlx::Error> {
+	let addresses: Vec<IpNet> = batch.iter().map(|r| IpNet::V4(Ipv4Net::from(r.ip))).collect();
+	let ports: Vec<i32> = batch.iter().map(|r| r.port as i32).collect();
+	let versions: Vec<Option<String>> = batch.iter().map(|r| r.version.clone()).collect();
+	let protocols: Vec<Option<i32>> = batch.iter().map(|r| r.protocol).collect();
+
+	// A batch can list a server twice, which one statement can't upsert
+	let result = sqlx::query(
+		"INSERT INTO servers (address, port, version, protocol, discovered_by)
+		SELECT DISTINCT ON (address, port) address, port, version, protocol, 'import'
+		FROM UNNEST($1::inet[], $2::int[], $3::text[], $4::int[]) AS t(address, port, version, protocol)
+		ON CONFLICT (address, port) DO UPDATE SET
+			version = COALESCE(servers.version, EXCLUDED.version),
+			protocol = COALESCE(servers.protocol, EXCLUDED.protocol),
+			discovered_by = COALESCE(servers.discovered_by, EXCLUDED.discovered_by)",
+	)
+	.bind(addresses)
+	.bind(ports)
+	.bind(versions)
+	.bind(protocols)
+	.execute(&database.0)
+	.await?;
+
+	Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn record(ip: [u8; 4], port: u16, version: Option<&str>, protocol: Option<i32>) -> ImportRecord {
+		ImportRecord {
+			ip: Ipv4Addr::from(ip),
+			port,
+			version: version.map(str::to_string),
+			protocol,
+		}
+	}
+
+	#[test]
+	fn test_parse_csv() {
+		assert_eq!(parse_record("203.0.113.7,25565"), Ok(Some(record([203, 0, 113, 7], 25565, None, None))));
+		assert_eq!(
+			parse_record("203.0.113.7, 25577, \"Velocity 3.3.0, 1.7.2-1.21\", 767"),
+			Ok(Some(record([203, 0, 113, 7], 25577, Some("Velocity 3.3.0, 1.7.2-1.21"), Some(767))))
+		);
+		assert_eq!(parse_record("203.0.113.7,25565,,47"), Ok(Some(record([203, 0, 113, 7], 25565, None, Some(47)))));
+		assert_eq!(parse_record("ip,port,version"), Ok(None));
+		assert_eq!(parse_record("# exported 2026-10-15"), Ok(None));
+		assert_eq!(parse_record("   "), Ok(None));
+	}
+
+	#[test]
+	fn test_parse_json() {
+		assert_eq!(
+			parse_record(r#"{"address": "203.0.113.7", "port": 25565, "version": "Paper 1.21.1", "motd": "hi"}"#),
+			Ok(Some(record([203, 0, 113, 7], 25565, Some("Paper 1.21.1"), None)))
+		);
+		assert!(parse_record(r#"{"ip": "203.0.113.7"}"#).is_err());
+	}
+
+	#[test]
+	fn test_parse_malformed() {
+		assert_eq!(parse_record("203.0.113.7"), Err("missing port".to_string()));
+		assert_eq!(parse_record("203.0.113.7,70000"), Err("invalid port \"70000\"".to_string()));
+		assert_eq!(parse_record("203.0.113.7,0"), Err("invalid port \"0\"".to_string()));
+		assert_eq!(parse_record("2001:db8::1,25565"), Err("invalid ip \"2001:db8::1\"".to_string()));
+		assert!(parse_record("203.0.113.7,25565,Paper,latest").is_err());
+	}
+}
@@ -1,7 +1,7 @@
+#[cfg(target_os = "windows")]
+use crate::download;
 use anyhow::{Context, Result};
 use std::fs;
-#[cfg(target_os = "windows")]
-use std::io::Cursor;
 use std::path::Path;
 #[cfg(target_os = "windows")]
 use tracing::info;
@@ -11,14 +11,13 @@ const RUSTSCAN_URL: &str = "https://github.com/bee-san/RustScan/releases/downloa
 #[cfg(target_os = "windows")]
 const MASSCAN_URL: &str = "https://github.com/Arryboom/MasscanForWindows/blob/master/masscan64.exe?raw=true";
 
-pub async fn install_binaries() -> Result<()> {
+pub async fn install_binaries(bin_dir: &Path) -> Result<()> {
     if !cfg!(target_os = "windows") {
         return Ok(());
     }
 
-    let bin_dir = Path::new("bin");
     if !bin_dir.exists() {
-        fs::create_dir(bin_dir).context("Failed to create bin directory")?;
+        fs::create_dir_all(bin_dir).context("Failed to create bin directory")?;
     }
 
     install_rustscan(bin_dir).await?;
@@ -35,14 +34,19 @@ async fn install_rustscan(bin_dir: &Path) -> Result<()> {
     }
 
     info!("Downloading RustScan from {}", RUSTSCAN_URL);
-    let response = reqwest::get(RUSTSCAN_URL)
+    let zip_path = bin_dir.join("rustscan.zip");
+    download::fetch_to_file(RUSTSCAN_URL, &zip_path, download::MAX_BINARY_BYTES)
         .await
-        .context("Failed to download RustScan")?
-        .bytes()
-        .await
-        .context("Failed to get RustScan bytes")?;
+        .context("Failed to download RustScan")?;
+
+    let result = extract_rustscan(&zip_path, &target_path);
+    let _ = fs::remove_file(&zip_path);
+    result
+}
 
-    let reader = Cursor::new(response);
+#[cfg(target_os = "windows")]
+fn extract_rustscan(zip_path: &Path, target_path: &Path) -> Result<()> {
+    let reader = fs::File::open(zip_path).context("Failed to open RustScan zip")?;
     let mut zip = zip::ZipArchive::new(reader).context("Failed to open RustScan zip")?;
 
     for i in 0..zip.len() {
@@ -50,7 +54,7 @@ async fn install_rustscan(bin_dir: &Path) -> Result<()> {
         // O nome dentro do zip pode ser rustscan.exe ou algo como x86_64-windows-rustscan.exe
         if file.name().ends_with(".exe") {
             info!("Extracting {} to rustscan.exe", file.name());
-            let mut outfile = fs::File::create(&target_path).context("Failed to create rustscan.exe")?;
+            let mut outfile = fs::File::create(target_path).context("Failed to create rustscan.exe")?;
             std::io::copy(&mut file, &mut outfile).context("Failed to write content to rustscan.exe")?;
             info!("RustScan installed successfully.");
             return Ok(());
@@ -73,14 +77,9 @@ async fn install_masscan(bin_dir: &Path) -> Result<()> {
     }
 
     info!("Downloading Masscan from {}", MASSCAN_URL);
-    let response = reqwest::get(MASSCAN_URL)
+    download::fetch_to_file(MASSCAN_URL, &target_path, download::MAX_BINARY_BYTES)
         .await
-        .context("Failed to download Masscan")?
-        .bytes()
-        .await
-        .context("Failed to get Masscan bytes")?;
-
-    fs::write(&target_path, response).context("Failed to write masscan.exe")?;
+        .context("Failed to download Masscan")?;
     info!("Masscan installed successfully.");
 
     Ok(())
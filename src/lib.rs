@@ -0,0 +1,42 @@
+mod backpressure;
+mod blocklist;
+mod bot_scanner;
+mod cli;
+pub mod config;
+mod country_tracking;
+mod database;
+mod db_check;
+mod dns;
+mod download;
+mod events;
+mod fronting;
+mod geoip;
+mod health;
+mod import;
+mod installer;
+mod links;
+pub mod ping;
+mod profile;
+pub mod protocol;
+mod rate_limit;
+mod reparse;
+mod rescan_filter;
+pub mod response;
+mod scanner;
+mod schedule;
+pub mod sink;
+mod stats;
+mod status;
+pub mod target_source;
+mod targeting;
+mod tcp_fingerprint;
+mod utils;
+mod varint;
+mod write_queue;
+
+pub use cli::run;
+pub use ping::{ping_server, PingMethod, PingOptions, PingResult};
+pub use protocol::PingableServer;
+pub use response::Server;
+pub use scanner::ScanBuilder;
+pub use utils::RunError;
@@ -0,0 +1,114 @@
+// Characters that never appear in the links we look for but often wrap them in MOTDs
+const SEPARATORS: &[char] = &['|', ',', '(', ')', '<', '>', '[', ']', '{', '}', '"', '\'', '«', '»', '•'];
+// Sentence punctuation trimmed off the ends of a match
+const PUNCTUATION: &[char] = &['.', ',', '!', '?', ':', ';', '-', '*', '~'];
+
+// Bare domains only count with one of these, a dotted word like "Mr.Smith" otherwise
+// looks just like one. Links with a scheme can use any alphabetic TLD
+const KNOWN_TLDS: &[&str] = &[
+	"app", "au", "be", "br", "ca", "cc", "ch", "cl", "club", "co", "com", "cz", "de", "dev", "dk", "es", "eu",
+	"fi", "fr", "fun", "games", "gg", "host", "hu", "id", "in", "info", "io", "it", "jp", "kr", "me", "mx",
+	"net", "nl", "no", "nz", "online", "org", "pl", "pro", "pt", "ro", "ru", "se", "shop", "site", "sk",
+	"store", "tk", "tv", "ua", "uk", "us", "vip", "world", "xyz",
+];
+
+/// Finds URLs, domains, Discord invites and `ip:port` addresses in plain text, in the order
+/// they appear and without duplicates. Matching is conservative, anything it isn't sure
+/// about is left out
+pub fn extract_links(text: &str) -> Vec<String> {
+	let mut links: Vec<String> = Vec::new();
+
+	for token in text.split(|c: char| c.is_whitespace() || SEPARATORS.contains(&c)) {
+		let token = token.trim_matches(PUNCTUATION);
+		if is_link(token) && !links.iter().any(|link| link == token) {
+			links.push(token.to_string());
+		}
+	}
+
+	links
+}
+
+fn is_link(token: &str) -> bool {
+	let lower = token.to_ascii_lowercase();
+	let (rest, has_scheme) = match lower.strip_prefix("https://").or_else(|| lower.strip_prefix("http://")) {
+		Some(rest) => (rest, true),
+		None => (lower.as_str(), false),
+	};
+
+	let authority = rest.split('/').next().unwrap_or_default();
+	let (host, port) = match authority.split_once(':') {
+		Some((host, port)) => (host, Some(port)),
+		None => (authority, None),
+	};
+
+	if let Some(port) = port {
+		if !port.bytes().all(|b| b.is_ascii_digit()) || !matches!(port.parse::<u16>(), Ok(1..)) {
+			return false;
+		}
+	}
+
+	is_ipv4(host) || is_domain(host, has_scheme)
+}
+
+fn is_ipv4(host: &str) -> bool {
+	let parts: Vec<&str> = host.split('.').collect();
+	// No leading zeros, "01.2.3.4" is more likely something else
+	parts.len() == 4
+		&& parts.iter().all(|part| {
+			!part.is_empty()
+				&& part.len() <= 3
+				&& part.bytes().all(|b| b.is_ascii_digit())
+				&& (part.len() == 1 || !part.starts_with('0'))
+				&& part.parse::<u8>().is_ok()
+		})
+}
+
+fn is_domain(host: &str, has_scheme: bool) -> bool {
+	let labels: Vec<&str> = host.split('.').collect();
+	let Some((tld, names)) = labels.split_last() else {
+		return false;
+	};
+
+	let valid_label = |label: &&str| {
+		!label.is_empty()
+			&& label.len() <= 63
+			&& !label.starts_with('-')
+			&& !label.ends_with('-')
+			&& label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+	};
+
+	let valid_tld = match has_scheme {
+		true => tld.len() >= 2 && tld.bytes().all(|b| b.is_ascii_alphabetic()),
+		false => KNOWN_TLDS.contains(tld),
+	};
+
+	!names.is_empty() && names.iter().all(valid_label) && valid_tld
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_extract_links() {
+		assert_eq!(
+			extract_links("§6Join our Discord: discord.gg/AbC123 §7| §aStore: https://store.example.net/ranks!"),
+			vec!["discord.gg/AbC123", "https://store.example.net/ranks"]
+		);
+		assert_eq!(
+			extract_links("New IP -> 203.0.113.7:25577 (old one is play.example.com)"),
+			vec!["203.0.113.7:25577", "play.example.com"]
+		);
+		assert_eq!(extract_links("Vote at mc.example.org. Vote at mc.example.org"), vec!["mc.example.org"]);
+		assert_eq!(extract_links("Bare 198.51.100.4 works too"), vec!["198.51.100.4"]);
+	}
+
+	#[test]
+	fn test_extract_links_false_positives() {
+		// Versions, names with dots, emails and ports out of range
+		assert!(extract_links("Now on 1.20.4! Built by Mr.Smith").is_empty());
+		assert!(extract_links("Mail support@example.com").is_empty());
+		assert!(extract_links("play.example.com:99999 and 1.2.3.400").is_empty());
+		assert!(extract_links("...").is_empty());
+	}
+}
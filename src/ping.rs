@@ -0,0 +1,312 @@
+use crate::config::{ProxyProtocol, ScannerConfig};
+use crate::protocol::{PingableServer, DEFAULT_HANDSHAKE_PROTOCOL, DEFAULT_MAX_STATUS_BYTES};
+use crate::response::Server;
+use crate::utils::RunError;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Which protocol method produced a ping result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingMethod {
+	Proper,
+	Legacy,
+}
+
+impl PingMethod {
+	/// Methods tried in order until one of them gets a status
+	pub fn sequence(legacy_fallback: bool, legacy_only: bool) -> &'static [PingMethod] {
+		match (legacy_fallback, legacy_only) {
+			(_, true) => &[PingMethod::Legacy],
+			(true, false) => &[PingMethod::Proper, PingMethod::Legacy],
+			(false, false) => &[PingMethod::Proper],
+		}
+	}
+
+	/// Name stored in the `ping_method` column
+	pub fn as_str(self) -> &'static str {
+		match self {
+			PingMethod::Proper => "proper",
+			PingMethod::Legacy => "legacy",
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct PingOptions {
+	/// Timeout applied to each ping method
+	pub timeout: Duration,
+	/// PROXY protocol header sent before the handshake, if any
+	pub proxy_protocol: Option<ProxyProtocol>,
+	/// Protocol version claimed in the handshake
+	pub handshake_protocol: i32,
+	/// Upper bound of the random delay between the handshake and status request
+	pub handshake_jitter: Duration,
+	/// Local addresses to spread connections over, empty lets the OS choose
+	pub source_addresses: Vec<Ipv4Addr>,
+	/// Largest status JSON accepted from a modern server
+	pub max_status_bytes: usize,
+	/// Record what the kernel observed during the TCP handshake
+	pub tcp_fingerprint: bool,
+	/// Methods tried in order, see `PingMethod::sequence`
+	pub methods: &'static [PingMethod],
+	/// Hostname sent in the modern handshake, the address when unset
+	pub handshake_host: Option<String>,
+}
+
+impl PingOptions {
+	/// Picks a source address by hashing the target, so every attempt
+	/// against the same server leaves from the same local address
+	pub fn source_address_for(&self, socket: SocketAddrV4) -> Option<Ipv4Addr> {
+		if self.source_addresses.is_empty() {
+			return None;
+		}
+
+		let hash = socket.ip().to_bits() ^ (socket.port() as u32).rotate_left(16);
+		Some(self.source_addresses[hash as usize % self.source_addresses.len()])
+	}
+}
+
+impl Default for PingOptions {
+	fn default() -> Self {
+		PingOptions {
+			timeout: crate::scanner::TIMEOUT_SECS,
+			proxy_protocol: None,
+			handshake_protocol: DEFAULT_HANDSHAKE_PROTOCOL,
+			handshake_jitter: Duration::ZERO,
+			source_addresses: Vec::new(),
+			max_status_bytes: DEFAULT_MAX_STATUS_BYTES,
+			tcp_fingerprint: false,
+			methods: PingMethod::sequence(true, false),
+			handshake_host: None,
+		}
+	}
+}
+
+impl From<&ScannerConfig> for PingOptions {
+	fn from(config: &ScannerConfig) -> Self {
+		PingOptions {
+			timeout: Duration::from_millis(config.timeout_ms),
+			proxy_protocol: config.proxy_protocol,
+			handshake_protocol: config.handshake_protocol,
+			handshake_jitter: Duration::from_millis(config.handshake_jitter_ms),
+			source_addresses: config.source_addresses.clone(),
+			max_status_bytes: config.max_status_bytes,
+			tcp_fingerprint: config.tcp_fingerprint,
+			methods: PingMethod::sequence(config.legacy_fallback, config.legacy_only),
+			handshake_host: None,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct PingResult {
+	/// The status JSON exactly as the server sent it
+	pub raw: String,
+	pub server: Server,
+	/// Server software detected with `Server::get_type`
+	pub server_type: &'static str,
+	/// Ping/Pong round trip in milliseconds, or the TCP connect time when there was none
+	pub latency: i32,
+	pub method: PingMethod,
+}
+
+/// Pings a server without touching the database. Tries each of `opts.methods` in turn,
+/// by default a proper (1.7+) ping and then a legacy ping if that fails or returns a status
+/// that doesn't parse
+pub async fn ping_server(socket: SocketAddrV4, opts: PingOptions) -> Result<PingResult, RunError> {
+	let server = PingableServer::new(socket)
+		.with_proxy_protocol(opts.proxy_protocol)
+		.with_handshake_protocol(opts.handshake_protocol)
+		.with_handshake_jitter(opts.handshake_jitter)
+		.with_connect_timeout(opts.timeout)
+		.with_read_timeout(opts.timeout)
+		.with_source_address(opts.source_address_for(socket))
+		.with_max_status_bytes(opts.max_status_bytes)
+		.with_tcp_fingerprint(opts.tcp_fingerprint)
+		.with_handshake_host(opts.handshake_host.clone());
+	let start_time = Instant::now();
+	let mut error: Option<RunError> = None;
+	let mut found = None;
+
+	for &method in opts.methods {
+		// Wrap with timeout to prevent hanging reads
+		let result = match method {
+			PingMethod::Proper => tokio::time::timeout(opts.timeout, server.proper_ping()).await,
+			PingMethod::Legacy => tokio::time::timeout(opts.timeout, server.legacy_ping()).await,
+		};
+
+		// Some quirky servers answer the modern ping with a status we can't read,
+		// their legacy response is often fine so that's worth trying before giving up
+		let result = match result {
+			Ok(Ok(response)) => match Server::from_lenient_json(&response.json) {
+				Ok(parsed) => Ok((response, parsed)),
+				Err(e) => {
+					debug!("Failed to parse server response for {}: {}. Response: {}", socket, e, response.json);
+					Err(RunError::from(e))
+				}
+			},
+			// Something else is listening, another method won't change that
+			Ok(Err(e @ RunError::NotMinecraft(_))) => return Err(e),
+			Ok(Err(e)) => Err(e),
+			Err(e) => Err(e.into()),
+		};
+
+		match result {
+			Ok((response, parsed)) => {
+				found = Some((response, parsed, method));
+				break;
+			}
+			Err(e) => {
+				debug!("{:?} ping failed for {}: {:?}", method, socket, e);
+				// An unreadable status still means a server answered, that's the more useful error
+				if !matches!(error, Some(RunError::ParseResponse(_))) {
+					error = Some(e);
+				}
+			}
+		}
+	}
+
+	let Some((response, mut server, method)) = found else {
+		return Err(error.unwrap_or(RunError::MalformedResponse));
+	};
+	let total_latency = start_time.elapsed().as_millis() as i32;
+	let connect_latency = response.connect_time.as_millis() as i32;
+	// The Pong is the closest thing to the in-game ping, the connect time is the only
+	// part every server has in common, so that's used when there's no Pong
+	let latency = response.round_trip.map_or(connect_latency, |round_trip| round_trip.as_millis() as i32);
+
+	server.latency = Some(latency);
+	server.connect_latency = Some(connect_latency);
+	server.total_latency = Some(total_latency);
+	server.ping_method = Some(method.as_str());
+	server.tcp_fingerprint = response.tcp_fingerprint;
+
+	Ok(PingResult {
+		server_type: server.get_type(),
+		raw: response.json,
+		server,
+		latency,
+		method,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::SocketAddr;
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::TcpListener;
+
+	/// Serves each response to one connection, in order, on a random local port
+	async fn serve_sequence(responses: Vec<Vec<u8>>) -> SocketAddrV4 {
+		serve_after(Duration::ZERO, responses).await
+	}
+
+	/// Like `serve_sequence`, but waits before answering each request
+	async fn serve_after(delay: Duration, responses: Vec<Vec<u8>>) -> SocketAddrV4 {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let SocketAddr::V4(address) = listener.local_addr().unwrap() else {
+			unreachable!()
+		};
+
+		tokio::spawn(async move {
+			for response in responses {
+				let (mut stream, _) = listener.accept().await.unwrap();
+				let mut request = [0u8; 1024];
+				let _ = stream.read(&mut request).await;
+				tokio::time::sleep(delay).await;
+				let _ = stream.write_all(&response).await;
+				let _ = stream.shutdown().await;
+			}
+		});
+
+		address
+	}
+
+	/// A modern status response that's valid JSON, but not a status even with lenient parsing
+	fn unparseable_proper() -> Vec<u8> {
+		let json = br#"["not", "a", "status"]"#;
+		let mut proper = vec![json.len() as u8 + 2, 0x00, json.len() as u8];
+		proper.extend_from_slice(json);
+		proper
+	}
+
+	fn legacy_response() -> Vec<u8> {
+		let legacy_message = "§1\u{0}78\u{0}1.6.4\u{0}A Minecraft Server\u{0}3\u{0}20";
+		let utf16: Vec<u16> = legacy_message.encode_utf16().collect();
+		let mut legacy = vec![0xFF];
+		legacy.extend_from_slice(&(utf16.len() as u16).to_be_bytes());
+		for unit in utf16 {
+			legacy.extend_from_slice(&unit.to_be_bytes());
+		}
+		legacy
+	}
+
+	#[tokio::test]
+	async fn test_unparseable_status_falls_back_to_legacy() {
+		let socket = serve_sequence(vec![unparseable_proper(), legacy_response()]).await;
+		let result = ping_server(socket, PingOptions::default()).await.unwrap();
+
+		assert_eq!(result.method, PingMethod::Legacy);
+		assert_eq!(result.server.ping_method, Some("legacy"));
+		assert_eq!(result.server.version.name, "1.6.4");
+		assert_eq!(result.server.players.online, 3);
+	}
+
+	#[tokio::test]
+	async fn test_legacy_fallback_disabled() {
+		// The legacy response is there, but with the fallback off it's never asked for
+		let socket = serve_sequence(vec![unparseable_proper(), legacy_response()]).await;
+		let options = PingOptions {
+			methods: PingMethod::sequence(false, false),
+			..PingOptions::default()
+		};
+
+		let result = ping_server(socket, options).await;
+		assert!(matches!(result, Err(RunError::ParseResponse(_))));
+	}
+
+	#[tokio::test]
+	async fn test_legacy_only() {
+		// A single connection is served, a modern ping first would have used it up
+		let socket = serve_sequence(vec![legacy_response()]).await;
+		let options = PingOptions {
+			methods: PingMethod::sequence(true, true),
+			..PingOptions::default()
+		};
+
+		let result = ping_server(socket, options).await.unwrap();
+		assert_eq!(result.method, PingMethod::Legacy);
+		assert_eq!(result.server.version.name, "1.6.4");
+	}
+
+	#[tokio::test]
+	async fn test_configured_timeout() {
+		let options = |timeout: Duration| PingOptions {
+			timeout,
+			methods: PingMethod::sequence(true, true),
+			..PingOptions::default()
+		};
+
+		// Read phase, the configured timeout is used instead of the 5 second default either way
+		let socket = serve_after(Duration::from_millis(300), vec![legacy_response(); 2]).await;
+		assert!(ping_server(socket, options(Duration::from_millis(200))).await.is_err());
+		let socket = serve_after(Duration::from_millis(300), vec![legacy_response()]).await;
+		let result = ping_server(socket, options(Duration::from_secs(1))).await.unwrap();
+		assert_eq!(result.server.version.name, "1.6.4");
+
+		// Connect phase, a listener with a full backlog drops the handshake
+		let listener = tokio::net::TcpSocket::new_v4().unwrap();
+		listener.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+		let listener = listener.listen(0).unwrap();
+		let SocketAddr::V4(socket) = listener.local_addr().unwrap() else {
+			unreachable!()
+		};
+		let _queued = tokio::net::TcpStream::connect(socket).await.unwrap();
+
+		let started = std::time::Instant::now();
+		assert!(ping_server(socket, options(Duration::from_millis(200))).await.is_err());
+		assert!(started.elapsed() < Duration::from_secs(2));
+	}
+}
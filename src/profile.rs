@@ -0,0 +1,158 @@
+use crate::config::Config;
+use tracing::{debug, info, warn};
+
+/// Presets for the settings that decide how hard the scanner pushes, picked with `--profile`.
+/// They only fill in what the config file leaves out, and command line flags override both
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+	/// Local networks, short timeouts, no rate limits and private ranges allowed
+	Lan,
+	/// Residential connections, slow enough not to trip ISP abuse limits
+	Home,
+	/// A rented server with a decent uplink
+	Vps,
+	/// As fast as the machine allows, only for hosts that are allowed to scan at that rate
+	Aggressive,
+}
+
+struct Settings {
+	timeout_ms: u64,
+	// None leaves discovery limited only by the global ping limit
+	discovery_concurrency: Option<usize>,
+	per_prefix_per_sec: f64,
+	masscan_rate: u64,
+	triage_timeout_ms: u64,
+	allow_private: bool,
+}
+
+impl Profile {
+	fn settings(self) -> Settings {
+		match self {
+			Profile::Lan => Settings {
+				timeout_ms: 1000,
+				discovery_concurrency: None,
+				per_prefix_per_sec: 0.0,
+				masscan_rate: 10_000,
+				triage_timeout_ms: 200,
+				allow_private: true,
+			},
+			Profile::Home => Settings {
+				timeout_ms: 5000,
+				discovery_concurrency: Some(100),
+				per_prefix_per_sec: 5.0,
+				masscan_rate: 1000,
+				triage_timeout_ms: 1500,
+				allow_private: false,
+			},
+			Profile::Vps => Settings {
+				timeout_ms: 4000,
+				discovery_concurrency: Some(500),
+				per_prefix_per_sec: 20.0,
+				masscan_rate: 10_000,
+				triage_timeout_ms: 1000,
+				allow_private: false,
+			},
+			Profile::Aggressive => Settings {
+				timeout_ms: 2000,
+				discovery_concurrency: None,
+				per_prefix_per_sec: 0.0,
+				masscan_rate: 100_000,
+				triage_timeout_ms: 500,
+				allow_private: false,
+			},
+		}
+	}
+
+	/// Applies the preset to every setting that isn't in `explicit`, the config file as it
+	/// was written
+	pub fn apply(self, config: &mut Config, explicit: &toml::Table) {
+		let settings = self.settings();
+		let is_set = |path: &[&str]| {
+			let mut table = explicit;
+			for key in &path[..path.len() - 1] {
+				match table.get(*key).and_then(toml::Value::as_table) {
+					Some(inner) => table = inner,
+					None => return false,
+				}
+			}
+			let set = table.contains_key(path[path.len() - 1]);
+			if set {
+				debug!("Keeping {} from the config file over the profile", path.join("."));
+			}
+			set
+		};
+
+		if !is_set(&["scanner", "timeout_ms"]) {
+			config.scanner.timeout_ms = settings.timeout_ms;
+		}
+		if !is_set(&["discovery", "concurrency"]) {
+			config.discovery.concurrency = settings.discovery_concurrency;
+		}
+		if !is_set(&["scanner", "rate_limit", "per_prefix_per_sec"]) {
+			config.scanner.rate_limit.per_prefix_per_sec = settings.per_prefix_per_sec;
+		}
+		if !is_set(&["masscan", "rate"]) {
+			config.masscan.rate = Some(settings.masscan_rate);
+		}
+		if !is_set(&["rescanner", "triage_timeout_ms"]) {
+			config.rescanner.triage_timeout_ms = settings.triage_timeout_ms;
+		}
+		if !is_set(&["targeting", "allow_private"]) {
+			config.targeting.allow_private = settings.allow_private;
+		}
+
+		info!("Using the {:?} profile", self);
+		if self == Profile::Aggressive {
+			warn!(
+				"The aggressive profile sends masscan at {} packets per second with no per network limit, \
+				scanning large ranges like this gets hosts suspended and abuse reports sent",
+				config.masscan.rate.unwrap_or(settings.masscan_rate)
+			);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_apply_keeps_explicit_settings() {
+		let explicit: toml::Table = toml::from_str(
+			r#"
+			[scanner]
+			timeout_ms = 7000
+			[scanner.rate_limit]
+			per_prefix_per_sec = 2.5
+			"#,
+		)
+		.unwrap();
+
+		let mut config = Config::default();
+		config.scanner.timeout_ms = 7000;
+		config.scanner.rate_limit.per_prefix_per_sec = 2.5;
+		Profile::Vps.apply(&mut config, &explicit);
+
+		assert_eq!(config.scanner.timeout_ms, 7000);
+		assert_eq!(config.scanner.rate_limit.per_prefix_per_sec, 2.5);
+		assert_eq!(config.discovery.concurrency, Some(500));
+		assert_eq!(config.masscan.rate, Some(10_000));
+		assert_eq!(config.rescanner.triage_timeout_ms, 1000);
+		assert!(!config.targeting.allow_private);
+
+		let mut config = Config::default();
+		Profile::Lan.apply(&mut config, &toml::from_str("[targeting]\nallow_private = false").unwrap());
+		assert!(!config.targeting.allow_private);
+		Profile::Lan.apply(&mut config, &toml::Table::new());
+		assert!(config.targeting.allow_private);
+	}
+
+	#[test]
+	fn test_profiles_pass_validation() {
+		for profile in [Profile::Lan, Profile::Home, Profile::Vps, Profile::Aggressive] {
+			let mut config = Config::default();
+			profile.apply(&mut config, &toml::Table::new());
+			assert!(config.validate().is_ok(), "{:?}", profile);
+		}
+	}
+}
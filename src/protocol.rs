@@ -1,8 +1,14 @@
+use crate::config::ProxyProtocol;
+use crate::tcp_fingerprint::TcpFingerprint;
 use crate::utils::RunError;
+use crate::varint;
+use rand::Rng;
 use serde_json::json;
-use std::net::SocketAddrV4;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::time::{timeout_at, Instant};
 use tracing::debug;
 
 #[allow(dead_code)]
@@ -17,23 +23,220 @@ const SIMPLE_PAYLOAD: [u8; 9] = [
 	0, // ID
 ];
 
+/// Protocol version sent in the handshake unless configured otherwise (1.8)
+pub const DEFAULT_HANDSHAKE_PROTOCOL: i32 = 47;
+
+/// Largest status JSON accepted unless configured otherwise. Vanilla caps the string at
+/// 32767 characters, this leaves room for modded servers and proxies that go over it
+pub const DEFAULT_MAX_STATUS_BYTES: usize = 256 * 1024;
+
+/// Longest wait for a Pong once the status has been read, servers that ignore
+/// the Ping packet shouldn't hold the connection for the whole read timeout
+const PONG_TIMEOUT: Duration = Duration::from_secs(2);
+
+const PROXY_V2_SIGNATURE: [u8; 12] = [
+	0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+/// A status response along with how long the exchange took
+#[derive(Debug, Clone)]
+pub struct StatusResponse {
+	/// Status JSON, legacy responses are converted to the modern format
+	pub json: String,
+	/// Time taken by the TCP connect alone
+	pub connect_time: Duration,
+	/// Ping/Pong round trip, only modern servers that answer the Ping packet have one
+	pub round_trip: Option<Duration>,
+	/// Handshake characteristics, only read when fingerprinting is enabled
+	pub tcp_fingerprint: Option<TcpFingerprint>,
+}
+
+/// How far a login attempt got, from the first packet the server answers Login Start with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginStatus {
+	/// Asked for encryption, joining takes a Mojang account
+	OnlineMode,
+	/// Let the username in without authentication
+	OfflineAccessible,
+	/// Disconnected because the username isn't on the whitelist
+	Whitelisted,
+	/// Disconnected because the server is full
+	Full,
+	/// Disconnected because the username or address is banned
+	Banned,
+	/// Disconnected because the client version doesn't match
+	WrongVersion,
+	/// Asked for a login plugin response, backends of proxies with modern forwarding and
+	/// Forge do this
+	LoginPlugin,
+	/// Disconnected for any other reason
+	Disconnected,
+}
+
+impl LoginStatus {
+	pub const ALL: [LoginStatus; 8] = [
+		LoginStatus::OnlineMode,
+		LoginStatus::OfflineAccessible,
+		LoginStatus::Whitelisted,
+		LoginStatus::Full,
+		LoginStatus::Banned,
+		LoginStatus::WrongVersion,
+		LoginStatus::LoginPlugin,
+		LoginStatus::Disconnected,
+	];
+
+	/// Name stored in the `login_status` column
+	pub fn as_str(self) -> &'static str {
+		match self {
+			LoginStatus::OnlineMode => "online_mode",
+			LoginStatus::OfflineAccessible => "offline_accessible",
+			LoginStatus::Whitelisted => "whitelisted",
+			LoginStatus::Full => "full",
+			LoginStatus::Banned => "banned",
+			LoginStatus::WrongVersion => "wrong_version",
+			LoginStatus::LoginPlugin => "login_plugin",
+			LoginStatus::Disconnected => "disconnected",
+		}
+	}
+}
+
 #[derive(Debug)]
 pub struct PingableServer {
 	pub socket: SocketAddrV4,
+	pub proxy_protocol: Option<ProxyProtocol>,
+	pub handshake_protocol: i32,
+	pub handshake_jitter: Duration,
+	pub connect_timeout: Duration,
+	pub read_timeout: Duration,
+	pub source_address: Option<Ipv4Addr>,
+	pub max_status_bytes: usize,
+	pub tcp_fingerprint: bool,
+	pub handshake_host: Option<String>,
 }
 
 impl PingableServer {
 	pub fn new(socket: SocketAddrV4) -> Self {
-		Self { socket }
+		Self {
+			socket,
+			proxy_protocol: None,
+			handshake_protocol: DEFAULT_HANDSHAKE_PROTOCOL,
+			handshake_jitter: Duration::ZERO,
+			connect_timeout: crate::scanner::TIMEOUT_SECS,
+			read_timeout: crate::scanner::TIMEOUT_SECS,
+			source_address: None,
+			max_status_bytes: DEFAULT_MAX_STATUS_BYTES,
+			tcp_fingerprint: false,
+			handshake_host: None,
+		}
+	}
+
+	/// Binds outgoing connections to a local address instead of letting the OS pick one
+	pub fn with_source_address(mut self, source_address: Option<Ipv4Addr>) -> Self {
+		self.source_address = source_address;
+		self
+	}
+
+	/// Sets the protocol version claimed in the handshake, -1 is the status probe convention
+	pub fn with_handshake_protocol(mut self, handshake_protocol: i32) -> Self {
+		self.handshake_protocol = handshake_protocol;
+		self
+	}
+
+	/// Waits a random time up to `handshake_jitter` between the handshake and status request,
+	/// some anti-bot plugins flag clients that send both at once
+	pub fn with_handshake_jitter(mut self, handshake_jitter: Duration) -> Self {
+		self.handshake_jitter = handshake_jitter;
+		self
+	}
+
+	/// Limits how long establishing the TCP connection may take
+	pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+		self.connect_timeout = connect_timeout;
+		self
+	}
+
+	/// Limits the total time spent reading a response, so a server trickling
+	/// bytes can't hold the connection open
+	pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+		self.read_timeout = read_timeout;
+		self
+	}
+
+	/// Rejects status responses that declare a longer JSON string than this, before
+	/// anything is allocated for it
+	pub fn with_max_status_bytes(mut self, max_status_bytes: usize) -> Self {
+		self.max_status_bytes = max_status_bytes;
+		self
+	}
+
+	/// Reads what the kernel observed during the TCP handshake right after connecting
+	pub fn with_tcp_fingerprint(mut self, tcp_fingerprint: bool) -> Self {
+		self.tcp_fingerprint = tcp_fingerprint;
+		self
+	}
+
+	/// Sends this hostname in the handshake instead of the address, the way a player
+	/// connecting through a domain would
+	pub fn with_handshake_host(mut self, handshake_host: Option<String>) -> Self {
+		self.handshake_host = handshake_host;
+		self
+	}
+
+	/// Sends a PROXY protocol header before any Minecraft packets
+	pub fn with_proxy_protocol(mut self, proxy_protocol: Option<ProxyProtocol>) -> Self {
+		self.proxy_protocol = proxy_protocol;
+		self
+	}
+
+	async fn connect_tcp(&self, timeout: Duration) -> Result<TcpStream, RunError> {
+		let stream = match self.source_address {
+			Some(source) => {
+				let socket = TcpSocket::new_v4()?;
+				socket.bind(SocketAddr::from((source, 0)))?;
+				tokio::time::timeout(timeout, socket.connect(SocketAddr::V4(self.socket))).await??
+			}
+			None => tokio::time::timeout(timeout, TcpStream::connect(&self.socket)).await??,
+		};
+
+		Ok(stream)
+	}
+
+	/// Only checks that the port accepts connections, nothing is sent
+	pub async fn probe(&self, timeout: Duration) -> Result<(), RunError> {
+		self.connect_tcp(timeout).await?;
+		Ok(())
+	}
+
+	async fn connect(&self) -> Result<TcpStream, RunError> {
+		let mut stream = self.connect_tcp(self.connect_timeout).await?;
+
+		// Load balancers expecting a PROXY header drop or garble anything else
+		if let Some(version) = self.proxy_protocol {
+			let source = match stream.local_addr()? {
+				SocketAddr::V4(addr) => addr,
+				SocketAddr::V6(_) => return Err(RunError::MalformedResponse),
+			};
+			stream.write_all(&proxy_header(version, source, self.socket)).await?;
+		}
+
+		Ok(stream)
+	}
+
+	fn read_tcp_fingerprint(&self, stream: &TcpStream) -> Option<TcpFingerprint> {
+		if !self.tcp_fingerprint {
+			return None;
+		}
+
+		let fingerprint = TcpFingerprint::read(stream);
+		if fingerprint.is_none() {
+			debug!("[{}] TCP handshake details aren't available", self.socket);
+		}
+		fingerprint
 	}
 
 	#[allow(dead_code)]
 	pub async fn simple_ping(&self) -> Result<String, RunError> {
-		let mut stream = tokio::time::timeout(
-			crate::scanner::TIMEOUT_SECS,
-			TcpStream::connect(&self.socket),
-		)
-		.await??;
+		let mut stream = self.connect().await?;
 
 		stream.write_all(&SIMPLE_PAYLOAD).await?;
 		let mut response = [0; 1024];
@@ -52,18 +255,21 @@ impl PingableServer {
 		}
 
 		// Packet length
-		index += decode_varint(&response).1;
+		index += varint::decode_from_slice(&response[..total_read_bytes])
+			.map_err(|_| RunError::MalformedResponse)?
+			.1;
 
 		// Since Packet ID should always be 0 and will never take more than 1 byte to encode
 		// We can ignore it entirely and just advance the index by 1
 		index += 1;
 
 		// Decode the string length
-		let (string_length, string_length_bytes) = decode_varint(&response[index as usize..]);
+		let (string_length, string_length_bytes) = varint::decode_from_slice(&response[index.min(total_read_bytes)..total_read_bytes])
+			.map_err(|_| RunError::MalformedResponse)?;
 		index += string_length_bytes;
 
 		// Error checking
-		if string_length == 0 || string_length > 32767 {
+		if string_length <= 0 || string_length > 32767 {
 			debug!(
 				"[{}] String length: {string_length} was either 0 or too long",
 				&self.socket.ip()
@@ -76,8 +282,8 @@ impl PingableServer {
 		// program by attempting to allocate insane amounts of memory this way.
 		//
 		// Adds everything we have read so far minus the packet ID and packet length to a new vec
-		let mut output = Vec::from(&response[index as usize..total_read_bytes]);
-		let string_length = string_length + index as usize;
+		let mut output = Vec::from(&response[index..total_read_bytes]);
+		let string_length = string_length as usize + index;
 
 		if total_read_bytes > string_length {
 			debug!(
@@ -97,12 +303,11 @@ impl PingableServer {
 		Ok(String::from_utf8_lossy(&output).into_owned())
 	}
 
-	pub async fn legacy_ping(&self) -> Result<String, RunError> {
-		let mut stream = tokio::time::timeout(
-			crate::scanner::TIMEOUT_SECS,
-			TcpStream::connect(&self.socket),
-		)
-		.await??;
+	pub async fn legacy_ping(&self) -> Result<StatusResponse, RunError> {
+		let started = Instant::now();
+		let mut stream = self.connect().await?;
+		let connect_time = started.elapsed();
+		let tcp_fingerprint = self.read_tcp_fingerprint(&stream);
 
 		// Legacy Server List Ping (1.6+)
 		// Send 0xFE (Packet ID) 0x01 (Payload)
@@ -111,125 +316,47 @@ impl PingableServer {
 		let mut buffer = Vec::new();
 		// Legacy responses are usually small, but let's read enough
 		let mut temp_buf = [0u8; 1024];
-		let n = stream.read(&mut temp_buf).await?;
+		let n = tokio::time::timeout(self.read_timeout, stream.read(&mut temp_buf)).await??;
 		buffer.extend_from_slice(&temp_buf[..n]);
 
-		if buffer.is_empty() {
-			return Err(RunError::MalformedResponse);
-		}
-
-		// Packet ID for Kick (0xFF)
-		if buffer[0] != 0xFF {
-			return Err(RunError::MalformedResponse);
-		}
-
-		if buffer.len() < 3 {
-			return Err(RunError::MalformedResponse);
-		}
-
-		// Read length (Big Endian Short)
-		let len = ((buffer[1] as u16) << 8) | (buffer[2] as u16);
-		let byte_len = (len * 2) as usize;
-
-		if buffer.len() < 3 + byte_len {
-			return Err(RunError::MalformedResponse);
-		}
-
-		// Extract UTF-16BE bytes
-		let utf16_bytes = &buffer[3..3 + byte_len];
-		
-		// Convert to u16 vec
-		let utf16_vec: Vec<u16> = utf16_bytes
-			.chunks_exact(2)
-			.map(|chunk| ((chunk[0] as u16) << 8) | (chunk[1] as u16))
-			.collect();
-
-		let response_str = String::from_utf16(&utf16_vec)
-			.map_err(|_| RunError::MalformedResponse)?;
-
-		// Format: §1\0<Protocol>\0<Version>\0<MOTD>\0<Online>\0<Max>
-		// First check if it starts with §1\0 (Protocol 1.6+)
-		if response_str.starts_with("§1\0") {
-			let parts: Vec<&str> = response_str.split('\0').collect();
-			if parts.len() >= 6 {
-				// parts[0] is "§1"
-				let protocol = parts[1].parse::<i32>().unwrap_or(0);
-				let version = parts[2];
-				let motd = parts[3];
-				let online = parts[4].parse::<i32>().unwrap_or(0);
-				let max = parts[5].parse::<i32>().unwrap_or(0);
-
-				let json_resp = json!({
-					"version": {
-						"name": version,
-						"protocol": protocol
-					},
-					"players": {
-						"max": max,
-						"online": online,
-						"sample": []
-					},
-					"description": {
-						"text": motd
-					}
-				});
-
-				return Ok(json_resp.to_string());
-			}
-		} else {
-			// Older format (1.4-1.5): <MOTD>§<Online>§<Max>
-			let parts: Vec<&str> = response_str.split('§').collect();
-			if parts.len() >= 3 {
-				let motd = parts[0];
-				let online = parts[1].parse::<i32>().unwrap_or(0);
-				let max = parts[2].parse::<i32>().unwrap_or(0);
-
-				let json_resp = json!({
-					"version": {
-						"name": "Legacy < 1.6",
-						"protocol": 0
-					},
-					"players": {
-						"max": max,
-						"online": online,
-						"sample": []
-					},
-					"description": {
-						"text": motd
-					}
-				});
-				
-				return Ok(json_resp.to_string());
-			}
-		}
-
-		Err(RunError::MalformedResponse)
+		// The legacy protocol has no Ping/Pong, the kick packet is all there is
+		Ok(StatusResponse {
+			json: parse_legacy_response(&buffer)?,
+			connect_time,
+			round_trip: None,
+			tcp_fingerprint,
+		})
 	}
 
-	pub async fn proper_ping(&self) -> Result<String, RunError> {
-		let mut stream = tokio::time::timeout(
-			crate::scanner::TIMEOUT_SECS,
-			TcpStream::connect(&self.socket),
-		)
-		.await??;
+	pub async fn proper_ping(&self) -> Result<StatusResponse, RunError> {
+		let started = Instant::now();
+		let mut stream = self.connect().await?;
+		let connect_time = started.elapsed();
+		let tcp_fingerprint = self.read_tcp_fingerprint(&stream);
 
 		// --- Handshake Packet ---
 		// Packet ID: 0x00
-		// Protocol Version (VarInt): -1 or 47 (1.8) or anything. Let's use 47.
+		// Protocol Version (VarInt): -1 or 47 (1.8) or anything. Configurable, defaults to 47.
 		// Server Address (String)
 		// Server Port (Unsigned Short)
 		// Next State (VarInt): 1 (Status)
 
 		let mut handshake = Vec::new();
-		write_varint(&mut handshake, 0x00); // Packet ID
-		write_varint(&mut handshake, 47);   // Protocol Version (1.8)
-		write_string(&mut handshake, &self.socket.ip().to_string()); // Host
+		varint::encode(&mut handshake, 0x00); // Packet ID
+		varint::encode(&mut handshake, self.handshake_protocol); // Protocol Version
+		let host = self.handshake_host.clone().unwrap_or_else(|| self.socket.ip().to_string());
+		write_string(&mut handshake, &host); // Host
 		handshake.extend_from_slice(&self.socket.port().to_be_bytes()); // Port
-		write_varint(&mut handshake, 1);    // Next State: Status
+		varint::encode(&mut handshake, 1);    // Next State: Status
 
 		// Send Handshake
 		write_packet(&mut stream, handshake).await?;
 
+		if !self.handshake_jitter.is_zero() {
+			let delay = rand::thread_rng().gen_range(Duration::ZERO..=self.handshake_jitter);
+			tokio::time::sleep(delay).await;
+		}
+
 		// --- Request Packet ---
 		// Packet ID: 0x00
 		// Empty body
@@ -240,122 +367,787 @@ impl PingableServer {
 		// Packet ID (VarInt) should be 0x00
 		// JSON String (String)
 
-		// We need to read VarInts one byte at a time to know the length
-		let _packet_len = read_varint_from_stream(&mut stream).await?;
-		let packet_id = read_varint_from_stream(&mut stream).await?;
+		// Check the first bytes before decoding anything, other services
+		// listening on the port would otherwise just look malformed
+		// Every read shares one deadline, a timeout per read would let a slow server
+		// keep the connection alive by sending a byte at a time
+		let deadline = Instant::now() + self.read_timeout;
 
-		if packet_id != 0x00 {
-			debug!("[{}] Expected packet ID 0x00 for response, got {}", self.socket, packet_id);
-			return Err(RunError::MalformedResponse);
+		let mut first_bytes = [0u8; 8];
+		let peeked = timeout_at(deadline, stream.peek(&mut first_bytes)).await??;
+		if let Some(protocol) = identify_non_minecraft(&first_bytes[..peeked]) {
+			debug!("[{}] Response looks like {}, not Minecraft", self.socket, protocol);
+			return Err(RunError::NotMinecraft(protocol));
 		}
 
-		// The remaining length in the packet is for the string
-		// Since we already read packet_id (likely 1 byte), we need to calculate remaining bytes
-		// But wait, read_varint_from_stream consumes bytes from the stream.
-		// The `packet_len` includes the length of Packet ID + Data.
-		// We need to know how many bytes `packet_id` took.
-		// Let's refactor to read the whole packet data into a buffer based on packet_len.
-		
-		// Actually, reading string is safer if we just read string length first.
-		// The standard Read String format is: Length (VarInt) + UTF-8 Bytes.
-		
-		let json_len = read_varint_from_stream(&mut stream).await?;
-		
-		// Sanity check
-		if json_len == 0 || json_len > 32767 * 4 { // *4 for safety margin on wide chars
-	            // Basic sanity check, strict limit is usually 32767 chars
-	            // but let's trust the varint length for now as long as it fits in memory
+		// VarInts are decoded a byte at a time, buffering turns that into one read per
+		// segment the server sends instead of a syscall per byte
+		let mut stream = BufReader::new(stream);
+		let json = read_status_response(&mut stream, deadline, self.max_status_bytes).await?;
+		let round_trip = ping_pong(&mut stream, deadline).await;
+
+		Ok(StatusResponse {
+			json,
+			connect_time,
+			round_trip,
+			tcp_fingerprint,
+		})
+	}
+
+	/// Starts a login as `username` with the handshake claiming `protocol`, which should be
+	/// the server's own since anything else is kicked as outdated, and classifies the first
+	/// answer. The connection is dropped there, an offline mode server has let the username
+	/// in by then but never sees it spawn
+	pub async fn login_probe(&self, protocol: i32, username: &str) -> Result<LoginStatus, RunError> {
+		let mut stream = self.connect().await?;
+
+		let mut handshake = Vec::new();
+		varint::encode(&mut handshake, 0x00); // Packet ID
+		varint::encode(&mut handshake, protocol); // Protocol Version
+		let host = self.handshake_host.clone().unwrap_or_else(|| self.socket.ip().to_string());
+		write_string(&mut handshake, &host); // Host
+		handshake.extend_from_slice(&self.socket.port().to_be_bytes()); // Port
+		varint::encode(&mut handshake, 2); // Next State: Login
+
+		write_packet(&mut stream, handshake).await?;
+		write_packet(&mut stream, login_start(protocol, username)).await?;
+
+		let deadline = Instant::now() + self.read_timeout;
+		let mut stream = BufReader::new(stream);
+		read_login_response(&mut stream, deadline, self.max_status_bytes).await
+	}
+}
+
+/// Builds a Login Start packet, its fields changed a few times during 1.19 and 1.20
+fn login_start(protocol: i32, username: &str) -> Vec<u8> {
+	let mut packet = vec![0x00];
+	write_string(&mut packet, username);
+
+	match protocol {
+		// 1.19: Has Sig Data
+		759 => packet.push(0),
+		// 1.19.1 and 1.19.2: Has Sig Data, Has Player UUID
+		760 => packet.extend_from_slice(&[0, 0]),
+		// 1.19.3 to 1.20.1: Has Player UUID
+		761..=763 => packet.push(0),
+		// 1.20.2 onwards: Player UUID, offline mode servers make their own
+		764.. => packet.extend_from_slice(&[0; 16]),
+		_ => {}
+	}
+
+	packet
+}
+
+/// Reads the first packet of the login sequence and classifies it
+async fn read_login_response<R: AsyncRead + Unpin>(
+	stream: &mut R,
+	deadline: Instant,
+	max_bytes: usize,
+) -> Result<LoginStatus, RunError> {
+	let (packet_len, _) = timeout_at(deadline, read_length_from_stream(stream)).await??;
+	let (packet_id, packet_id_bytes) = timeout_at(deadline, varint::decode_from_reader(stream)).await??;
+
+	match packet_id {
+		// Disconnect, the reason is a chat component as JSON
+		0x00 => {
+			let remaining = packet_len.checked_sub(packet_id_bytes).ok_or(RunError::MalformedResponse)?;
+			let reason = read_packet_string(stream, deadline, remaining, max_bytes).await?;
+			debug!("Login disconnected: {}", reason);
+			Ok(classify_disconnect(&reason))
 		}
+		// Encryption Request
+		0x01 => Ok(LoginStatus::OnlineMode),
+		// Login Success, or Set Compression which online mode servers only send after encryption
+		0x02 | 0x03 => Ok(LoginStatus::OfflineAccessible),
+		// Login Plugin Request
+		0x04 => Ok(LoginStatus::LoginPlugin),
+		_ => {
+			debug!("Unexpected packet ID {} during login", packet_id);
+			Err(RunError::MalformedResponse)
+		}
+	}
+}
+
+/// Sorts a login disconnect reason by what it's about. Vanilla sends translation keys like
+/// `multiplayer.disconnect.not_whitelisted`, plugins send their own text
+fn classify_disconnect(reason: &str) -> LoginStatus {
+	let reason = reason.to_lowercase();
+	let mentions = |words: &[&str]| words.iter().any(|word| reason.contains(word));
+
+	if mentions(&["whitelist", "white-list", "white list"]) {
+		LoginStatus::Whitelisted
+	} else if mentions(&["server_full", "server is full", "full server"]) {
+		LoginStatus::Full
+	} else if mentions(&["banned"]) {
+		LoginStatus::Banned
+	} else if mentions(&["outdated", "incompatible"]) {
+		LoginStatus::WrongVersion
+	} else {
+		LoginStatus::Disconnected
+	}
+}
+
+/// Sends a Ping packet after the status and times the matching Pong. Plenty of servers
+/// close the connection or never answer instead, that just leaves the round trip unknown
+async fn ping_pong<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, deadline: Instant) -> Option<Duration> {
+	let payload: i64 = rand::thread_rng().gen();
+	let mut ping = vec![0x01];
+	ping.extend_from_slice(&payload.to_be_bytes());
 
-	       // Read the JSON string bytes
-	       let mut json_buffer = vec![0u8; json_len];
-	       stream.read_exact(&mut json_buffer).await?;
-	       
-	       let json_str = String::from_utf8_lossy(&json_buffer).into_owned();
-
-	       // --- Ping Packet (Optional for basic status, but good for latency check) ---
-	       // We could send Ping (0x01) here, but we already have the JSON.
-	       // The scanner only needs the JSON description.
-	       // "proper_ping" usually implies the full sequence, but for getting info,
-	       // Request->Response is enough. The TODO said "Handshake -> Request -> Ping".
-	       // Let's add the Ping/Pong for completeness if needed, but returning the JSON is the goal.
-	       
-	       // If we want to measure latency, we would do the ping.
-	       // But the function returns Result<String, ...>, implying it just wants the JSON.
-	       // So we can stop here.
-
-		Ok(json_str)
+	let sent = Instant::now();
+	let deadline = deadline.min(sent + PONG_TIMEOUT);
+	write_packet(stream, ping).await.ok()?;
+
+	let (packet_len, _) = timeout_at(deadline, read_length_from_stream(stream)).await.ok()?.ok()?;
+	let (packet_id, _) = timeout_at(deadline, varint::decode_from_reader(stream)).await.ok()?.ok()?;
+	if packet_len != 9 || packet_id != 0x01 {
+		debug!("Expected a Pong, got packet {} of length {}", packet_id, packet_len);
+		return None;
 	}
+
+	let mut echoed = [0u8; 8];
+	timeout_at(deadline, stream.read_exact(&mut echoed)).await.ok()?.ok()?;
+	(i64::from_be_bytes(echoed) == payload).then(|| sent.elapsed())
 }
 
-fn write_varint(buf: &mut Vec<u8>, value: i32) {
-	let mut u_val = value as u32;
-	loop {
-		let mut temp = (u_val & 0x7F) as u8;
-		u_val >>= 7;
-		if u_val != 0 {
-			temp |= 0x80;
+/// Converts a legacy kick packet (0xFF) into the same JSON a modern status response uses
+fn parse_legacy_response(buffer: &[u8]) -> Result<String, RunError> {
+	if buffer.is_empty() {
+		return Err(RunError::MalformedResponse);
+	}
+
+	// Packet ID for Kick (0xFF)
+	if buffer[0] != 0xFF {
+		if let Some(protocol) = identify_non_minecraft(buffer) {
+			return Err(RunError::NotMinecraft(protocol));
 		}
-		buf.push(temp);
-		if u_val == 0 {
-			break;
+		return Err(RunError::MalformedResponse);
+	}
+
+	if buffer.len() < 3 {
+		return Err(RunError::MalformedResponse);
+	}
+
+	// String length in UTF-16 code units (Big Endian Short)
+	let len = u16::from_be_bytes([buffer[1], buffer[2]]) as usize;
+	let byte_len = len * 2;
+
+	if buffer.len() < 3 + byte_len {
+		return Err(RunError::MalformedResponse);
+	}
+
+	// Extract UTF-16BE bytes
+	let utf16_vec: Vec<u16> = buffer[3..3 + byte_len]
+		.chunks_exact(2)
+		.map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]))
+		.collect();
+
+	let response_str = String::from_utf16(&utf16_vec).map_err(|_| RunError::MalformedResponse)?;
+
+	let (protocol, version, motd, online, max) = if let Some(fields) = response_str.strip_prefix("§1\0") {
+		// Format (1.6+): §1\0<Protocol>\0<Version>\0<MOTD>\0<Online>\0<Max>
+		// The counts are taken from the end so a MOTD containing \0 stays intact
+		let mut head = fields.splitn(3, '\0');
+		let (Some(protocol), Some(version), Some(rest)) = (head.next(), head.next(), head.next()) else {
+			return Err(RunError::MalformedResponse);
+		};
+
+		let mut tail = rest.rsplitn(3, '\0');
+		let (Some(max), Some(online), Some(motd)) = (tail.next(), tail.next(), tail.next()) else {
+			return Err(RunError::MalformedResponse);
+		};
+
+		(protocol.parse::<i32>().unwrap_or(0), version, motd, online, max)
+	} else {
+		// Older format (1.4-1.5): <MOTD>§<Online>§<Max>
+		// Split from the end, MOTDs can contain § themselves
+		let mut tail = response_str.rsplitn(3, '§');
+		let (Some(max), Some(online), Some(motd)) = (tail.next(), tail.next(), tail.next()) else {
+			return Err(RunError::MalformedResponse);
+		};
+
+		(0, "Legacy < 1.6", motd, online, max)
+	};
+
+	let json_resp = json!({
+		"version": {
+			"name": version,
+			"protocol": protocol
+		},
+		"players": {
+			// Parsed wider than the i32 a count is, so the lenient parse clamps overflows instead of zeroing them
+			"max": max.parse::<i64>().unwrap_or(0),
+			"online": online.parse::<i64>().unwrap_or(0),
+			"sample": []
+		},
+		"description": {
+			"text": motd
+		}
+	});
+
+	Ok(json_resp.to_string())
+}
+
+/// Builds a PROXY protocol header for a TCP over IPv4 connection
+fn proxy_header(version: ProxyProtocol, source: SocketAddrV4, destination: SocketAddrV4) -> Vec<u8> {
+	match version {
+		ProxyProtocol::V1 => format!(
+			"PROXY TCP4 {} {} {} {}\r\n",
+			source.ip(),
+			destination.ip(),
+			source.port(),
+			destination.port()
+		)
+		.into_bytes(),
+		ProxyProtocol::V2 => {
+			let mut header = PROXY_V2_SIGNATURE.to_vec();
+			header.push(0x21); // Version 2, PROXY command
+			header.push(0x11); // TCP over IPv4
+			header.extend_from_slice(&12u16.to_be_bytes()); // Address block length
+			header.extend_from_slice(&source.ip().octets());
+			header.extend_from_slice(&destination.ip().octets());
+			header.extend_from_slice(&source.port().to_be_bytes());
+			header.extend_from_slice(&destination.port().to_be_bytes());
+			header
 		}
 	}
 }
 
+/// Recognizes common non-Minecraft services from the first bytes they send
+fn identify_non_minecraft(bytes: &[u8]) -> Option<&'static str> {
+	if bytes.starts_with(b"HTTP/") {
+		Some("HTTP")
+	} else if bytes.starts_with(b"SSH-") {
+		Some("SSH")
+	} else if bytes.len() >= 2 && (0x14..=0x17).contains(&bytes[0]) && bytes[1] == 0x03 {
+		// TLS record header: content type followed by the major version
+		Some("TLS")
+	} else if bytes.starts_with(b"220 ") || bytes.starts_with(b"220-") {
+		Some("FTP/SMTP")
+	} else {
+		None
+	}
+}
+
+/// Reads a status response packet and returns the JSON string inside it. Strings longer
+/// than `max_bytes` are rejected before the buffer for them is allocated
+async fn read_status_response<R: AsyncRead + Unpin>(
+	stream: &mut R,
+	deadline: Instant,
+	max_bytes: usize,
+) -> Result<String, RunError> {
+	// We need to read VarInts one byte at a time to know the length
+	let (packet_len, _) = timeout_at(deadline, read_length_from_stream(stream)).await??;
+	let (packet_id, packet_id_bytes) = timeout_at(deadline, varint::decode_from_reader(stream)).await??;
+
+	if packet_id != 0x00 {
+		debug!("Expected packet ID 0x00 for response, got {}", packet_id);
+		return Err(RunError::MalformedResponse);
+	}
+
+	let remaining = packet_len.checked_sub(packet_id_bytes).ok_or(RunError::MalformedResponse)?;
+	read_packet_string(stream, deadline, remaining, max_bytes).await
+}
+
+/// Reads a string that should fill the `remaining` bytes of a packet. Strings longer than
+/// `max_bytes` are rejected before the buffer for them is allocated
+async fn read_packet_string<R: AsyncRead + Unpin>(
+	stream: &mut R,
+	deadline: Instant,
+	remaining: usize,
+	max_bytes: usize,
+) -> Result<String, RunError> {
+	// The standard Read String format is: Length (VarInt) + UTF-8 Bytes.
+	let (json_len, json_len_bytes) = timeout_at(deadline, read_length_from_stream(stream)).await??;
+
+	// `remaining` covers the string length and the string itself.
+	// Some modded proxies declare a string longer than the packet that carries it,
+	// trusting the string length would block on read_exact until the timeout
+	let remaining = remaining.checked_sub(json_len_bytes).ok_or(RunError::MalformedResponse)?;
+
+	if json_len > remaining {
+		debug!("String length {} exceeds the {} bytes left in the packet", json_len, remaining);
+		return Err(RunError::MalformedResponse);
+	}
+
+	// Like in `simple_ping`, the declared size can't be trusted. A length that does fit
+	// in the declared packet would otherwise still allocate whatever the server asks for
+	if json_len > max_bytes {
+		debug!("String length {} is over the {} byte limit", json_len, max_bytes);
+		return Err(RunError::MalformedResponse);
+	}
+
+	// Read the JSON string bytes
+	let mut json_buffer = vec![0u8; json_len];
+	timeout_at(deadline, stream.read_exact(&mut json_buffer)).await??;
+
+	Ok(String::from_utf8_lossy(&json_buffer).into_owned())
+}
+
 fn write_string(buf: &mut Vec<u8>, s: &str) {
 	   let bytes = s.as_bytes();
-	   write_varint(buf, bytes.len() as i32);
+	   varint::encode(buf, bytes.len() as i32);
 	   buf.extend_from_slice(bytes);
 }
 
-async fn write_packet(stream: &mut TcpStream, data: Vec<u8>) -> Result<(), std::io::Error> {
+async fn write_packet<W: AsyncWrite + Unpin>(stream: &mut W, data: Vec<u8>) -> Result<(), std::io::Error> {
 	   let mut len_buf = Vec::new();
-	   write_varint(&mut len_buf, data.len() as i32);
+	   varint::encode(&mut len_buf, data.len() as i32);
 	   stream.write_all(&len_buf).await?;
 	   stream.write_all(&data).await?;
 	   Ok(())
 }
 
-async fn read_varint_from_stream(stream: &mut TcpStream) -> Result<usize, std::io::Error> {
-	   let mut value: usize = 0;
-	   let mut count: u8 = 0;
-	   loop {
-	       let mut buf = [0u8; 1];
-	       stream.read_exact(&mut buf).await?;
-	       let b = buf[0];
-	       
-	       value |= ((b & 0x7F) as usize) << count;
-	       
-	       if (b & 0x80) == 0 {
-	           break;
-	       }
-	       
-	       count += 7;
-	       if count >= 35 {
-	            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "VarInt too big"));
-	       }
-	   }
-	   Ok(value)
+/// Reads a VarInt holding a length, negative lengths are rejected
+async fn read_length_from_stream<R: AsyncRead + Unpin>(stream: &mut R) -> Result<(usize, usize), std::io::Error> {
+	let (value, bytes) = varint::decode_from_reader(stream).await?;
+	let value = usize::try_from(value)
+		.map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "negative length"))?;
+	Ok((value, bytes))
 }
 
-// returns the decoded varint and how many bytes were read
-#[allow(dead_code)]
-#[inline(always)]
-fn decode_varint(bytes: &[u8]) -> (usize, u8) {
-	let mut value: usize = 0;
-	let mut count: u8 = 0;
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio::net::TcpListener;
+
+	fn far_deadline() -> Instant {
+		Instant::now() + Duration::from_secs(60)
+	}
+
+	/// Serves `response` to a single connection on a random local port
+	async fn serve_once(response: Vec<u8>) -> SocketAddrV4 {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let SocketAddr::V4(address) = listener.local_addr().unwrap() else {
+			unreachable!()
+		};
+
+		tokio::spawn(async move {
+			let (mut stream, _) = listener.accept().await.unwrap();
+
+			// Wait for the client's request so closing doesn't reset the connection
+			let mut request = [0u8; 1024];
+			let _ = stream.read(&mut request).await;
+
+			let _ = stream.write_all(&response).await;
+			let _ = stream.shutdown().await;
 
-	for b in bytes {
-		value |= ((b & 0x7F) as usize) << count;
+			// Drain until the client hangs up
+			let _ = stream.read_to_end(&mut Vec::new()).await;
+		});
 
-		// right shift 7 times, if resulting value is 0 it means this is the end of the varint
-		if (b >> 7) != 1 {
-			break;
+		address
+	}
+
+	/// Frames a JSON string as a status response packet
+	fn status_packet(packet_id: i32, json: &str) -> Vec<u8> {
+		let mut data = Vec::new();
+		varint::encode(&mut data, packet_id);
+		write_string(&mut data, json);
+
+		let mut packet = Vec::new();
+		varint::encode(&mut packet, data.len() as i32);
+		packet.extend_from_slice(&data);
+		packet
+	}
+
+	/// Builds a legacy kick packet carrying a UTF-16BE string
+	fn legacy_packet(message: &str) -> Vec<u8> {
+		let utf16: Vec<u16> = message.encode_utf16().collect();
+		let mut packet = vec![0xFF];
+		packet.extend_from_slice(&(utf16.len() as u16).to_be_bytes());
+		for unit in utf16 {
+			packet.extend_from_slice(&unit.to_be_bytes());
 		}
+		packet
+	}
+
+	fn parse_legacy(message: &str) -> serde_json::Value {
+		serde_json::from_str(&parse_legacy_response(&legacy_packet(message)).unwrap()).unwrap()
+	}
 
-		count += 7;
+	#[test]
+	fn test_legacy_captured_responses() {
+		// 1.4.7 and 1.5.2 answer 0xFE 0x01 with the 1.6 format
+		let value = parse_legacy("§1\u{0}51\u{0}1.4.7\u{0}A Minecraft Server\u{0}0\u{0}20");
+		assert_eq!(value["version"]["name"], "1.4.7");
+		assert_eq!(value["version"]["protocol"], 51);
+
+		let value = parse_legacy("§1\u{0}61\u{0}1.5.2\u{0}§6Survival §7- §aWhitelisted\u{0}3\u{0}50");
+		assert_eq!(value["version"]["protocol"], 61);
+		assert_eq!(value["description"]["text"], "§6Survival §7- §aWhitelisted");
+		assert_eq!(value["players"]["online"], 3);
+		assert_eq!(value["players"]["max"], 50);
+
+		let value = parse_legacy("§1\u{0}78\u{0}1.6.4\u{0}A Minecraft Server\u{0}1\u{0}20");
+		assert_eq!(value["version"]["name"], "1.6.4");
+		assert_eq!(value["players"]["online"], 1);
+
+		// 1.7+ servers still answer legacy pings, with their netty protocol number
+		let value = parse_legacy("§1\u{0}127\u{0}1.7.10\u{0}A Minecraft Server\u{0}0\u{0}20");
+		assert_eq!(value["version"]["name"], "1.7.10");
+		assert_eq!(value["version"]["protocol"], 127);
+
+		// Beta 1.8 to 1.3 only send the MOTD and counts
+		let value = parse_legacy("A Minecraft Server§0§20");
+		assert_eq!(value["version"]["name"], "Legacy < 1.6");
+		assert_eq!(value["players"]["max"], 20);
+	}
+
+	#[test]
+	fn test_legacy_adversarial_responses() {
+		// MOTDs containing the delimiters
+		let value = parse_legacy("Best § server§4§100");
+		assert_eq!(value["description"]["text"], "Best § server");
+		assert_eq!(value["players"]["online"], 4);
+		assert_eq!(value["players"]["max"], 100);
+
+		let value = parse_legacy("§1\u{0}78\u{0}1.6.4\u{0}Line\u{0}break\u{0}2\u{0}10");
+		assert_eq!(value["description"]["text"], "Line\u{0}break");
+		assert_eq!(value["players"]["online"], 2);
+
+		// Empty and non-numeric fields
+		let value = parse_legacy("§1\u{0}\u{0}\u{0}\u{0}\u{0}");
+		assert_eq!(value["version"]["protocol"], 0);
+		assert_eq!(value["description"]["text"], "");
+
+		let value = parse_legacy("§1\u{0}78\u{0}1.6.4\u{0}MOTD\u{0}lots\u{0}-");
+		assert_eq!(value["players"]["online"], 0);
+		assert_eq!(value["players"]["max"], 0);
+
+		// Not enough fields, a length longer than the data and odd garbage
+		assert!(parse_legacy_response(&legacy_packet("Only a MOTD")).is_err());
+		assert!(parse_legacy_response(&legacy_packet("§1\u{0}78\u{0}1.6.4\u{0}MOTD")).is_err());
+		assert!(parse_legacy_response(&[0xFF, 0xFF, 0xFF, 0x00, 0x41]).is_err());
+		assert!(parse_legacy_response(&[0xFF]).is_err());
+		assert!(parse_legacy_response(&[]).is_err());
+	}
+
+	#[tokio::test]
+	async fn test_harness_modern_status() {
+		let json = r#"{"version":{"name":"1.20.4","protocol":765},"players":{"max":20,"online":3},"description":"Hello"}"#;
+		let socket = serve_once(status_packet(0x00, json)).await;
+
+		let response = PingableServer::new(socket).proper_ping().await.unwrap();
+		assert_eq!(response.json, json);
+		// The harness hangs up instead of answering the Ping
+		assert_eq!(response.round_trip, None);
+	}
+
+	#[tokio::test]
+	async fn test_harness_ping_pong() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let SocketAddr::V4(socket) = listener.local_addr().unwrap() else {
+			unreachable!()
+		};
+
+		tokio::spawn(async move {
+			let (mut stream, _) = listener.accept().await.unwrap();
+
+			// The handshake ends with the next state, the request is an empty packet 0x00
+			let mut request = Vec::new();
+			while !request.ends_with(&[0x01, 0x00]) {
+				let mut buffer = [0u8; 1024];
+				let n = stream.read(&mut buffer).await.unwrap();
+				request.extend_from_slice(&buffer[..n]);
+			}
+			stream.write_all(&status_packet(0x00, r#"{"description":"pong"}"#)).await.unwrap();
+
+			// Length, packet ID and the 8 byte payload, echoed back unchanged
+			let mut ping = [0u8; 10];
+			stream.read_exact(&mut ping).await.unwrap();
+			stream.write_all(&ping).await.unwrap();
+		});
+
+		let response = PingableServer::new(socket).proper_ping().await.unwrap();
+		assert_eq!(response.json, r#"{"description":"pong"}"#);
+		assert!(response.round_trip.is_some());
+	}
+
+	#[tokio::test]
+	async fn test_harness_segmented_response() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let SocketAddr::V4(socket) = listener.local_addr().unwrap() else {
+			unreachable!()
+		};
+
+		// Every byte of the status and the Pong goes out in its own segment
+		tokio::spawn(async move {
+			let (mut stream, _) = listener.accept().await.unwrap();
+			stream.set_nodelay(true).unwrap();
+			let _ = stream.read(&mut [0u8; 1024]).await;
+
+			async fn write_bytes(stream: &mut TcpStream, bytes: &[u8]) {
+				for byte in bytes {
+					stream.write_all(&[*byte]).await.unwrap();
+					tokio::time::sleep(Duration::from_millis(1)).await;
+				}
+			}
+
+			write_bytes(&mut stream, &status_packet(0x00, r#"{"description":"one byte at a time"}"#)).await;
+			let mut ping = [0u8; 10];
+			stream.read_exact(&mut ping).await.unwrap();
+			write_bytes(&mut stream, &ping).await;
+		});
+
+		let response = PingableServer::new(socket).proper_ping().await.unwrap();
+		assert_eq!(response.json, r#"{"description":"one byte at a time"}"#);
+		assert!(response.round_trip.is_some());
 	}
 
-	(value, (count / 7) + 1)
+	#[tokio::test]
+	async fn test_harness_slow_response() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let SocketAddr::V4(socket) = listener.local_addr().unwrap() else {
+			unreachable!()
+		};
+
+		// A valid response sent one byte every second
+		tokio::spawn(async move {
+			let (mut stream, _) = listener.accept().await.unwrap();
+			let _ = stream.read(&mut [0u8; 1024]).await;
+
+			for byte in status_packet(0x00, r#"{"description":"slow"}"#) {
+				if stream.write_all(&[byte]).await.is_err() {
+					break;
+				}
+				tokio::time::sleep(Duration::from_secs(1)).await;
+			}
+		});
+
+		let started = std::time::Instant::now();
+		let result = PingableServer::new(socket)
+			.with_read_timeout(Duration::from_millis(1500))
+			.proper_ping()
+			.await;
+
+		assert!(matches!(result, Err(RunError::TimedOut(_))));
+		assert!(started.elapsed() < Duration::from_secs(3));
+	}
+
+	#[tokio::test]
+	async fn test_harness_probe() {
+		let open = serve_once(Vec::new()).await;
+		assert!(PingableServer::new(open).probe(Duration::from_secs(1)).await.is_ok());
+
+		// Nothing listens on a port once its listener is dropped
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let SocketAddr::V4(closed) = listener.local_addr().unwrap() else {
+			unreachable!()
+		};
+		drop(listener);
+		assert!(PingableServer::new(closed).probe(Duration::from_secs(1)).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn test_harness_legacy_1_6() {
+		let socket = serve_once(legacy_packet("§1\u{0}78\u{0}1.6.4\u{0}A Minecraft Server\u{0}5\u{0}20")).await;
+
+		let response = PingableServer::new(socket).legacy_ping().await.unwrap();
+		let value: serde_json::Value = serde_json::from_str(&response.json).unwrap();
+		assert_eq!(value["version"]["name"], "1.6.4");
+		assert_eq!(value["version"]["protocol"], 78);
+		assert_eq!(value["description"]["text"], "A Minecraft Server");
+		assert_eq!(value["players"]["online"], 5);
+		assert_eq!(value["players"]["max"], 20);
+	}
+
+	#[tokio::test]
+	async fn test_harness_legacy_pre_1_6() {
+		let socket = serve_once(legacy_packet("A Minecraft Server§5§20")).await;
+
+		let response = PingableServer::new(socket).legacy_ping().await.unwrap();
+		let value: serde_json::Value = serde_json::from_str(&response.json).unwrap();
+		assert_eq!(value["version"]["name"], "Legacy < 1.6");
+		assert_eq!(value["description"]["text"], "A Minecraft Server");
+		assert_eq!(value["players"]["online"], 5);
+		assert_eq!(value["players"]["max"], 20);
+	}
+
+	#[tokio::test]
+	async fn test_harness_wrong_packet_id() {
+		let socket = serve_once(status_packet(0x01, "{}")).await;
+
+		let result = PingableServer::new(socket).proper_ping().await;
+		assert!(matches!(result, Err(RunError::MalformedResponse)));
+	}
+
+	#[tokio::test]
+	async fn test_harness_truncated_status() {
+		let mut packet = status_packet(0x00, r#"{"version":{"name":"1.20.4","protocol":765}}"#);
+		packet.truncate(packet.len() / 2);
+		let socket = serve_once(packet).await;
+
+		let result = PingableServer::new(socket).proper_ping().await;
+		assert!(matches!(result, Err(RunError::IOError(_))));
+	}
+
+	#[tokio::test]
+	async fn test_harness_empty_response() {
+		let socket = serve_once(Vec::new()).await;
+		assert!(PingableServer::new(socket).proper_ping().await.is_err());
+
+		let socket = serve_once(Vec::new()).await;
+		let result = PingableServer::new(socket).legacy_ping().await;
+		assert!(matches!(result, Err(RunError::MalformedResponse)));
+	}
+
+	#[tokio::test]
+	async fn test_harness_legacy_missing_fields() {
+		let socket = serve_once(legacy_packet("§1\u{0}78\u{0}1.6.4")).await;
+
+		let result = PingableServer::new(socket).legacy_ping().await;
+		assert!(matches!(result, Err(RunError::MalformedResponse)));
+	}
+
+	/// Frames a login packet with a string payload, like Disconnect
+	fn login_packet(packet_id: i32, payload: &[u8]) -> Vec<u8> {
+		let mut data = Vec::new();
+		varint::encode(&mut data, packet_id);
+		data.extend_from_slice(payload);
+
+		let mut packet = Vec::new();
+		varint::encode(&mut packet, data.len() as i32);
+		packet.extend_from_slice(&data);
+		packet
+	}
+
+	#[tokio::test]
+	async fn test_harness_login_probe() {
+		let mut reason = Vec::new();
+		write_string(&mut reason, r#"{"translate":"multiplayer.disconnect.not_whitelisted"}"#);
+		let socket = serve_once(login_packet(0x00, &reason)).await;
+		let result = PingableServer::new(socket).login_probe(767, "ServerSeeker").await;
+		assert_eq!(result.unwrap(), LoginStatus::Whitelisted);
+
+		// Encryption Request, the contents don't matter
+		let socket = serve_once(login_packet(0x01, &[0x00, 0x00, 0x00])).await;
+		let result = PingableServer::new(socket).login_probe(767, "ServerSeeker").await;
+		assert_eq!(result.unwrap(), LoginStatus::OnlineMode);
+
+		// Set Compression
+		let socket = serve_once(login_packet(0x03, &[0x80, 0x02])).await;
+		let result = PingableServer::new(socket).login_probe(47, "ServerSeeker").await;
+		assert_eq!(result.unwrap(), LoginStatus::OfflineAccessible);
+	}
+
+	#[test]
+	fn test_classify_disconnect() {
+		assert_eq!(classify_disconnect(r#"{"translate":"multiplayer.disconnect.server_full"}"#), LoginStatus::Full);
+		assert_eq!(classify_disconnect(r#"{"text":"You are not white-listed on this server!"}"#), LoginStatus::Whitelisted);
+		assert_eq!(
+			classify_disconnect(r#"{"translate":"multiplayer.disconnect.banned_ip.reason","with":["Spam"]}"#),
+			LoginStatus::Banned
+		);
+		assert_eq!(
+			classify_disconnect(r#"{"translate":"multiplayer.disconnect.outdated_client","with":["1.21.1"]}"#),
+			LoginStatus::WrongVersion
+		);
+		assert_eq!(classify_disconnect(r#"{"text":"Urban bandwidth limits"}"#), LoginStatus::Disconnected);
+	}
+
+	#[test]
+	fn test_login_start() {
+		let name = [0x00, 4, b'S', b'e', b'e', b'k'];
+		assert_eq!(login_start(47, "Seek"), name);
+		assert_eq!(login_start(760, "Seek")[name.len()..], [0, 0]);
+		assert_eq!(login_start(763, "Seek")[name.len()..], [0]);
+		assert_eq!(login_start(767, "Seek").len(), name.len() + 16);
+	}
+
+	#[tokio::test]
+	async fn test_harness_http_service() {
+		let socket = serve_once(b"HTTP/1.1 400 Bad Request\r\n\r\n".to_vec()).await;
+
+		let result = PingableServer::new(socket).proper_ping().await;
+		assert!(matches!(result, Err(RunError::NotMinecraft("HTTP"))));
+	}
+
+	#[test]
+	fn test_negative_handshake_protocol() {
+		let mut buf = Vec::new();
+		varint::encode(&mut buf, -1);
+		assert_eq!(buf, [0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+
+		assert_eq!(varint::decode_from_slice(&buf), Ok((-1, 5)));
+	}
+
+	#[tokio::test]
+	async fn test_signed_varint_round_trip() {
+		for value in [0, 1, 127, 128, 25565, -1, i32::MIN, i32::MAX] {
+			let mut buf = Vec::new();
+			varint::encode(&mut buf, value);
+
+			assert_eq!(varint::decode_from_slice(&buf), Ok((value, buf.len())));
+
+			let mut stream = buf.as_slice();
+			assert_eq!(varint::decode_from_reader(&mut stream).await.unwrap(), (value, buf.len()));
+		}
+
+		// Negative numbers always need all 5 bytes
+		let mut buf = Vec::new();
+		varint::encode(&mut buf, i32::MIN);
+		assert_eq!(buf, [0x80, 0x80, 0x80, 0x80, 0x08]);
+	}
+
+	#[tokio::test]
+	async fn test_varint_limits() {
+		// A 5th byte with the continuation bit set can't be an i32
+		let mut stream: &[u8] = &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+		assert!(varint::decode_from_reader(&mut stream).await.is_err());
+
+		// The slice decoder stops after 5 bytes instead of shifting past 32 bits
+		assert_eq!(
+			varint::decode_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]),
+			Err(varint::VarIntError::TooBig)
+		);
+
+		// -1 is a fine protocol version but not a length
+		let mut stream: &[u8] = &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F];
+		assert!(read_length_from_stream(&mut stream).await.is_err());
+
+		let packet: &[u8] = &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0x00];
+		let mut stream = packet;
+		assert!(read_status_response(&mut stream, far_deadline(), DEFAULT_MAX_STATUS_BYTES).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn test_status_string_longer_than_packet() {
+		// Packet length 5: ID (1 byte) + string length (1 byte) + 3 bytes of string,
+		// but the string claims to be 100 bytes long
+		let packet: &[u8] = &[5, 0x00, 100, b'{', b'}', b' '];
+		let mut stream = packet;
+
+		let result = read_status_response(&mut stream, far_deadline(), DEFAULT_MAX_STATUS_BYTES).await;
+		assert!(matches!(result, Err(RunError::MalformedResponse)));
+	}
+
+	#[tokio::test]
+	async fn test_status_string_over_limit() {
+		// Packet and string both claim close to 2GiB, reading it would mean allocating all of it
+		let mut packet = Vec::new();
+		varint::encode(&mut packet, i32::MAX);
+		varint::encode(&mut packet, 0x00);
+		varint::encode(&mut packet, i32::MAX - 6);
+		packet.extend_from_slice(b"{}");
+		let mut stream = packet.as_slice();
+
+		let result = read_status_response(&mut stream, far_deadline(), DEFAULT_MAX_STATUS_BYTES).await;
+		assert!(matches!(result, Err(RunError::MalformedResponse)));
+
+		// The limit applies to responses that are actually sent too
+		let packet: &[u8] = &[4, 0x00, 2, b'{', b'}'];
+		let mut stream = packet;
+		let result = read_status_response(&mut stream, far_deadline(), 1).await;
+		assert!(matches!(result, Err(RunError::MalformedResponse)));
+	}
+
+	#[tokio::test]
+	async fn test_status_string_fits_packet() {
+		let packet: &[u8] = &[4, 0x00, 2, b'{', b'}'];
+		let mut stream = packet;
+
+		let result = read_status_response(&mut stream, far_deadline(), DEFAULT_MAX_STATUS_BYTES).await.unwrap();
+		assert_eq!(result, "{}");
+	}
 }
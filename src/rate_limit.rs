@@ -0,0 +1,100 @@
+use crate::config::RateLimitConfig;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Buckets are pruned once this many networks are being tracked
+const MAX_TRACKED_PREFIXES: usize = 100_000;
+
+/// Token bucket rate limiter keyed on the network prefix of each target, so a
+/// dense target range can't direct a flood of connections at one network
+#[derive(Debug)]
+pub struct PrefixRateLimiter {
+	rate: f64,
+	burst: f64,
+	mask: u32,
+	buckets: Mutex<HashMap<u32, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+	tokens: f64,
+	last_refill: Instant,
+}
+
+impl PrefixRateLimiter {
+	/// Returns None when rate limiting is disabled
+	pub fn new(config: &RateLimitConfig) -> Option<Self> {
+		if config.per_prefix_per_sec <= 0.0 {
+			return None;
+		}
+
+		let prefix_len = config.prefix_len.min(32) as u32;
+		Some(Self {
+			rate: config.per_prefix_per_sec,
+			burst: config.burst.max(1.0),
+			mask: u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0),
+			buckets: Mutex::new(HashMap::new()),
+		})
+	}
+
+	/// Waits until a connection to `address` is allowed
+	pub async fn acquire(&self, address: Ipv4Addr) {
+		while let Some(wait) = self.try_acquire(address) {
+			tokio::time::sleep(wait).await;
+		}
+	}
+
+	// Takes a token if one is available, otherwise returns how long until the next one
+	fn try_acquire(&self, address: Ipv4Addr) -> Option<Duration> {
+		let prefix = address.to_bits() & self.mask;
+		let now = Instant::now();
+		let mut buckets = self.buckets.lock().unwrap();
+
+		if buckets.len() >= MAX_TRACKED_PREFIXES {
+			// A bucket that has refilled completely behaves the same as a new one
+			let (rate, burst) = (self.rate, self.burst);
+			buckets.retain(|_, b| b.tokens + now.duration_since(b.last_refill).as_secs_f64() * rate < burst);
+		}
+
+		let bucket = buckets.entry(prefix).or_insert(Bucket {
+			tokens: self.burst,
+			last_refill: now,
+		});
+
+		let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+		bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+		bucket.last_refill = now;
+
+		if bucket.tokens >= 1.0 {
+			bucket.tokens -= 1.0;
+			None
+		} else {
+			Some(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_prefix_buckets() {
+		let limiter = PrefixRateLimiter::new(&RateLimitConfig {
+			per_prefix_per_sec: 1.0,
+			prefix_len: 24,
+			burst: 2.0,
+		})
+		.unwrap();
+
+		// The burst is shared by the whole /24
+		assert!(limiter.try_acquire(Ipv4Addr::new(10, 0, 0, 1)).is_none());
+		assert!(limiter.try_acquire(Ipv4Addr::new(10, 0, 0, 2)).is_none());
+		assert!(limiter.try_acquire(Ipv4Addr::new(10, 0, 0, 3)).is_some());
+
+		// Other networks have their own bucket
+		assert!(limiter.try_acquire(Ipv4Addr::new(10, 0, 1, 1)).is_none());
+	}
+}
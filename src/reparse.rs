@@ -0,0 +1,47 @@
+use crate::database::Database;
+use crate::response::Server;
+use futures_util::TryStreamExt;
+use serde_json::Value;
+use sqlx::types::ipnet::IpNet;
+use sqlx::Row;
+use tracing::{info, warn};
+
+/// Runs every stored raw status response through the current parser and rewrites the
+/// derived columns, so parser fixes reach servers without scanning them again.
+/// Only touches columns computed from the response, running it twice changes nothing
+pub async fn reparse(database: &Database, limit: Option<i64>) -> Result<(), sqlx::Error> {
+	let mut rows = sqlx::query(
+		"SELECT address, port, raw_json FROM servers
+		WHERE raw_json IS NOT NULL
+		ORDER BY address, port
+		LIMIT $1",
+	)
+	.bind(limit)
+	.fetch(&database.0);
+
+	let (mut updated, mut failed) = (0u64, 0u64);
+	while let Some(row) = rows.try_next().await? {
+		let address: IpNet = row.try_get("address")?;
+		let port: i32 = row.try_get("port")?;
+		let raw: Value = row.try_get("raw_json")?;
+
+		let server = match Server::from_lenient_value(raw) {
+			Ok(server) => server,
+			Err(e) => {
+				warn!("Stored response for {}:{} no longer parses: {}", address.addr(), port, e);
+				failed += 1;
+				continue;
+			}
+		};
+
+		database.update_derived_columns(address, port, &server).await?;
+		updated += 1;
+
+		if updated % 10_000 == 0 {
+			info!("Reparsed {} servers", updated);
+		}
+	}
+
+	info!("Reparse finished: {} servers updated, {} failed to parse", updated, failed);
+	Ok(())
+}
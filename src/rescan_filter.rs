@@ -0,0 +1,216 @@
+use sqlx::postgres::PgArguments;
+use sqlx::query::Query;
+use sqlx::Postgres;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Columns a filter may compare against. Only these names ever reach the SQL,
+/// values are always bound as parameters
+const FIELDS: &[(&str, &str, FieldType)] = &[
+	("country", "country", FieldType::Text),
+	("asn", "asn", FieldType::Text),
+	("version", "version", FieldType::Text),
+	("canonical_version", "canonical_version", FieldType::Text),
+	("software", "software", FieldType::Text),
+	("hostname", "hostname", FieldType::Text),
+	("motd", "description_formatted", FieldType::Text),
+	("protocol", "protocol", FieldType::Integer),
+	("online", "online_players", FieldType::Integer),
+	("max", "max_players", FieldType::Integer),
+	("latency", "latency", FieldType::Integer),
+	("mods", "mod_count", FieldType::Integer),
+	("last_seen", "last_seen", FieldType::Integer),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldType {
+	Text,
+	Integer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+	Text(String),
+	Integer(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Condition {
+	column: &'static str,
+	operator: &'static str,
+	value: Value,
+}
+
+/// Conditions on the servers table joined with `and`, e.g.
+/// `country = BR and online > 10 and version like '1.20%'`.
+/// Text fields support `=`, `!=` and `like` (case-insensitive, `%` and `_` wildcards),
+/// number fields support `=`, `!=`, `<`, `<=`, `>` and `>=`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RescanFilter {
+	conditions: Vec<Condition>,
+}
+
+impl RescanFilter {
+	/// SQL for the conditions, with parameters numbered from `first_param`
+	pub fn to_sql(&self, first_param: usize) -> String {
+		let mut sql = String::new();
+		for (i, condition) in self.conditions.iter().enumerate() {
+			if i > 0 {
+				sql.push_str(" AND ");
+			}
+			let _ = write!(sql, "{} {} ${}", condition.column, condition.operator, first_param + i);
+		}
+		sql
+	}
+
+	/// Binds the values in the order `to_sql` numbered them
+	pub fn bind<'q>(&'q self, mut query: Query<'q, Postgres, PgArguments>) -> Query<'q, Postgres, PgArguments> {
+		for condition in &self.conditions {
+			query = match &condition.value {
+				Value::Text(text) => query.bind(text.as_str()),
+				Value::Integer(number) => query.bind(*number),
+			};
+		}
+		query
+	}
+}
+
+impl FromStr for RescanFilter {
+	type Err = String;
+
+	fn from_str(filter: &str) -> Result<Self, Self::Err> {
+		let tokens = tokenize(filter)?;
+		let mut tokens = tokens.iter();
+		let mut conditions = Vec::new();
+
+		loop {
+			let (Some(field), Some(operator), Some(value)) = (tokens.next(), tokens.next(), tokens.next()) else {
+				return Err("expected <field> <operator> <value>".to_string());
+			};
+
+			let Some(&(_, column, field_type)) = FIELDS.iter().find(|(name, ..)| name.eq_ignore_ascii_case(field.text()))
+			else {
+				let names: Vec<&str> = FIELDS.iter().map(|(name, ..)| *name).collect();
+				return Err(format!("unknown field {}, expected one of {}", field.text(), names.join(", ")));
+			};
+
+			let operator = match (operator.text().to_lowercase().as_str(), field_type) {
+				("=", _) => "=",
+				("!=", _) => "<>",
+				("like", FieldType::Text) => "ILIKE",
+				("<", FieldType::Integer) => "<",
+				("<=", FieldType::Integer) => "<=",
+				(">", FieldType::Integer) => ">",
+				(">=", FieldType::Integer) => ">=",
+				_ => return Err(format!("operator {} can't be used with {}", operator.text(), field.text())),
+			};
+
+			let value = match field_type {
+				FieldType::Text => Value::Text(value.text().to_string()),
+				FieldType::Integer => match value.text().parse() {
+					Ok(number) => Value::Integer(number),
+					Err(_) => return Err(format!("{} expects a number, found {}", field.text(), value.text())),
+				},
+			};
+
+			conditions.push(Condition { column, operator, value });
+
+			match tokens.next() {
+				None => break,
+				Some(Token::Word(word)) if word.eq_ignore_ascii_case("and") => continue,
+				Some(token) => return Err(format!("expected and, found {}", token.text())),
+			}
+		}
+
+		Ok(RescanFilter { conditions })
+	}
+}
+
+#[derive(Debug)]
+enum Token {
+	Word(String),
+	Quoted(String),
+	Operator(String),
+}
+
+impl Token {
+	fn text(&self) -> &str {
+		match self {
+			Token::Word(text) | Token::Quoted(text) | Token::Operator(text) => text,
+		}
+	}
+}
+
+fn tokenize(filter: &str) -> Result<Vec<Token>, String> {
+	let is_operator = |c: char| matches!(c, '=' | '!' | '<' | '>');
+	let mut tokens = Vec::new();
+	let mut chars = filter.chars().peekable();
+
+	while let Some(&c) = chars.peek() {
+		if c.is_whitespace() {
+			chars.next();
+		} else if c == '\'' || c == '"' {
+			chars.next();
+			let mut text = String::new();
+			loop {
+				match chars.next() {
+					Some(next) if next == c => break,
+					Some(next) => text.push(next),
+					None => return Err(format!("unterminated quote in {}", filter)),
+				}
+			}
+			tokens.push(Token::Quoted(text));
+		} else if is_operator(c) {
+			let mut text = String::new();
+			while let Some(&next) = chars.peek().filter(|next| is_operator(**next)) {
+				text.push(next);
+				chars.next();
+			}
+			tokens.push(Token::Operator(text));
+		} else {
+			let mut text = String::new();
+			while let Some(&next) = chars
+				.peek()
+				.filter(|next| !next.is_whitespace() && !is_operator(**next) && **next != '\'' && **next != '"')
+			{
+				text.push(next);
+				chars.next();
+			}
+			tokens.push(Token::Word(text));
+		}
+	}
+
+	Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_filter() {
+		let filter: RescanFilter = "country = BR and online>10 AND version like '1.20%'".parse().unwrap();
+		assert_eq!(filter.to_sql(3), "country = $3 AND online_players > $4 AND version ILIKE $5");
+		assert_eq!(
+			filter.conditions.iter().map(|c| c.value.clone()).collect::<Vec<_>>(),
+			vec![Value::Text("BR".to_string()), Value::Integer(10), Value::Text("1.20%".to_string())]
+		);
+
+		let filter: RescanFilter = r#"motd != "A Minecraft Server" and mods >= 1"#.parse().unwrap();
+		assert_eq!(filter.to_sql(4), "description_formatted <> $4 AND mod_count >= $5");
+	}
+
+	#[test]
+	fn test_reject_filter() {
+		assert!("".parse::<RescanFilter>().is_err());
+		assert!("password = x".parse::<RescanFilter>().is_err());
+		assert!("online > lots".parse::<RescanFilter>().is_err());
+		assert!("country > BR".parse::<RescanFilter>().is_err());
+		assert!("online like 5".parse::<RescanFilter>().is_err());
+		assert!("country = BR or 1 = 1".parse::<RescanFilter>().is_err());
+		assert!("country = 'BR".parse::<RescanFilter>().is_err());
+		// Anything after a value has to start another condition
+		assert!("country = BR; DROP TABLE servers".parse::<RescanFilter>().is_err());
+		assert!("country = 'BR'; DROP TABLE servers".parse::<RescanFilter>().is_err());
+	}
+}
@@ -1,10 +1,17 @@
-use crate::utils::MinecraftColorCodes;
-use serde::{Deserialize, Serialize};
+use crate::tcp_fingerprint::TcpFingerprint;
+use crate::links;
+use crate::utils::{protocol_version_name, MinecraftColorCodes};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use sha2::{Digest, Sha256};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
 
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Server {
+	// Milliseconds, the Ping/Pong round trip when the server answers the Ping packet and the
+	// TCP connect time otherwise, so modern and legacy servers can be compared
 	pub latency: Option<i32>,
 	pub version: Version,
 	pub favicon: Option<String>,
@@ -22,6 +29,71 @@ pub struct Server {
 	// "modinfo" is for legacy versions of forge
 	#[serde(rename = "forgeData", alias = "modinfo")]
 	pub forge_data: Option<ForgeData>,
+	// Fabric and Quilt have no forgeData equivalent, some mods advertise
+	// the registered networking channels at the top level instead
+	#[serde(default)]
+	pub channels: Option<Vec<Channel>>,
+	// Sent by modpack mods to identify the pack a server is running
+	#[serde(rename = "modpackData", default)]
+	pub modpack_data: Option<ModpackData>,
+	// Not part of the vanilla response, some plugins and proxies add these. Anything that
+	// isn't a string or a vanilla game mode id is ignored
+	#[serde(
+		rename = "gamemode",
+		alias = "gameMode",
+		alias = "gameType",
+		alias = "gametype",
+		default,
+		deserialize_with = "deserialize_game_mode"
+	)]
+	pub game_mode: Option<String>,
+	#[serde(alias = "mapName", alias = "levelName", default, deserialize_with = "deserialize_string")]
+	pub map: Option<String>,
+	// Top level fields not modelled above, like the chat preview flag 1.19 servers send or
+	// markers forks and plugins add, kept so nothing in the status is lost
+	#[serde(flatten)]
+	pub extra: HashMap<String, Value>,
+	// PTR record of the server's address, filled in by the scanner
+	#[serde(skip_deserializing)]
+	pub hostname: Option<String>,
+	// Unmodified status response, only filled in when raw storage is enabled
+	#[serde(skip_deserializing)]
+	pub raw_json: Option<Value>,
+	// Lowest and highest protocol the server accepted, only filled in when probing is enabled
+	#[serde(skip_deserializing)]
+	pub accepted_protocols: Option<(i32, i32)>,
+	// Ping method that produced this response, filled in by the pinger
+	#[serde(skip_deserializing)]
+	pub ping_method: Option<&'static str>,
+	// Set when the response only parsed after fixing it up, see `from_lenient_value`
+	#[serde(skip_deserializing)]
+	pub lenient_parse: bool,
+	// Scan engine that found the server, only filled in during discovery
+	#[serde(skip_deserializing)]
+	pub discovered_by: Option<&'static str>,
+	// Milliseconds spent on the TCP connect alone, filled in by the pinger
+	#[serde(skip_deserializing)]
+	pub connect_latency: Option<i32>,
+	// Milliseconds for the whole ping, including reading the status and a failed modern attempt
+	#[serde(skip_deserializing)]
+	pub total_latency: Option<i32>,
+	// What the kernel observed during the TCP handshake, only filled in when fingerprinting is enabled
+	#[serde(skip_deserializing)]
+	pub tcp_fingerprint: Option<TcpFingerprint>,
+	// Pings it took to get this response, more than one means earlier attempts timed out
+	#[serde(skip_deserializing)]
+	pub ping_attempts: Option<i32>,
+	// Country and ASN from the GeoIP databases, the countries table is used for what they don't know
+	#[serde(skip_deserializing)]
+	pub country_code: Option<String>,
+	#[serde(skip_deserializing)]
+	pub asn: Option<String>,
+	// Whether the server looks like it's behind a TCP proxy, only filled in when detection is enabled
+	#[serde(skip_deserializing)]
+	pub fronted: Option<bool>,
+	// How a login attempt was answered, only filled in when the login probe is enabled
+	#[serde(skip_deserializing)]
+	pub login_status: Option<&'static str>,
 }
 
 #[allow(dead_code)]
@@ -36,9 +108,102 @@ pub struct Version {
 pub struct Players {
 	pub max: i32,
 	pub online: i32,
+	#[serde(default, deserialize_with = "deserialize_sample")]
 	pub sample: Option<Vec<Player>>,
 }
 
+// Extra status fields above this size aren't stored on their own
+const MAX_EXTRA_FIELDS_BYTES: usize = 16 * 1024;
+
+// Counts at or above this are spoofed, no network has ever come close
+const MAX_PLAUSIBLE_PLAYERS: i32 = 10_000_000;
+// Online counts may pass max on servers that let staff or donors join when full, but not by both
+const MAX_OVERFILL: i64 = 1000;
+const MAX_OVERFILL_FACTOR: i64 = 10;
+
+impl Players {
+	/// Whether the counts can't be real: negative, absurdly large, or far more online than the
+	/// max allows. Honeypots and joke servers send these to break parsers, they're stored as sent
+	/// but left out of player statistics
+	pub fn implausible(&self) -> bool {
+		let (online, max) = (self.online as i64, self.max as i64);
+
+		online < 0
+			|| max < 0
+			|| self.online >= MAX_PLAUSIBLE_PLAYERS
+			|| self.max >= MAX_PLAUSIBLE_PLAYERS
+			|| (online - max > MAX_OVERFILL && online > max * MAX_OVERFILL_FACTOR)
+	}
+}
+
+// Servers send all sorts of things as the sample, anything that isn't a valid
+// player is dropped instead of failing the whole response
+fn deserialize_sample<'de, D>(deserializer: D) -> Result<Option<Vec<Player>>, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	let entries = match Value::deserialize(deserializer)? {
+		Value::Array(entries) => entries,
+		entry @ Value::Object(_) => vec![entry],
+		_ => return Ok(None),
+	};
+
+	Ok(Some(
+		entries
+			.into_iter()
+			.filter_map(|entry| serde_json::from_value(entry).ok())
+			.collect(),
+	))
+}
+
+/// Release versions like `1.20.4`, and the year based ones starting with `26.1`
+fn is_minecraft_version(token: &str) -> bool {
+	let parts: Vec<&str> = token.split('.').collect();
+	let numeric = parts.iter().all(|part| !part.is_empty() && part.len() <= 2 && part.bytes().all(|b| b.is_ascii_digit()));
+
+	numeric && (2..=3).contains(&parts.len()) && matches!(parts[0].parse::<u8>(), Ok(1 | 26..=99))
+}
+
+/// Proxies list the versions they accept, e.g. `1.8.x-1.21.x` or `1.8-1.21.4`
+fn is_version_range(token: &str) -> bool {
+	token
+		.split_once('-')
+		.is_some_and(|(from, to)| {
+			let looks_like_version = |v: &str| is_minecraft_version(v.trim_end_matches(".x")) || v.ends_with(".x");
+			looks_like_version(from) && looks_like_version(to)
+		})
+}
+
+// Game modes are sometimes sent as the numeric id the vanilla server uses
+fn deserialize_game_mode<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	let game_mode = match Value::deserialize(deserializer)? {
+		Value::String(game_mode) => game_mode,
+		Value::Number(id) => match id.as_u64() {
+			Some(0) => "survival".to_string(),
+			Some(1) => "creative".to_string(),
+			Some(2) => "adventure".to_string(),
+			Some(3) => "spectator".to_string(),
+			_ => return Ok(None),
+		},
+		_ => return Ok(None),
+	};
+
+	Ok(Some(game_mode))
+}
+
+fn deserialize_string<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	Ok(match Value::deserialize(deserializer)? {
+		Value::String(value) => Some(value),
+		_ => None,
+	})
+}
+
 #[allow(dead_code)]
 #[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
 pub struct Player {
@@ -51,8 +216,16 @@ pub struct Player {
 pub struct ForgeData {
 	// "mods", is for modern versions of forge
 	// "modList" is legacy forge versions
-	#[serde(rename = "mods", alias = "modList")]
+	#[serde(rename = "mods", alias = "modList", default)]
 	pub mods: Vec<Mod>,
+	// Networking channels registered by forge and its mods, modern versions only
+	#[serde(default)]
+	pub channels: Option<Vec<Channel>>,
+	#[serde(rename = "fmlNetworkVersion", default)]
+	pub fml_network_version: Option<i32>,
+	// Set when forge cut the mod list short to keep the response small
+	#[serde(default)]
+	pub truncated: Option<bool>,
 }
 
 #[allow(dead_code)]
@@ -64,20 +237,338 @@ pub struct Mod {
 	pub version: String,
 }
 
+#[allow(dead_code)]
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+#[serde(untagged)]
+pub enum Channel {
+	Name(String),
+	Entry {
+		#[serde(alias = "name", alias = "id")]
+		res: String,
+		version: Option<Value>,
+	},
+	// Anything else is kept as-is so an odd entry doesn't fail the whole response
+	Other(Value),
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct ModpackData {
+	#[serde(rename = "projectID")]
+	pub project_id: Option<i64>,
+	pub name: Option<String>,
+	pub version: Option<String>,
+	#[serde(rename = "releaseType")]
+	pub release_type: Option<String>,
+}
+
+/// How a server treats chat signing and reporting, from the `preventsChatReports` and
+/// `enforcesSecureChat` flags
+#[derive(Serialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityProfile {
+	/// Neither flag was sent, servers before 1.19.1 and most proxies
+	Unknown,
+	/// Signed chat is accepted but not required
+	SecureChatOptional,
+	/// Clients that don't sign their messages can't chat
+	SecureChatEnforced,
+	/// A plugin such as FreedomChat strips signatures, so messages can't be reported
+	NoChatReports,
+	/// The No Chat Reports mod on a modded server
+	NoChatReportsMod,
+	/// Claims to prevent reports while enforcing secure chat, usually a proxy passing on
+	/// flags from different backends
+	Conflicting,
+}
+
+impl SecurityProfile {
+	/// Name stored in the `security_profile` column
+	pub fn as_str(self) -> &'static str {
+		match self {
+			SecurityProfile::Unknown => "unknown",
+			SecurityProfile::SecureChatOptional => "secure_chat_optional",
+			SecurityProfile::SecureChatEnforced => "secure_chat_enforced",
+			SecurityProfile::NoChatReports => "no_chat_reports",
+			SecurityProfile::NoChatReportsMod => "no_chat_reports_mod",
+			SecurityProfile::Conflicting => "conflicting",
+		}
+	}
+}
+
 impl Server {
+	/// Parses a status response, falling back to `from_lenient_value` when it isn't valid
+	pub fn from_lenient_json(raw: &str) -> Result<Server, serde_json::Error> {
+		match serde_json::from_str::<Server>(raw) {
+			Ok(server) => Ok(server),
+			Err(e) => match serde_json::from_str::<Value>(raw) {
+				Ok(value) => Server::from_lenient_value(value),
+				Err(_) => Err(e),
+			},
+		}
+	}
+
+	/// Parses a status response, fixing up the common ways servers get it wrong when the
+	/// strict parse fails: numbers sent as strings, a missing players object or protocol,
+	/// and optional fields of the wrong type. Sets `lenient_parse` if anything was fixed
+	pub fn from_lenient_value(value: Value) -> Result<Server, serde_json::Error> {
+		let error = match serde_json::from_value::<Server>(value.clone()) {
+			Ok(server) => return Ok(server),
+			Err(e) => e,
+		};
+
+		let Value::Object(mut object) = value else {
+			return Err(error);
+		};
+		// Everything below fills in defaults, an object with none of these isn't a status
+		if !["version", "players", "description"].iter().any(|key| object.contains_key(*key)) {
+			return Err(error);
+		}
+
+		// A bare version string is the name, the protocol is unknown
+		let version = match object.remove("version") {
+			Some(Value::Object(version)) => version,
+			Some(Value::String(name)) => serde_json::Map::from_iter([("name".to_string(), Value::String(name))]),
+			_ => serde_json::Map::new(),
+		};
+		let name = match version.get("name") {
+			Some(Value::String(name)) => name.clone(),
+			Some(Value::Number(number)) => number.to_string(),
+			_ => String::new(),
+		};
+		object.insert(
+			"version".to_string(),
+			serde_json::json!({"name": name, "protocol": lenient_int(version.get("protocol")).unwrap_or(-1)}),
+		);
+
+		let mut players = match object.remove("players") {
+			Some(Value::Object(players)) => players,
+			_ => serde_json::Map::new(),
+		};
+		// Counts past the i32 range are clamped to it rather than zeroed, so they're still flagged
+		// by `Players::implausible`
+		for key in ["max", "online"] {
+			let count = lenient_i64(players.get(key)).map_or(0, |n| n.clamp(i32::MIN as i64, i32::MAX as i64));
+			players.insert(key.to_string(), count.into());
+		}
+		object.insert("players".to_string(), Value::Object(players));
+
+		// Optional fields that don't parse are dropped instead of failing the whole response
+		for key in ["preventsChatReports", "enforcesSecureChat", "isModded"] {
+			if object.get(key).is_some_and(|value| !value.is_boolean()) {
+				object.remove(key);
+			}
+		}
+		if object.get("favicon").is_some_and(|value| !value.is_string()) {
+			object.remove("favicon");
+		}
+		for key in ["forgeData", "modinfo"] {
+			if object.get(key).is_some_and(|value| serde_json::from_value::<ForgeData>(value.clone()).is_err()) {
+				object.remove(key);
+			}
+		}
+		if object.get("modpackData").is_some_and(|value| serde_json::from_value::<ModpackData>(value.clone()).is_err()) {
+			object.remove("modpackData");
+		}
+		if object.get("channels").is_some_and(|value| !value.is_array()) {
+			object.remove("channels");
+		}
+
+		let mut server = serde_json::from_value::<Server>(Value::Object(object))?;
+		server.lenient_parse = true;
+		Ok(server)
+	}
+
+	/// Lists the names of all networking channels the server advertised
+	pub fn known_channels(&self) -> Vec<&str> {
+		self.channels
+			.iter()
+			.flatten()
+			.filter_map(|channel| match channel {
+				Channel::Name(name) => Some(name.as_str()),
+				Channel::Entry { res, .. } => Some(res.as_str()),
+				Channel::Other(_) => None,
+			})
+			.collect()
+	}
+
+	/// Whether any advertised channel is in `namespace`, e.g. `fabric` for `fabric:registry/sync`
+	fn has_channel_namespace(&self, namespace: &str) -> bool {
+		self.known_channels()
+			.iter()
+			.any(|channel| channel.split_once(':').is_some_and(|(prefix, _)| prefix == namespace))
+	}
+
+	/// Number of mods the server has, None for servers without forge data and when the
+	/// list was truncated, since the server has more than it listed
+	pub fn mod_count(&self) -> Option<i32> {
+		self.forge_data
+			.as_ref()
+			.filter(|_| !self.mods_truncated())
+			.map(|forge| forge.mods.len() as i32)
+	}
+
+	/// The top level fields nothing else reads as a JSON object, for storage. None when there
+	/// are none, or when they're too big to be capability flags, raw JSON storage keeps those
+	pub fn extra_fields(&self) -> Option<Value> {
+		if self.extra.is_empty() {
+			return None;
+		}
+
+		let fields = Value::Object(self.extra.clone().into_iter().collect());
+		(fields.to_string().len() <= MAX_EXTRA_FIELDS_BYTES).then_some(fields)
+	}
+
+	/// Whether the server advertised any sign of being modded
+	pub fn is_modded(&self) -> bool {
+		self.modded == Some(true) || self.forge_data.is_some() || !self.known_channels().is_empty()
+	}
+
+	/// Classifies the chat flags, a missing `enforcesSecureChat` alone doesn't say anything
+	/// since servers that don't know about signing leave both out
+	pub fn security_profile(&self) -> SecurityProfile {
+		match (self.prevents_reports, self.enforces_secure_chat) {
+			(Some(true), Some(true)) => SecurityProfile::Conflicting,
+			(Some(true), _) if self.is_modded() => SecurityProfile::NoChatReportsMod,
+			(Some(true), _) => SecurityProfile::NoChatReports,
+			(_, Some(true)) => SecurityProfile::SecureChatEnforced,
+			(_, Some(false)) => SecurityProfile::SecureChatOptional,
+			(_, None) => SecurityProfile::Unknown,
+		}
+	}
+
+	/// The advertised game mode in lowercase, e.g. `survival`
+	pub fn game_mode(&self) -> Option<String> {
+		self.game_mode
+			.as_deref()
+			.map(|game_mode| game_mode.trim().to_lowercase())
+			.filter(|game_mode| !game_mode.is_empty())
+	}
+
+	/// The advertised map or level name
+	pub fn map(&self) -> Option<String> {
+		self.map
+			.as_deref()
+			.map(str::trim)
+			.filter(|map| !map.is_empty())
+			.map(str::to_string)
+	}
+
+	/// Whether the server has more mods than it listed
+	pub fn mods_truncated(&self) -> bool {
+		self.forge_data
+			.as_ref()
+			.and_then(|forge| forge.truncated)
+			.unwrap_or(false)
+	}
+
+	/// The release matching the reported protocol, which stays accurate when proxies
+	/// replace the version name with something of their own
+	pub fn canonical_version(&self) -> Option<String> {
+		protocol_version_name(self.version.protocol).map(str::to_string)
+	}
+
+	/// Splits the version name into the server software and the Minecraft version, e.g.
+	/// `Paper 1.20.4` into `Paper` and `1.20.4`. The software comes from `get_type`, or the
+	/// leading word of the name when that doesn't know it. Names without a single Minecraft
+	/// version, like proxies listing a range, fall back to `canonical_version` when the
+	/// protocol belongs to one release. None when it's shared by several
+	pub fn parse_version(&self) -> (Option<String>, Option<String>) {
+		let tokens: Vec<&str> = self
+			.version
+			.name
+			.split(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | ','))
+			.filter(|token| !token.is_empty())
+			.collect();
+
+		let mc_version = tokens
+			.iter()
+			// Plugin builds add suffixes like 1.8.8-R0.1-SNAPSHOT
+			.filter_map(|token| token.split('-').next())
+			.find(|token| is_minecraft_version(token))
+			.filter(|_| !tokens.iter().any(|token| is_version_range(token)))
+			.map(str::to_string);
+
+		let software = match self.get_type() {
+			"Java" => match tokens.as_slice() {
+				[name, version, ..]
+					if name.starts_with(|c: char| c.is_ascii_alphabetic())
+						&& name.chars().all(|c| c.is_ascii_alphanumeric())
+						&& is_minecraft_version(version.split('-').next().unwrap_or_default()) =>
+				{
+					Some(name.to_string())
+				}
+				_ => None,
+			},
+			software => Some(software.to_string()),
+		};
+
+		let canonical_version = || self.canonical_version().filter(|version| is_minecraft_version(version));
+		(software, mc_version.or_else(canonical_version))
+	}
+
+	/// The favicon image the server sent, None when there's no favicon or it isn't valid base64
+	pub fn decoded_favicon(&self) -> Option<Vec<u8>> {
+		let favicon = self.favicon.as_deref()?;
+
+		// Favicons are data URLs, e.g. data:image/png;base64,iVBORw0...
+		let encoded = favicon.split_once(',').map_or(favicon, |(_, data)| data);
+		BASE64_STANDARD.decode(encoded.trim()).ok()
+	}
+
+	/// Hash of the decoded favicon image, see `hash_favicon`
+	pub fn favicon_hash(&self) -> Option<String> {
+		let favicon = self.favicon.as_deref()?;
+
+		// Still worth fingerprinting when it doesn't decode, hash it as sent
+		let image = self.decoded_favicon().unwrap_or_else(|| favicon.as_bytes().to_vec());
+		Some(hash_favicon(&image))
+	}
+
 	pub fn get_type(&self) -> &'static str {
-		// Check for modded servers first, as they have distinct identifiers.
-		// Neoforge sends an "isModded" field.
-		if self.modded.is_some() {
+		let version_name = self.version.name.to_lowercase();
+		// Servers send "isModded": false as well, only true says anything
+		let modded = self.modded == Some(true);
+
+		// Forge sends a "forgeData" object for modern servers and "modinfo" for legacy versions.
+		// Neoforge sends the same object along with "isModded", and lists itself as a mod
+		if let Some(forge_data) = &self.forge_data {
+			let neoforge = modded
+				|| version_name.contains("neoforge")
+				|| forge_data.mods.iter().any(|m| m.id.eq_ignore_ascii_case("neoforge"));
+			return if neoforge { "Neoforge" } else { "Lexforge" };
+		}
+
+		// "isModded" alone is ambiguous, mods for other loaders and hybrid forks set it too,
+		// so the version name decides whenever it names the software
+		if let Some(software) = self.type_from_version(&version_name) {
+			return software;
+		}
+		if modded {
 			return "Neoforge";
 		}
 
-		// Forge sends a "forgeData" object for modern servers and "modinfo" for legacy versions.
-		if self.forge_data.is_some() {
-			return "Lexforge";
+		// Fallback for any other Java server if no specific type is identified.
+		"Java"
+	}
+
+	/// The software a lowercase version name or the advertised channels point to
+	fn type_from_version(&self, version_name: &str) -> Option<&'static str> {
+		if version_name.contains("neoforge") {
+			return Some("Neoforge");
+		}
+		if version_name.contains("forge") {
+			return Some("Lexforge");
 		}
 
-		let version_name = self.version.name.to_lowercase();
+		// Fabric and Quilt only show up through their version name or advertised channels. Quilt
+		// also registers Fabric's channels, so it's checked first
+		if version_name.contains("quilt") || self.has_channel_namespace("quilt") {
+			return Some("Quilt");
+		}
+		if version_name.contains("fabric") || self.has_channel_namespace("fabric") {
+			return Some("Fabric");
+		}
 
 		// The order of these checks is important. Forks often include the parent software's
 		// name in their version string (e.g., Paper may contain "Spigot").
@@ -86,49 +577,77 @@ impl Server {
 
 		// Proxies - These are checked first as they are distinct from server jars.
 		if version_name.contains("velocity") {
-			return "Velocity";
+			return Some("Velocity");
 		}
 		if version_name.contains("waterfall") {
-			return "Waterfall";
+			return Some("Waterfall");
 		}
 		if version_name.contains("bungeecord") {
-			return "Bungeecord";
+			return Some("Bungeecord");
 		}
 
 		// Paper and its forks (from most to least specific)
 		if version_name.contains("leaves") {
-			return "Leaves";
+			return Some("Leaves");
 		}
 		if version_name.contains("folia") {
-			return "Folia";
+			return Some("Folia");
 		}
 		if version_name.contains("purpur") {
-			return "Purpur";
+			return Some("Purpur");
 		}
 		if version_name.contains("pufferfish") {
-			return "Pufferfish";
+			return Some("Pufferfish");
 		}
 		if version_name.contains("paper") {
-			return "Paper";
+			return Some("Paper");
 		}
 
 		// Spigot and its base
 		if version_name.contains("spigot") {
-			return "Spigot";
+			return Some("Spigot");
 		}
 		if version_name.contains("bukkit") {
-			return "Bukkit";
+			return Some("Bukkit");
 		}
 
-		// Fallback for any other Java server if no specific type is identified.
-		"Java"
+		None
+	}
+
+	/// The description as plain text, with formatting codes removed
+	pub fn plain_description(&self) -> Option<String> {
+		let formatted = match (&self.description_formatted, &self.description_raw) {
+			(Some(description), _) => description.clone(),
+			(None, Some(raw)) => self.build_formatted_description(raw),
+			(None, None) => return None,
+		};
+
+		let mut plain = String::with_capacity(formatted.len());
+		let mut chars = formatted.chars();
+		while let Some(c) = chars.next() {
+			if c == '§' {
+				chars.next();
+			} else {
+				plain.push(c);
+			}
+		}
+		Some(plain)
+	}
+
+	/// URLs, domains, Discord invites and addresses advertised in the description
+	pub fn extract_links(&self) -> Vec<String> {
+		self.plain_description().map_or_else(Vec::new, |description| links::extract_links(&description))
 	}
 
 	// Has the user opted out of scanning?
 	pub fn check_opt_out(&self) -> bool {
-		match &self.description_formatted {
-			Some(description) => String::from(description).contains("§b§d§f§d§b"),
-			None => false,
+		const OPT_OUT_MARKER: &str = "§b§d§f§d§b";
+
+		// Status responses only carry the raw description, the formatted one is built from it
+		match (&self.description_formatted, &self.description_raw) {
+			(Some(description), _) => description.contains(OPT_OUT_MARKER),
+			(None, Some(raw)) => self.build_formatted_description(raw).contains(OPT_OUT_MARKER),
+			(None, None) => false,
 		}
 	}
 
@@ -138,6 +657,8 @@ impl Server {
 
 		match value {
 			Value::String(s) => output.push_str(s),
+			Value::Number(n) => output.push_str(&n.to_string()),
+			Value::Bool(b) => output.push_str(&b.to_string()),
 			Value::Array(array) => {
 				for value in array {
 					output.push_str(&self.build_formatted_description(value));
@@ -194,10 +715,19 @@ impl Server {
 				// MiniMOTD can put the "extra" field before the text field, this causes some servers
 				// using it to format incorrectly unless we specifically add the text AFTER
 				// all other format codes but BEFORE the extra field
-				if object.contains_key("text") {
-					if let Some(text) = object.get("text") {
-						if let Some(text) = text.as_str() {
-							output.push_str(text);
+				match object.get("text") {
+					// Plugins send numbers and booleans as the text too, and some proxies
+					// nest a whole component in it
+					Some(text) if !text.is_null() => output.push_str(&self.build_formatted_description(text)),
+					_ => {
+						if let Some(translate) = object.get("translate").and_then(Value::as_str) {
+							let with: Vec<String> = match object.get("with") {
+								Some(Value::Array(args)) => args.iter().map(|arg| self.build_formatted_description(arg)).collect(),
+								_ => Vec::new(),
+							};
+							output.push_str(&format_translation(translate, &with));
+						} else if let Some(keybind) = object.get("keybind").and_then(Value::as_str) {
+							output.push_str(keybind);
 						}
 					}
 				}
@@ -214,3 +744,497 @@ impl Server {
 		output
 	}
 }
+
+/// Reads an integer sent as a number or a string, e.g. 20, 20.0 or "20"
+fn lenient_int(value: Option<&Value>) -> Option<i32> {
+	lenient_i64(value).and_then(|n| i32::try_from(n).ok())
+}
+
+fn lenient_i64(value: Option<&Value>) -> Option<i64> {
+	match value? {
+		Value::Number(number) => number.as_i64().or_else(|| number.as_f64().map(|n| n as i64)),
+		Value::String(text) => text.trim().parse().ok(),
+		_ => None,
+	}
+}
+
+/// Truncated SHA-256 of a favicon image. The same icon on many
+/// addresses usually means one operator or a honeypot network
+pub fn hash_favicon(image: &[u8]) -> String {
+	let digest = Sha256::digest(image);
+	digest[..16].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Rebuilds the data URL servers send from a stored favicon image
+pub fn favicon_data_uri(image: &[u8]) -> String {
+	format!("data:image/png;base64,{}", BASE64_STANDARD.encode(image))
+}
+
+/// Fills in the `%s` and `%1$s` placeholders of a translation key. The scanner has no
+/// language files, so the key itself is the format, which is how the client shows
+/// unknown keys and how most servers use translate for MOTDs anyway
+fn format_translation(format: &str, args: &[String]) -> String {
+	let mut output = String::new();
+	let mut next_arg = 0;
+	let mut rest = format;
+
+	while let Some(start) = rest.find('%') {
+		output.push_str(&rest[..start]);
+		let placeholder = &rest[start + 1..];
+
+		if let Some(after) = placeholder.strip_prefix('%') {
+			output.push('%');
+			rest = after;
+		} else if let Some(after) = placeholder.strip_prefix('s') {
+			output.push_str(args.get(next_arg).map_or("", String::as_str));
+			next_arg += 1;
+			rest = after;
+		} else if let Some((index, after)) = placeholder.split_once("$s") {
+			match index.parse::<usize>() {
+				Ok(index) if index > 0 => {
+					output.push_str(args.get(index - 1).map_or("", String::as_str));
+					rest = after;
+				}
+				_ => {
+					output.push('%');
+					rest = placeholder;
+				}
+			}
+		} else {
+			output.push('%');
+			rest = placeholder;
+		}
+	}
+
+	output.push_str(rest);
+	output
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A 1.20.1 status with no players, plus the fields in `extra` which replace the ones it has
+	fn status_json(extra: &str) -> String {
+		let mut status = serde_json::json!({
+			"version": {"name": "1.20.1", "protocol": 763},
+			"players": {"max": 20, "online": 0},
+		});
+		let extra: serde_json::Map<String, Value> = serde_json::from_str(&format!("{{{}}}", extra)).unwrap();
+		status.as_object_mut().unwrap().extend(extra);
+		status.to_string()
+	}
+
+	fn formatted(json: &str) -> String {
+		let server: Server = serde_json::from_str(&status_json(&format!(r#""description": {}"#, json))).unwrap();
+		server.build_formatted_description(server.description_raw.as_ref().unwrap())
+	}
+
+	#[test]
+	fn test_description_shapes() {
+		assert_eq!(formatted(r#""§aA bare string""#), "§aA bare string");
+		assert_eq!(formatted(r#"{"text": "Just text"}"#), "Just text");
+		assert_eq!(
+			formatted(r#"[{"text": "Hello ", "color": "gold"}, {"text": "world", "bold": true}]"#),
+			"§6Hello §lworld"
+		);
+		// MiniMOTD puts extra first, the text still has to come before it
+		assert_eq!(formatted(r#"{"extra": [{"text": "b"}], "text": "a"}"#), "ab");
+		assert_eq!(formatted(r#"{"text": "", "extra": ["plain ", {"text": "component"}]}"#), "plain component");
+		assert_eq!(formatted(r#"{"text": 2024}"#), "2024");
+		assert_eq!(formatted(r#"{"text": {"text": "Wrapped by a proxy"}}"#), "Wrapped by a proxy");
+	}
+
+	#[test]
+	fn test_security_profile() {
+		let profile = |prevents: Option<bool>, enforces: Option<bool>, modded: bool| {
+			let mut server: Server = serde_json::from_str(&status_json("")).unwrap();
+			server.prevents_reports = prevents;
+			server.enforces_secure_chat = enforces;
+			server.modded = modded.then_some(true);
+			server.security_profile()
+		};
+
+		assert_eq!(profile(None, None, false), SecurityProfile::Unknown);
+		assert_eq!(profile(Some(false), None, false), SecurityProfile::Unknown);
+		assert_eq!(profile(None, Some(false), false), SecurityProfile::SecureChatOptional);
+		assert_eq!(profile(Some(false), Some(true), false), SecurityProfile::SecureChatEnforced);
+		assert_eq!(profile(Some(true), None, false), SecurityProfile::NoChatReports);
+		assert_eq!(profile(Some(true), Some(false), false), SecurityProfile::NoChatReports);
+		assert_eq!(profile(Some(true), Some(false), true), SecurityProfile::NoChatReportsMod);
+		assert_eq!(profile(Some(true), Some(true), true), SecurityProfile::Conflicting);
+	}
+
+	#[test]
+	fn test_parse_version() {
+		let parse = |name: &str, protocol: i32, extra: &str| {
+			let status = status_json(&format!(r#""version": {{"name": "{}", "protocol": {}}}{}"#, name, protocol, extra));
+			let server: Server = serde_json::from_str(&status).unwrap();
+			let (software, mc_version) = server.parse_version();
+			(software.unwrap_or_default(), mc_version.unwrap_or_default())
+		};
+		let pair = |software: &str, mc_version: &str| (software.to_string(), mc_version.to_string());
+
+		assert_eq!(parse("Paper 1.20.4", 765, ""), pair("Paper", "1.20.4"));
+		assert_eq!(parse("CraftBukkit 1.8.8", 47, ""), pair("Bukkit", "1.8.8"));
+		assert_eq!(parse("Spigot 1.8.8-R0.1-SNAPSHOT", 47, ""), pair("Spigot", "1.8.8"));
+		assert_eq!(parse("git-Purpur-2062 (MC: 1.20.1)", 763, ""), pair("Purpur", "1.20.1"));
+		assert_eq!(parse("Folia 1.21.1", 767, ""), pair("Folia", "1.21.1"));
+		assert_eq!(parse("Leaves 1.21", 767, ""), pair("Leaves", "1.21"));
+		assert_eq!(parse("Pufferfish 1.19.4", 762, ""), pair("Pufferfish", "1.19.4"));
+		assert_eq!(parse("fabric 1.20.1", 763, ""), pair("Fabric", "1.20.1"));
+		assert_eq!(parse("1.20.1", 763, r#", "forgeData": {"mods": []}"#), pair("Lexforge", "1.20.1"));
+		assert_eq!(parse("Mohist 1.20.1", 763, ""), pair("Mohist", "1.20.1"));
+		assert_eq!(parse("26.1", 775, ""), pair("", "26.1"));
+
+		// Proxies list a range or their own version, the protocol is all there is to go on. It
+		// only says which release when no other shares it
+		assert_eq!(parse("Velocity 3.3.0-SNAPSHOT", 762, ""), pair("Velocity", "1.19.4"));
+		assert_eq!(parse("Velocity 3.3.0-SNAPSHOT", 767, ""), pair("Velocity", ""));
+		assert_eq!(parse("BungeeCord 1.8.x-1.21.x", 767, ""), pair("Bungeecord", ""));
+		assert_eq!(parse("Waterfall 1.8-1.20.4", 765, ""), pair("Waterfall", ""));
+		assert_eq!(parse("§cMaintenance", 762, ""), pair("", "1.19.4"));
+		assert_eq!(parse("§cMaintenance", 765, ""), pair("", ""));
+	}
+
+	#[test]
+	fn test_game_mode_and_map() {
+		let parse = |extra: &str| -> Server { serde_json::from_str(&status_json(extra)).unwrap() };
+
+		let server = parse(r#""gameType": "SURVIVAL ", "map": "Lobby""#);
+		assert_eq!(server.game_mode().as_deref(), Some("survival"));
+		assert_eq!(server.map().as_deref(), Some("Lobby"));
+
+		assert_eq!(parse(r#""gamemode": 1"#).game_mode().as_deref(), Some("creative"));
+
+		// Missing or unexpected values don't fail the response
+		let server = parse("");
+		assert_eq!(server.game_mode(), None);
+		assert_eq!(server.map(), None);
+		let server = parse(r#""gamemode": {"id": 0}, "map": 7"#);
+		assert_eq!(server.game_mode(), None);
+		assert_eq!(server.map(), None);
+	}
+
+	#[test]
+	fn test_extract_links_from_description() {
+		let server: Server = serde_json::from_str(
+			r#"{"version": {"name": "1.20.1", "protocol": 763}, "players": {"max": 20, "online": 0},
+			"description": {"text": "", "extra": [{"text": "play.", "color": "gold"}, {"text": "example.net", "bold": true},
+			" | discord.gg/example"]}}"#,
+		)
+		.unwrap();
+
+		assert_eq!(server.plain_description().unwrap(), "play.example.net | discord.gg/example");
+		assert_eq!(server.extract_links(), vec!["play.example.net", "discord.gg/example"]);
+	}
+
+	#[test]
+	fn test_opt_out_from_raw_description() {
+		let server = |description: &str| -> Server {
+			serde_json::from_str(&status_json(&format!(r#""description": {}"#, description))).unwrap()
+		};
+
+		assert!(server(r#"{"text": "Private §b§d§f§d§b"}"#).check_opt_out());
+		assert!(!server(r#"{"text": "Survival"}"#).check_opt_out());
+	}
+
+	#[test]
+	fn test_translate_descriptions() {
+		assert_eq!(
+			formatted(r#"{"translate": "Welcome to %s, %s!", "with": ["Hypixel", {"text": "Steve", "color": "red"}]}"#),
+			"Welcome to Hypixel, §cSteve!"
+		);
+		assert_eq!(formatted(r#"{"translate": "%2$s before %1$s", "with": ["a", "b"]}"#), "b before a");
+		assert_eq!(formatted(r#"{"translate": "100%% uptime"}"#), "100% uptime");
+		assert_eq!(formatted(r#"{"translate": "multiplayer.status.unknown"}"#), "multiplayer.status.unknown");
+		assert_eq!(formatted(r#"{"translate": "Missing %s"}"#), "Missing ");
+	}
+
+	#[test]
+	fn test_extra_fields() {
+		let json = r#"{"version": {"name": "1.19.2", "protocol": 760}, "players": {"max": 20, "online": 0},
+			"description": "Hi", "previewsChat": true, "anticheat": {"name": "Grim"}, "enforcesSecureChat": false}"#;
+		let server = Server::from_lenient_json(json).unwrap();
+		assert!(!server.lenient_parse);
+		assert_eq!(server.extra.len(), 2);
+		assert_eq!(server.extra["previewsChat"], Value::Bool(true));
+		assert_eq!(
+			server.extra_fields(),
+			Some(serde_json::json!({"anticheat": {"name": "Grim"}, "previewsChat": true}))
+		);
+		// Serialized back at the top level
+		assert_eq!(serde_json::to_value(&server).unwrap()["previewsChat"], Value::Bool(true));
+
+		// Kept through the lenient parse too
+		let server = Server::from_lenient_json(r#"{"version": "1.19.2", "previewsChat": true}"#).unwrap();
+		assert!(server.lenient_parse);
+		assert_eq!(server.extra["previewsChat"], Value::Bool(true));
+
+		let server = Server::from_lenient_json(r#"{"version": {"name": "1.21.1", "protocol": 767}}"#).unwrap();
+		assert_eq!(server.extra_fields(), None);
+		let mut padded = server.clone();
+		padded.extra.insert("junk".to_string(), Value::String("a".repeat(MAX_EXTRA_FIELDS_BYTES)));
+		assert_eq!(padded.extra_fields(), None);
+	}
+
+	#[test]
+	fn test_implausible_players() {
+		let parse = |players: &str| {
+			Server::from_lenient_json(&status_json(&format!(r#""players": {}"#, players)))
+				.unwrap()
+				.players
+		};
+
+		assert!(!parse(r#"{"max": 20, "online": 3}"#).implausible());
+		// Full servers that let staff in, and networks that show a max of 1
+		assert!(!parse(r#"{"max": 100, "online": 104}"#).implausible());
+		assert!(!parse(r#"{"max": 1, "online": 950}"#).implausible());
+
+		let negative = parse(r#"{"max": 20, "online": -5}"#);
+		assert_eq!(negative.online, -5);
+		assert!(negative.implausible());
+		assert!(parse(r#"{"max": -1, "online": 0}"#).implausible());
+		assert!(parse(r#"{"max": 20, "online": 2147483647}"#).implausible());
+		assert!(parse(r#"{"max": 100, "online": 50000}"#).implausible());
+
+		// Past the i32 range, clamped instead of zeroed
+		let overflow = parse(r#"{"max": 99999999999, "online": "-99999999999"}"#);
+		assert_eq!((overflow.online, overflow.max), (i32::MIN, i32::MAX));
+		assert!(overflow.implausible());
+	}
+
+	#[test]
+	fn test_lenient_parse() {
+		// Valid responses take the strict path
+		let server = Server::from_lenient_json(
+			r#"{"version": {"name": "1.20.4", "protocol": 765}, "players": {"max": 20, "online": 1}}"#,
+		)
+		.unwrap();
+		assert!(!server.lenient_parse);
+
+		// Player counts as strings
+		let server = Server::from_lenient_json(
+			r#"{"version": {"name": "1.20.4", "protocol": 765}, "players": {"max": "100", "online": " 7"}}"#,
+		)
+		.unwrap();
+		assert!(server.lenient_parse);
+		assert_eq!((server.players.online, server.players.max), (7, 100));
+
+		// No players object at all
+		let server = Server::from_lenient_json(r#"{"version": {"name": "1.8.8", "protocol": 47}, "description": "Hi"}"#).unwrap();
+		assert_eq!((server.players.online, server.players.max), (0, 0));
+		assert_eq!(server.version.protocol, 47);
+
+		// Missing or string protocol, and a bare version string
+		let server = Server::from_lenient_json(r#"{"version": {"name": "Spigot 1.12"}, "players": {"max": 20, "online": 0}}"#).unwrap();
+		assert_eq!(server.version.protocol, -1);
+		let server = Server::from_lenient_json(r#"{"version": {"name": "1.16.5", "protocol": "754"}, "players": {"max": 1, "online": 0}}"#).unwrap();
+		assert_eq!(server.version.protocol, 754);
+		let server = Server::from_lenient_json(r#"{"version": "1.7.10", "players": {"max": 20.0, "online": 3}}"#).unwrap();
+		assert_eq!((server.version.name.as_str(), server.version.protocol), ("1.7.10", -1));
+		assert_eq!(server.players.max, 20);
+
+		// Wrongly typed optional fields are dropped, the rest is kept
+		let server = Server::from_lenient_json(
+			r#"{"version": {"name": "1.19.4", "protocol": 762}, "players": {"max": 20, "online": 0},
+			"enforcesSecureChat": "true", "favicon": 5, "forgeData": {"mods": "lots"}, "description": {"text": "Kept"}}"#,
+		)
+		.unwrap();
+		assert!(server.enforces_secure_chat.is_none());
+		assert!(server.favicon.is_none());
+		assert!(server.forge_data.is_none());
+		assert_eq!(server.description_raw, Some(serde_json::json!({"text": "Kept"})));
+
+		// Still nothing to work with
+		assert!(Server::from_lenient_json("[1, 2, 3]").is_err());
+		assert!(Server::from_lenient_json("not json").is_err());
+		assert!(Server::from_lenient_json("{}").is_err());
+		assert!(Server::from_lenient_json(r#"{"error": "rate limited"}"#).is_err());
+	}
+
+	#[test]
+	fn test_fabric_channels() {
+		let json = r#"{
+			"version": {"name": "1.20.1", "protocol": 763},
+			"players": {"max": 20, "online": 1, "sample": [{"id": "4566e69f-c907-48ee-8d71-d7ba5aa00d20", "name": "Steve"}]},
+			"description": {"text": "A Fabric server"},
+			"enforcesSecureChat": true,
+			"channels": ["fabric:registry/sync", {"res": "fabric-screen-handler-api-v1:open_screen", "version": "1"}, 5],
+			"modpackData": {"projectID": 123456, "name": "Fabulously Optimized", "version": "5.4.1", "releaseType": "release", "isMetadata": true}
+		}"#;
+
+		let server: Server = serde_json::from_str(json).unwrap();
+		assert_eq!(server.version.protocol, 763);
+		assert_eq!(server.players.online, 1);
+		assert_eq!(
+			server.known_channels(),
+			vec!["fabric:registry/sync", "fabric-screen-handler-api-v1:open_screen"]
+		);
+		assert_eq!(server.modpack_data.as_ref().unwrap().name.as_deref(), Some("Fabulously Optimized"));
+		assert_eq!(server.get_type(), "Fabric");
+	}
+
+	#[test]
+	fn test_no_fabric_channels() {
+		let json = r#"{
+			"version": {"name": "Paper 1.20.4", "protocol": 765},
+			"players": {"max": 100, "online": 0},
+			"description": "A Paper server"
+		}"#;
+
+		let server: Server = serde_json::from_str(json).unwrap();
+		assert_eq!(server.players.max, 100);
+		assert!(server.channels.is_none());
+		assert!(server.known_channels().is_empty());
+		assert!(server.modpack_data.is_none());
+		assert_eq!(server.get_type(), "Paper");
+	}
+
+	#[test]
+	fn test_modern_forge_data() {
+		let json = r#"{
+			"version": {"name": "1.20.1", "protocol": 763},
+			"players": {"max": 20, "online": 0},
+			"description": "A Forge server",
+			"forgeData": {
+				"channels": [{"res": "forge:tier_sorting", "version": "1.0", "required": false}],
+				"mods": [{"modId": "forge", "modmarker": "47.2.0"}, {"modId": "jei", "modmarker": "15.2.0.27"}],
+				"fmlNetworkVersion": 3,
+				"truncated": true
+			}
+		}"#;
+
+		let server: Server = serde_json::from_str(json).unwrap();
+		let forge_data = server.forge_data.as_ref().unwrap();
+		assert_eq!(forge_data.fml_network_version, Some(3));
+		assert_eq!(forge_data.channels.as_ref().map(Vec::len), Some(1));
+		assert_eq!(server.mod_count(), None);
+		assert!(server.mods_truncated());
+		assert_eq!(server.get_type(), "Lexforge");
+	}
+
+	#[test]
+	fn test_is_modded_flag() {
+		let server = |version: &str, extra: &str| -> Server {
+			let json = status_json(&format!(
+				r#""version": {{"name": "{}", "protocol": 767}}, "description": "Hi"{}"#,
+				version, extra
+			));
+			serde_json::from_str(&json).unwrap()
+		};
+		let forge_data = r#", "forgeData": {"mods": [{"modId": "jei", "modmarker": "19.8.0"}], "fmlNetworkVersion": 3}"#;
+
+		// Present but false says nothing about the server
+		assert_eq!(server("1.21.1", r#", "isModded": false"#).get_type(), "Java");
+		assert_eq!(server("Paper 1.21.1", r#", "isModded": false"#).get_type(), "Paper");
+		assert!(!server("1.21.1", r#", "isModded": false"#).is_modded());
+		assert_eq!(server("1.21.1", &format!(r#", "isModded": false{}"#, forge_data)).get_type(), "Lexforge");
+
+		assert_eq!(server("1.21.1", &format!(r#", "isModded": true{}"#, forge_data)).get_type(), "Neoforge");
+		assert_eq!(server("1.21.1", r#", "isModded": true"#).get_type(), "Neoforge");
+		// Without forgeData the version name wins over the flag
+		assert_eq!(server("Purpur 1.21.1", r#", "isModded": true"#).get_type(), "Purpur");
+		assert_eq!(server("Forge 1.20.1", r#", "isModded": true"#).get_type(), "Lexforge");
+		assert_eq!(server("1.21.1", r#", "isModded": true, "channels": ["fabric:registry/sync"]"#).get_type(), "Fabric");
+		assert_eq!(server("1.21.1", r#", "channels": ["fabric:registry/sync", "quilt:registry_sync"]"#).get_type(), "Quilt");
+		// Only the namespace counts, channels from plugins or other loaders don't make a server Fabric
+		assert_eq!(server("Paper 1.21.1", r#", "channels": ["bungeecord:main"]"#).get_type(), "Paper");
+		assert_eq!(server("1.21.1", r#", "channels": ["myfabric:sync", "minecraft:brand"]"#).get_type(), "Java");
+		assert_eq!(server("NeoForge 1.21.1", "").get_type(), "Neoforge");
+		let listed = r#", "forgeData": {"mods": [{"modId": "neoforge", "modmarker": "21.1.77"}], "fmlNetworkVersion": 4}"#;
+		assert_eq!(server("1.21.1", listed).get_type(), "Neoforge");
+	}
+
+	#[test]
+	fn test_legacy_modinfo() {
+		let json = r#"{
+			"version": {"name": "1.7.10", "protocol": 5},
+			"players": {"max": 20, "online": 3},
+			"description": "A legacy Forge server",
+			"modinfo": {
+				"type": "FML",
+				"modList": [{"modid": "mcp", "version": "9.05"}, {"modid": "FML", "version": "7.10.99.99"}, {"modid": "Forge", "version": "10.13.4.1614"}]
+			}
+		}"#;
+
+		let server: Server = serde_json::from_str(json).unwrap();
+		let forge_data = server.forge_data.as_ref().unwrap();
+		assert_eq!(forge_data.mods[0].id, "mcp");
+		assert!(forge_data.fml_network_version.is_none());
+		assert_eq!(server.mod_count(), Some(3));
+		assert!(!server.mods_truncated());
+	}
+
+	fn parse_sample(sample: &str) -> Server {
+		let json = status_json(&format!(
+			r#""version": {{"name": "1.21", "protocol": 767}}, "players": {{"max": 20, "online": 2{}}}"#,
+			sample
+		));
+		serde_json::from_str(&json).unwrap()
+	}
+
+	#[test]
+	fn test_sample_shapes() {
+		assert!(parse_sample("").players.sample.is_none());
+		assert!(parse_sample(r#", "sample": null"#).players.sample.is_none());
+		assert!(parse_sample(r#", "sample": "Steve""#).players.sample.is_none());
+
+		let single = parse_sample(r#", "sample": {"id": "4566e69f-c907-48ee-8d71-d7ba5aa00d20", "name": "Steve"}"#);
+		assert_eq!(single.players.sample.unwrap()[0].name, "Steve");
+
+		let extra = parse_sample(
+			r#", "sample": [{"id": "4566e69f-c907-48ee-8d71-d7ba5aa00d20", "name": "§aSteve", "displayName": "Steve"}]"#,
+		);
+		assert_eq!(extra.players.sample.unwrap()[0].name, "§aSteve");
+	}
+
+	#[test]
+	fn test_sample_skips_bad_entries() {
+		let server = parse_sample(
+			r#", "sample": [{"id": "4566e69f-c907-48ee-8d71-d7ba5aa00d20", "name": "Steve"}, {"name": "No id"}, 5, null]"#,
+		);
+
+		let sample = server.players.sample.unwrap();
+		assert_eq!(sample.len(), 1);
+		assert_eq!(sample[0].name, "Steve");
+		assert_eq!(server.players.online, 2);
+	}
+
+	#[test]
+	fn test_canonical_version() {
+		let proxy = parse_sample("");
+		assert_eq!(proxy.canonical_version().as_deref(), Some("1.21-1.21.1"));
+
+		let json = r#"{"version": {"name": "§cJoin now!", "protocol": 47}, "players": {"max": 1, "online": 0}}"#;
+		let server: Server = serde_json::from_str(json).unwrap();
+		assert_eq!(server.canonical_version().as_deref(), Some("1.8-1.8.9"));
+
+		let json = r#"{"version": {"name": "24w14a", "protocol": 1073742009}, "players": {"max": 1, "online": 0}}"#;
+		let server: Server = serde_json::from_str(json).unwrap();
+		assert!(server.canonical_version().is_none());
+	}
+
+	#[test]
+	fn test_favicon_hash() {
+		let mut server = parse_sample("");
+		assert!(server.favicon_hash().is_none());
+
+		// 1x1 PNG
+		server.favicon = Some(
+			"data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8z8BQDwAEhQGAhKmMIQAAAABJRU5ErkJggg=="
+				.to_string(),
+		);
+		assert_eq!(server.favicon_hash().as_deref(), Some("c414cd0e204de974f73753c7e28d7638"));
+
+		// Stored as bytes and turned back into the same data URL
+		let image = server.decoded_favicon().unwrap();
+		assert_eq!(&image[1..4], b"PNG");
+		assert_eq!(Some(favicon_data_uri(&image)), server.favicon);
+
+		// Broken base64 can't be stored as an image but still gets a hash
+		server.favicon = Some("data:image/png;base64,not base64!".to_string());
+		assert!(server.decoded_favicon().is_none());
+		assert!(server.favicon_hash().is_some());
+	}
+}
@@ -1,36 +1,73 @@
 use crate::bot_scanner::BotScanner;
-use crate::config::{Config, ScanEngine};
-use crate::database::Database;
+use crate::config::{Config, RescanOrder, ScanEngine};
+use crate::database::{connect_due, Database, EventType, Severity, RESCAN_DUE};
+use crate::geoip::{self, GeoIp};
+use crate::health::Health;
+use crate::dns;
+use crate::fronting;
+use crate::backpressure::DatabaseBreaker;
+use crate::blocklist::{BlockSet, Blocklist};
+use crate::ping::{ping_server, PingMethod, PingOptions};
 use crate::protocol::PingableServer;
-use crate::response::Server;
+use crate::schedule::{Schedule, UtcTime};
+use crate::sink::{self, ServerSink};
+use crate::stats;
+use crate::status::{self, StatusFile};
+use crate::rate_limit::PrefixRateLimiter;
+use crate::reparse;
+use crate::rescan_filter::RescanFilter;
+use crate::target_source::{self, Target, TargetSource};
 use crate::targeting;
+use crate::utils::{jittered, RunError};
+use crate::write_queue::WriteQueue;
+use clap::ValueEnum;
 use futures_util::StreamExt;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{HumanDuration, ProgressBar, ProgressStyle};
+use sqlx::postgres::PgArguments;
+use sqlx::query::Query;
 use sqlx::types::ipnet::{IpNet, Ipv4Net};
 use sqlx::{Pool, Postgres, Row};
+use std::collections::HashSet;
 use std::fmt::Debug;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
 use std::net::{Ipv4Addr, SocketAddrV4};
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::{Duration, SystemTime};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock, Mutex};
 use rand::Rng;
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
-use tokio::sync::Semaphore;
+use tokio::process::{Child, Command};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 use tracing::{debug, error, info, warn};
 
-pub static PERMITS: Semaphore = Semaphore::const_new(1000);
+pub static PERMITS: LazyLock<Arc<Semaphore>> = LazyLock::new(|| Arc::new(Semaphore::new(1000)));
 pub const TIMEOUT_SECS: Duration = Duration::from_secs(5);
+// Wait before the first retry of a timed out ping, doubled for each one after
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+// Where masscan saves its progress when interrupted with Ctrl+C, always in its working directory
+const MASSCAN_PAUSED_FILE: &str = "paused.conf";
+// How long an interrupted masscan gets to save its state and exit before it's killed. It
+// waits 10 seconds for late replies by default
+const MASSCAN_INTERRUPT_GRACE: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Default)]
 pub struct ScanBuilder {
 	config: Config,
 	mode: Mode,
 	pool: Option<Pool<Postgres>>,
+	replicas: Vec<Pool<Postgres>>,
+	blocklist: Option<Arc<Blocklist>>,
+	rate_limiter: Option<Arc<PrefixRateLimiter>>,
+	write_queue: Option<Arc<WriteQueue>>,
+	health: Option<Arc<Health>>,
+	geoip: Option<Arc<GeoIp>>,
+	target_source: Option<Box<dyn TargetSource>>,
 }
 
 impl ScanBuilder {
@@ -44,26 +81,98 @@ impl ScanBuilder {
 		self
 	}
 
+	/// Read replicas rescans stream servers from, writes still go to `pool`
+	pub fn replicas(mut self, replicas: Vec<Pool<Postgres>>) -> ScanBuilder {
+		self.replicas = replicas;
+		self
+	}
+
 	pub fn mode(mut self, mode: Mode) -> ScanBuilder {
 		self.mode = mode;
 		self
 	}
 
+	pub fn blocklist(mut self, blocklist: Option<Arc<Blocklist>>) -> ScanBuilder {
+		self.blocklist = blocklist;
+		self
+	}
+
+	/// Shares a rate limiter with other scanners, otherwise one is made from the config
+	pub fn rate_limiter(mut self, rate_limiter: Option<Arc<PrefixRateLimiter>>) -> ScanBuilder {
+		self.rate_limiter = rate_limiter;
+		self
+	}
+
+	/// Reports passes and finished pings to the health check server
+	pub fn health(mut self, health: Option<Arc<Health>>) -> ScanBuilder {
+		self.health = health;
+		self
+	}
+
+	/// Local databases countries and ASNs are looked up in, opened once and shared by every scanner
+	pub fn geoip(mut self, geoip: Option<Arc<GeoIp>>) -> ScanBuilder {
+		self.geoip = geoip;
+		self
+	}
+
+	/// Where servers go when the database fails to take them, shared by every scanner
+	pub fn write_queue(mut self, write_queue: Option<Arc<WriteQueue>>) -> ScanBuilder {
+		self.write_queue = write_queue;
+		self
+	}
+
+	/// Where discovery gets its targets, otherwise the source is picked from the targeting config
+	pub fn target_source(mut self, target_source: Box<dyn TargetSource>) -> ScanBuilder {
+		self.target_source = Some(target_source);
+		self
+	}
+
 	pub fn build(self) -> Scanner {
 		let initial_delay = self.config.scanner.adaptive.min_delay_ms;
+		let rate_limiter = self
+			.rate_limiter
+			.or_else(|| PrefixRateLimiter::new(&self.config.scanner.rate_limit).map(Arc::new));
+		let discovery_permits = self.config.discovery.concurrency.map(|permits| Arc::new(Semaphore::new(permits)));
+		let database_breaker = DatabaseBreaker::new(&self.config.scanner.backpressure).map(Arc::new);
+		let database = match self.pool {
+			Some(pool) => Database::new(pool).with_replicas(self.replicas),
+			None => {
+				error!("Failed to connect to database!");
+				std::process::exit(1);
+			}
+		};
+		let sinks = sink::build_sinks(
+			database.clone(),
+			database_breaker.clone(),
+			self.write_queue,
+			Duration::from_secs(self.config.rescanner.backoff.opt_out_secs),
+			&self.config.sinks,
+		);
+		let status = self.config.scanner.status_file.as_deref().map(|path| {
+			let mode = self.mode.to_possible_value().map_or_else(String::new, |v| v.get_name().to_string());
+			Arc::new(StatusFile::new(self.config.paths.resolve(path), &mode))
+		});
+		let target_source = self
+			.target_source
+			.unwrap_or_else(|| target_source::from_config(&self.config, &database));
+
 		Scanner {
 			config: self.config,
 			mode: self.mode,
-			database: {
-				match self.pool {
-					Some(pool) => Database::new(pool),
-					None => {
-						error!("Failed to connect to database!");
-						std::process::exit(1);
-					}
-				}
-			},
+			database,
 			current_delay: Arc::new(AtomicU64::new(initial_delay)),
+			rate_limiter,
+			database_breaker,
+			sinks: Arc::new(sinks),
+			status,
+			filtered: Arc::new(AtomicU64::new(0)),
+			reserved: Arc::new(AtomicU64::new(0)),
+			failures: Arc::default(),
+			blocklist: self.blocklist,
+			discovery_permits,
+			health: self.health,
+			geoip: self.geoip,
+			target_source: Mutex::new(Some(target_source)),
 		}
 	}
 }
@@ -74,12 +183,106 @@ pub enum Mode {
 	Discovery,
 	Rescanner,
 	BotScan,
+	/// Prints a summary of the servers table
+	Stats,
+	/// Runs stored raw status responses through the current parser
+	Reparse,
+	/// Runs discovery and the rescanner side by side
+	Both,
+	/// Reports applied migrations and missing tables, --repair fixes what it can
+	DbCheck,
+	/// Adds the servers listed in --input (CSV or JSON Lines) to the servers table
+	Import,
+	/// Fills in the country and ASN of stored servers from the GeoIP databases
+	BackfillGeo,
+	/// Prints events from the scan log as scanners write them, until stopped
+	WatchEvents,
 }
 
-#[derive(Debug, Clone)]
-enum Target {
-	File(PathBuf),
-	Direct(String),
+impl Mode {
+	/// Modes that run once and exit instead of being restarted
+	pub fn is_one_shot(&self) -> bool {
+		matches!(self, Mode::Stats | Mode::Reparse | Mode::DbCheck | Mode::Import | Mode::BackfillGeo)
+	}
+
+	/// The modes that actually get a scanner, `Both` runs one for discovery and one for
+	/// the rescanner so they keep separate delays, concurrency and progress
+	pub fn parts(&self) -> Vec<Mode> {
+		match self {
+			Mode::Both => vec![Mode::Discovery, Mode::Rescanner],
+			mode => vec![mode.clone()],
+		}
+	}
+}
+
+/// Append-only stream of discovered `ip:port` lines for piping into other tools
+struct FoundOutput(Box<dyn Write + Send>);
+
+impl FoundOutput {
+	/// Opens a file for appending, or stdout when the path is `-`
+	fn open(path: &str) -> std::io::Result<Self> {
+		if path == "-" {
+			return Ok(Self(Box::new(std::io::stdout())));
+		}
+
+		let file = OpenOptions::new().create(true).append(true).open(path)?;
+		Ok(Self(Box::new(file)))
+	}
+
+	fn write(&mut self, socket: SocketAddrV4) {
+		// Flush every line so the output can be tailed while the scan runs
+		if let Err(e) = writeln!(self.0, "{}", socket).and_then(|_| self.0.flush()) {
+			error!("Failed to write found host {}: {}", socket, e);
+		}
+	}
+}
+
+// Hosts remembered per engine run before the set is cleared
+const MAX_SEEN_HOSTS: usize = 1_000_000;
+
+/// Hosts already reported during one engine run. Engines repeat results on
+/// retransmits, which are close together, so the set is simply cleared when full
+#[derive(Default)]
+struct SeenHosts(HashSet<SocketAddrV4>);
+
+impl SeenHosts {
+	/// Returns false if the host was already seen
+	fn insert(&mut self, socket: SocketAddrV4) -> bool {
+		if self.0.len() >= MAX_SEEN_HOSTS && !self.0.contains(&socket) {
+			self.0.clear();
+		}
+
+		self.0.insert(socket)
+	}
+}
+
+/// Failed pings counted by `RunError` variant, indexed by the `usize` each one converts to
+#[derive(Debug, Default)]
+pub struct FailureCounts([AtomicU64; RunError::KINDS.len()]);
+
+impl FailureCounts {
+	pub fn record(&self, error: RunError) {
+		self.0[usize::from(error)].fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Non-zero counts, largest first, e.g. `timed_out: 120, io: 4`
+	pub fn summary(&self) -> String {
+		let mut counts: Vec<(&str, u64)> = RunError::KINDS
+			.iter()
+			.zip(&self.0)
+			.map(|(kind, count)| (*kind, count.load(Ordering::Relaxed)))
+			.filter(|(_, count)| *count > 0)
+			.collect();
+		counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+		counts.iter().map(|(kind, count)| format!("{}: {}", kind, count)).collect::<Vec<_>>().join(", ")
+	}
+
+	fn reset(&self) {
+		for count in &self.0 {
+			count.store(0, Ordering::Relaxed);
+		}
+	}
 }
 
 #[derive(Debug)]
@@ -88,27 +291,157 @@ pub struct Scanner {
 	pub mode: Mode,
 	pub database: Database,
 	pub current_delay: Arc<AtomicU64>,
+	pub rate_limiter: Option<Arc<PrefixRateLimiter>>,
+	pub database_breaker: Option<Arc<DatabaseBreaker>>,
+	pub sinks: Arc<Vec<Box<dyn ServerSink>>>,
+	pub status: Option<Arc<StatusFile>>,
+	/// Servers left out by the version filter during the current pass
+	pub filtered: Arc<AtomicU64>,
+	/// Reserved addresses skipped during the current pass
+	pub reserved: Arc<AtomicU64>,
+	/// Failed pings during the current pass, by reason
+	pub failures: Arc<FailureCounts>,
+	pub blocklist: Option<Arc<Blocklist>>,
+	/// Bounds the pings discovery has in flight, separate from the rescanner's permits
+	pub discovery_permits: Option<Arc<Semaphore>>,
+	pub health: Option<Arc<Health>>,
+	pub geoip: Option<Arc<GeoIp>>,
+	/// Taken by discovery when it starts
+	target_source: Mutex<Option<Box<dyn TargetSource>>>,
+}
+
+/// Everything a ping task needs, cloned into each spawned task
+#[derive(Debug, Clone)]
+struct PingContext {
+	database: Database,
+	config: Config,
+	current_delay: Arc<AtomicU64>,
+	rate_limiter: Option<Arc<PrefixRateLimiter>>,
+	sinks: Arc<Vec<Box<dyn ServerSink>>>,
+	status: Option<Arc<StatusFile>>,
+	filtered: Arc<AtomicU64>,
+	reserved: Arc<AtomicU64>,
+	// Engine that found the host, unset when rescanning
+	discovered_by: Option<ScanEngine>,
+	// Rescans of a stored address and port, failed pings count toward its backoff
+	stored: bool,
+	blocklist: Option<Arc<Blocklist>>,
+	health: Option<Arc<Health>>,
+	geoip: Option<Arc<GeoIp>>,
+}
+
+impl PingContext {
+	/// Checks the blocklist and, unless private ranges are allowed, the reserved ranges. Blocked
+	/// hosts are never contacted and count as failures in the status file
+	fn blocked(&self, socket: SocketAddrV4) -> bool {
+		if !self.config.targeting.allow_private && targeting::is_reserved(*socket.ip()) {
+			debug!("Skipping {}, it's in a reserved range", socket);
+			self.reserved.fetch_add(1, Ordering::Relaxed);
+			if let Some(status) = &self.status {
+				status.record_failure("reserved");
+			}
+			return true;
+		}
+
+		let blocked = self.blocklist.as_ref().is_some_and(|blocklist| blocklist.contains(*socket.ip()));
+		if blocked {
+			debug!("Skipping {}, it's on the blocklist", socket);
+			if let Some(status) = &self.status {
+				status.record_failure("blocked");
+			}
+		}
+		blocked
+	}
 }
 
 impl Scanner {
 	/// Creates a new instance of a ScanBuilder
+	#[allow(clippy::new_ret_no_self)]
 	pub fn new() -> ScanBuilder {
 		ScanBuilder::default()
 	}
 
 	/// Starts the scanner based on the selected mode
 	pub async fn start(&self) {
-		if !self.config.scanner.repeat {
-			warn!("Repeat is not enabled in config file! Will only scan once!");
+		if let Some(status) = &self.status {
+			let status = status.clone();
+			tokio::spawn(async move { status.write_periodically(status::WRITE_INTERVAL).await });
 		}
 
 		match self.mode {
 			Mode::Discovery => self.discovery().await,
 			Mode::Rescanner => self.rescan().await,
 			Mode::BotScan => {
+				self.pass_started();
 				let bot_scanner = BotScanner::new(self.config.bot.clone(), self.database.clone());
-				bot_scanner.start().await;
+				if let Err(e) = bot_scanner.start().await {
+					error!("Bot scanner stopped: {:#}", e);
+				}
+				self.pass_finished();
+			}
+			Mode::Stats => {
+				if let Err(e) = stats::print_stats(&self.database, self.config.country_tracking.enabled).await {
+					error!("Failed to query stats: {}", e);
+				}
+			}
+			Mode::Reparse => {
+				if let Err(e) = reparse::reparse(&self.database, self.config.rescanner.limit).await {
+					error!("Failed to reparse stored responses: {}", e);
+				}
 			}
+			Mode::Both => error!("Both has to be started as its parts, see Mode::parts"),
+			// Runs before migrations, which might be what's broken
+			Mode::DbCheck => error!("The database check is run by main before migrations"),
+			Mode::Import => error!("Imports are run by main, they need the --input file"),
+			Mode::WatchEvents => error!("Watching events is run by main, it takes the event filters"),
+			Mode::BackfillGeo => match &self.geoip {
+				Some(geoip) => {
+					if let Err(e) = geoip::backfill(&self.database, geoip, self.config.rescanner.limit).await {
+						error!("Failed to backfill countries and ASNs: {}", e);
+					}
+				}
+				None => error!("No GeoIP database could be opened, set geoip.databases"),
+			},
+		}
+	}
+
+	/// When the pass starting now has to stop, if passes have a maximum runtime
+	fn pass_deadline(&self) -> Option<Instant> {
+		self.config.scanner.max_pass_duration_secs.map(|secs| Instant::now() + Duration::from_secs(secs))
+	}
+
+	/// How often progress is logged when there's no terminal to draw it on
+	fn progress_log_interval(&self) -> Option<Duration> {
+		Some(self.config.scanner.progress_log_secs).filter(|secs| *secs > 0).map(Duration::from_secs)
+	}
+
+	fn pass_started(&self) {
+		if let Some(health) = &self.health {
+			health.pass_started();
+		}
+	}
+
+	fn pass_finished(&self) {
+		if let Some(health) = &self.health {
+			health.pass_finished();
+		}
+	}
+
+	fn ping_context(&self) -> PingContext {
+		PingContext {
+			database: self.database.clone(),
+			config: self.config.clone(),
+			current_delay: self.current_delay.clone(),
+			rate_limiter: self.rate_limiter.clone(),
+			sinks: self.sinks.clone(),
+			status: self.status.clone(),
+			filtered: self.filtered.clone(),
+			reserved: self.reserved.clone(),
+			discovered_by: None,
+			stored: false,
+			blocklist: self.blocklist.clone(),
+			health: self.health.clone(),
+			geoip: self.geoip.clone(),
 		}
 	}
 
@@ -126,18 +459,58 @@ impl Scanner {
 		Duration::from_millis(base_delay + jitter)
 	}
 
+	/// Logs how many servers the version filter kept out of the database this pass
+	fn report_filtered(&self) {
+		let filtered = self.filtered.swap(0, Ordering::Relaxed);
+		if filtered > 0 {
+			info!("{} servers outside the version filter were not stored", filtered);
+		}
+
+		let reserved = self.reserved.swap(0, Ordering::Relaxed);
+		if reserved > 0 {
+			info!("Skipped {} servers in reserved ranges, set targeting.allow_private to ping them", reserved);
+		}
+
+		let failures = self.failures.summary();
+		if !failures.is_empty() {
+			info!("Failed pings this pass: {}", failures);
+		}
+		self.failures.reset();
+	}
+
+	/// Waits before dispatching the next ping, holding off while the database catches up
+	async fn wait_before_dispatch(&self) {
+		tokio::time::sleep(self.get_sleep_duration()).await;
+
+		if let Some(breaker) = &self.database_breaker {
+			breaker.wait_until_healthy().await;
+		}
+	}
+
 	/// Rescan servers already found in the database
 	async fn rescan(&self) {
 		self.database.log_event(
 			None,
-			"INFO".to_string(),
-			"SCAN_START".to_string(),
-			format!(
-				"Rescan started. Ports: {}-{}",
-				self.config.scanner.port_range_start, self.config.scanner.port_range_end
-			),
+			Severity::Info,
+			EventType::ScanStart,
+			match self.config.rescanner.include_config_range {
+				true => format!(
+					"Rescan started. Ports: known and {}-{}",
+					self.config.scanner.port_range_start, self.config.scanner.port_range_end
+				),
+				false => "Rescan started. Ports: known".to_string(),
+			},
 		);
 
+		let filter = match self.config.rescanner.filter.as_deref().map(str::parse::<RescanFilter>) {
+			Some(Ok(filter)) => Some(filter),
+			Some(Err(e)) => {
+				error!("Invalid rescan filter: {}", e);
+				std::process::exit(1);
+			}
+			None => None,
+		};
+
 		loop {
 			let start_time = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
 				Ok(n) => n.as_secs(),
@@ -145,40 +518,127 @@ impl Scanner {
 			};
 
 			let ports = self.config.scanner.port_range_start..=self.config.scanner.port_range_end;
-			let (tx, mut rx) = tokio::sync::mpsc::channel::<SocketAddrV4>(10);
+			let limit = self.config.rescanner.limit;
+			// Triage passes leave out servers that have been unreachable for a while
+			let max_connect_failures = self
+				.config
+				.rescanner
+				.triage
+				.then_some(self.config.rescanner.max_connect_failures);
+			let order = self.config.rescanner.order;
+			// Sockets with whether they're stored, the extra config range ports aren't
+			let (tx, mut rx) = tokio::sync::mpsc::channel::<(SocketAddrV4, bool)>(10);
+
+			self.database.next_replica();
+			self.pass_started();
+			if let Some(status) = &self.status {
+				let target = match &self.config.rescanner.targets_file {
+					Some(path) => path.display().to_string(),
+					None => "database".to_string(),
+				};
+				status.start_pass(Some(target));
+			}
 
-			let mut stream = sqlx::query(
-				"SELECT (address - '0.0.0.0'::inet) AS address FROM servers ORDER BY last_seen ASC",
-			)
-			.fetch(&self.database.0);
+			let total = if let Some(path) = &self.config.rescanner.targets_file {
+				// Only rescan the provided set of addresses
+				let content = match targeting::read_target_file(path) {
+					Ok(c) => c,
+					Err(e) => {
+						error!("Failed to read targets file {}: {:#}", path.display(), e);
+						std::process::exit(1);
+					}
+				};
 
-			// Spawn a task to produce values and send them down the transmitter
-			tokio::spawn(async move {
-				// Streams results from database. This works great for memory usage
-				while let Some(Ok(row)) = stream.next().await {
-					let address = match row.try_get::<i64, _>("address") {
-						Ok(a) => Ipv4Addr::from_bits(a as u32),
-						Err(_) => continue,
-					};
-
-					// Run for each port specified in config
-					//
-					// NOTE: clone is needed because RangeInclusive<T> doesn't implement copy
-					// This should be optimized away anyway
-					for port in ports.clone() {
-						match tx.send(SocketAddrV4::new(address, port)).await {
-							Ok(_) => {}
-							Err(e) => debug!("send channel has been closed! {e}"),
+				let mut targets = targeting::parse_socket_list(&content, ports);
+				if let Some(limit) = limit {
+					targets.truncate(limit as usize);
+				}
+
+				let total = targets.len() as u64;
+				tokio::spawn(async move {
+					for socket in targets {
+						if let Err(e) = tx.send((socket, true)).await {
+							debug!("send channel has been closed! {e}");
+							return;
+						}
+					}
+				});
+
+				total
+			} else {
+				// Each row is a known address and port pair. With the config range included,
+				// the first row of each address also brings the ports it isn't known on
+				let include_range = self.config.rescanner.include_config_range;
+				let columns = if include_range {
+					"address, port, array_agg(port) OVER (PARTITION BY address) AS known_ports,
+					row_number() OVER (PARTITION BY address ORDER BY port) = 1 AS first_port"
+				} else {
+					"address, port"
+				};
+				let query = rescan_query(columns, order, filter.as_ref());
+				let pool = self.database.read_pool().clone();
+				let row_filter = filter.clone();
+				let range = include_range.then_some(ports);
+
+				// Spawn a task to produce values and send them down the transmitter
+				tokio::spawn(async move {
+					// LIMIT NULL is the same as having no limit
+					let mut stream = rescan_rows(&query, limit, max_connect_failures, order, row_filter.as_ref()).fetch(&pool);
+
+					// Streams results from database. This works great for memory usage
+					while let Some(row) = stream.next().await {
+						let row = match row {
+							Ok(row) => row,
+							Err(e) => {
+								error!("Failed to read servers to rescan: {}", e);
+								return;
+							}
+						};
+						let (address, port) = match (row.try_get::<IpNet, _>("address"), row.try_get::<i32, _>("port")) {
+							(Ok(a), Ok(p)) => match host_address(a) {
+								Some(a) => (a, p as u16),
+								None => continue,
+							},
+							_ => continue,
+						};
+
+						let range = range.clone().filter(|_| row.try_get::<bool, _>("first_port").unwrap_or(false));
+						let known_ports: Vec<i32> = row.try_get("known_ports").unwrap_or_default();
+
+						// The row's own port comes first
+						for (i, port) in rescan_ports(port, &known_ports, range).into_iter().enumerate() {
+							if let Err(e) = tx.send((SocketAddrV4::new(address, port), i == 0)).await {
+								debug!("send channel has been closed! {e}");
+								return;
+							}
 						}
 					}
+				});
+
+				let total_servers = self
+					.database
+					.count_servers(max_connect_failures, filter.as_ref())
+					.await
+					.expect("failed to count servers!");
+				let mut total = limit.map_or(total_servers, |limit| total_servers.min(limit));
+
+				if include_range {
+					let range_pings = self
+						.database
+						.count_range_pings(
+							max_connect_failures,
+							filter.as_ref(),
+							self.config.scanner.port_range_start,
+							self.config.scanner.port_range_end,
+						)
+						.await
+						.expect("failed to count servers!");
+					// Only an estimate with a limit, it cuts off rows rather than addresses
+					total += range_pings * total / total_servers.max(1);
 				}
-			});
 
-			let total_servers = self
-				.database
-				.count_servers()
-				.await
-				.expect("failed to count servers!");
+				total as u64
+			};
 
 			let style = ProgressStyle::with_template(
 				"[{elapsed_precise}] [{bar:40.white/blue}] {human_pos}/{human_len} {msg}",
@@ -186,35 +646,103 @@ impl Scanner {
 			.expect("failed to create progress bar style")
 			.progress_chars("=>-");
 
-			let bar =
-				ProgressBar::new((total_servers * self.config.scanner.total_ports() as i64) as u64)
-					.with_style(style);
+			let bar = ProgressBar::new(total).with_style(style);
+			let progress_reporter =
+				tokio::spawn(report_progress(bar.clone(), self.status.clone(), self.progress_log_interval()));
+			// Connection checks are much cheaper than full pings, so they get their own limit
+			let triage_permits = self
+				.config
+				.rescanner
+				.triage
+				.then(|| Arc::new(Semaphore::new(self.config.rescanner.triage_concurrency)));
+
+			let deadline = self.pass_deadline();
+			let mut cut_short = false;
 
 			// Consume values from the receiver
-			while let Some(socket) = rx.recv().await {
-				// Apply dynamic sleep before spawning task
-				tokio::time::sleep(self.get_sleep_duration()).await;
+			loop {
+				let (socket, stored) = match before_deadline(deadline, rx.recv()).await {
+					Some(Some(socket)) => socket,
+					Some(None) => break,
+					None => {
+						// Dropping the receiver stops the producer at its next send
+						cut_short = true;
+						break;
+					}
+				};
 
-				let permit = PERMITS.acquire().await;
+				// Apply dynamic sleep before spawning task
+				self.wait_before_dispatch().await;
 
-				let pool = self.database.clone();
+				let mut context = self.ping_context();
+				context.stored = stored;
 				let bar = bar.clone();
-				let config = self.config.clone();
-				let current_delay = self.current_delay.clone();
+				let failures = self.failures.clone();
 
-				tokio::spawn(async move {
-					// Move permit to future so it blocks the task as well
-					let _permit = permit;
+				// Checked before triage too, which would otherwise connect to it
+				if context.blocked(socket) {
+					bar.inc(1);
+					continue;
+				}
+
+				self.wait_for_rate_limit(socket).await;
+
+				if let Some(triage_permits) = &triage_permits {
+					// Both taken before spawning, so the tasks in flight stay bounded
+					let triage_permit = triage_permits.clone().acquire_owned().await;
+					let slot = PingSlot::acquire(Some(PERMITS.clone())).await;
+
+					tokio::spawn(async move {
+						// A closed port costs one short connect instead of timing out twice in a full ping
+						let open = triage_connect(socket, &context).await;
+						drop(triage_permit);
+
+						if open {
+							if let Err(e) = task_wrapper(socket, context, slot).await {
+								failures.record(e);
+								bar.set_message(failures.summary());
+							}
+						} else if let Some(status) = &context.status {
+							status.record_failure("closed");
+						}
+						bar.inc(1);
+					});
+					continue;
+				}
 
-					task_wrapper(socket, pool, config, current_delay).await;
+				let slot = PingSlot::acquire(Some(PERMITS.clone())).await;
+
+				tokio::spawn(async move {
+					if let Err(e) = task_wrapper(socket, context, slot).await {
+						failures.record(e);
+						bar.set_message(failures.summary());
+					}
 					bar.inc(1);
 				});
 			}
 
+			drop(rx);
+
 			// Sleep for 10 seconds to ensure that all tasks finish
 			tokio::time::sleep(Duration::from_secs(10)).await;
+			progress_reporter.abort();
 			bar.finish_and_clear();
 
+			if cut_short {
+				warn!(
+					"Pass cut short after {} seconds, {} of {} servers covered",
+					self.config.scanner.max_pass_duration_secs.unwrap_or_default(),
+					bar.position(),
+					bar.length().unwrap_or_default()
+				);
+			}
+			self.report_filtered();
+
+			self.pass_finished();
+			if let Some(status) = &self.status {
+				status.finish_pass();
+			}
+
 			let end_time = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
 				Ok(d) => d.as_secs(),
 				Err(_) => panic!("system time before unix epoch!"),
@@ -222,20 +750,7 @@ impl Scanner {
 
 			info!("Scan completed in {} seconds", end_time - start_time);
 
-			// Quit if only one scan is requested in config
-			if !self.config.scanner.repeat {
-				info!("Exiting");
-				std::process::exit(0);
-			}
-
-			// Wait rescan delay before starting a new scan
-			if self.config.scanner.scan_delay > 0 {
-				info!(
-					"Waiting {} seconds before starting another scan...",
-					self.config.scanner.scan_delay
-				);
-				tokio::time::sleep(Duration::from_secs(self.config.scanner.scan_delay)).await;
-			}
+			self.wait_for_next_cycle(Some(Duration::from_secs(end_time - start_time))).await;
 		}
 	}
 
@@ -243,90 +758,242 @@ impl Scanner {
 	async fn discovery(&self) {
 		self.database.log_event(
 			None,
-			"INFO".to_string(),
-			"SCAN_START".to_string(),
+			Severity::Info,
+			EventType::ScanStart,
 			format!(
-				"Engine: {:?}, Ports: {}-{}",
+				"Engine: {:?}, Ports: {}-{}{}",
 				self.config.scanner.engine,
 				self.config.scanner.port_range_start,
-				self.config.scanner.port_range_end
+				self.config.scanner.port_range_end,
+				self.sample_description().map_or_else(String::new, |sample| format!(", Sample: {}", sample)),
 			),
 		);
 
+		if let Some(sample) = self.sample_description() {
+			info!("Scanning a sample of {}, pass the same seed to repeat it", sample);
+		}
+
+		let Some(mut source) = self.target_source.lock().unwrap().take() else {
+			error!("Discovery is already running on this scanner");
+			return;
+		};
+		let mut found_output = self.config.discovery.output_file.as_deref().and_then(|path| {
+			match FoundOutput::open(path) {
+				Ok(output) => Some(output),
+				Err(e) => {
+					error!("Failed to open found hosts output {}: {}", path, e);
+					None
+				}
+			}
+		});
+
 		loop {
-			// Prepare targets
-			let target = if let Some(custom) = &self.config.targeting.custom_target {
-				Some(Target::Direct(custom.clone()))
-			} else if let Some(country) = &self.config.targeting.country {
-				match targeting::fetch_country_cidrs(country).await {
-					Ok(path) => Some(Target::File(path)),
-					Err(e) => {
-						error!("Failed to fetch targets for country {}: {}", country, e);
-						None
-					}
+			let target = match source.next_targets().await {
+				Ok(target) => target,
+				// Never fall back to scanning everything because the targets couldn't be fetched
+				Err(e) => {
+					error!("Failed to get targets from {}, skipping this cycle: {:#}", source.name(), e);
+					self.wait_for_next_cycle(None).await;
+					continue;
 				}
-			} else {
-				None
 			};
 
-			match self.config.scanner.engine {
-				ScanEngine::Masscan => self.run_masscan_once(target).await,
-				ScanEngine::Rustscan => self.run_rustscan_once(target).await,
+			self.pass_started();
+			if let Some(status) = &self.status {
+				status.start_pass(Some(match &target {
+					Some(Target::File(path)) => path.display().to_string(),
+					Some(Target::Direct(target)) => target.clone(),
+					None => "0.0.0.0/0".to_string(),
+				}));
 			}
 
-			// Quit if only one scan is requested in config
-			if !self.config.scanner.repeat {
-				info!("Exiting");
-				std::process::exit(0);
+			match self.config.scanner.engine {
+				ScanEngine::Masscan => self.run_masscan_once(target, &mut found_output).await,
+				ScanEngine::Rustscan => self.run_rustscan_once(target, &mut found_output).await,
 			}
+			self.report_filtered();
 
-			// Wait rescan delay before starting a new scan
-			if self.config.scanner.scan_delay > 0 {
-				info!(
-					"Waiting {} seconds before starting another scan",
-					self.config.scanner.scan_delay
-				);
-				tokio::time::sleep(Duration::from_secs(self.config.scanner.scan_delay)).await;
+			self.pass_finished();
+			if let Some(status) = &self.status {
+				status.finish_pass();
 			}
+
+			self.wait_for_next_cycle(None).await;
+		}
+	}
+
+	/// Cleans up before a normal exit. Directories named after the process id are never used
+	/// again, ones with a run_id are kept for the next run
+	fn remove_run_dir(&self) {
+		if self.config.paths.run_id.is_some() {
+			return;
+		}
+		let Some(run_dir) = self.config.paths.run_dir().filter(|dir| dir.exists()) else {
+			return;
+		};
+		if let Err(e) = std::fs::remove_dir_all(&run_dir) {
+			warn!("Failed to remove run directory {}: {}", run_dir.display(), e);
 		}
 	}
 
-	async fn run_masscan_once(&self, target: Option<Target>) {
-		let mut args = vec!["masscan".to_string(), "-c".to_string(), self.config.masscan.config_file.clone()];
+	/// The fraction actually scanned and the seed, masscan rounds to a whole number of shards
+	fn sample_description(&self) -> Option<String> {
+		let fraction = self.config.targeting.sample_fraction?;
+		let fraction = match self.config.scanner.engine {
+			ScanEngine::Masscan => 1.0 / targeting::sample_shards(fraction) as f64,
+			ScanEngine::Rustscan => fraction,
+		};
+
+		Some(format!("{:.3}% with seed {}", fraction * 100.0, self.config.targeting.sample_seed.unwrap_or_default()))
+	}
+
+	/// Exits if only one scan was requested, otherwise waits before the next scan. Rescans
+	/// pass how long the pass took so `rescanner.min_cycle_secs` can pad it out
+	async fn wait_for_next_cycle(&self, pass_duration: Option<Duration>) {
+		// Quit if only one scan is requested in config
+		if !self.config.scanner.repeat {
+			info!("Exiting");
+			self.remove_run_dir();
+			std::process::exit(0);
+		}
 
-	       // Safety exclusion required by masscan for large ranges
-	       args.push("--exclude".to_string());
-	       args.push("255.255.255.255".to_string());
+		// A schedule starts scans at fixed times no matter how long the last one took
+		if let Some(schedule) = &self.config.scanner.schedule {
+			let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
 
-		if let Some(t) = target {
-			match t {
-				Target::File(path) => {
-					args.push("-iL".to_string());
-					args.push(path.to_string_lossy().to_string());
+			match schedule.parse::<Schedule>().ok().and_then(|s| s.next_after(now)) {
+				Some(next) => {
+					info!("Next scan scheduled for {}", UtcTime(next));
+					tokio::time::sleep(Duration::from_secs(next - now)).await;
 				}
-				Target::Direct(cidr) => {
-					args.push(cidr);
+				None => {
+					error!("Schedule {} never runs again, exiting", schedule);
+					self.remove_run_dir();
+					std::process::exit(0);
 				}
 			}
-		} else {
-			// Scan the entire internet if no target is specified
-			args.push("0.0.0.0/0".to_string());
+
+			return;
 		}
 
-		// Determine command and args based on OS
-		let (program, final_args) = if cfg!(target_os = "windows") {
-			let local_bin = Path::new("bin/masscan.exe");
-			if local_bin.exists() {
-				(local_bin.to_string_lossy().to_string(), &args[1..])
-			} else {
-				("masscan.exe".to_string(), &args[1..])
+		if let (Some(elapsed), Some(min_cycle)) = (pass_duration, self.config.rescanner.min_cycle_secs) {
+			let sleep = cycle_sleep(elapsed, Duration::from_secs(min_cycle));
+			info!(
+				"Pass took {} seconds of a {} second minimum cycle, waiting {} seconds before the next one",
+				elapsed.as_secs(),
+				min_cycle,
+				sleep.as_secs()
+			);
+			tokio::time::sleep(sleep).await;
+			return;
+		}
+
+		// Wait rescan delay before starting a new scan
+		if self.config.scanner.scan_delay > 0 {
+			let delay = jittered(
+				Duration::from_secs(self.config.scanner.scan_delay),
+				self.config.scanner.delay_jitter_percent,
+			);
+			info!("Waiting {} seconds before starting another scan...", delay.as_secs());
+			tokio::time::sleep(delay).await;
+		}
+	}
+
+	/// Waits until the target's network is allowed another connection, before a ping permit
+	/// is taken so a throttled network doesn't sit on permits other networks could use
+	async fn wait_for_rate_limit(&self, socket: SocketAddrV4) {
+		if let Some(rate_limiter) = &self.rate_limiter {
+			rate_limiter.acquire(*socket.ip()).await;
+		}
+	}
+
+	/// What the scan engines have to leave out, the current blocklist and unless private
+	/// ranges are allowed the reserved ranges. `None` when that's nothing
+	fn exclusions(&self) -> Option<BlockSet> {
+		let mut set = self.blocklist.as_ref().map(|blocklist| (*blocklist.snapshot()).clone()).unwrap_or_default();
+		if !self.config.targeting.allow_private {
+			set = set.union(&BlockSet::from_cidrs(targeting::reserved_ranges()));
+		}
+		Some(set).filter(|set| !set.is_empty())
+	}
+
+	/// Logs how many addresses of the target are reserved, the engines are told to skip them
+	fn report_reserved_target(&self, target: &Option<Target>) {
+		if self.config.targeting.allow_private {
+			return;
+		}
+
+		let reserved = match target {
+			Some(Target::File(path)) => match targeting::read_target_file(path) {
+				Ok(content) => targeting::reserved_in_list(&content),
+				Err(_) => return,
+			},
+			Some(Target::Direct(cidr)) => targeting::reserved_in_list(cidr),
+			None => targeting::reserved_in_list("0.0.0.0/0"),
+		};
+		if reserved > 0 {
+			info!(
+				"Skipping {} reserved addresses in the target, set targeting.allow_private to scan them",
+				reserved
+			);
+		}
+	}
+
+	/// Writes the exclusions where masscan can read them, one CIDR per line
+	fn write_exclude_file(&self, set: &BlockSet) -> std::io::Result<PathBuf> {
+		let dir = self.config.paths.run_dir().unwrap_or_else(|| self.config.paths.cache_dir());
+		std::fs::create_dir_all(&dir)?;
+
+		let path = dir.join("exclude.txt");
+		let content: String = set.to_cidrs().iter().map(|cidr| format!("{}\n", cidr)).collect();
+		std::fs::write(&path, content)?;
+		Ok(path)
+	}
+
+	async fn run_masscan_once(&self, target: Option<Target>, found_output: &mut Option<FoundOutput>) {
+		let exclude_file = match self.exclusions().map(|set| self.write_exclude_file(&set)).transpose() {
+			Ok(exclude_file) => exclude_file,
+			Err(e) => {
+				error!("Failed to write the exclusions for masscan, skipping this scan: {}", e);
+				return;
+			}
+		};
+		// Masscan saves paused.conf to its working directory, with a work_dir it's one that
+		// outlives the process
+		let run_dir = self.config.paths.resume_dir();
+		if let Some(run_dir) = &run_dir {
+			if let Err(e) = std::fs::create_dir_all(run_dir) {
+				error!("Failed to create directory {}, skipping this scan: {}", run_dir.display(), e);
+				return;
 			}
+		}
+		let paused = run_dir.as_deref().unwrap_or(Path::new("")).join(MASSCAN_PAUSED_FILE);
+		let resuming = self.config.masscan.resume && paused.exists();
+		let mut args = if resuming {
+			// The saved state has the targets and excludes of the interrupted scan, this pass's
+			// target waits for the next one. Excludes can't be added, they'd shift the saved position
+			info!("Resuming the interrupted masscan scan from {}", paused.display());
+			build_masscan_resume_args(&paused)
 		} else {
-			("sudo".to_string(), &args[..])
+			self.report_reserved_target(&target);
+			build_masscan_args(&self.config, &target, exclude_file.as_deref())
 		};
+		if run_dir.is_some() {
+			make_masscan_paths_absolute(&mut args);
+		}
+
+		let binary_path = self.config.masscan.binary_path.as_deref().map(|path| match &run_dir {
+			Some(_) => std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+			None => path.to_path_buf(),
+		});
+		let (program, final_args) = self.resolve_command(&args, binary_path.as_deref());
 
 		// Spawn masscan
-		let mut command = Command::new(program)
+		let mut command = Command::new(program);
+		if let Some(run_dir) = &run_dir {
+			command.current_dir(run_dir);
+		}
+		let mut command = command
 			.args(final_args)
 			.stdout(std::process::Stdio::piped())
 			.stderr(std::process::Stdio::piped())
@@ -353,9 +1020,36 @@ impl Scanner {
 		};
 
 		let mut reader = BufReader::new(stdout).lines();
+		let mut seen = SeenHosts::default();
+		let progress = DiscoveryProgress::start("Masscan", self.progress_log_interval());
+		let mut deadline = self.pass_deadline();
+		let mut cut_short = false;
 
 		// Iterate over the lines of output from masscan
-		while let Ok(Some(line)) = reader.next_line().await {
+		loop {
+			let line = match before_deadline(deadline, reader.next_line()).await {
+				Some(Ok(Some(line))) => line,
+				Some(_) => break,
+				None if !cut_short => {
+					// Interrupted masscan saves where it stopped to paused.conf and still prints
+					// what it finds while it waits for late replies, so keep reading until it exits
+					interrupt(&mut command);
+					deadline = Some(Instant::now() + MASSCAN_INTERRUPT_GRACE);
+					cut_short = true;
+					continue;
+				}
+				None => {
+					warn!(
+						"Masscan didn't exit within {} seconds of being interrupted, killing it",
+						MASSCAN_INTERRUPT_GRACE.as_secs()
+					);
+					if let Err(e) = command.start_kill() {
+						error!("Failed to kill masscan: {}", e);
+					}
+					deadline = None;
+					continue;
+				}
+			};
 			let line_str = line.clone(); // Keep original line for parsing if needed, or just split
 			let mut line = line_str.split_whitespace();
 
@@ -376,100 +1070,199 @@ impl Scanner {
 				None => continue,
 			};
 
+			if !seen.insert(SocketAddrV4::new(address, port)) {
+				debug!("Skipping duplicate result {}:{} from masscan", address, port);
+				continue;
+			}
+			progress.found();
+
 			self.database.log_event(
 				Some(IpNet::from(Ipv4Net::from(address))),
-				"INFO".to_string(),
-				"HOST_FOUND".to_string(),
+				Severity::Info,
+				EventType::HostFound,
 				format!("Port: {} (Masscan)", port),
 			);
 
-			let pool = self.database.clone();
-			let config = self.config.clone();
-			let current_delay = self.current_delay.clone();
+			if let Some(output) = found_output {
+				output.write(SocketAddrV4::new(address, port));
+			}
+
+			let mut context = self.ping_context();
+			context.discovered_by = Some(ScanEngine::Masscan);
+			let failures = self.failures.clone();
+
+			let socket = SocketAddrV4::new(address, port);
 
 			// Wait dynamic delay
-			tokio::time::sleep(self.get_sleep_duration()).await;
+			self.wait_before_dispatch().await;
+			self.wait_for_rate_limit(socket).await;
+			let slot = PingSlot::acquire(self.discovery_permits.clone()).await;
 
 			// Spawn a pinging task for each server found
 			tokio::spawn(async move {
-				let socket = SocketAddrV4::new(address, port);
-
-				task_wrapper(socket, pool, config, current_delay).await;
+				if let Err(e) = task_wrapper(socket, context, slot).await {
+					failures.record(e);
+				}
 			});
 		}
-	}
 
-	async fn run_rustscan_once(&self, target: Option<Target>) {
-		let mut args = vec![self.config.rustscan.command.clone()];
-
-		if self.config.scanner.port_range_start != self.config.scanner.port_range_end {
-			args.push("-r".to_string());
-			args.push(format!("{}-{}", self.config.scanner.port_range_start, self.config.scanner.port_range_end));
-		} else {
-			args.push("-p".to_string());
-			args.push(self.config.scanner.port_range_start.to_string());
-		}
-
-		if let Some(t) = target {
-			args.push("-a".to_string());
-			match t {
-				Target::File(path) => args.push(path.to_string_lossy().to_string()),
-				Target::Direct(cidr_str) => {
-					// Expand CIDR to file to avoid RustScan resolution issues on Windows
-					if let Ok(net) = cidr_str.parse::<IpNet>() {
-						let temp_path = Path::new("temp_rustscan_targets.txt");
-						match File::create(temp_path) {
-							Ok(mut file) => {
-								let mut count = 0;
-								for ip in net.hosts() {
-									if let Err(_) = writeln!(file, "{}", ip) {
-										break;
-									}
-									count += 1;
-								}
-								// If it's a single IP or empty (network address only), write the address itself
-								if count == 0 {
-									let _ = writeln!(file, "{}", net.addr());
-								}
-								args.push(temp_path.to_string_lossy().to_string());
-							}
-							Err(e) => {
-								error!("Failed to create temp targets file: {}", e);
-								args.push(cidr_str); // Fallback
-							}
-						}
-					} else {
-						args.push(cidr_str);
+		if cut_short {
+			warn!(
+				"Masscan pass cut short after {} seconds with {} hosts found{}",
+				self.config.scanner.max_pass_duration_secs.unwrap_or_default(),
+				progress.count(),
+				if self.config.masscan.resume { ", the next pass resumes where it stopped" } else { "" }
+			);
+		}
+		progress.finish();
+
+		match command.wait().await {
+			// The interrupted scan's state was just saved, resumed or not
+			Ok(status) if cut_short => debug!("Masscan exited with {} after being interrupted", status),
+			// Masscan doesn't remove the file after a resumed scan finishes
+			Ok(status) if status.success() => {
+				if resuming {
+					if let Err(e) = std::fs::remove_file(&paused) {
+						warn!("Failed to remove {} after resuming from it: {}", paused.display(), e);
 					}
 				}
 			}
-		} else {
-			warn!("No targets specified for RustScan (use --country or configure targeting). Skipping scan.");
-			return;
+			Ok(status) if resuming => warn!(
+				"Masscan exited with {} while resuming, remove {} to start over if this keeps happening",
+				status,
+				paused.display()
+			),
+			Ok(status) => warn!("Masscan exited with {}", status),
+			Err(e) => error!("Failed to wait for masscan: {}", e),
 		}
+	}
 
-		args.push("--scripts".to_string());
-		args.push("none".to_string());
+	/// Splits an argv into the program to run and its arguments. A configured binary path
+	/// replaces the program name, otherwise Windows prefers the copy in the bin directory
+	/// and Unix runs through sudo unless it's turned off
+	fn resolve_command(&self, args: &[String], binary_path: Option<&Path>) -> (String, Vec<String>) {
+		let name = binary_path.map_or_else(|| args[0].clone(), |path| path.to_string_lossy().to_string());
 
-		info!("Starting RustScan: {:?}", args);
+		if cfg!(target_os = "windows") {
+			if binary_path.is_some() {
+				return (name, args[1..].to_vec());
+			}
 
-		// Determine command and args based on OS
-		let (program, final_args) = if cfg!(target_os = "windows") {
-			let local_bin = Path::new("bin/rustscan.exe");
+			let exe = if name.to_lowercase().ends_with(".exe") { name } else { name + ".exe" };
+			let local_bin = self.config.paths.bin_dir().join(&exe);
 			if local_bin.exists() {
-				(local_bin.to_string_lossy().to_string(), &args[1..])
+				(local_bin.to_string_lossy().to_string(), args[1..].to_vec())
 			} else {
-				let cmd = self.config.rustscan.command.clone();
-				if !cmd.to_lowercase().ends_with(".exe") {
-					(cmd + ".exe", &args[1..])
-				} else {
-					(cmd, &args[1..])
-				}
+				(exe, args[1..].to_vec())
 			}
+		} else if self.config.scanner.use_sudo {
+			let mut sudo_args = vec![name];
+			sudo_args.extend_from_slice(&args[1..]);
+			("sudo".to_string(), sudo_args)
 		} else {
-			("sudo".to_string(), &args[..])
+			(name, args[1..].to_vec())
+		}
+	}
+
+	/// Writes the sampled addresses of a target to a file for RustScan, None when that fails
+	/// since scanning the whole target instead would be far more than was asked for
+	fn sample_rustscan_target(&self, target: Target, fraction: f64) -> Option<Target> {
+		let content = match &target {
+			Target::Direct(cidr) => cidr.clone(),
+			Target::File(path) => match targeting::read_target_file(path) {
+				Ok(content) => content,
+				Err(e) => {
+					error!("Failed to read {} to sample it: {:#}", path.display(), e);
+					return None;
+				}
+			},
 		};
 
+		let temp_dir = self.config.paths.temp_dir();
+		if let Err(e) = std::fs::create_dir_all(&temp_dir) {
+			error!("Failed to create temp directory {}: {}", temp_dir.display(), e);
+		}
+
+		let path = temp_dir.join("temp_rustscan_sample.txt");
+		let seed = self.config.targeting.sample_seed.unwrap_or_default();
+		match targeting::write_sampled_hosts(&content, fraction, seed, &path) {
+			Ok(count) => {
+				info!("Sampled {} addresses to scan with seed {}", count, seed);
+				Some(Target::File(path))
+			}
+			Err(e) => {
+				error!("Failed to sample targets: {:#}", e);
+				None
+			}
+		}
+	}
+
+	/// Expands a CIDR into a file of addresses to avoid RustScan resolution issues on Windows.
+	/// Falls back to the original target if the file can't be written
+	fn expand_rustscan_target(&self, target: Target) -> Target {
+		let Target::Direct(cidr_str) = &target else {
+			return target;
+		};
+
+		let Ok(net) = cidr_str.parse::<IpNet>() else {
+			return target;
+		};
+
+		let temp_dir = self.config.paths.temp_dir();
+		if let Err(e) = std::fs::create_dir_all(&temp_dir) {
+			error!("Failed to create temp directory {}: {}", temp_dir.display(), e);
+		}
+
+		let temp_path = temp_dir.join("temp_rustscan_targets.txt");
+		match File::create(&temp_path) {
+			Ok(mut file) => {
+				let mut count = 0;
+				for ip in net.hosts() {
+					if writeln!(file, "{}", ip).is_err() {
+						break;
+					}
+					count += 1;
+				}
+				// If it's a single IP or empty (network address only), write the address itself
+				if count == 0 {
+					let _ = writeln!(file, "{}", net.addr());
+				}
+				Target::File(temp_path)
+			}
+			Err(e) => {
+				error!("Failed to create temp targets file: {}", e);
+				target
+			}
+		}
+	}
+
+	async fn run_rustscan_once(&self, target: Option<Target>, found_output: &mut Option<FoundOutput>) {
+		if target.is_none() {
+			warn!("No targets specified for RustScan (use --country or configure targeting). Skipping scan.");
+			return;
+		}
+
+		let target = match (target, self.config.targeting.sample_fraction) {
+			(Some(target), Some(fraction)) => match self.sample_rustscan_target(target, fraction) {
+				Some(target) => Some(target),
+				None => {
+					warn!("Skipping this scan, its targets couldn't be sampled");
+					return;
+				}
+			},
+			(target, None) => target.map(|t| self.expand_rustscan_target(t)),
+			(None, _) => None,
+		};
+		self.report_reserved_target(&target);
+		let exclude = self.exclusions().map_or_else(Vec::new, |set| set.to_cidrs());
+		let Some(args) = build_rustscan_args(&self.config, &target, &exclude) else {
+			return;
+		};
+
+		info!("Starting RustScan: {:?}", args);
+
+		let (program, final_args) = self.resolve_command(&args, self.config.rustscan.binary_path.as_deref());
+
 		let mut command = Command::new(program)
 			.args(final_args)
 			.stdout(std::process::Stdio::piped())
@@ -498,79 +1291,613 @@ impl Scanner {
 		}
 
 		let mut reader = BufReader::new(stdout).lines();
+		let mut seen = SeenHosts::default();
+		let progress = DiscoveryProgress::start("RustScan", self.progress_log_interval());
+		let mut unrecognized = 0u64;
+		let deadline = self.pass_deadline();
 
-		while let Ok(Some(line)) = reader.next_line().await {
-			info!("RustScan output: {}", line); // Log output for debug
-			if !line.starts_with("Open") {
-				continue;
+		loop {
+			let line = match before_deadline(deadline, reader.next_line()).await {
+				Some(Ok(Some(line))) => line,
+				Some(_) => break,
+				None => {
+					// RustScan has no way to resume, so it's simply stopped
+					if let Err(e) = command.kill().await {
+						error!("Failed to stop RustScan: {}", e);
+					}
+					warn!(
+						"RustScan pass cut short after {} seconds with {} hosts found",
+						self.config.scanner.max_pass_duration_secs.unwrap_or_default(),
+						progress.count()
+					);
+					break;
+				}
+			};
+			debug!("RustScan output: {}", line);
+			let sockets = parse_rustscan_line(&line);
+			// Human readable output is mostly banners and progress, only greppable output is all results
+			if self.config.rustscan.greppable && sockets.is_empty() && !line.trim().is_empty() {
+				unrecognized += 1;
 			}
 
-			let parts: Vec<&str> = line.split_whitespace().collect();
-			if parts.len() < 2 { continue; }
+			for socket in sockets {
+				let (address, port) = (*socket.ip(), socket.port());
+				if !seen.insert(socket) {
+					debug!("Skipping duplicate result {}:{} from RustScan", address, port);
+					continue;
+				}
+				progress.found();
+
+				self.database.log_event(
+					Some(IpNet::from(Ipv4Net::from(address))),
+					Severity::Info,
+					EventType::HostFound,
+					format!("Port: {} (Rustscan)", port),
+				);
 
-			let ip_port = parts[1];
-			let Some((ip_str, port_str)) = ip_port.split_once(':') else { continue };
+				if let Some(output) = found_output {
+					output.write(socket);
+				}
 
-			let Ok(address) = Ipv4Addr::from_str(ip_str) else { continue };
-			let Ok(port) = port_str.parse::<u16>() else { continue };
+				let mut context = self.ping_context();
+				context.discovered_by = Some(ScanEngine::Rustscan);
+				let failures = self.failures.clone();
 
-			self.database.log_event(
-				Some(IpNet::from(Ipv4Net::from(address))),
-				"INFO".to_string(),
-				"HOST_FOUND".to_string(),
-				format!("Port: {} (Rustscan)", port),
+				// Wait dynamic delay
+				self.wait_before_dispatch().await;
+				self.wait_for_rate_limit(socket).await;
+				let slot = PingSlot::acquire(self.discovery_permits.clone()).await;
+
+				tokio::spawn(async move {
+					if let Err(e) = task_wrapper(socket, context, slot).await {
+						failures.record(e);
+					}
+				});
+			}
+		}
+
+		// An output format this parser doesn't know would otherwise look like an empty scan
+		if progress.count() == 0 && unrecognized > 0 {
+			warn!(
+				"None of the {} lines RustScan printed were results it could read, if this RustScan \
+				doesn't support greppable output set rustscan.greppable = false",
+				unrecognized
 			);
+		}
+		progress.finish();
+	}
+}
 
-			let pool = self.database.clone();
-			let config = self.config.clone();
-			let current_delay = self.current_delay.clone();
+/// Reads the open ports in a line of RustScan output. Greppable output (`-g`) lists every
+/// open port of an address at once, `203.0.113.7 -> [25565,25566]`, otherwise each port
+/// has its own `Open 203.0.113.7:25565` line. Anything else gives nothing
+fn parse_rustscan_line(line: &str) -> Vec<SocketAddrV4> {
+	let line = strip_ansi(line);
+	let line = line.trim();
 
-			// Wait dynamic delay
-			tokio::time::sleep(self.get_sleep_duration()).await;
+	if let Some((address, ports)) = line.split_once("->") {
+		let Ok(address) = address.trim().parse::<Ipv4Addr>() else {
+			return Vec::new();
+		};
+		let Some(ports) = ports.trim().strip_prefix('[').and_then(|ports| ports.strip_suffix(']')) else {
+			return Vec::new();
+		};
 
-			tokio::spawn(async move {
-				let socket = SocketAddrV4::new(address, port);
-				task_wrapper(socket, pool, config, current_delay).await;
-			});
+		return ports
+			.split(',')
+			.filter_map(|port| port.trim().parse::<u16>().ok())
+			.map(|port| SocketAddrV4::new(address, port))
+			.collect();
+	}
+
+	line.strip_prefix("Open")
+		.and_then(|rest| rest.split_whitespace().next())
+		.and_then(|socket| socket.parse::<SocketAddrV4>().ok())
+		.into_iter()
+		.collect()
+}
+
+/// Removes terminal color codes, RustScan colors its output unless told not to
+fn strip_ansi(line: &str) -> String {
+	let mut plain = String::with_capacity(line.len());
+	let mut chars = line.chars();
+
+	while let Some(c) = chars.next() {
+		if c == '\u{1b}' {
+			// CSI sequences like ESC[35m end with a letter
+			for c in chars.by_ref() {
+				if c.is_ascii_alphabetic() {
+					break;
+				}
+			}
+		} else {
+			plain.push(c);
 		}
 	}
+
+	plain
 }
 
-#[inline(always)]
-async fn task_wrapper(socket: SocketAddrV4, pool: Database, config: Config, current_delay: Arc<AtomicU64>) {
-	info!("Attempting to ping server: {}", socket);
-	let server = PingableServer::new(socket);
-	let start_time = std::time::Instant::now();
-
-	// Try proper ping first (Modern servers 1.7+)
-	// Wrap with timeout to prevent hanging reads
-	let proper_result = tokio::time::timeout(TIMEOUT_SECS, server.proper_ping()).await;
-
-	let response = match proper_result {
-		Ok(Ok(r)) => Some(r),
-		// If proper ping failed (error or timeout), try legacy
-		_ => {
-			match tokio::time::timeout(TIMEOUT_SECS, server.legacy_ping()).await {
-				Ok(Ok(r)) => Some(r),
-				Ok(Err(e)) => {
-					// Log specific error
-					warn!("Ping failed for {}. Proper result: {:?}, Legacy error: {:?}", socket, proper_result, e);
-					None
+/// Running count of the hosts a scan engine found, drawn as a spinner since discovery has
+/// no total to measure progress against
+struct DiscoveryProgress {
+	engine: &'static str,
+	bar: ProgressBar,
+	reporter: JoinHandle<()>,
+}
+
+impl DiscoveryProgress {
+	fn start(engine: &'static str, log_interval: Option<Duration>) -> DiscoveryProgress {
+		let style = ProgressStyle::with_template("[{elapsed_precise}] {spinner} {prefix}: {human_pos} hosts found, {per_sec}")
+			.expect("failed to create progress bar style");
+		let bar = ProgressBar::new_spinner().with_style(style).with_prefix(engine);
+		bar.enable_steady_tick(Duration::from_millis(250));
+
+		// Not part of the status file, its progress is only for passes with a known length
+		let reporter = tokio::spawn(report_progress(bar.clone(), None, log_interval));
+		DiscoveryProgress { engine, bar, reporter }
+	}
+
+	fn found(&self) {
+		self.bar.inc(1);
+	}
+
+	fn count(&self) -> u64 {
+		self.bar.position()
+	}
+
+	/// Stops the spinner and logs the final count. Nothing found usually means the engine is
+	/// misconfigured, e.g. the wrong interface or a firewall dropping its replies
+	fn finish(self) {
+		self.reporter.abort();
+		self.bar.finish_and_clear();
+
+		let found = self.count();
+		let elapsed = HumanDuration(self.bar.elapsed());
+		if found == 0 {
+			warn!("{} found no hosts in {}, check its configuration if the target isn't empty", self.engine, elapsed);
+		} else {
+			info!("{} found {} hosts in {}", self.engine, found, elapsed);
+		}
+	}
+}
+
+/// Copies the progress bar's counters into the status file, and into the log when the bar
+/// isn't drawn because there's no terminal. Runs until it's aborted at the end of the pass
+async fn report_progress(bar: ProgressBar, status: Option<Arc<StatusFile>>, log_interval: Option<Duration>) {
+	let period = log_interval.map_or(status::WRITE_INTERVAL, |interval| interval.min(status::WRITE_INTERVAL));
+	let mut last_log = Instant::now();
+	let mut ticker = tokio::time::interval(period);
+
+	loop {
+		// Measured between ticks, timing the log itself would let it slip a whole tick
+		let now = ticker.tick().await;
+
+		let progress = status::Progress {
+			position: bar.position(),
+			length: bar.length().unwrap_or_default(),
+			per_sec: bar.per_sec(),
+			eta_secs: bar.eta().as_secs(),
+		};
+
+		if let Some(interval) = log_interval.filter(|_| bar.is_hidden()) {
+			if now - last_log >= interval {
+				match bar.length() {
+					Some(_) => info!("{}", progress_line(&progress)),
+					None => info!("{}", found_line(&bar.prefix(), &progress)),
 				}
-				Err(_) => {
-					warn!("Ping timed out for {} (both Proper and Legacy)", socket);
-					None
+				last_log = now;
+			}
+		}
+
+		if let Some(status) = &status {
+			status.set_progress(progress);
+		}
+	}
+}
+
+/// e.g. `Rescan progress: 70.0% (7000/10000), 12.5 servers/s, 4 minutes left`
+fn progress_line(progress: &status::Progress) -> String {
+	let percent = match progress.length {
+		0 => 100.0,
+		length => progress.position as f64 * 100.0 / length as f64,
+	};
+
+	format!(
+		"Rescan progress: {:.1}% ({}/{}), {:.1} servers/s, {} left",
+		percent,
+		progress.position,
+		progress.length,
+		progress.per_sec,
+		HumanDuration(Duration::from_secs(progress.eta_secs))
+	)
+}
+
+/// e.g. `Masscan: 1200 hosts found, 3.1 hosts/s`
+fn found_line(engine: &str, progress: &status::Progress) -> String {
+	format!("{}: {} hosts found, {:.1} hosts/s", engine, progress.position, progress.per_sec)
+}
+
+/// Starts a login with the server's own protocol and returns how it was answered
+async fn probe_login(
+	socket: SocketAddrV4,
+	protocol: i32,
+	config: &Config,
+	rate_limiter: Option<&PrefixRateLimiter>,
+) -> Option<&'static str> {
+	if let Some(rate_limiter) = rate_limiter {
+		rate_limiter.acquire(*socket.ip()).await;
+	}
+
+	let options = PingOptions::from(&config.scanner);
+	let server = PingableServer::new(socket)
+		.with_proxy_protocol(options.proxy_protocol)
+		.with_connect_timeout(options.timeout)
+		.with_read_timeout(options.timeout)
+		.with_source_address(options.source_address_for(socket))
+		.with_max_status_bytes(options.max_status_bytes);
+
+	match tokio::time::timeout(options.timeout, server.login_probe(protocol, &config.scanner.login_probe.username)).await {
+		Ok(Ok(status)) => Some(status.as_str()),
+		Ok(Err(e)) => {
+			debug!("Login probe failed for {}: {:?}", socket, e);
+			None
+		}
+		Err(_) => {
+			debug!("Login probe timed out for {}", socket);
+			None
+		}
+	}
+}
+
+/// Waits for `future` unless `deadline` passes first, which gives `None`
+async fn before_deadline<F: std::future::Future>(deadline: Option<Instant>, future: F) -> Option<F::Output> {
+	match deadline {
+		Some(deadline) => tokio::time::timeout_at(deadline, future).await.ok(),
+		None => Some(future.await),
+	}
+}
+
+/// Asks a scanner process to stop the way Ctrl+C would, so it can clean up and save its
+/// progress. Where that isn't possible it's killed
+fn interrupt(child: &mut Child) {
+	#[cfg(target_os = "linux")]
+	if let Some(pid) = child.id() {
+		// SAFETY: kill has no memory safety requirements, the pid is our own child's
+		if unsafe { libc::kill(pid as libc::pid_t, libc::SIGINT) } == 0 {
+			return;
+		}
+	}
+
+	if let Err(e) = child.start_kill() {
+		error!("Failed to stop the scanner process: {}", e);
+	}
+}
+
+/// The address of a server row, which should always be a single IPv4 host. Anything else
+/// was stored by mistake, scanning it would mean pinging some unrelated address
+fn host_address(address: IpNet) -> Option<Ipv4Addr> {
+	match address {
+		IpNet::V4(net) if net.prefix_len() == 32 => Some(net.addr()),
+		other => {
+			warn!("Skipping server stored with address {}, it isn't a single IPv4 host", other);
+			None
+		}
+	}
+}
+
+/// Ports pinged for one row of a rescan pass, the port the server was found on and, when
+/// `range` is given, every port in it the address isn't already known on
+fn rescan_ports(port: u16, known_ports: &[i32], range: Option<RangeInclusive<u16>>) -> Vec<u16> {
+	let mut ports = vec![port];
+	if let Some(range) = range {
+		ports.extend(range.filter(|p| *p != port && !known_ports.contains(&(*p as i32))));
+	}
+	ports
+}
+
+/// How long to wait after a rescan pass so passes start at most once per `min_cycle`,
+/// nothing when the pass already took that long
+fn cycle_sleep(elapsed: Duration, min_cycle: Duration) -> Duration {
+	min_cycle.saturating_sub(elapsed)
+}
+
+/// Builds the query streaming a rescan pass. Binds the limit as $1, the connect failure
+/// cutoff as $2, for a random order only the starting address as $3, and then the
+/// values of the rescan filter
+fn rescan_query(columns: &str, order: RescanOrder, rescan_filter: Option<&RescanFilter>) -> String {
+	let mut filter = format!("{} AND {}", RESCAN_DUE, connect_due(2));
+	if let Some(rescan_filter) = rescan_filter {
+		let first_param = if order == RescanOrder::Random { 4 } else { 3 };
+		filter = format!("{} AND {}", filter, rescan_filter.to_sql(first_param));
+	}
+
+	let order_by = match order {
+		RescanOrder::OldestFirst => "last_seen ASC",
+		RescanOrder::NewestFirst => "last_seen DESC",
+		// Fake player counts would otherwise always come first
+		RescanOrder::MostPopulous => "players_implausible IS NOT TRUE DESC, online_players DESC NULLS LAST",
+		// Walks the primary key from a random address and wraps around, so there's no sort
+		// and servers aren't pinged at the same point of every pass
+		RescanOrder::Random => {
+			return format!(
+				"SELECT * FROM (
+				(SELECT {columns} FROM servers WHERE {filter} AND address >= $3 ORDER BY address, port)
+				UNION ALL
+				(SELECT {columns} FROM servers WHERE {filter} AND address < $3 ORDER BY address, port)
+				) AS servers LIMIT $1"
+			)
+		}
+	};
+
+	format!("SELECT {columns} FROM servers WHERE {filter} ORDER BY {order_by} LIMIT $1")
+}
+
+/// Binds the parameters of a query built by `rescan_query`
+fn rescan_rows<'q>(
+	query: &'q str,
+	limit: Option<i64>,
+	max_connect_failures: Option<i32>,
+	order: RescanOrder,
+	filter: Option<&'q RescanFilter>,
+) -> Query<'q, Postgres, PgArguments> {
+	let query = sqlx::query(query).bind(limit).bind(max_connect_failures);
+
+	let query = match order {
+		RescanOrder::Random => {
+			// Where this pass starts walking the address space
+			let pivot = IpNet::from(Ipv4Net::from(Ipv4Addr::from_bits(rand::random())));
+			query.bind(pivot)
+		}
+		_ => query,
+	};
+
+	match filter {
+		Some(filter) => filter.bind(query),
+		None => query,
+	}
+}
+
+/// Builds the full masscan argv, scanning the entire internet when there is no target
+fn build_masscan_args(config: &Config, target: &Option<Target>, exclude_file: Option<&Path>) -> Vec<String> {
+	let mut args = vec!["masscan".to_string(), "-c".to_string(), config.masscan.config_file.clone()];
+
+	// Safety exclusion required by masscan for large ranges
+	args.push("--exclude".to_string());
+	args.push("255.255.255.255".to_string());
+
+	if let Some(rate) = config.masscan.rate {
+		args.push("--rate".to_string());
+		args.push(rate.to_string());
+	}
+
+	// Masscan already visits targets in an order randomized by its seed, one shard of that
+	// order is a sample of the space
+	if let Some(fraction) = config.targeting.sample_fraction {
+		let shards = targeting::sample_shards(fraction);
+		if shards > 1 {
+			args.push("--seed".to_string());
+			args.push(config.targeting.sample_seed.unwrap_or_default().to_string());
+			args.push("--shard".to_string());
+			args.push(format!("1/{}", shards));
+		}
+	}
+
+	if let Some(exclude_file) = exclude_file {
+		args.push("--excludefile".to_string());
+		args.push(exclude_file.to_string_lossy().to_string());
+	}
+
+	match target {
+		Some(Target::File(path)) => {
+			args.push("-iL".to_string());
+			args.push(path.to_string_lossy().to_string());
+		}
+		Some(Target::Direct(cidr)) => args.push(cidr.clone()),
+		// Scan the entire internet if no target is specified
+		None => args.push("0.0.0.0/0".to_string()),
+	}
+
+	args
+}
+
+/// Builds the masscan argv continuing from a saved scan, everything else comes from the file
+fn build_masscan_resume_args(paused: &Path) -> Vec<String> {
+	vec!["masscan".to_string(), "--resume".to_string(), paused.to_string_lossy().to_string()]
+}
+
+/// Makes the files in a masscan argv absolute, for running it from another directory
+fn make_masscan_paths_absolute(args: &mut [String]) {
+	for index in 1..args.len() {
+		if matches!(args[index - 1].as_str(), "-c" | "--excludefile" | "-iL" | "--resume") {
+			if let Ok(path) = std::path::absolute(&args[index]) {
+				args[index] = path.to_string_lossy().to_string();
+			}
+		}
+	}
+}
+
+/// Builds the full RustScan argv. RustScan has no whole internet mode, so there is
+/// nothing to run without a target. RustScan only takes exclusions on the command line
+fn build_rustscan_args(config: &Config, target: &Option<Target>, exclude: &[Ipv4Net]) -> Option<Vec<String>> {
+	let mut args = vec![config.rustscan.command.clone()];
+
+	if config.scanner.port_range_start != config.scanner.port_range_end {
+		args.push("-r".to_string());
+		args.push(format!("{}-{}", config.scanner.port_range_start, config.scanner.port_range_end));
+	} else {
+		args.push("-p".to_string());
+		args.push(config.scanner.port_range_start.to_string());
+	}
+
+	args.push("-a".to_string());
+	match target.as_ref()? {
+		Target::File(path) => args.push(path.to_string_lossy().to_string()),
+		Target::Direct(cidr) => args.push(cidr.clone()),
+	}
+
+	if !exclude.is_empty() {
+		args.push("-x".to_string());
+		args.push(exclude.iter().map(|cidr| cidr.to_string()).collect::<Vec<_>>().join(","));
+	}
+
+	if config.rustscan.greppable {
+		args.push("-g".to_string());
+	}
+
+	args.push("--scripts".to_string());
+	args.push("none".to_string());
+
+	Some(args)
+}
+
+/// Checks that the server accepts connections and stores the result
+async fn triage_connect(socket: SocketAddrV4, context: &PingContext) -> bool {
+	let options = PingOptions::from(&context.config.scanner);
+	let timeout = Duration::from_millis(context.config.rescanner.triage_timeout_ms);
+
+	let open = PingableServer::new(socket)
+		.with_source_address(options.source_address_for(socket))
+		.probe(timeout)
+		.await
+		.is_ok();
+
+	if let Some(health) = &context.health {
+		health.record_activity();
+	}
+
+	let recheck = Duration::from_secs(context.config.rescanner.connect_recheck_secs);
+	if let Err(e) = context.database.record_connect(socket, open, recheck).await {
+		error!("Failed to store connection check for {}: {}", socket, e);
+	}
+
+	if !open {
+		debug!("{} is not accepting connections, skipping", socket);
+	}
+
+	open
+}
+
+/// Pings the server once for every configured protocol version and returns the lowest and
+/// highest it accepted. A version counts as accepted when the status reports it back, vanilla
+/// always answers with its own protocol while proxies and ViaVersion echo supported clients
+async fn probe_protocol_range(
+	socket: SocketAddrV4,
+	config: &Config,
+	rate_limiter: Option<&PrefixRateLimiter>,
+) -> Option<(i32, i32)> {
+	let mut range: Option<(i32, i32)> = None;
+
+	for &version in &config.scanner.protocol_probe.versions {
+		if let Some(rate_limiter) = rate_limiter {
+			rate_limiter.acquire(*socket.ip()).await;
+		}
+
+		let options = PingOptions {
+			handshake_protocol: version,
+			..PingOptions::from(&config.scanner)
+		};
+
+		match ping_server(socket, options).await {
+			Ok(result) if result.server.version.protocol == version => {
+				range = Some(range.map_or((version, version), |(min, max)| (min.min(version), max.max(version))));
+			}
+			Ok(result) => debug!(
+				"{} rejected protocol {}, answered with {}",
+				socket, version, result.server.version.protocol
+			),
+			Err(e) => debug!("Protocol {} ping failed for {}: {:?}", version, socket, e),
+		}
+	}
+
+	range
+}
+
+/// The concurrency permit a ping task holds, given back while the task waits to retry so a
+/// failing or throttled network doesn't keep other pings from starting
+struct PingSlot {
+	semaphore: Option<Arc<Semaphore>>,
+	permit: Option<OwnedSemaphorePermit>,
+}
+
+impl PingSlot {
+	/// Waits for a permit, there's nothing to wait for without a semaphore
+	async fn acquire(semaphore: Option<Arc<Semaphore>>) -> PingSlot {
+		let permit = match &semaphore {
+			Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+			None => None,
+		};
+		PingSlot { semaphore, permit }
+	}
+
+	/// Runs `wait` without the permit and takes a new one afterwards
+	async fn release_during(&mut self, wait: impl std::future::Future<Output = ()>) {
+		self.permit = None;
+		wait.await;
+		if let Some(semaphore) = &self.semaphore {
+			self.permit = semaphore.clone().acquire_owned().await.ok();
+		}
+	}
+}
+
+/// Pings a server and stores the result. The caller has already waited for the rate limiter
+/// and taken `slot`, which is held until the task is done
+#[inline(always)]
+async fn task_wrapper(socket: SocketAddrV4, context: PingContext, mut slot: PingSlot) -> Result<(), RunError> {
+	if context.blocked(socket) {
+		return Ok(());
+	}
+
+	let PingContext {
+		database: pool,
+		config,
+		current_delay,
+		rate_limiter,
+		sinks,
+		status,
+		filtered,
+		reserved: _,
+		discovered_by,
+		stored,
+		blocklist: _,
+		health,
+		geoip,
+	} = context;
+
+	let options = PingOptions::from(&config.scanner);
+	let mut attempts = 0;
+	let result = loop {
+		if attempts > 0 {
+			// Retries count against the network's rate limit like any other ping
+			let backoff = RETRY_BACKOFF * 2u32.pow(attempts - 1);
+			slot.release_during(async {
+				tokio::time::sleep(backoff).await;
+				if let Some(rate_limiter) = &rate_limiter {
+					rate_limiter.acquire(*socket.ip()).await;
 				}
+			})
+			.await;
+			if let Some(status) = &status {
+				status.record_retry();
 			}
 		}
+		attempts += 1;
+
+		debug!("Attempting to ping server: {} (attempt {})", socket, attempts);
+		match ping_server(socket, options.clone()).await {
+			Err(e) if e.is_retryable() && attempts <= config.scanner.ping_retries => {
+				debug!("Ping for {} failed with {}, retrying", socket, e.kind());
+			}
+			result => break result,
+		}
 	};
-	let latency = start_time.elapsed().as_millis() as i32;
 
 	// Adaptive Logic
 	let adaptive = &config.scanner.adaptive;
 	let current = current_delay.load(Ordering::Relaxed);
 
-	if response.is_some() {
+	// A response that failed to parse still means the server answered
+	if matches!(result, Ok(_) | Err(RunError::ParseResponse(_))) {
 		// Success: Decrease delay
 		if current > adaptive.min_delay_ms {
 			let new_delay = current.saturating_sub(adaptive.decrease_step_ms).max(adaptive.min_delay_ms);
@@ -584,25 +1911,369 @@ async fn task_wrapper(socket: SocketAddrV4, pool: Database, config: Config, curr
 		}
 	}
 
-	if let Some(response) = response {
-		match serde_json::from_str::<Server>(&response) {
-			Ok(mut server) => {
-				server.latency = Some(latency);
-				if let Err(e) = pool.update_server(server, socket).await {
-					error!("Error updating server in database! {e}");
-				} else {
-					info!("Successfully updated server: {}", socket);
+	if let Some(health) = &health {
+		health.record_activity();
+	}
+
+	if let Some(status) = &status {
+		match &result {
+			Ok(_) => status.record_success(),
+			Err(e) => status.record_failure(e.kind()),
+		}
+	}
+
+	if result.is_err() && stored {
+		if let Err(e) = pool.record_ping_failure(socket, &config.rescanner.backoff).await {
+			error!("Failed to store ping failure for {}: {}", socket, e);
+		}
+	}
+
+	match result {
+		Ok(mut result) => {
+			let version = &result.server.version;
+			if !config.scanner.version_filter.matches(version.protocol, &version.name) {
+				debug!("Not storing {}, {} is outside the version filter", socket, version.name);
+				filtered.fetch_add(1, Ordering::Relaxed);
+				return Ok(());
+			}
+
+			if config.scanner.reverse_dns {
+				result.server.hostname = dns::reverse_lookup(*socket.ip(), config.scanner.dns_resolver).await;
+			}
+
+			if let Some(geoip) = &geoip {
+				let location = geoip.lookup(*socket.ip());
+				result.server.country_code = location.country_code;
+				result.server.asn = location.asn;
+			}
+
+			if config.scanner.store_raw_json {
+				result.server.raw_json = serde_json::from_str(&result.raw).ok();
+			}
+
+			result.server.discovered_by = discovered_by.map(|engine| engine.as_str());
+			result.server.ping_attempts = Some(attempts as i32);
+
+			// Legacy pings carry no hostname to compare with
+			if config.scanner.fronting_detection && result.method == PingMethod::Proper {
+				result.server.fronted =
+					fronting::detect(socket, &result.server, &options, rate_limiter.as_deref()).await;
+			}
+
+			if config.scanner.protocol_probe.enabled {
+				result.server.accepted_protocols = probe_protocol_range(socket, &config, rate_limiter.as_deref()).await;
+			}
+
+			// Legacy servers log in with a different protocol
+			if config.scanner.login_probe.enabled && result.method == PingMethod::Proper {
+				result.server.login_status =
+					probe_login(socket, result.server.version.protocol, &config, rate_limiter.as_deref()).await;
+			}
+
+			for sink in sinks.iter() {
+				if let Err(e) = sink.emit(&result.server, socket).await {
+					error!("Error sending server {} to {}! {e}", socket, sink.name());
+				}
+			}
+
+			Ok(())
+		}
+		Err(e) => {
+			match &e {
+				RunError::NotMinecraft(protocol) => {
+					debug!("{} is running {}, not Minecraft", socket, protocol);
 					pool.log_event(
 						Some(IpNet::from(Ipv4Net::from(*socket.ip()))),
-						"INFO".to_string(),
-						"SERVER_UPDATED".to_string(),
-						format!("Server updated on port {}", socket.port())
+						Severity::Info,
+						EventType::NotMinecraft,
+						format!("Port {} is running {}", socket.port(), protocol),
 					);
 				}
+				RunError::ParseResponse(e) => warn!("Failed to parse server response for {}: {}", socket, e),
+				// Most hosts found by a scan aren't reachable anymore, that's not worth a warning
+				RunError::TimedOut(_) => debug!("Ping timed out for {}", socket),
+				e => debug!("Ping failed for {}: {:?}", socket, e),
 			}
-			Err(e) => {
-				warn!("Failed to parse server response for {}: {}. Response: {}", socket, e, response);
-			}
+
+			Err(e)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config(start: u16, end: u16) -> Config {
+		let mut config = Config::default();
+		config.scanner.port_range_start = start;
+		config.scanner.port_range_end = end;
+		config
+	}
+
+	#[tokio::test]
+	async fn test_ping_slot() {
+		let semaphore = Arc::new(Semaphore::new(1));
+		let mut slot = PingSlot::acquire(Some(semaphore.clone())).await;
+		assert_eq!(semaphore.available_permits(), 0);
+
+		// Other pings can start while this one waits to retry
+		slot.release_during(async { assert_eq!(semaphore.available_permits(), 1) }).await;
+		assert_eq!(semaphore.available_permits(), 0);
+
+		drop(slot);
+		assert_eq!(semaphore.available_permits(), 1);
+		assert!(PingSlot::acquire(None).await.permit.is_none());
+	}
+
+	fn argv(args: &[&str]) -> Vec<String> {
+		args.iter().map(|a| a.to_string()).collect()
+	}
+
+	#[test]
+	fn test_masscan_args() {
+		let config = config(25565, 25565);
+
+		assert_eq!(
+			build_masscan_args(&config, &None, None),
+			argv(&["masscan", "-c", "masscan.conf", "--exclude", "255.255.255.255", "0.0.0.0/0"])
+		);
+		assert_eq!(
+			build_masscan_args(&config, &Some(Target::File(PathBuf::from("cache/BR.txt"))), None),
+			argv(&["masscan", "-c", "masscan.conf", "--exclude", "255.255.255.255", "-iL", "cache/BR.txt"])
+		);
+		assert_eq!(
+			build_masscan_args(&config, &Some(Target::Direct("192.168.1.0/24".to_string())), None),
+			argv(&["masscan", "-c", "masscan.conf", "--exclude", "255.255.255.255", "192.168.1.0/24"])
+		);
+		assert_eq!(
+			build_masscan_args(&config, &None, Some(Path::new("cache/blocklist.txt"))),
+			argv(&[
+				"masscan",
+				"-c",
+				"masscan.conf",
+				"--exclude",
+				"255.255.255.255",
+				"--excludefile",
+				"cache/blocklist.txt",
+				"0.0.0.0/0"
+			])
+		);
+
+		let mut limited = config.clone();
+		limited.masscan.rate = Some(1000);
+		assert_eq!(
+			build_masscan_args(&limited, &None, None),
+			argv(&["masscan", "-c", "masscan.conf", "--exclude", "255.255.255.255", "--rate", "1000", "0.0.0.0/0"])
+		);
+
+		let mut sampled = config.clone();
+		sampled.targeting.sample_fraction = Some(0.01);
+		sampled.targeting.sample_seed = Some(42);
+		assert_eq!(
+			build_masscan_args(&sampled, &None, None),
+			argv(&[
+				"masscan",
+				"-c",
+				"masscan.conf",
+				"--exclude",
+				"255.255.255.255",
+				"--seed",
+				"42",
+				"--shard",
+				"1/100",
+				"0.0.0.0/0"
+			])
+		);
+
+		assert_eq!(
+			build_masscan_resume_args(Path::new(MASSCAN_PAUSED_FILE)),
+			argv(&["masscan", "--resume", "paused.conf"])
+		);
+
+		// Relative files are taken from where the scanner runs, not masscan's run directory
+		let cwd = std::env::current_dir().unwrap();
+		let absolute = |path: &str| cwd.join(path).to_string_lossy().to_string();
+		let mut args = build_masscan_args(
+			&config,
+			&Some(Target::File(PathBuf::from("cache/BR.txt"))),
+			Some(&cwd.join("exclude.txt")),
+		);
+		make_masscan_paths_absolute(&mut args);
+		assert_eq!(
+			args,
+			vec![
+				"masscan".to_string(),
+				"-c".to_string(),
+				absolute("masscan.conf"),
+				"--exclude".to_string(),
+				"255.255.255.255".to_string(),
+				"--excludefile".to_string(),
+				absolute("exclude.txt"),
+				"-iL".to_string(),
+				absolute("cache/BR.txt"),
+			]
+		);
+	}
+
+	#[test]
+	fn test_rustscan_args() {
+		let single = config(25565, 25565);
+		let range = config(25500, 25600);
+		let file = Some(Target::File(PathBuf::from("cache/BR.txt")));
+		let direct = Some(Target::Direct("192.168.1.0/24".to_string()));
+
+		assert_eq!(
+			build_rustscan_args(&single, &direct, &[]),
+			Some(argv(&["rustscan", "-p", "25565", "-a", "192.168.1.0/24", "-g", "--scripts", "none"]))
+		);
+		assert_eq!(
+			build_rustscan_args(&range, &direct, &[]),
+			Some(argv(&["rustscan", "-r", "25500-25600", "-a", "192.168.1.0/24", "-g", "--scripts", "none"]))
+		);
+		assert_eq!(
+			build_rustscan_args(&single, &file, &[]),
+			Some(argv(&["rustscan", "-p", "25565", "-a", "cache/BR.txt", "-g", "--scripts", "none"]))
+		);
+		assert_eq!(build_rustscan_args(&range, &None, &[]), None);
+
+		let exclude = ["10.0.0.0/8".parse().unwrap(), "192.168.1.7/32".parse().unwrap()];
+		assert_eq!(
+			build_rustscan_args(&single, &direct, &exclude),
+			Some(argv(&[
+				"rustscan",
+				"-p",
+				"25565",
+				"-a",
+				"192.168.1.0/24",
+				"-x",
+				"10.0.0.0/8,192.168.1.7/32",
+				"-g",
+				"--scripts",
+				"none"
+			]))
+		);
+
+		let mut human = config(25565, 25565);
+		human.rustscan.greppable = false;
+		assert_eq!(
+			build_rustscan_args(&human, &direct, &[]),
+			Some(argv(&["rustscan", "-p", "25565", "-a", "192.168.1.0/24", "--scripts", "none"]))
+		);
+	}
+
+	#[test]
+	fn test_parse_rustscan_line() {
+		let socket = |port| SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, 7), port);
+
+		// Greppable
+		assert_eq!(parse_rustscan_line("203.0.113.7 -> [25565,25566]"), vec![socket(25565), socket(25566)]);
+		assert_eq!(parse_rustscan_line("203.0.113.7 -> [25565]"), vec![socket(25565)]);
+		assert_eq!(parse_rustscan_line("203.0.113.7 -> []"), vec![]);
+
+		// Human readable, colored when RustScan thinks it's on a terminal
+		assert_eq!(parse_rustscan_line("Open 203.0.113.7:25565"), vec![socket(25565)]);
+		assert_eq!(parse_rustscan_line("Open \u{1b}[35m203.0.113.7:25565\u{1b}[0m"), vec![socket(25565)]);
+
+		for noise in [
+			"",
+			"The Modern Day Port Scanner.",
+			"[~] The config file is expected to be at \"/root/.rustscan.toml\"",
+			"[>] Running script \"nmap -vvv -p {{port}} {{ip}}\" on ip 203.0.113.7",
+			"Open 203.0.113.7",
+			"not.an.ip -> [25565]",
+		] {
+			assert_eq!(parse_rustscan_line(noise), vec![], "{}", noise);
 		}
 	}
+
+	#[test]
+	fn test_rescan_query() {
+		let query = rescan_query("address, port", RescanOrder::NewestFirst, None);
+		assert!(query.ends_with("ORDER BY last_seen DESC LIMIT $1"));
+		assert!(!query.contains("$3"));
+		let query = rescan_query("address, port", RescanOrder::MostPopulous, None);
+		assert!(query.ends_with("ORDER BY players_implausible IS NOT TRUE DESC, online_players DESC NULLS LAST LIMIT $1"));
+
+		// Random walks the primary key from $3 and wraps around
+		let query = rescan_query("address, port", RescanOrder::Random, None);
+		assert!(query.contains("address >= $3 ORDER BY address, port"));
+		assert!(query.contains("address < $3 ORDER BY address, port"));
+		assert!(query.ends_with("LIMIT $1"));
+
+		// Filter values are numbered after the parameters the order uses
+		let filter: RescanFilter = "country = BR and online > 10".parse().unwrap();
+		let query = rescan_query("address, port", RescanOrder::OldestFirst, Some(&filter));
+		assert!(query.contains(&format!("{} AND country = $3 AND online_players > $4 ORDER BY", connect_due(2))));
+		let query = rescan_query("address, port", RescanOrder::Random, Some(&filter));
+		assert!(query.contains("country = $4 AND online_players > $5 AND address >= $3"));
+	}
+
+	#[test]
+	fn test_rescan_ports() {
+		assert_eq!(rescan_ports(25577, &[25577], None), vec![25577]);
+		assert_eq!(rescan_ports(25577, &[25565, 25577], Some(25565..=25567)), vec![25577, 25566, 25567]);
+		assert_eq!(rescan_ports(25565, &[25565], Some(25565..=25566)), vec![25565, 25566]);
+	}
+
+	#[test]
+	fn test_cycle_sleep() {
+		let secs = Duration::from_secs;
+		assert_eq!(cycle_sleep(secs(40), secs(3600)), secs(3560));
+		assert_eq!(cycle_sleep(secs(3600), secs(3600)), secs(0));
+		assert_eq!(cycle_sleep(secs(7200), secs(3600)), secs(0));
+	}
+
+	#[test]
+	fn test_progress_line() {
+		let progress = status::Progress { position: 7000, length: 10000, per_sec: 12.5, eta_secs: 240 };
+		assert_eq!(progress_line(&progress), "Rescan progress: 70.0% (7000/10000), 12.5 servers/s, 4 minutes left");
+
+		let empty = status::Progress { position: 0, length: 0, per_sec: 0.0, eta_secs: 0 };
+		assert!(progress_line(&empty).starts_with("Rescan progress: 100.0% (0/0)"));
+
+		let discovery = status::Progress { position: 1200, length: 0, per_sec: 3.08, eta_secs: 0 };
+		assert_eq!(found_line("Masscan", &discovery), "Masscan: 1200 hosts found, 3.1 hosts/s");
+	}
+
+	#[test]
+	fn test_failure_counts() {
+		let failures = FailureCounts::default();
+		assert_eq!(failures.summary(), "");
+
+		failures.record(RunError::MalformedResponse);
+		failures.record(RunError::NotMinecraft("HTTP"));
+		failures.record(RunError::NotMinecraft("SSH"));
+		assert_eq!(failures.summary(), "not_minecraft: 2, malformed_response: 1");
+
+		failures.reset();
+		assert_eq!(failures.summary(), "");
+	}
+
+	#[tokio::test]
+	async fn test_before_deadline() {
+		let deadline = Instant::now() + Duration::from_millis(50);
+		assert_eq!(before_deadline(Some(deadline), async { 1 }).await, Some(1));
+		assert_eq!(before_deadline(Some(deadline), std::future::pending::<()>()).await, None);
+		assert_eq!(before_deadline(None, tokio::time::sleep(Duration::from_millis(100))).await, Some(()));
+	}
+
+	#[test]
+	fn test_host_address() {
+		let host = |s: &str| host_address(s.parse().unwrap());
+
+		assert_eq!(host("203.0.113.7/32"), Some(Ipv4Addr::new(203, 0, 113, 7)));
+		assert_eq!(host("203.0.113.0/24"), None);
+		assert_eq!(host("2001:db8::1/128"), None);
+	}
+
+	#[test]
+	fn test_seen_hosts() {
+		let mut seen = SeenHosts::default();
+		let socket = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 25565);
+
+		assert!(seen.insert(socket));
+		assert!(!seen.insert(socket));
+		assert!(seen.insert(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 25566)));
+	}
 }
@@ -0,0 +1,196 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A five field cron expression (minute, hour, day of month, month, day of week)
+/// evaluated in UTC. Supports `*`, lists, ranges and steps, e.g. `*/15 2-4 * * 1,3`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+	minutes: u64,
+	hours: u64,
+	days_of_month: u64,
+	months: u64,
+	days_of_week: u64,
+	// Cron matches either day field when both are restricted
+	any_day_of_month: bool,
+	any_day_of_week: bool,
+}
+
+// Far enough ahead for any valid expression, e.g. the 29th of February
+const SEARCH_DAYS: u64 = 366 * 8;
+
+impl FromStr for Schedule {
+	type Err = String;
+
+	fn from_str(expression: &str) -> Result<Self, Self::Err> {
+		let fields: Vec<&str> = expression.split_whitespace().collect();
+		let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+			return Err(format!("expected 5 fields, found {}", fields.len()));
+		};
+
+		// Sunday can be written as 0 or 7
+		let mut days_of_week = parse_field(day_of_week, 0, 7, "day of week")?;
+		if days_of_week & (1 << 7) != 0 {
+			days_of_week |= 1;
+		}
+
+		Ok(Schedule {
+			minutes: parse_field(minute, 0, 59, "minute")?,
+			hours: parse_field(hour, 0, 23, "hour")?,
+			days_of_month: parse_field(day_of_month, 1, 31, "day of month")?,
+			months: parse_field(month, 1, 12, "month")?,
+			days_of_week,
+			any_day_of_month: day_of_month == "*",
+			any_day_of_week: day_of_week == "*",
+		})
+	}
+}
+
+fn parse_field(field: &str, min: u64, max: u64, name: &str) -> Result<u64, String> {
+	let mut bits = 0;
+
+	for part in field.split(',') {
+		let (range, step) = match part.split_once('/') {
+			Some((range, step)) => match step.parse::<u64>() {
+				Ok(step) if step > 0 => (range, step),
+				_ => return Err(format!("invalid step in {} field: {}", name, part)),
+			},
+			None => (part, 1),
+		};
+
+		let (start, end) = if range == "*" {
+			(min, max)
+		} else if let Some((start, end)) = range.split_once('-') {
+			(parse_value(start, name)?, parse_value(end, name)?)
+		} else {
+			let value = parse_value(range, name)?;
+			// `5/10` means every 10 starting at 5
+			(value, if step > 1 { max } else { value })
+		};
+
+		if start < min || end > max || start > end {
+			return Err(format!("{} field out of range {}-{}: {}", name, min, max, part));
+		}
+
+		for value in (start..=end).step_by(step as usize) {
+			bits |= 1 << value;
+		}
+	}
+
+	Ok(bits)
+}
+
+fn parse_value(value: &str, name: &str) -> Result<u64, String> {
+	value
+		.parse()
+		.map_err(|_| format!("invalid value in {} field: {}", name, value))
+}
+
+impl Schedule {
+	/// The first matching time strictly after `after`, both in seconds since the unix epoch
+	pub fn next_after(&self, after: u64) -> Option<u64> {
+		// Cron runs on whole minutes
+		let after_minute = after / 60 + 1;
+		let first_day = after_minute / 1440;
+
+		for day in first_day..first_day + SEARCH_DAYS {
+			if !self.matches_day(day) {
+				continue;
+			}
+
+			for hour in 0..24 {
+				if self.hours & (1 << hour) == 0 {
+					continue;
+				}
+
+				for minute in 0..60 {
+					let candidate = day * 1440 + hour * 60 + minute;
+					if self.minutes & (1 << minute) != 0 && candidate >= after_minute {
+						return Some(candidate * 60);
+					}
+				}
+			}
+		}
+
+		None
+	}
+
+	fn matches_day(&self, days_since_epoch: u64) -> bool {
+		let (_, month, day) = civil_from_days(days_since_epoch);
+		if self.months & (1 << month) == 0 {
+			return false;
+		}
+
+		// 1970-01-01 was a Thursday
+		let weekday = (days_since_epoch + 4) % 7;
+		let day_of_month = self.days_of_month & (1 << day) != 0;
+		let day_of_week = self.days_of_week & (1 << weekday) != 0;
+
+		match (self.any_day_of_month, self.any_day_of_week) {
+			(true, true) => true,
+			(false, true) => day_of_month,
+			(true, false) => day_of_week,
+			(false, false) => day_of_month || day_of_week,
+		}
+	}
+}
+
+// Converts days since the unix epoch to a (year, month, day) date
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+	let z = days + 719_468;
+	let era = z / 146_097;
+	let day_of_era = z % 146_097;
+	let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let mp = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+	(year, month, day)
+}
+
+/// Formats a unix timestamp as `YYYY-MM-DD HH:MM UTC` for logging
+pub struct UtcTime(pub u64);
+
+impl fmt::Display for UtcTime {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let (year, month, day) = civil_from_days(self.0 / 86400);
+		let minutes = self.0 % 86400 / 60;
+		write!(f, "{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, minutes / 60, minutes % 60)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// 2026-10-15 12:34:56 UTC, a Thursday
+	const NOW: u64 = 1_792_067_696;
+
+	fn next(expression: &str) -> String {
+		let schedule: Schedule = expression.parse().unwrap();
+		UtcTime(schedule.next_after(NOW).unwrap()).to_string()
+	}
+
+	#[test]
+	fn test_next_run() {
+		assert_eq!(UtcTime(NOW).to_string(), "2026-10-15 12:34 UTC");
+		assert_eq!(next("* * * * *"), "2026-10-15 12:35 UTC");
+		assert_eq!(next("0 3 * * *"), "2026-10-16 03:00 UTC");
+		assert_eq!(next("*/15 * * * *"), "2026-10-15 12:45 UTC");
+		assert_eq!(next("30 2 * * 0"), "2026-10-18 02:30 UTC");
+		assert_eq!(next("0 0 1 1 *"), "2027-01-01 00:00 UTC");
+		assert_eq!(next("0 0 29 2 *"), "2028-02-29 00:00 UTC");
+		// Either day field matches when both are set
+		assert_eq!(next("0 0 20 * 5"), "2026-10-16 00:00 UTC");
+	}
+
+	#[test]
+	fn test_invalid_expressions() {
+		assert!("0 3 * *".parse::<Schedule>().is_err());
+		assert!("60 * * * *".parse::<Schedule>().is_err());
+		assert!("*/0 * * * *".parse::<Schedule>().is_err());
+		assert!("0 5-2 * * *".parse::<Schedule>().is_err());
+		assert!("0 0 31 2 *".parse::<Schedule>().unwrap().next_after(NOW).is_none());
+	}
+}
@@ -0,0 +1,156 @@
+use crate::backpressure::DatabaseBreaker;
+use crate::config::SinkConfig;
+use crate::database::{Database, EventType, Severity};
+use crate::response::Server;
+use crate::write_queue::WriteQueue;
+use futures_util::future::BoxFuture;
+use reqwest::Client;
+use serde::Serialize;
+use sqlx::types::ipnet::{IpNet, Ipv4Net};
+use std::net::SocketAddrV4;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, warn};
+
+/// Somewhere successfully pinged servers are sent
+pub trait ServerSink: Send + Sync + std::fmt::Debug {
+	/// Short name used in logs
+	fn name(&self) -> &str;
+
+	fn emit<'a>(&'a self, server: &'a Server, socket: SocketAddrV4) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// Builds the configured sinks. The database always comes first, so opted out
+/// servers are removed before anything else sees them
+pub fn build_sinks(
+	database: Database,
+	breaker: Option<Arc<DatabaseBreaker>>,
+	write_queue: Option<Arc<WriteQueue>>,
+	opt_out_recheck: Duration,
+	configs: &[SinkConfig],
+) -> Vec<Box<dyn ServerSink>> {
+	let mut sinks: Vec<Box<dyn ServerSink>> = vec![Box::new(DatabaseSink {
+		database,
+		breaker,
+		write_queue,
+		opt_out_recheck,
+	})];
+
+	for config in configs {
+		match config {
+			SinkConfig::Webhook { url, timeout_ms } => sinks.push(Box::new(WebhookSink::new(url.clone(), *timeout_ms))),
+		}
+	}
+
+	sinks
+}
+
+/// Stores servers in the servers table
+#[derive(Debug)]
+pub struct DatabaseSink {
+	database: Database,
+	breaker: Option<Arc<DatabaseBreaker>>,
+	// Holds servers the database failed to take until it's back
+	write_queue: Option<Arc<WriteQueue>>,
+	// How long opted out servers are left alone by rescans
+	opt_out_recheck: Duration,
+}
+
+impl ServerSink for DatabaseSink {
+	fn name(&self) -> &str {
+		"database"
+	}
+
+	fn emit<'a>(&'a self, server: &'a Server, socket: SocketAddrV4) -> BoxFuture<'a, anyhow::Result<()>> {
+		Box::pin(async move {
+			let write_start = Instant::now();
+			let seen = SystemTime::now();
+			let update = self
+				.database
+				.update_server(server.clone(), socket, seen, self.opt_out_recheck)
+				.await;
+
+			let database_error = matches!(&update, Err(e) if e.downcast_ref::<sqlx::Error>().is_some());
+			if let Some(breaker) = &self.breaker {
+				breaker.record(write_start.elapsed(), database_error);
+			}
+
+			if let (Some(write_queue), true) = (&self.write_queue, database_error) {
+				match write_queue.push(server, socket, seen) {
+					Ok(()) => {
+						debug!("Queued {} until the database is back", socket);
+						return Ok(());
+					}
+					Err(e) => warn!("Failed to queue {}: {:#}", socket, e),
+				}
+			}
+
+			update?;
+
+			debug!("Successfully updated server: {}", socket);
+			self.database.log_event(
+				Some(IpNet::from(Ipv4Net::from(*socket.ip()))),
+				Severity::Info,
+				EventType::ServerUpdated,
+				format!("Server updated on port {}", socket.port()),
+			);
+
+			Ok(())
+		})
+	}
+}
+
+/// POSTs every server as JSON to a URL
+#[derive(Debug)]
+pub struct WebhookSink {
+	url: String,
+	client: Client,
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+	address: String,
+	port: u16,
+	server: &'a Server,
+}
+
+impl WebhookSink {
+	pub fn new(url: String, timeout_ms: u64) -> Self {
+		let client = Client::builder()
+			.timeout(Duration::from_millis(timeout_ms))
+			.build()
+			.unwrap_or_default();
+
+		Self { url, client }
+	}
+}
+
+impl ServerSink for WebhookSink {
+	fn name(&self) -> &str {
+		&self.url
+	}
+
+	fn emit<'a>(&'a self, server: &'a Server, socket: SocketAddrV4) -> BoxFuture<'a, anyhow::Result<()>> {
+		Box::pin(async move {
+			// The database sink deletes these, they shouldn't leak out anywhere else
+			if server.check_opt_out() {
+				return Ok(());
+			}
+
+			let payload = WebhookPayload {
+				address: socket.ip().to_string(),
+				port: socket.port(),
+				server,
+			};
+
+			self.client
+				.post(&self.url)
+				.json(&payload)
+				.send()
+				.await?
+				.error_for_status()?;
+
+			Ok(())
+		})
+	}
+}
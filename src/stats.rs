@@ -0,0 +1,307 @@
+use crate::database::Database;
+use sqlx::Row;
+
+/// Prints a summary of everything in the servers table
+pub async fn print_stats(database: &Database, country_tracking: bool) -> Result<(), sqlx::Error> {
+	let pool = &database.0;
+
+	let totals = sqlx::query(
+		"SELECT COUNT(*) AS servers,
+		COALESCE(SUM(online_players) FILTER (WHERE players_implausible IS NOT TRUE), 0) AS players,
+		percentile_cont(0.5) WITHIN GROUP (ORDER BY latency) AS median_latency
+		FROM servers",
+	)
+	.fetch_one(pool)
+	.await?;
+
+	let median_latency: Option<f64> = totals.try_get("median_latency")?;
+	print_table(
+		"Totals",
+		&["Servers", "Online players", "Median latency"],
+		vec![vec![
+			totals.try_get::<i64, _>("servers")?.to_string(),
+			totals.try_get::<i64, _>("players")?.to_string(),
+			median_latency.map_or("-".to_string(), |l| format!("{:.0}ms", l)),
+		]],
+	);
+
+	// Legacy servers never answer the Ping packet, so their latency is always the connect
+	// time and isn't comparable with modern servers
+	let rows = sqlx::query(
+		"SELECT ping_method, COUNT(*) AS servers,
+		percentile_cont(0.5) WITHIN GROUP (ORDER BY latency) AS p50,
+		percentile_cont(0.9) WITHIN GROUP (ORDER BY latency) AS p90,
+		percentile_cont(0.99) WITHIN GROUP (ORDER BY latency) AS p99
+		FROM servers WHERE latency IS NOT NULL GROUP BY ping_method ORDER BY servers DESC",
+	)
+	.fetch_all(pool)
+	.await?;
+
+	let format_latency = |latency: Option<f64>| latency.map_or("-".to_string(), |l| format!("{:.0}ms", l));
+	print_table(
+		"Latency percentiles",
+		&["Ping method", "Servers", "p50", "p90", "p99"],
+		rows.iter()
+			.map(|row| {
+				vec![
+					ping_method_name(row.get("ping_method")).to_string(),
+					row.get::<i64, _>("servers").to_string(),
+					format_latency(row.get("p50")),
+					format_latency(row.get("p90")),
+					format_latency(row.get("p99")),
+				]
+			})
+			.collect(),
+	);
+
+	let rows = sqlx::query(
+		"SELECT ping_method, latency, COUNT(*) AS servers FROM servers
+		WHERE latency IS NOT NULL GROUP BY ping_method, latency",
+	)
+	.fetch_all(pool)
+	.await?;
+
+	let mut histogram = [[0i64; 3]; LATENCY_BUCKETS];
+	for row in &rows {
+		let column = match ping_method_name(row.get("ping_method")) {
+			"proper" => 0,
+			"legacy" => 1,
+			_ => 2,
+		};
+		histogram[latency_bucket(row.get("latency"))][column] += row.get::<i64, _>("servers");
+	}
+
+	print_table(
+		"Latency histogram",
+		&["Latency", "Proper", "Legacy", "Unknown"],
+		histogram
+			.iter()
+			.enumerate()
+			.map(|(bucket, counts)| {
+				let mut row = vec![latency_bucket_label(bucket)];
+				row.extend(counts.iter().map(|count| count.to_string()));
+				row
+			})
+			.collect(),
+	);
+
+	let rows = sqlx::query(
+		"SELECT protocol, MODE() WITHIN GROUP (ORDER BY version) AS version, COUNT(*) AS servers
+		FROM servers GROUP BY protocol ORDER BY servers DESC LIMIT 15",
+	)
+	.fetch_all(pool)
+	.await?;
+
+	print_table(
+		"Protocol versions",
+		&["Protocol", "Most common version", "Servers"],
+		rows.iter()
+			.map(|row| {
+				vec![
+					row.get::<Option<i32>, _>("protocol").map_or("-".to_string(), |p| p.to_string()),
+					row.get::<Option<String>, _>("version").unwrap_or_default(),
+					row.get::<i64, _>("servers").to_string(),
+				]
+			})
+			.collect(),
+	);
+
+	let rows = sqlx::query(
+		"SELECT software, COUNT(*) AS servers FROM servers GROUP BY software ORDER BY servers DESC",
+	)
+	.fetch_all(pool)
+	.await?;
+
+	print_table(
+		"Server types",
+		&["Type", "Servers"],
+		rows.iter()
+			.map(|row| {
+				vec![
+					row.get::<Option<String>, _>("software").unwrap_or_default(),
+					row.get::<i64, _>("servers").to_string(),
+				]
+			})
+			.collect(),
+	);
+
+	let rows = sqlx::query(
+		"SELECT discovered_by, COUNT(*) AS servers FROM servers GROUP BY discovered_by ORDER BY servers DESC",
+	)
+	.fetch_all(pool)
+	.await?;
+
+	print_table(
+		"Discovered by",
+		&["Engine", "Servers"],
+		rows.iter()
+			.map(|row| {
+				vec![
+					// Servers stored before the column existed, or only ever rescanned
+					row.get::<Option<String>, _>("discovered_by").unwrap_or_else(|| "unknown".to_string()),
+					row.get::<i64, _>("servers").to_string(),
+				]
+			})
+			.collect(),
+	);
+
+	let rows = sqlx::query(
+		"SELECT CASE
+			WHEN online_players = 0 THEN '0'
+			WHEN online_players <= 10 THEN '1-10'
+			WHEN online_players <= 50 THEN '11-50'
+			WHEN online_players <= 100 THEN '51-100'
+			ELSE '100+'
+		END AS bucket, MIN(online_players) AS bucket_start, COUNT(*) AS servers
+		FROM servers WHERE online_players IS NOT NULL AND players_implausible IS NOT TRUE
+		GROUP BY bucket ORDER BY bucket_start",
+	)
+	.fetch_all(pool)
+	.await?;
+
+	print_table(
+		"Online players",
+		&["Players", "Servers"],
+		rows.iter()
+			.map(|row| vec![row.get::<String, _>("bucket"), row.get::<i64, _>("servers").to_string()])
+			.collect(),
+	);
+
+	// Countries are only known when country tracking fills in the countries table
+	if country_tracking {
+		let rows = sqlx::query(
+			"SELECT country, COUNT(*) AS servers FROM servers GROUP BY country ORDER BY servers DESC LIMIT 15",
+		)
+		.fetch_all(pool)
+		.await?;
+
+		print_table(
+			"Countries",
+			&["Country", "Servers"],
+			rows.iter()
+				.map(|row| {
+					vec![
+						row.get::<Option<String>, _>("country").unwrap_or_default(),
+						row.get::<i64, _>("servers").to_string(),
+					]
+				})
+				.collect(),
+		);
+	}
+
+	Ok(())
+}
+
+// Upper bound of the first latency bucket, every bucket after it is twice as wide
+const FIRST_BUCKET_MS: i32 = 10;
+const LATENCY_BUCKETS: usize = 9;
+
+/// Servers stored before the ping method was recorded have none
+fn ping_method_name(ping_method: Option<String>) -> &'static str {
+	match ping_method.as_deref() {
+		Some("proper") => "proper",
+		Some("legacy") => "legacy",
+		_ => "unknown",
+	}
+}
+
+/// Histogram bucket for a latency, buckets double in width and the last one is open-ended
+fn latency_bucket(latency_ms: i32) -> usize {
+	if latency_ms < FIRST_BUCKET_MS {
+		return 0;
+	}
+
+	((latency_ms / FIRST_BUCKET_MS).ilog2() as usize + 1).min(LATENCY_BUCKETS - 1)
+}
+
+fn latency_bucket_label(bucket: usize) -> String {
+	if bucket == 0 {
+		return format!("<{}ms", FIRST_BUCKET_MS);
+	}
+
+	let start = FIRST_BUCKET_MS << (bucket - 1);
+	if bucket == LATENCY_BUCKETS - 1 {
+		format!("{}ms+", start)
+	} else {
+		format!("{}-{}ms", start, (start << 1) - 1)
+	}
+}
+
+pub(crate) fn print_table(title: &str, headers: &[&str], rows: Vec<Vec<String>>) {
+	println!("{}", format_table(title, headers, &rows));
+}
+
+fn format_table(title: &str, headers: &[&str], rows: &[Vec<String>]) -> String {
+	let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+	for row in rows {
+		for (width, cell) in widths.iter_mut().zip(row) {
+			*width = (*width).max(cell.chars().count());
+		}
+	}
+
+	let format_row = |cells: Vec<&str>| {
+		cells
+			.iter()
+			.zip(&widths)
+			.map(|(cell, width)| format!("{:<width$}", cell, width = width))
+			.collect::<Vec<_>>()
+			.join("  ")
+			.trim_end()
+			.to_string()
+	};
+
+	let mut output = format!("{}\n", title);
+	output.push_str(&format_row(headers.to_vec()));
+	output.push('\n');
+	let separator: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+	output.push_str(&format_row(separator.iter().map(String::as_str).collect()));
+	output.push('\n');
+
+	for row in rows {
+		output.push_str(&format_row(row.iter().map(String::as_str).collect()));
+		output.push('\n');
+	}
+
+	output
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_latency_buckets() {
+		assert_eq!(latency_bucket(0), 0);
+		assert_eq!(latency_bucket(9), 0);
+		assert_eq!(latency_bucket(10), 1);
+		assert_eq!(latency_bucket(19), 1);
+		assert_eq!(latency_bucket(20), 2);
+		assert_eq!(latency_bucket(1279), 7);
+		assert_eq!(latency_bucket(1280), 8);
+		assert_eq!(latency_bucket(i32::MAX), 8);
+
+		let labels: Vec<String> = (0..LATENCY_BUCKETS).map(latency_bucket_label).collect();
+		assert_eq!(labels[0], "<10ms");
+		assert_eq!(labels[1], "10-19ms");
+		assert_eq!(labels[2], "20-39ms");
+		assert_eq!(labels[7], "640-1279ms");
+		assert_eq!(labels[8], "1280ms+");
+	}
+
+	#[test]
+	fn test_format_table() {
+		let table = format_table(
+			"Server types",
+			&["Type", "Servers"],
+			&[
+				vec!["Paper".to_string(), "1200".to_string()],
+				vec!["Neoforge".to_string(), "7".to_string()],
+			],
+		);
+
+		assert_eq!(
+			table,
+			"Server types\nType      Servers\n--------  -------\nPaper     1200\nNeoforge  7\n"
+		);
+	}
+}
@@ -0,0 +1,169 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// How often the status file is rewritten while a pass is running
+pub const WRITE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+	mode: String,
+	running: bool,
+	target: Option<String>,
+	processed: u64,
+	succeeded: u64,
+	retries: u64,
+	failures: BTreeMap<&'static str, u64>,
+	progress: Option<Progress>,
+	started_at: Option<u64>,
+	finished_at: Option<u64>,
+	updated_at: u64,
+}
+
+/// How far through its target list a pass is, only known when the list is counted up front
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Progress {
+	pub position: u64,
+	pub length: u64,
+	pub per_sec: f64,
+	pub eta_secs: u64,
+}
+
+/// Progress of the current pass, written as JSON for healthchecks and scripts.
+/// Writes go to a temporary file that's renamed over the old one, so readers
+/// never see a partially written file
+#[derive(Debug)]
+pub struct StatusFile {
+	path: PathBuf,
+	report: Mutex<Report>,
+}
+
+fn now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+impl StatusFile {
+	pub fn new(path: PathBuf, mode: &str) -> Self {
+		Self {
+			path,
+			report: Mutex::new(Report {
+				mode: mode.to_string(),
+				..Report::default()
+			}),
+		}
+	}
+
+	/// Resets the counters for a new pass over `target`
+	pub fn start_pass(&self, target: Option<String>) {
+		{
+			let mut report = self.report.lock().unwrap();
+			let mode = std::mem::take(&mut report.mode);
+			*report = Report {
+				mode,
+				running: true,
+				target,
+				started_at: Some(now()),
+				..Report::default()
+			};
+		}
+		self.write();
+	}
+
+	pub fn finish_pass(&self) {
+		{
+			let mut report = self.report.lock().unwrap();
+			report.running = false;
+			report.finished_at = Some(now());
+		}
+		self.write();
+	}
+
+	pub fn record_success(&self) {
+		let mut report = self.report.lock().unwrap();
+		report.processed += 1;
+		report.succeeded += 1;
+	}
+
+	pub fn record_failure(&self, kind: &'static str) {
+		let mut report = self.report.lock().unwrap();
+		report.processed += 1;
+		*report.failures.entry(kind).or_default() += 1;
+	}
+
+	pub fn set_progress(&self, progress: Progress) {
+		self.report.lock().unwrap().progress = Some(progress);
+	}
+
+	/// Counts a ping sequence repeated after a timeout, the outcome is recorded separately
+	pub fn record_retry(&self) {
+		self.report.lock().unwrap().retries += 1;
+	}
+
+	/// Rewrites the status file, failures are logged since a missing status
+	/// file shouldn't stop the scan
+	pub fn write(&self) {
+		let json = {
+			let mut report = self.report.lock().unwrap();
+			report.updated_at = now();
+			serde_json::to_vec_pretty(&*report)
+		};
+
+		let mut temp_path = self.path.clone().into_os_string();
+		temp_path.push(".tmp");
+
+		let result = json
+			.map_err(std::io::Error::from)
+			.and_then(|json| std::fs::write(&temp_path, json))
+			.and_then(|_| std::fs::rename(&temp_path, &self.path));
+
+		if let Err(e) = result {
+			error!("Failed to write status file {}: {}", self.path.display(), e);
+		}
+	}
+
+	/// Keeps the file fresh during long passes
+	pub async fn write_periodically(&self, interval: Duration) {
+		let mut ticker = tokio::time::interval(interval);
+		loop {
+			ticker.tick().await;
+			if self.report.lock().unwrap().running {
+				self.write();
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::TestDir;
+
+	#[test]
+	fn test_status_file() {
+		let dir = TestDir::new("status");
+		let path = dir.path().join("status.json");
+
+		let status = StatusFile::new(path.clone(), "rescanner");
+		status.start_pass(Some("database".to_string()));
+		status.record_success();
+		status.record_failure("timed_out");
+		status.record_retry();
+		status.record_failure("timed_out");
+		status.finish_pass();
+
+		let written: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+		assert_eq!(written["mode"], "rescanner");
+		assert_eq!(written["running"], false);
+		assert_eq!(written["target"], "database");
+		assert_eq!(written["processed"], 3);
+		assert_eq!(written["succeeded"], 1);
+		assert_eq!(written["failures"]["timed_out"], 2);
+		assert_eq!(written["retries"], 1);
+		assert!(written["finished_at"].as_u64() >= written["started_at"].as_u64());
+		// Only the renamed file is left behind
+		assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+	}
+}
@@ -0,0 +1,306 @@
+use crate::config::{Config, RotationEntry};
+use crate::database::Database;
+use crate::targeting::{self, Rotation};
+use anyhow::{bail, Context};
+use futures_util::future::BoxFuture;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::info;
+
+/// What one discovery cycle scans
+#[derive(Debug, Clone)]
+pub enum Target {
+	/// A list of CIDRs or addresses, one per line
+	File(PathBuf),
+	/// A single CIDR or address
+	Direct(String),
+}
+
+/// Where discovery gets its targets, asked once at the start of every cycle. Sources other
+/// than the built-in ones can be handed to `ScanBuilder::target_source`
+pub trait TargetSource: Send + std::fmt::Debug {
+	/// Short description used in logs
+	fn name(&self) -> String;
+
+	/// The target for the next cycle, `None` scans the whole internet. The cycle is skipped
+	/// on errors rather than scanning more than was asked for
+	fn next_targets(&mut self) -> BoxFuture<'_, anyhow::Result<Option<Target>>>;
+}
+
+/// Builds the source the config describes: `--target`, then the rotation, then the command,
+/// then the country, and the whole internet when none of them are set
+pub fn from_config(config: &Config, database: &Database) -> Box<dyn TargetSource> {
+	let targeting = &config.targeting;
+
+	if let Some(target) = &targeting.custom_target {
+		return Box::new(DirectSource(target.clone()));
+	}
+	if let Some(rotation) = Rotation::new(targeting) {
+		return Box::new(RotationSource {
+			rotation,
+			config: config.clone(),
+			database: database.clone(),
+		});
+	}
+	if let Some(command) = &targeting.command {
+		return Box::new(CommandSource {
+			command: command.clone(),
+			timeout: Duration::from_secs(targeting.command_timeout_secs),
+			cache_dir: config.paths.cache_dir(),
+		});
+	}
+	if let Some(country) = &targeting.country {
+		return Box::new(CountrySource::new(country, config));
+	}
+
+	Box::new(InternetSource)
+}
+
+/// The source for a single rotation entry
+fn entry_source(entry: &RotationEntry, config: &Config, database: &Database) -> Box<dyn TargetSource> {
+	let cache_dir = config.paths.cache_dir();
+
+	match entry {
+		RotationEntry::Country(country) => Box::new(CountrySource::new(country, config)),
+		RotationEntry::Asn(asn) => Box::new(AsnSource {
+			asn: asn.clone(),
+			database: database.clone(),
+			cache_dir,
+		}),
+		RotationEntry::File(path) => Box::new(FileSource {
+			path: PathBuf::from(path),
+			cache_dir,
+		}),
+		RotationEntry::Cidr(cidr) => Box::new(DirectSource(cidr.clone())),
+		RotationEntry::Command(command) => Box::new(CommandSource {
+			command: command.clone(),
+			timeout: Duration::from_secs(config.targeting.command_timeout_secs),
+			cache_dir,
+		}),
+	}
+}
+
+#[derive(Debug)]
+pub struct InternetSource;
+
+impl TargetSource for InternetSource {
+	fn name(&self) -> String {
+		"the whole internet".to_string()
+	}
+
+	fn next_targets(&mut self) -> BoxFuture<'_, anyhow::Result<Option<Target>>> {
+		Box::pin(async { Ok(None) })
+	}
+}
+
+/// A CIDR or address, the same every cycle
+#[derive(Debug)]
+pub struct DirectSource(pub String);
+
+impl TargetSource for DirectSource {
+	fn name(&self) -> String {
+		format!("CIDR {}", self.0)
+	}
+
+	fn next_targets(&mut self) -> BoxFuture<'_, anyhow::Result<Option<Target>>> {
+		Box::pin(async { Ok(Some(Target::Direct(self.0.clone()))) })
+	}
+}
+
+/// A country's CIDR list, downloaded again once the cached copy is a week old
+#[derive(Debug)]
+pub struct CountrySource {
+	country: String,
+	url_template: Option<String>,
+	cache_dir: PathBuf,
+	jitter_percent: u8,
+}
+
+impl CountrySource {
+	pub fn new(country: &str, config: &Config) -> CountrySource {
+		CountrySource {
+			country: country.to_string(),
+			url_template: config.targeting.source_url_template.clone(),
+			cache_dir: config.paths.cache_dir(),
+			jitter_percent: config.scanner.delay_jitter_percent,
+		}
+	}
+}
+
+impl TargetSource for CountrySource {
+	fn name(&self) -> String {
+		format!("country {}", self.country)
+	}
+
+	fn next_targets(&mut self) -> BoxFuture<'_, anyhow::Result<Option<Target>>> {
+		Box::pin(async {
+			let path = targeting::fetch_country_cidrs(
+				&self.country,
+				self.url_template.as_deref(),
+				&self.cache_dir,
+				self.jitter_percent,
+			)
+			.await?;
+			Ok(Some(Target::File(path)))
+		})
+	}
+}
+
+/// The networks country tracking recorded for an ASN
+#[derive(Debug)]
+pub struct AsnSource {
+	asn: String,
+	database: Database,
+	cache_dir: PathBuf,
+}
+
+impl TargetSource for AsnSource {
+	fn name(&self) -> String {
+		format!("ASN {}", self.asn)
+	}
+
+	fn next_targets(&mut self) -> BoxFuture<'_, anyhow::Result<Option<Target>>> {
+		Box::pin(async {
+			let path = targeting::fetch_asn_cidrs(&self.database.0, &self.asn, &self.cache_dir).await?;
+			Ok(Some(Target::File(path)))
+		})
+	}
+}
+
+/// A target list on disk, read again every cycle so it can be edited between them
+#[derive(Debug)]
+pub struct FileSource {
+	path: PathBuf,
+	cache_dir: PathBuf,
+}
+
+impl TargetSource for FileSource {
+	fn name(&self) -> String {
+		format!("file {}", self.path.display())
+	}
+
+	fn next_targets(&mut self) -> BoxFuture<'_, anyhow::Result<Option<Target>>> {
+		Box::pin(async {
+			let path = targeting::prepare_target_file(&self.path, &self.cache_dir)?;
+			Ok(Some(Target::File(path)))
+		})
+	}
+}
+
+/// A shell command that prints CIDRs or addresses, one per line. Run every cycle, which makes
+/// it the way to pull targets from an internal API, a database or a search engine export
+#[derive(Debug)]
+pub struct CommandSource {
+	command: String,
+	timeout: Duration,
+	cache_dir: PathBuf,
+}
+
+impl TargetSource for CommandSource {
+	fn name(&self) -> String {
+		format!("command {}", self.command)
+	}
+
+	fn next_targets(&mut self) -> BoxFuture<'_, anyhow::Result<Option<Target>>> {
+		Box::pin(async {
+			let path = run_target_command(&self.command, self.timeout, &self.cache_dir).await?;
+			Ok(Some(Target::File(path)))
+		})
+	}
+}
+
+/// Runs `command` through the system shell and saves what it printed as a target list. The
+/// shell is killed if it takes longer than `timeout`
+async fn run_target_command(command: &str, timeout: Duration, cache_dir: &Path) -> anyhow::Result<PathBuf> {
+	let (shell, flag) = if cfg!(target_os = "windows") { ("cmd", "/C") } else { ("sh", "-c") };
+	let output = Command::new(shell)
+		.args([flag, command])
+		.stdin(std::process::Stdio::null())
+		.kill_on_drop(true)
+		.output();
+	let output = tokio::time::timeout(timeout, output)
+		.await
+		.with_context(|| format!("{} didn't finish within {}s", command, timeout.as_secs()))?
+		.with_context(|| format!("Failed to run {}", command))?;
+
+	if !output.status.success() {
+		bail!(
+			"{} exited with {}: {}",
+			command,
+			output.status,
+			String::from_utf8_lossy(&output.stderr).trim()
+		);
+	}
+
+	let content = String::from_utf8(output.stdout).context("Target command output is not valid text")?;
+	let targets = content.lines().filter(|line| !line.trim().is_empty() && !line.starts_with('#')).count();
+	if targets == 0 {
+		bail!("{} printed no targets", command);
+	}
+
+	std::fs::create_dir_all(cache_dir).context("Failed to create cache directory")?;
+	let path = cache_dir.join("command_targets.txt");
+	std::fs::write(&path, content).context("Failed to write command targets")?;
+	info!("Got {} targets from {}", targets, command);
+
+	Ok(path)
+}
+
+/// Cycles through `targeting.rotation`, each entry is its own source
+#[derive(Debug)]
+pub struct RotationSource {
+	rotation: Rotation,
+	config: Config,
+	database: Database,
+}
+
+impl TargetSource for RotationSource {
+	fn name(&self) -> String {
+		"rotation".to_string()
+	}
+
+	fn next_targets(&mut self) -> BoxFuture<'_, anyhow::Result<Option<Target>>> {
+		Box::pin(async {
+			let entry = self.rotation.next_entry().clone();
+			info!("Scanning rotation target: {}", entry);
+
+			let mut source = entry_source(&entry, &self.config, &self.database);
+			source.next_targets().await.with_context(|| format!("Rotation target {} failed", entry))
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::TestDir;
+
+	// Needs a POSIX shell
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn test_command_source() {
+		let cache_dir = TestDir::new("command");
+		let mut source = CommandSource {
+			command: "echo '# from an export'; echo 1.0.0.0/24; echo; echo 1.0.4.7".to_string(),
+			timeout: Duration::from_secs(10),
+			cache_dir: cache_dir.path().to_path_buf(),
+		};
+
+		let Some(Target::File(path)) = source.next_targets().await.unwrap() else {
+			panic!("expected a target file");
+		};
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "# from an export\n1.0.0.0/24\n\n1.0.4.7\n");
+
+		source.command = "true".to_string();
+		assert!(source.next_targets().await.is_err());
+		source.command = "echo 1.0.0.0/24; exit 3".to_string();
+		assert!(source.next_targets().await.is_err());
+
+		source.command = "sleep 10; echo 1.0.0.0/24".to_string();
+		source.timeout = Duration::from_millis(200);
+		let started = std::time::Instant::now();
+		assert!(source.next_targets().await.is_err());
+		assert!(started.elapsed() < Duration::from_secs(5));
+	}
+}
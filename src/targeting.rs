@@ -1,18 +1,104 @@
+use crate::config::{RotationEntry, Targeting};
+use crate::download;
+use crate::utils::jittered;
 use anyhow::{Context, Result};
+use rand::Rng;
+use sqlx::types::ipnet::{IpNet, Ipv4Net};
+use sqlx::{PgPool, Row};
 use std::fs;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
-use tracing::info;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
 
-const BASE_URL: &str = "https://raw.githubusercontent.com/herrbischoff/country-ip-blocks/master/ipv4/";
+const DEFAULT_URL_TEMPLATE: &str =
+    "https://raw.githubusercontent.com/herrbischoff/country-ip-blocks/master/ipv4/{country}.cidr";
 const CACHE_TTL_SECS: u64 = 7 * 24 * 60 * 60; // 7 days
 
-pub async fn fetch_country_cidrs(country_code: &str) -> Result<PathBuf> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// Guesses the compression from the content type or file name, then the magic bytes
+fn detect_compression(name: &str, content_type: Option<&str>, bytes: &[u8]) -> Compression {
+    let content_type = content_type.unwrap_or_default().to_lowercase();
+    let name = name.to_lowercase();
+
+    if content_type.contains("gzip") || name.ends_with(".gz") || bytes.starts_with(&[0x1F, 0x8B]) {
+        Compression::Gzip
+    } else if content_type.contains("zstd") || name.ends_with(".zst") || bytes.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+        Compression::Zstd
+    } else {
+        Compression::None
+    }
+}
+
+fn decompress(bytes: Vec<u8>, compression: Compression, name: &str) -> Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(bytes),
+        Compression::Gzip => {
+            let mut output = Vec::new();
+            flate2::read::GzDecoder::new(bytes.as_slice())
+                .read_to_end(&mut output)
+                .with_context(|| format!("Failed to decompress {} as gzip", name))?;
+            Ok(output)
+        }
+        Compression::Zstd => zstd::decode_all(bytes.as_slice())
+            .with_context(|| format!("Failed to decompress {} as zstd", name)),
+    }
+}
+
+/// Reads a target list from disk, decompressing gzip or zstd files
+pub fn read_target_file(path: &Path) -> Result<String> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let name = path.to_string_lossy();
+    let compression = detect_compression(&name, None, &bytes);
+    let bytes = decompress(bytes, compression, &name)?;
+
+    String::from_utf8(bytes).with_context(|| format!("{} is not valid text", path.display()))
+}
+
+/// Returns a target file the scan engines can read directly. Compressed files are
+/// decompressed into the cache, plain files are used as-is
+pub fn prepare_target_file(path: &Path, cache_dir: &Path) -> Result<PathBuf> {
+    let name = path.to_string_lossy();
+    let mut header = Vec::new();
+    fs::File::open(path)
+        .and_then(|file| file.take(4).read_to_end(&mut header))
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    if detect_compression(&name, None, &header) == Compression::None {
+        return Ok(path.to_path_buf());
+    }
+
+    fs::create_dir_all(cache_dir).context("Failed to create cache directory")?;
+
+    // list.txt.gz is cached as list.txt
+    let file_name = path.file_stem().map(PathBuf::from).unwrap_or_else(|| PathBuf::from("targets.txt"));
+    let file_path = cache_dir.join(file_name);
+    fs::write(&file_path, read_target_file(path)?).context("Failed to write decompressed target file")?;
+
+    Ok(file_path)
+}
+
+/// Downloads the CIDR list for a country, or reuses the cached copy while it's fresh.
+/// `url_template` replaces `{country}` with the lowercase country code. The cache lifetime
+/// is moved by up to `jitter_percent` each time so scanners don't all refresh together
+pub async fn fetch_country_cidrs(
+    country_code: &str,
+    url_template: Option<&str>,
+    cache_dir: &Path,
+    jitter_percent: u8,
+) -> Result<PathBuf> {
     let country_code = country_code.to_lowercase();
     
-    let cache_dir = Path::new("cache");
     if !cache_dir.exists() {
-        fs::create_dir(cache_dir).context("Failed to create cache directory")?;
+        fs::create_dir_all(cache_dir).context("Failed to create cache directory")?;
     }
 
     let file_name = format!("{}.txt", country_code);
@@ -25,7 +111,7 @@ pub async fn fetch_country_cidrs(country_code: &str) -> Result<PathBuf> {
             Ok(metadata) => match metadata.modified() {
                 Ok(modified) => match SystemTime::now().duration_since(modified) {
                     Ok(age) => {
-                        if age.as_secs() < CACHE_TTL_SECS {
+                        if age < jittered(Duration::from_secs(CACHE_TTL_SECS), jitter_percent) {
                             use_cache = true;
                         } else {
                             info!("Cache for {} is expired (age: {:?}), downloading fresh copy", country_code, age);
@@ -44,21 +130,363 @@ pub async fn fetch_country_cidrs(country_code: &str) -> Result<PathBuf> {
         return Ok(file_path);
     }
 
-    let url = format!("{}{}.cidr", BASE_URL, country_code);
+    let url = url_template.unwrap_or(DEFAULT_URL_TEMPLATE).replace("{country}", &country_code);
     info!("Downloading CIDR list for {} from {}", country_code, url);
 
-    let response = reqwest::get(&url)
+    let download::Download { body: bytes, content_type } = download::fetch(&url, download::MAX_CIDR_LIST_BYTES)
         .await
-        .context("Failed to download CIDR list")?
-        .error_for_status()
-        .context("Server returned error")?;
+        .context("Failed to download CIDR list")?;
 
-    let content = response
-        .text()
-        .await
-        .context("Failed to get response text")?;
+    // Always cache the plain list so the scan engines can read it
+    let compression = detect_compression(&url, content_type.as_deref(), &bytes);
+    let content = decompress(bytes, compression, &url)?;
 
     fs::write(&file_path, content).context("Failed to write CIDR list to file")?;
 
     Ok(file_path)
 }
+
+/// Writes every network announced by an ASN to a target file, using the
+/// countries table populated by country tracking
+pub async fn fetch_asn_cidrs(pool: &PgPool, asn: &str, cache_dir: &Path) -> Result<PathBuf> {
+    let asn = asn.to_uppercase();
+
+    if !cache_dir.exists() {
+        fs::create_dir_all(cache_dir).context("Failed to create cache directory")?;
+    }
+
+    let rows = sqlx::query("SELECT network FROM countries WHERE asn = $1")
+        .bind(&asn)
+        .fetch_all(pool)
+        .await
+        .context("Failed to query networks for ASN")?;
+
+    if rows.is_empty() {
+        anyhow::bail!("No networks found for {} (is country tracking enabled?)", asn);
+    }
+
+    let mut content = String::new();
+    for row in rows {
+        let network: IpNet = row.try_get("network")?;
+        content.push_str(&network.to_string());
+        content.push('\n');
+    }
+
+    let file_path = cache_dir.join(format!("{}.txt", asn.to_lowercase()));
+    fs::write(&file_path, content).context("Failed to write ASN network list to file")?;
+
+    Ok(file_path)
+}
+
+/// Cycles discovery through a list of targets, one per scan cycle
+#[derive(Debug)]
+pub struct Rotation {
+    entries: Vec<RotationEntry>,
+    last_scanned: Vec<Option<Instant>>,
+    weighted: bool,
+    next: usize,
+}
+
+impl Rotation {
+    pub fn new(targeting: &Targeting) -> Option<Self> {
+        if targeting.rotation.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            entries: targeting.rotation.clone(),
+            last_scanned: vec![None; targeting.rotation.len()],
+            weighted: targeting.rotation_weighted,
+            next: 0,
+        })
+    }
+
+    /// Picks the target for this cycle and marks it as scanned
+    pub fn next_entry(&mut self) -> &RotationEntry {
+        let index = if self.weighted {
+            self.pick_weighted()
+        } else {
+            let index = self.next;
+            self.next = (self.next + 1) % self.entries.len();
+            index
+        };
+
+        self.last_scanned[index] = Some(Instant::now());
+        &self.entries[index]
+    }
+
+    // Targets that were never scanned always go first, otherwise the chance of
+    // picking a target grows with the time since it was last scanned
+    fn pick_weighted(&self) -> usize {
+        if let Some(index) = self.last_scanned.iter().position(Option::is_none) {
+            return index;
+        }
+
+        let weights: Vec<u64> = self
+            .last_scanned
+            .iter()
+            .map(|t| t.map_or(0, |t| t.elapsed().as_millis() as u64).max(1))
+            .collect();
+
+        let mut roll = rand::thread_rng().gen_range(0..weights.iter().sum::<u64>());
+        for (index, weight) in weights.iter().enumerate() {
+            if roll < *weight {
+                return index;
+            }
+            roll -= weight;
+        }
+
+        0
+    }
+}
+
+/// Parses a list of `ip:port` entries, one per line. Bare addresses are expanded
+/// to every port in `ports`, blank lines and lines starting with `#` are ignored
+pub fn parse_socket_list(content: &str, ports: RangeInclusive<u16>) -> Vec<SocketAddrV4> {
+    let mut sockets = Vec::new();
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Ok(socket) = line.parse::<SocketAddrV4>() {
+            sockets.push(socket);
+        } else if let Ok(address) = line.parse::<Ipv4Addr>() {
+            sockets.extend(ports.clone().map(|port| SocketAddrV4::new(address, port)));
+        } else {
+            warn!("Skipping invalid target entry: {}", line);
+        }
+    }
+
+    sockets
+}
+
+/// Whether an address is part of the sample for `seed`. Each address is decided by a hash
+/// instead of a stream of random numbers, so the order targets are read in doesn't matter
+/// and the sample for a larger fraction contains the one for a smaller fraction
+pub fn in_sample(address: Ipv4Addr, fraction: f64, seed: u64) -> bool {
+    // splitmix64
+    let mut x = seed ^ (address.to_bits() as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+
+    (x as f64) < fraction * u64::MAX as f64
+}
+
+/// Writes the sampled hosts of every network in `content`, a list of CIDRs or addresses
+/// one per line, to `path`. Returns how many were written
+pub fn write_sampled_hosts(content: &str, fraction: f64, seed: u64, path: &Path) -> Result<u64> {
+    let mut file = std::io::BufWriter::new(fs::File::create(path).context("Failed to create sampled targets file")?);
+    let mut count = 0;
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let network = match line.parse::<Ipv4Net>().or_else(|_| line.parse::<Ipv4Addr>().map(Ipv4Net::from)) {
+            Ok(network) => network,
+            Err(_) => {
+                warn!("Skipping invalid target entry: {}", line);
+                continue;
+            }
+        };
+
+        // A single address has no separate network and broadcast addresses to leave out
+        let hosts: Box<dyn Iterator<Item = Ipv4Addr>> = match network.prefix_len() {
+            32 => Box::new(std::iter::once(network.addr())),
+            _ => Box::new(network.hosts()),
+        };
+        for host in hosts.filter(|host| in_sample(*host, fraction, seed)) {
+            writeln!(file, "{}", host).context("Failed to write sampled targets file")?;
+            count += 1;
+        }
+    }
+
+    file.flush().context("Failed to write sampled targets file")?;
+    Ok(count)
+}
+
+// Special purpose ranges that no public server is reachable on: this network, private
+// (RFC 1918), shared address space (RFC 6598), loopback, link local, IETF protocol
+// assignments, documentation, 6to4 relays, benchmarking, multicast and reserved
+const RESERVED: &[(Ipv4Addr, u8)] = &[
+    (Ipv4Addr::new(0, 0, 0, 0), 8),
+    (Ipv4Addr::new(10, 0, 0, 0), 8),
+    (Ipv4Addr::new(100, 64, 0, 0), 10),
+    (Ipv4Addr::new(127, 0, 0, 0), 8),
+    (Ipv4Addr::new(169, 254, 0, 0), 16),
+    (Ipv4Addr::new(172, 16, 0, 0), 12),
+    (Ipv4Addr::new(192, 0, 0, 0), 24),
+    (Ipv4Addr::new(192, 0, 2, 0), 24),
+    (Ipv4Addr::new(192, 88, 99, 0), 24),
+    (Ipv4Addr::new(192, 168, 0, 0), 16),
+    (Ipv4Addr::new(198, 18, 0, 0), 15),
+    (Ipv4Addr::new(198, 51, 100, 0), 24),
+    (Ipv4Addr::new(203, 0, 113, 0), 24),
+    (Ipv4Addr::new(224, 0, 0, 0), 4),
+    (Ipv4Addr::new(240, 0, 0, 0), 4),
+];
+
+/// The ranges skipped unless `targeting.allow_private` is set
+pub fn reserved_ranges() -> impl Iterator<Item = Ipv4Net> {
+    RESERVED.iter().map(|(address, prefix_len)| Ipv4Net::new(*address, *prefix_len).unwrap())
+}
+
+/// Whether an address is private, loopback, multicast or otherwise never a public server
+pub fn is_reserved(address: Ipv4Addr) -> bool {
+    reserved_ranges().any(|network| network.contains(&address))
+}
+
+/// How many addresses of `network` are reserved, the reserved ranges don't overlap each other
+pub fn reserved_overlap(network: Ipv4Net) -> u64 {
+    reserved_ranges()
+        .map(|reserved| {
+            let start = network.network().to_bits().max(reserved.network().to_bits()) as u64;
+            let end = network.broadcast().to_bits().min(reserved.broadcast().to_bits()) as u64;
+            (end + 1).saturating_sub(start)
+        })
+        .sum()
+}
+
+/// How many reserved addresses a list of CIDRs or addresses, one per line, covers
+pub fn reserved_in_list(content: &str) -> u64 {
+    content
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| line.parse::<Ipv4Net>().or_else(|_| line.parse::<Ipv4Addr>().map(Ipv4Net::from)).ok())
+        .map(reserved_overlap)
+        .sum()
+}
+
+/// Masscan samples by only scanning one of this many shards
+pub fn sample_shards(fraction: f64) -> u64 {
+    (1.0 / fraction).round().max(1.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TestDir;
+
+    const CIDRS: &str = "1.0.0.0/24\n1.0.4.0/22\n";
+
+    fn rotation(weighted: bool) -> Rotation {
+        let targeting = Targeting {
+            rotation: vec![
+                RotationEntry::Country("BR".to_string()),
+                RotationEntry::Asn("AS13335".to_string()),
+                RotationEntry::Cidr("1.0.0.0/24".to_string()),
+            ],
+            rotation_weighted: weighted,
+            ..Default::default()
+        };
+        Rotation::new(&targeting).unwrap()
+    }
+
+    #[test]
+    fn test_rotation_in_order() {
+        assert!(Rotation::new(&Targeting::default()).is_none());
+
+        let mut rotation = rotation(false);
+        let picked: Vec<_> = (0..4).map(|_| rotation.next_entry().to_string()).collect();
+        assert_eq!(picked, ["country BR", "ASN AS13335", "CIDR 1.0.0.0/24", "country BR"]);
+    }
+
+    #[test]
+    fn test_rotation_weighted() {
+        let mut rotation = rotation(true);
+        // Never scanned targets go first
+        let picked: Vec<_> = (0..3).map(|_| rotation.next_entry().to_string()).collect();
+        assert_eq!(picked, ["country BR", "ASN AS13335", "CIDR 1.0.0.0/24"]);
+
+        // Then the one scanned longest ago is by far the likeliest
+        let now = Instant::now();
+        rotation.last_scanned = vec![Some(now), now.checked_sub(Duration::from_secs(60)), Some(now)];
+        let stalest = (0..100).filter(|_| rotation.pick_weighted() == 1).count();
+        assert!(stalest > 90, "picked the stalest target {} times", stalest);
+    }
+
+    #[test]
+    fn test_detect_compression() {
+        assert_eq!(detect_compression("br.cidr", Some("text/plain"), b"1.0.0.0/24"), Compression::None);
+        assert_eq!(detect_compression("br.cidr.gz", None, b""), Compression::Gzip);
+        assert_eq!(detect_compression("br", Some("application/zstd"), b""), Compression::Zstd);
+        // Mirrors don't always get the name or content type right
+        assert_eq!(detect_compression("br.cidr", None, &[0x28, 0xB5, 0x2F, 0xFD]), Compression::Zstd);
+    }
+
+    #[test]
+    fn test_decompress() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(CIDRS.as_bytes()).unwrap();
+        let gzip = encoder.finish().unwrap();
+        assert_eq!(decompress(gzip, Compression::Gzip, "br.cidr.gz").unwrap(), CIDRS.as_bytes());
+
+        let zstd = zstd::encode_all(CIDRS.as_bytes(), 0).unwrap();
+        assert_eq!(decompress(zstd, Compression::Zstd, "br.cidr.zst").unwrap(), CIDRS.as_bytes());
+
+        let error = decompress(b"not gzip".to_vec(), Compression::Gzip, "br.cidr.gz").unwrap_err();
+        assert_eq!(error.to_string(), "Failed to decompress br.cidr.gz as gzip");
+    }
+
+    #[test]
+    fn test_in_sample() {
+        let network: Ipv4Net = "10.0.0.0/16".parse().unwrap();
+        let sampled = |fraction| network.hosts().filter(|host| in_sample(*host, fraction, 42)).collect::<Vec<_>>();
+
+        let small = sampled(0.01);
+        let large = sampled(0.05);
+        // 1% of 65534 hosts, with room for randomness
+        assert!((500..800).contains(&small.len()), "{}", small.len());
+        assert!(small.iter().all(|host| large.contains(host)));
+        assert_eq!(sampled(1.0).len(), 65534);
+
+        let other_seed = network.hosts().filter(|host| in_sample(*host, 0.01, 43)).collect::<Vec<_>>();
+        assert_ne!(small, other_seed);
+    }
+
+    #[test]
+    fn test_write_sampled_hosts() {
+        let dir = TestDir::new("sample");
+        let path = dir.path().join("sample.txt");
+
+        let count = write_sampled_hosts("# comment\n1.0.0.0/24\n1.0.4.7\nnot a cidr\n", 1.0, 7, &path).unwrap();
+        assert_eq!(count, 255);
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("1.0.0.1\n"));
+        assert!(content.ends_with("1.0.0.254\n1.0.4.7\n"));
+
+        let count = write_sampled_hosts(CIDRS, 0.1, 7, &path).unwrap();
+        assert_eq!(count as usize, fs::read_to_string(&path).unwrap().lines().count());
+
+        assert_eq!(sample_shards(0.01), 100);
+        assert_eq!(sample_shards(0.3), 3);
+        assert_eq!(sample_shards(1.0), 1);
+    }
+
+    #[test]
+    fn test_reserved() {
+        let reserved = [
+            "10.1.2.3", "172.31.255.255", "192.168.1.1", "100.64.0.1", "127.0.0.1", "169.254.1.1", "224.0.0.1",
+            "255.255.255.255", "0.0.0.0",
+        ];
+        for address in reserved {
+            assert!(is_reserved(address.parse().unwrap()), "{}", address);
+        }
+        for address in ["1.1.1.1", "172.32.0.1", "100.128.0.1", "8.8.8.8", "223.255.255.255"] {
+            assert!(!is_reserved(address.parse().unwrap()), "{}", address);
+        }
+
+        assert_eq!(reserved_overlap("1.0.0.0/24".parse().unwrap()), 0);
+        assert_eq!(reserved_overlap("192.168.1.0/24".parse().unwrap()), 256);
+        assert_eq!(reserved_overlap("172.0.0.0/8".parse().unwrap()), 1 << 20);
+        assert_eq!(reserved_overlap("10.0.0.1/32".parse().unwrap()), 1);
+        assert_eq!(reserved_in_list("# lab\n10.0.0.0/24\n1.0.0.0/24\n127.0.0.1\nnot a cidr\n"), 257);
+        // Every range is counted once
+        let total: u64 = reserved_ranges().map(|network| 1u64 << (32 - network.prefix_len())).sum();
+        assert_eq!(reserved_overlap("0.0.0.0/0".parse().unwrap()), total);
+    }
+}
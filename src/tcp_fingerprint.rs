@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+
+/// What the kernel learned about a connection from the handshake. The window scale and
+/// options come from the server's SYN-ACK, so they vary with its OS and anything in the
+/// path, and the MSS and path MTU drop below the usual values behind tunnels and VPNs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TcpFingerprint {
+	/// Smoothed round trip measured by the kernel, in microseconds
+	pub rtt_us: u32,
+	/// Segment size used when sending to the server
+	pub mss: u32,
+	/// Path MTU towards the server
+	pub pmtu: u32,
+	/// Window scale the server advertised, none if it didn't send the option
+	pub window_scale: Option<u8>,
+	pub timestamps: bool,
+	pub sack: bool,
+	pub ecn: bool,
+}
+
+impl TcpFingerprint {
+	/// Short signature of the server's TCP options, e.g. `ws=7,sack,ts`
+	pub fn signature(&self) -> String {
+		let mut parts = Vec::new();
+		if let Some(window_scale) = self.window_scale {
+			parts.push(format!("ws={}", window_scale));
+		}
+		if self.sack {
+			parts.push("sack".to_string());
+		}
+		if self.timestamps {
+			parts.push("ts".to_string());
+		}
+		if self.ecn {
+			parts.push("ecn".to_string());
+		}
+
+		parts.join(",")
+	}
+
+	/// Reads the fingerprint of a connected stream. Only Linux exposes this through
+	/// TCP_INFO, everywhere else there's nothing to read
+	#[cfg(target_os = "linux")]
+	pub fn read(stream: &TcpStream) -> Option<TcpFingerprint> {
+		use std::os::fd::AsRawFd;
+
+		const TCPI_OPT_TIMESTAMPS: u8 = 1;
+		const TCPI_OPT_SACK: u8 = 2;
+		const TCPI_OPT_WSCALE: u8 = 4;
+		const TCPI_OPT_ECN: u8 = 8;
+
+		// The start of the kernel's struct tcp_info, which only ever grows at the end.
+		// libc's version doesn't have the window scale fields on every target
+		#[repr(C)]
+		#[derive(Default)]
+		struct TcpInfo {
+			state: u8,
+			ca_state: u8,
+			retransmits: u8,
+			probes: u8,
+			backoff: u8,
+			options: u8,
+			// Two 4 bit fields, the send window scale comes first
+			wscale: u8,
+			flags: u8,
+			rto: u32,
+			ato: u32,
+			snd_mss: u32,
+			rcv_mss: u32,
+			unacked: u32,
+			sacked: u32,
+			lost: u32,
+			retrans: u32,
+			fackets: u32,
+			last_data_sent: u32,
+			last_ack_sent: u32,
+			last_data_recv: u32,
+			last_ack_recv: u32,
+			pmtu: u32,
+			rcv_ssthresh: u32,
+			rtt: u32,
+		}
+
+		let mut info = TcpInfo::default();
+		let mut length = std::mem::size_of::<TcpInfo>() as libc::socklen_t;
+		// SAFETY: the kernel writes at most `length` bytes into `info` and updates `length`
+		let result = unsafe {
+			libc::getsockopt(
+				stream.as_raw_fd(),
+				libc::IPPROTO_TCP,
+				libc::TCP_INFO,
+				&mut info as *mut TcpInfo as *mut libc::c_void,
+				&mut length,
+			)
+		};
+		if result != 0 || (length as usize) < std::mem::size_of::<TcpInfo>() {
+			return None;
+		}
+
+		let send_window_scale = if cfg!(target_endian = "little") { info.wscale & 0x0F } else { info.wscale >> 4 };
+
+		Some(TcpFingerprint {
+			rtt_us: info.rtt,
+			mss: info.snd_mss,
+			pmtu: info.pmtu,
+			window_scale: (info.options & TCPI_OPT_WSCALE != 0).then_some(send_window_scale),
+			timestamps: info.options & TCPI_OPT_TIMESTAMPS != 0,
+			sack: info.options & TCPI_OPT_SACK != 0,
+			ecn: info.options & TCPI_OPT_ECN != 0,
+		})
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	pub fn read(_stream: &TcpStream) -> Option<TcpFingerprint> {
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_signature() {
+		let fingerprint = TcpFingerprint {
+			rtt_us: 1200,
+			mss: 1460,
+			pmtu: 1500,
+			window_scale: Some(7),
+			timestamps: true,
+			sack: true,
+			ecn: false,
+		};
+		assert_eq!(fingerprint.signature(), "ws=7,sack,ts");
+
+		let bare = TcpFingerprint {
+			window_scale: None,
+			timestamps: false,
+			sack: false,
+			..fingerprint
+		};
+		assert_eq!(bare.signature(), "");
+	}
+
+	#[cfg(target_os = "linux")]
+	#[tokio::test]
+	async fn test_read_loopback() {
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let address = listener.local_addr().unwrap();
+		let stream = TcpStream::connect(address).await.unwrap();
+
+		let fingerprint = TcpFingerprint::read(&stream).unwrap();
+		assert!(fingerprint.mss > 0);
+		assert!(fingerprint.pmtu >= fingerprint.mss);
+		// Linux always offers SACK and window scaling to itself
+		assert!(fingerprint.sack);
+		assert!(fingerprint.window_scale.is_some());
+	}
+}
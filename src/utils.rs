@@ -1,3 +1,5 @@
+use rand::Rng;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -16,6 +18,51 @@ pub enum RunError {
 	ServerOptOut,
 	#[error("Error while updating server in database")]
 	DatabaseError(#[from] sqlx::Error),
+	#[error("Server is running {0}, not Minecraft")]
+	NotMinecraft(&'static str),
+}
+
+impl RunError {
+	/// Every `kind`, indexed by the `usize` each variant converts to
+	pub const KINDS: [&'static str; 8] = [
+		"address_parse",
+		"io",
+		"malformed_response",
+		"parse_response",
+		"timed_out",
+		"opted_out",
+		"database",
+		"not_minecraft",
+	];
+
+	/// Short name of the failure, used to count failures by type
+	pub fn kind(&self) -> &'static str {
+		use RunError::*;
+
+		match self {
+			AddressParseError(_) => "address_parse",
+			IOError(_) => "io",
+			MalformedResponse => "malformed_response",
+			ParseResponse(_) => "parse_response",
+			TimedOut(_) => "timed_out",
+			ServerOptOut => "opted_out",
+			DatabaseError(_) => "database",
+			NotMinecraft(_) => "not_minecraft",
+		}
+	}
+
+	/// Whether the failure could be a lost packet on the way to a live server. A refused
+	/// connection or anything that got an answer won't go differently a second time
+	pub fn is_retryable(&self) -> bool {
+		match self {
+			RunError::TimedOut(_) => true,
+			RunError::IOError(e) => matches!(
+				e.kind(),
+				std::io::ErrorKind::TimedOut | std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted
+			),
+			_ => false,
+		}
+	}
 }
 
 impl From<RunError> for usize {
@@ -30,10 +77,79 @@ impl From<RunError> for usize {
 			TimedOut(_) => 4,
 			ServerOptOut => 5,
 			DatabaseError(_) => 6,
+			NotMinecraft(_) => 7,
 		}
 	}
 }
 
+// Java Edition releases by protocol number. Releases sharing a protocol are listed as a range
+const PROTOCOL_VERSIONS: &[(i32, &str)] = &[
+	// Pre-netty versions, only seen in legacy ping responses
+	(49, "1.4.4-1.4.5"),
+	(51, "1.4.6-1.4.7"),
+	(60, "1.5-1.5.1"),
+	(61, "1.5.2"),
+	(73, "1.6.1"),
+	(74, "1.6.2"),
+	(78, "1.6.4"),
+	(4, "1.7.2-1.7.5"),
+	(5, "1.7.6-1.7.10"),
+	(47, "1.8-1.8.9"),
+	(107, "1.9"),
+	(108, "1.9.1"),
+	(109, "1.9.2"),
+	(110, "1.9.3-1.9.4"),
+	(210, "1.10-1.10.2"),
+	(315, "1.11"),
+	(316, "1.11.1-1.11.2"),
+	(335, "1.12"),
+	(338, "1.12.1"),
+	(340, "1.12.2"),
+	(393, "1.13"),
+	(401, "1.13.1"),
+	(404, "1.13.2"),
+	(477, "1.14"),
+	(480, "1.14.1"),
+	(485, "1.14.2"),
+	(490, "1.14.3"),
+	(498, "1.14.4"),
+	(573, "1.15"),
+	(575, "1.15.1"),
+	(578, "1.15.2"),
+	(735, "1.16"),
+	(736, "1.16.1"),
+	(751, "1.16.2"),
+	(753, "1.16.3"),
+	(754, "1.16.4-1.16.5"),
+	(755, "1.17"),
+	(756, "1.17.1"),
+	(757, "1.18-1.18.1"),
+	(758, "1.18.2"),
+	(759, "1.19"),
+	(760, "1.19.1-1.19.2"),
+	(761, "1.19.3"),
+	(762, "1.19.4"),
+	(763, "1.20-1.20.1"),
+	(764, "1.20.2"),
+	(765, "1.20.3-1.20.4"),
+	(766, "1.20.5-1.20.6"),
+	(767, "1.21-1.21.1"),
+	(768, "1.21.2-1.21.3"),
+	(769, "1.21.4"),
+	(770, "1.21.5"),
+	(771, "1.21.6"),
+	(772, "1.21.7-1.21.8"),
+	(773, "1.21.9-1.21.10"),
+];
+
+/// Looks up the release name for a protocol number, None for snapshots and unknown protocols
+pub fn protocol_version_name(protocol: i32) -> Option<&'static str> {
+	PROTOCOL_VERSIONS
+		.iter()
+		.find(|(number, _)| *number == protocol)
+		.map(|(_, name)| *name)
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MinecraftColorCodes {
 	Black,
@@ -154,10 +270,76 @@ impl MinecraftColorCodes {
 	}
 }
 
+/// Moves `duration` up or down by a random amount of at most `percent` of it, so scanners
+/// started together drift apart instead of hitting the same upstreams at the same time
+pub fn jittered(duration: Duration, percent: u8) -> Duration {
+	if percent == 0 {
+		return duration;
+	}
+
+	let spread = percent.min(100) as f64 / 100.0;
+	duration.mul_f64(rand::thread_rng().gen_range(1.0 - spread..=1.0 + spread))
+}
+
+/// A directory of its own under the system temp dir for a test, removed with its contents when dropped
+#[cfg(test)]
+pub struct TestDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+	pub fn new(name: &str) -> Self {
+		let path = std::env::temp_dir().join(format!("serverseeker-{}-{}", name, std::process::id()));
+		std::fs::create_dir_all(&path).unwrap();
+		Self(path)
+	}
+
+	pub fn path(&self) -> &std::path::Path {
+		&self.0
+	}
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
 
+	#[tokio::test]
+	async fn test_kinds_match_index() {
+		let elapsed = tokio::time::timeout(Duration::ZERO, std::future::pending::<()>()).await.unwrap_err();
+		let errors = [
+			RunError::from("x".parse::<std::net::Ipv4Addr>().unwrap_err()),
+			RunError::from(std::io::Error::other("x")),
+			RunError::MalformedResponse,
+			RunError::from(serde_json::from_str::<u8>("x").unwrap_err()),
+			RunError::from(elapsed),
+			RunError::ServerOptOut,
+			RunError::from(sqlx::Error::RowNotFound),
+			RunError::NotMinecraft("HTTP"),
+		];
+
+		for error in errors {
+			let kind = error.kind();
+			assert_eq!(RunError::KINDS[usize::from(error)], kind);
+		}
+	}
+
+	#[test]
+	fn test_jittered() {
+		let hour = Duration::from_secs(3600);
+		assert_eq!(jittered(hour, 0), hour);
+
+		for _ in 0..1000 {
+			let delay = jittered(hour, 10);
+			assert!(delay >= Duration::from_secs(3240) && delay <= Duration::from_secs(3960));
+		}
+	}
+
 	#[test]
 	fn test_hex_conversion() {
 		// Exact matches for legacy color definitions
@@ -173,4 +355,15 @@ mod tests {
 		assert_eq!(MinecraftColorCodes::from("#FE5555"), MinecraftColorCodes::Red); // Almost Red
 		assert_eq!(MinecraftColorCodes::from("#111111"), MinecraftColorCodes::Black); // Almost black
 	}
+
+	#[test]
+	fn test_retryable_errors() {
+		use std::io::{Error, ErrorKind};
+
+		assert!(RunError::IOError(Error::from(ErrorKind::TimedOut)).is_retryable());
+		assert!(RunError::IOError(Error::from(ErrorKind::ConnectionReset)).is_retryable());
+		assert!(!RunError::IOError(Error::from(ErrorKind::ConnectionRefused)).is_retryable());
+		assert!(!RunError::MalformedResponse.is_retryable());
+		assert!(!RunError::NotMinecraft("HTTP").is_retryable());
+	}
 }
@@ -0,0 +1,139 @@
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Bytes an i32 can take, negative values always take all of them
+pub const MAX_LEN: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum VarIntError {
+	#[error("VarInt ended early")]
+	Incomplete,
+	#[error("VarInt too big")]
+	TooBig,
+}
+
+impl From<VarIntError> for std::io::Error {
+	fn from(e: VarIntError) -> Self {
+		let kind = match e {
+			VarIntError::Incomplete => std::io::ErrorKind::UnexpectedEof,
+			VarIntError::TooBig => std::io::ErrorKind::InvalidData,
+		};
+		std::io::Error::new(kind, e)
+	}
+}
+
+/// Appends `value` as a VarInt. VarInts are two's complement i32s, so -1 is FF FF FF FF 0F
+pub fn encode(buf: &mut Vec<u8>, value: i32) {
+	let mut value = value as u32;
+	loop {
+		let byte = (value & 0x7F) as u8;
+		value >>= 7;
+		if value == 0 {
+			buf.push(byte);
+			return;
+		}
+		buf.push(byte | 0x80);
+	}
+}
+
+/// Adds the `index`th byte of a VarInt to `value`, returns whether it was the last one.
+/// Padded encodings like 80 00 are allowed, proxies pad lengths to a fixed size, but the
+/// 5th byte can only hold the top 4 bits and must end the VarInt
+fn push_byte(value: &mut u32, index: usize, byte: u8) -> Result<bool, VarIntError> {
+	if index == MAX_LEN - 1 && byte > 0x0F {
+		return Err(VarIntError::TooBig);
+	}
+
+	*value |= ((byte & 0x7F) as u32) << (7 * index);
+	Ok(byte & 0x80 == 0)
+}
+
+/// Decodes a VarInt from the start of `bytes`, returns the value and how many bytes it took
+pub fn decode_from_slice(bytes: &[u8]) -> Result<(i32, usize), VarIntError> {
+	let mut value = 0;
+	for (index, byte) in bytes.iter().take(MAX_LEN).enumerate() {
+		if push_byte(&mut value, index, *byte)? {
+			return Ok((value as i32, index + 1));
+		}
+	}
+
+	Err(VarIntError::Incomplete)
+}
+
+/// Reads a VarInt a byte at a time, returns the value and how many bytes were read.
+/// Buffer the reader, unbuffered sockets make this a read call per byte
+pub async fn decode_from_reader<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(i32, usize), std::io::Error> {
+	let mut value = 0;
+	for index in 0..MAX_LEN {
+		let byte = reader.read_u8().await?;
+		if push_byte(&mut value, index, byte)? {
+			return Ok((value as i32, index + 1));
+		}
+	}
+
+	// push_byte rejects a 5th byte that doesn't end the VarInt
+	unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::rngs::StdRng;
+	use rand::{Rng, SeedableRng};
+
+	#[tokio::test]
+	async fn test_round_trip() {
+		let mut rng = StdRng::seed_from_u64(25565);
+		let edges = [0, 1, 127, 128, 255, 25565, 2097151, 2097152, -1, i32::MIN, i32::MAX];
+
+		for value in edges.into_iter().chain((0..10_000).map(|_| rng.gen::<i32>())) {
+			let mut buf = Vec::new();
+			encode(&mut buf, value);
+
+			assert_eq!(decode_from_slice(&buf), Ok((value, buf.len())), "{}", value);
+			assert_eq!(decode_from_reader(&mut buf.as_slice()).await.unwrap(), (value, buf.len()));
+			// The length is exact even with more data after it
+			buf.extend_from_slice(&[0x80, 0x01]);
+			assert_eq!(decode_from_slice(&buf).unwrap().0, value);
+		}
+
+		let mut buf = Vec::new();
+		encode(&mut buf, -1);
+		assert_eq!(buf, [0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+		assert_eq!(decode_from_slice(&[0x00]), Ok((0, 1)));
+	}
+
+	#[tokio::test]
+	async fn test_random_bytes() {
+		// Both decoders agree on any input, and only ever accept at most 5 bytes
+		let mut rng = StdRng::seed_from_u64(47);
+
+		for _ in 0..10_000 {
+			let len = rng.gen_range(0..8);
+			let bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+
+			let slice = decode_from_slice(&bytes);
+			let reader = decode_from_reader(&mut bytes.as_slice()).await;
+			match (slice, reader) {
+				(Ok(decoded), Ok(read)) => {
+					assert_eq!(decoded, read, "{:02X?}", bytes);
+					assert!(decoded.1 <= MAX_LEN);
+				}
+				(Err(VarIntError::Incomplete), Err(e)) => assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof),
+				(Err(VarIntError::TooBig), Err(e)) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidData),
+				(slice, reader) => panic!("{:02X?}: {:?} and {:?}", bytes, slice, reader),
+			}
+		}
+	}
+
+	#[test]
+	fn test_overlong() {
+		assert_eq!(decode_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]), Err(VarIntError::TooBig));
+		// Bits past the 32nd
+		assert_eq!(decode_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0x1F]), Err(VarIntError::TooBig));
+		assert_eq!(decode_from_slice(&[0x80, 0x80]), Err(VarIntError::Incomplete));
+		assert_eq!(decode_from_slice(&[]), Err(VarIntError::Incomplete));
+		// Padded, as proxies write fixed size lengths
+		assert_eq!(decode_from_slice(&[0x85, 0x80, 0x00]), Ok((5, 3)));
+	}
+}
@@ -0,0 +1,489 @@
+use crate::config::{Paths, ScanEngine, WriteQueueConfig};
+use crate::database::Database;
+use crate::ping::PingMethod;
+use crate::protocol::LoginStatus;
+use crate::response::Server;
+use crate::tcp_fingerprint::TcpFingerprint;
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::SocketAddrV4;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, error, info, warn};
+
+/// Servers the database failed to take during an outage, kept in a file until it's back.
+/// Entries are written back in the order they failed, and survive restarts
+#[derive(Debug)]
+pub struct WriteQueue {
+	path: PathBuf,
+	max_entries: usize,
+	retry: Duration,
+	// Entries in the queue file, the one being drained and on their way to the writer
+	queued: Arc<AtomicUsize>,
+	// Held while the queue file is appended to or moved aside
+	file: Arc<Mutex<()>>,
+	// Lines for the writer thread, so pushes never wait on the disk
+	sender: UnboundedSender<String>,
+	// Held open while the queue is in use, see `lock`
+	_lock: File,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+	socket: SocketAddrV4,
+	// Unix seconds the server was pinged, written back as its last_seen
+	seen: u64,
+	// Only what came from the status, anything left over is read back into `extra`
+	server: Value,
+	scan: ScanFields,
+}
+
+// Filled in by the scanner after the status is parsed, a Server doesn't read these back
+#[derive(Serialize, Deserialize)]
+struct ScanFields {
+	hostname: Option<String>,
+	raw_json: Option<Value>,
+	accepted_protocols: Option<(i32, i32)>,
+	ping_method: Option<String>,
+	#[serde(default)]
+	lenient_parse: bool,
+	discovered_by: Option<String>,
+	connect_latency: Option<i32>,
+	total_latency: Option<i32>,
+	tcp_fingerprint: Option<TcpFingerprint>,
+	ping_attempts: Option<i32>,
+	country_code: Option<String>,
+	asn: Option<String>,
+	fronted: Option<bool>,
+	login_status: Option<String>,
+}
+
+impl ScanFields {
+	fn of(server: &Server) -> Self {
+		Self {
+			hostname: server.hostname.clone(),
+			raw_json: server.raw_json.clone(),
+			accepted_protocols: server.accepted_protocols,
+			ping_method: server.ping_method.map(str::to_string),
+			lenient_parse: server.lenient_parse,
+			discovered_by: server.discovered_by.map(str::to_string),
+			connect_latency: server.connect_latency,
+			total_latency: server.total_latency,
+			tcp_fingerprint: server.tcp_fingerprint.clone(),
+			ping_attempts: server.ping_attempts,
+			country_code: server.country_code.clone(),
+			asn: server.asn.clone(),
+			fronted: server.fronted,
+			login_status: server.login_status.map(str::to_string),
+		}
+	}
+
+	fn fill(self, server: &mut Server) {
+		server.hostname = self.hostname;
+		server.raw_json = self.raw_json;
+		server.accepted_protocols = self.accepted_protocols;
+		server.ping_method = known_name(self.ping_method, [PingMethod::Proper, PingMethod::Legacy].map(PingMethod::as_str));
+		server.lenient_parse = self.lenient_parse;
+		server.discovered_by = known_name(self.discovered_by, ScanEngine::value_variants().iter().map(ScanEngine::as_str));
+		server.connect_latency = self.connect_latency;
+		server.total_latency = self.total_latency;
+		server.tcp_fingerprint = self.tcp_fingerprint;
+		server.ping_attempts = self.ping_attempts;
+		server.country_code = self.country_code;
+		server.asn = self.asn;
+		server.fronted = self.fronted;
+		server.login_status = known_name(self.login_status, LoginStatus::ALL.map(LoginStatus::as_str));
+	}
+}
+
+/// One line of the queue file
+fn encode(server: &Server, socket: SocketAddrV4, seen: SystemTime) -> serde_json::Result<String> {
+	let seen = seen.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+	let scan = ScanFields::of(server);
+	// Server serializes the scanner's fields next to the status ones, they're kept apart here
+	let mut status = serde_json::to_value(server)?;
+	if let (Value::Object(status), Value::Object(scan)) = (&mut status, serde_json::to_value(&scan)?) {
+		for key in scan.keys() {
+			status.remove(key);
+		}
+	}
+	serde_json::to_string(&Entry { socket, seen, server: status, scan })
+}
+
+fn decode(line: &str) -> serde_json::Result<(Server, SocketAddrV4, SystemTime)> {
+	let entry: Entry = serde_json::from_str(line)?;
+	let mut server: Server = serde_json::from_value(entry.server)?;
+	entry.scan.fill(&mut server);
+
+	Ok((server, entry.socket, UNIX_EPOCH + Duration::from_secs(entry.seen)))
+}
+
+fn known_name(name: Option<String>, known: impl IntoIterator<Item = &'static str>) -> Option<&'static str> {
+	let name = name?;
+	known.into_iter().find(|known| *known == name)
+}
+
+fn count_lines(path: &Path) -> usize {
+	match File::open(path) {
+		Ok(file) => BufReader::new(file)
+			.lines()
+			.map_while(Result::ok)
+			.filter(|line| !line.trim().is_empty())
+			.count(),
+		Err(_) => 0,
+	}
+}
+
+fn is_database_error(e: &anyhow::Error) -> bool {
+	e.downcast_ref::<sqlx::Error>().is_some()
+}
+
+impl WriteQueue {
+	/// Returns None when the queue is disabled. Servers left queued by an earlier run are
+	/// picked up again
+	pub fn new(config: &WriteQueueConfig, paths: &Paths) -> Option<Self> {
+		if !config.enabled {
+			return None;
+		}
+
+		let path = match (&config.path, paths.run_id.is_some()) {
+			(Some(path), _) => paths.resolve(path),
+			// Named runs keep their own queue, a process id would be new after every restart
+			(None, true) => paths.run_dir().unwrap_or_else(|| paths.cache_dir()).join("write_queue.jsonl"),
+			(None, false) => paths.cache_dir().join("write_queue.jsonl"),
+		};
+		let lock = match lock(&path) {
+			Ok(lock) => lock,
+			Err(e) => {
+				error!(
+					"Running without a write queue, {} is in use by another instance ({}). Set paths.run_id \
+					or database.write_queue.path to give each its own",
+					path.display(),
+					e
+				);
+				return None;
+			}
+		};
+
+		let (sender, receiver) = mpsc::unbounded_channel();
+		let queue = WriteQueue {
+			path,
+			max_entries: config.max_entries,
+			retry: Duration::from_secs(config.retry_secs),
+			queued: Arc::new(AtomicUsize::new(0)),
+			file: Arc::new(Mutex::new(())),
+			sender,
+			_lock: lock,
+		};
+
+		let queued = count_lines(&queue.path) + count_lines(&queue.draining_path());
+		if queued > 0 {
+			info!("{} servers are still queued in {} from an earlier run", queued, queue.path.display());
+		}
+		queue.queued.store(queued, Ordering::SeqCst);
+
+		let (path, file, queued) = (queue.path.clone(), queue.file.clone(), queue.queued.clone());
+		tokio::task::spawn_blocking(move || append_batches(receiver, &path, &file, &queued));
+
+		Some(queue)
+	}
+
+	/// Where the oldest entries are moved while they're written back
+	fn draining_path(&self) -> PathBuf {
+		let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+		name.push(".draining");
+		self.path.with_file_name(name)
+	}
+
+	pub fn len(&self) -> usize {
+		self.queued.load(Ordering::SeqCst)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Hands a server the database failed to take to the writer thread, errors once the
+	/// queue is full
+	pub fn push(&self, server: &Server, socket: SocketAddrV4, seen: SystemTime) -> anyhow::Result<()> {
+		let line = encode(server, socket, seen)?;
+		let Ok(queued) = self
+			.queued
+			.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| (queued < self.max_entries).then_some(queued + 1))
+		else {
+			bail!("the write queue is full ({} servers)", self.max_entries);
+		};
+
+		if self.sender.send(line).is_err() {
+			release(&self.queued, 1);
+			bail!("the write queue writer has stopped");
+		}
+		if queued == 0 {
+			warn!("Database writes are failing, queueing servers in {}", self.path.display());
+		}
+
+		Ok(())
+	}
+
+	/// Writes queued servers back every `retry_secs` until the process exits
+	pub async fn run(self: std::sync::Arc<Self>, database: Database, opt_out_recheck: Duration) {
+		let mut interval = tokio::time::interval(self.retry);
+		interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+		loop {
+			interval.tick().await;
+
+			// A file at a time, until the queue is empty or the database fails again
+			loop {
+				match self.drain_file(&database, opt_out_recheck).await {
+					Ok(true) => continue,
+					Ok(false) => break,
+					Err(e) => {
+						error!("Failed to drain the write queue: {:#}", e);
+						break;
+					}
+				}
+			}
+		}
+	}
+
+	/// Writes the oldest queued file back, moving the queue file aside first if nothing is
+	/// being drained. Returns whether all of it was written and there may be more
+	async fn drain_file(&self, database: &Database, opt_out_recheck: Duration) -> anyhow::Result<bool> {
+		let draining = self.draining_path();
+		{
+			let _file = self.file.lock().unwrap();
+			if self.is_empty() {
+				return Ok(false);
+			}
+			// A file the database failed partway through last time goes first
+			if !draining.exists() {
+				if !self.path.exists() {
+					return Ok(false);
+				}
+				std::fs::rename(&self.path, &draining).context("Failed to move the write queue aside")?;
+			}
+		}
+
+		let file = File::open(&draining).with_context(|| format!("Failed to open {}", draining.display()))?;
+		let mut lines = BufReader::new(file).lines();
+		let (mut written, mut dropped) = (0, 0);
+		let mut failed = None;
+
+		for line in lines.by_ref() {
+			let line = line?;
+			if line.trim().is_empty() {
+				continue;
+			}
+
+			let (server, socket, seen) = match decode(&line) {
+				Ok(entry) => entry,
+				Err(e) => {
+					warn!("Dropping unreadable write queue entry: {}", e);
+					dropped += 1;
+					continue;
+				}
+			};
+
+			match database.update_server(server, socket, seen, opt_out_recheck).await {
+				Ok(()) => written += 1,
+				Err(e) if is_database_error(&e) => {
+					failed = Some((line, e));
+					break;
+				}
+				// Opted out servers are replaced by a placeholder in update_server itself
+				Err(e) => {
+					debug!("Dropping queued server {}: {}", socket, e);
+					dropped += 1;
+				}
+			}
+		}
+
+		// What's left is written to a new file first, so a crash can't lose entries
+		if let Some((line, _)) = &failed {
+			let rest = draining.with_extension("tmp");
+			let mut out = BufWriter::new(File::create(&rest).context("Failed to write the write queue")?);
+			writeln!(out, "{}", line)?;
+			for line in lines {
+				writeln!(out, "{}", line?)?;
+			}
+			out.into_inner()?.sync_data()?;
+			std::fs::rename(&rest, &draining).context("Failed to replace the write queue")?;
+		} else {
+			std::fs::remove_file(&draining).context("Failed to remove the drained write queue")?;
+		}
+
+		release(&self.queued, written + dropped);
+		let left = self.len();
+
+		if written > 0 || dropped > 0 {
+			info!("Backfilled {} queued servers into the database, {} left", written, left);
+		}
+		if let Some((_, e)) = failed {
+			debug!("Database is still failing, {} servers stay queued: {}", left, e);
+			return Ok(false);
+		}
+		if left == 0 {
+			info!("Write queue drained");
+		}
+
+		Ok(left > 0)
+	}
+}
+
+/// Takes an exclusive lock on `<queue>.lock`, released when the file is closed. Two
+/// instances draining the same queue would write entries twice and lose the ones appended
+/// while the other moved the file aside
+fn lock(path: &Path) -> anyhow::Result<File> {
+	if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+		std::fs::create_dir_all(parent).context("Failed to create write queue directory")?;
+	}
+	let mut name = path.file_name().unwrap_or_default().to_os_string();
+	name.push(".lock");
+	let file = OpenOptions::new()
+		.create(true)
+		.truncate(false)
+		.write(true)
+		.open(path.with_file_name(name))?;
+
+	#[cfg(target_os = "linux")]
+	{
+		use std::os::fd::AsRawFd;
+		// SAFETY: flock only takes the descriptor, which `file` keeps open
+		if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+			return Err(std::io::Error::last_os_error().into());
+		}
+	}
+
+	Ok(file)
+}
+
+/// Appends lines as they arrive until the queue is dropped, syncing once for everything that
+/// piled up while the last batch was written
+fn append_batches(mut receiver: UnboundedReceiver<String>, path: &Path, file: &Mutex<()>, queued: &AtomicUsize) {
+	while let Some(line) = receiver.blocking_recv() {
+		let mut batch = vec![line];
+		while let Ok(line) = receiver.try_recv() {
+			batch.push(line);
+		}
+
+		let _file = file.lock().unwrap();
+		if let Err(e) = append(path, &batch) {
+			error!("Failed to queue {} servers in {}: {:#}", batch.len(), path.display(), e);
+			release(queued, batch.len());
+		}
+	}
+}
+
+fn append(path: &Path, lines: &[String]) -> anyhow::Result<()> {
+	if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+		std::fs::create_dir_all(parent).context("Failed to create write queue directory")?;
+	}
+	let file = OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(path)
+		.with_context(|| format!("Failed to open {}", path.display()))?;
+
+	let mut out = BufWriter::new(file);
+	for line in lines {
+		writeln!(out, "{}", line)?;
+	}
+	out.into_inner()?.sync_data()?;
+	Ok(())
+}
+
+fn release(queued: &AtomicUsize, count: usize) {
+	let _ = queued.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| Some(queued.saturating_sub(count)));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::utils::TestDir;
+
+	#[test]
+	fn test_entry_round_trip() {
+		let json = serde_json::json!({
+			"version": {"name": "Paper 1.21.1", "protocol": 767},
+			"players": {"max": 20, "online": 2, "sample": [{"id": "4566e69f-c907-48ee-8d71-d7ba5aa00d20", "name": "Steve"}]},
+			"description": {"text": "§aA Minecraft Server"},
+			"enforcesSecureChat": true,
+			"previewsChat": false,
+		});
+		let mut server = Server::from_lenient_json(&json.to_string()).unwrap();
+		server.hostname = Some("mc.example.net".to_string());
+		server.ping_method = Some(PingMethod::Legacy.as_str());
+		server.discovered_by = Some(ScanEngine::Rustscan.as_str());
+		server.login_status = Some(LoginStatus::Whitelisted.as_str());
+		server.total_latency = Some(42);
+		server.accepted_protocols = Some((47, 767));
+		server.tcp_fingerprint = Some(TcpFingerprint {
+			rtt_us: 1500,
+			mss: 1448,
+			pmtu: 1500,
+			window_scale: Some(7),
+			timestamps: true,
+			sack: true,
+			ecn: false,
+		});
+		let socket: SocketAddrV4 = "203.0.113.7:25565".parse().unwrap();
+
+		let seen = UNIX_EPOCH + Duration::from_secs(1_790_000_000);
+
+		let line = encode(&server, socket, seen).unwrap();
+		assert!(!line.contains('\n'));
+		let (decoded, decoded_socket, decoded_seen) = decode(&line).unwrap();
+
+		assert_eq!(decoded_socket, socket);
+		assert_eq!(decoded_seen, seen);
+		// Compared as JSON, Server isn't PartialEq
+		assert_eq!(serde_json::to_value(&decoded).unwrap(), serde_json::to_value(&server).unwrap());
+		assert_eq!(decoded.ping_method, Some("legacy"));
+		assert_eq!(decoded.extra.keys().collect::<Vec<_>>(), ["previewsChat"]);
+		assert_eq!(decoded.players.sample.unwrap()[0].name, "Steve");
+
+		assert!(decode(r#"{"socket": "203.0.113.7:25565"}"#).is_err());
+	}
+
+	#[tokio::test]
+	async fn test_push_bound() {
+		let dir = TestDir::new("write-queue");
+		let config = WriteQueueConfig {
+			enabled: true,
+			path: Some(dir.path().join("queue.jsonl")),
+			max_entries: 2,
+			retry_secs: 1,
+		};
+		let server = Server::from_lenient_json(r#"{"version": {"name": "1.21.1", "protocol": 767}}"#).unwrap();
+		let socket: SocketAddrV4 = "203.0.113.7:25565".parse().unwrap();
+
+		let paths = Paths::default();
+		let queue = WriteQueue::new(&config, &paths).unwrap();
+		queue.push(&server, socket, SystemTime::now()).unwrap();
+		queue.push(&server, socket, SystemTime::now()).unwrap();
+		assert!(queue.push(&server, socket, SystemTime::now()).is_err());
+
+		// Queued servers are counted again on startup, once the writer has them on disk
+		let path = config.path.as_deref().unwrap();
+		for _ in 0..100 {
+			if count_lines(path) == 2 {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		// Only one instance can have the queue open
+		#[cfg(target_os = "linux")]
+		assert!(WriteQueue::new(&config, &paths).is_none());
+		drop(queue);
+		assert_eq!(WriteQueue::new(&config, &paths).unwrap().len(), 2);
+		assert!(WriteQueue::new(&WriteQueueConfig::default(), &paths).is_none());
+	}
+}